@@ -13,11 +13,17 @@ use nix::unistd::Pid;
 use postgres::Config;
 use reqwest::blocking::{Client, RequestBuilder, Response};
 use reqwest::{IntoUrl, Method};
-use safekeeper::http::models::TimelineCreateRequest;
+use safekeeper::http::models::{
+    TimelineCreateRequest, TimelineResetRequest, TimelineResetResponse,
+};
+use safekeeper::safekeeper::Term;
+use serde::Deserialize;
+use serde_with::{serde_as, DisplayFromStr};
 use thiserror::Error;
 use utils::{
     connstring::connection_address,
     http::error::HttpErrorBody,
+    lsn::Lsn,
     zid::{NodeId, ZTenantId, ZTimelineId},
 };
 
@@ -58,6 +64,20 @@ impl ResponseErrorMessageExt for Response {
     }
 }
 
+/// The part of the safekeeper's timeline status that the control plane needs.
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct SafekeeperTimelineStatus {
+    pub acceptor_state: SafekeeperAcceptorState,
+    #[serde_as(as = "DisplayFromStr")]
+    pub flush_lsn: Lsn,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SafekeeperAcceptorState {
+    pub term: Term,
+}
+
 //
 // Control routines for safekeeper.
 //
@@ -310,4 +330,43 @@ impl SafekeeperNode {
             .error_from_body()?
             .json()?)
     }
+
+    pub fn timeline_status(
+        &self,
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+    ) -> Result<SafekeeperTimelineStatus> {
+        Ok(self
+            .http_request(
+                Method::GET,
+                format!(
+                    "{}/timeline/{}/{}",
+                    self.http_base_url, tenant_id, timeline_id
+                ),
+            )
+            .send()?
+            .error_from_body()?
+            .json()?)
+    }
+
+    pub fn timeline_reset(
+        &self,
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+        lsn: Lsn,
+        term: Term,
+    ) -> Result<TimelineResetResponse> {
+        Ok(self
+            .http_request(
+                Method::POST,
+                format!(
+                    "{}/tenant/{}/timeline/{}/reset",
+                    self.http_base_url, tenant_id, timeline_id
+                ),
+            )
+            .json(&TimelineResetRequest { lsn, term })
+            .send()?
+            .error_from_body()?
+            .json()?)
+    }
 }
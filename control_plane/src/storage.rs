@@ -10,7 +10,10 @@ use anyhow::{bail, Context};
 use nix::errno::Errno;
 use nix::sys::signal::{kill, Signal};
 use nix::unistd::Pid;
-use pageserver::http::models::{TenantConfigRequest, TenantCreateRequest, TimelineCreateRequest};
+use pageserver::http::models::{
+    TenantConfigRequest, TenantCreateRequest, TimelineConfigRequest, TimelineCreateRequest,
+    TimelineResetRequest,
+};
 use pageserver::timelines::TimelineInfo;
 use postgres::{Config, NoTls};
 use reqwest::blocking::{Client, RequestBuilder, Response};
@@ -400,6 +403,11 @@ impl PageServerNode {
                     .get("compaction_threshold")
                     .map(|x| x.parse::<usize>())
                     .transpose()?,
+                compaction_strategy: settings.get("compaction_strategy").map(|x| x.to_string()),
+                compaction_tier_ratio: settings
+                    .get("compaction_tier_ratio")
+                    .map(|x| x.parse::<usize>())
+                    .transpose()?,
                 gc_horizon: settings
                     .get("gc_horizon")
                     .map(|x| x.parse::<u64>())
@@ -410,6 +418,20 @@ impl PageServerNode {
                     .map(|x| x.parse::<usize>())
                     .transpose()?,
                 pitr_interval: settings.get("pitr_interval").map(|x| x.to_string()),
+                getpage_rate_limit: settings
+                    .get("getpage_rate_limit")
+                    .map(|x| x.parse::<u64>())
+                    .transpose()?,
+                getpage_burst: settings
+                    .get("getpage_burst")
+                    .map(|x| x.parse::<u64>())
+                    .transpose()?,
+                compression: settings.get("compression").map(|x| x.to_string()),
+                scrub_period: settings.get("scrub_period").map(|x| x.to_string()),
+                scrub_rate_limit: settings
+                    .get("scrub_rate_limit")
+                    .map(|x| x.parse::<u64>())
+                    .transpose()?,
             })
             .send()?
             .error_from_body()?
@@ -441,6 +463,10 @@ impl PageServerNode {
                 compaction_threshold: settings
                     .get("compaction_threshold")
                     .map(|x| x.parse::<usize>().unwrap()),
+                compaction_strategy: settings.get("compaction_strategy").map(|x| x.to_string()),
+                compaction_tier_ratio: settings
+                    .get("compaction_tier_ratio")
+                    .map(|x| x.parse::<usize>().unwrap()),
                 gc_horizon: settings
                     .get("gc_horizon")
                     .map(|x| x.parse::<u64>().unwrap()),
@@ -449,6 +475,17 @@ impl PageServerNode {
                     .get("image_creation_threshold")
                     .map(|x| x.parse::<usize>().unwrap()),
                 pitr_interval: settings.get("pitr_interval").map(|x| x.to_string()),
+                getpage_rate_limit: settings
+                    .get("getpage_rate_limit")
+                    .map(|x| x.parse::<u64>().unwrap()),
+                getpage_burst: settings
+                    .get("getpage_burst")
+                    .map(|x| x.parse::<u64>().unwrap()),
+                compression: settings.get("compression").map(|x| x.to_string()),
+                scrub_period: settings.get("scrub_period").map(|x| x.to_string()),
+                scrub_rate_limit: settings
+                    .get("scrub_rate_limit")
+                    .map(|x| x.parse::<u64>().unwrap()),
             })
             .send()?
             .error_from_body()?;
@@ -456,6 +493,57 @@ impl PageServerNode {
         Ok(())
     }
 
+    pub fn timeline_config(
+        &self,
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+        settings: HashMap<&str, &str>,
+    ) -> anyhow::Result<()> {
+        self.http_request(
+            Method::PUT,
+            format!(
+                "{}/tenant/{}/timeline/{}/config",
+                self.http_base_url, tenant_id, timeline_id
+            ),
+        )
+        .json(&TimelineConfigRequest {
+            gc_horizon: settings
+                .get("gc_horizon")
+                .map(|x| x.parse::<u64>())
+                .transpose()
+                .context("Failed to parse 'gc_horizon' as an integer")?,
+            pitr_interval: settings.get("pitr_interval").map(|x| x.to_string()),
+        })
+        .send()?
+        .error_from_body()?;
+
+        Ok(())
+    }
+
+    pub fn timeline_reset(
+        &self,
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+        lsn: Lsn,
+        prev_record_lsn: Option<Lsn>,
+    ) -> anyhow::Result<()> {
+        self.http_request(
+            Method::POST,
+            format!(
+                "{}/tenant/{}/timeline/{}/reset",
+                self.http_base_url, tenant_id, timeline_id
+            ),
+        )
+        .json(&TimelineResetRequest {
+            lsn,
+            prev_record_lsn,
+        })
+        .send()?
+        .error_from_body()?;
+
+        Ok(())
+    }
+
     pub fn timeline_list(&self, tenant_id: &ZTenantId) -> anyhow::Result<Vec<TimelineInfo>> {
         let timeline_infos: Vec<TimelineInfo> = self
             .http_request(
@@ -485,6 +573,7 @@ impl PageServerNode {
                 new_timeline_id,
                 ancestor_start_lsn,
                 ancestor_timeline_id,
+                ancestor_start_timestamp: None,
             })
             .send()?
             .error_from_body()?
@@ -498,6 +498,11 @@ impl XLogPageHeaderData {
         use utils::bin_ser::LeSer;
         XLogPageHeaderData::des_from(&mut buf.reader())
     }
+
+    pub fn encode(&self) -> Result<Bytes, SerializeError> {
+        use utils::bin_ser::LeSer;
+        self.ser().map(|b| b.into())
+    }
 }
 
 impl XLogLongPageHeaderData {
@@ -588,6 +593,85 @@ pub fn generate_wal_segment(segno: u64, system_id: u64) -> Result<Bytes, Seriali
     Ok(seg_buf.freeze())
 }
 
+/// Size of the shutdown checkpoint record generated by [`generate_checkpoint_wal_segment`]
+const CHECKPOINT_RECORD_SIZE: usize =
+    XLOG_SIZE_OF_XLOG_RECORD + SIZE_OF_XLOG_RECORD_DATA_HEADER_SHORT + SIZEOF_CHECKPOINT;
+
+/// Find the position of a shutdown checkpoint record written at or after `lsn`,
+/// such that the record doesn't cross a page boundary.
+pub fn checkpoint_record_lsn(lsn: Lsn, seg_sz: usize) -> Lsn {
+    let rec_lsn = normalize_lsn(lsn, seg_sz);
+    let page_offs = rec_lsn.block_offset();
+    if page_offs as usize + CHECKPOINT_RECORD_SIZE > XLOG_BLCKSZ {
+        normalize_lsn(rec_lsn + (XLOG_BLCKSZ as u64 - page_offs), seg_sz)
+    } else {
+        rec_lsn
+    }
+}
+
+///
+/// Generate a WAL segment containing a single shutdown checkpoint record at
+/// `rec_lsn`, which should come from [`checkpoint_record_lsn`].
+///
+/// Unlike the segment from [`generate_wal_segment`], this is enough for a
+/// vanilla PostgreSQL server to start up, if pg_control points to the record.
+///
+pub fn generate_checkpoint_wal_segment(
+    rec_lsn: Lsn,
+    prev_lsn: Lsn,
+    system_id: u64,
+    checkpoint: &CheckPoint,
+) -> Result<Bytes, SerializeError> {
+    let seg_sz = pg_constants::WAL_SEGMENT_SIZE;
+    let segno = rec_lsn.segment_number(seg_sz);
+    let mut seg_buf = BytesMut::from(&generate_wal_segment(segno, system_id)?[..]);
+
+    // The first page of the segment already has a long header. Any other
+    // page needs a header of its own.
+    let seg_offs = rec_lsn.segment_offset(seg_sz);
+    let page_start = seg_offs - rec_lsn.block_offset() as usize;
+    if page_start != 0 {
+        let hdr = XLogPageHeaderData {
+            xlp_magic: XLOG_PAGE_MAGIC as u16,
+            xlp_info: 0,
+            xlp_tli: PG_TLI,
+            xlp_pageaddr: rec_lsn.0 - rec_lsn.block_offset(),
+            xlp_rem_len: 0,
+            ..Default::default() // Put 0 in padding fields.
+        };
+        let hdr_bytes = hdr.encode()?;
+        seg_buf[page_start..page_start + hdr_bytes.len()].copy_from_slice(&hdr_bytes);
+    }
+
+    // Main data of the record is the CheckPoint struct
+    let mut data = BytesMut::with_capacity(CHECKPOINT_RECORD_SIZE - XLOG_SIZE_OF_XLOG_RECORD);
+    data.extend_from_slice(&[
+        pg_constants::XLR_BLOCK_ID_DATA_SHORT,
+        SIZEOF_CHECKPOINT as u8,
+    ]);
+    data.extend_from_slice(&checkpoint.encode()?);
+
+    let mut record = XLogRecord {
+        xl_tot_len: CHECKPOINT_RECORD_SIZE as u32,
+        xl_xid: 0,
+        xl_prev: prev_lsn.0,
+        xl_info: pg_constants::XLOG_CHECKPOINT_SHUTDOWN,
+        xl_rmid: pg_constants::RM_XLOG_ID,
+        ..Default::default() // Put 0 in padding fields.
+    };
+    // The CRC covers the record data, followed by the header up to the CRC field.
+    let hdr_bytes = record.encode()?;
+    let crc = crc32c_append(0, &data);
+    record.xl_crc = crc32c_append(crc, &hdr_bytes[0..XLOG_RECORD_CRC_OFFS]);
+
+    let rec_start = seg_offs;
+    let data_start = rec_start + XLOG_SIZE_OF_XLOG_RECORD;
+    seg_buf[rec_start..data_start].copy_from_slice(&record.encode()?);
+    seg_buf[data_start..data_start + data.len()].copy_from_slice(&data);
+
+    Ok(seg_buf.freeze())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -716,6 +800,61 @@ mod tests {
     ///
     /// NOTE: These checks are sensitive to the value of XID_CHECKPOINT_INTERVAL,
     /// currently 1024.
+    #[test]
+    pub fn test_checkpoint_record_lsn() {
+        let seg_sz = pg_constants::WAL_SEGMENT_SIZE;
+
+        // Start of segment: after the long page header
+        assert_eq!(
+            checkpoint_record_lsn(Lsn(0x1000000), seg_sz),
+            Lsn(0x1000000 + XLOG_SIZE_OF_XLOG_LONG_PHD as u64)
+        );
+        // Middle of a page: 8-byte aligned
+        assert_eq!(
+            checkpoint_record_lsn(Lsn(0x1000103), seg_sz),
+            Lsn(0x1000108)
+        );
+        // Not enough room left on the page: start of the next page
+        assert_eq!(
+            checkpoint_record_lsn(Lsn(0x1001ff0), seg_sz),
+            Lsn(0x1002000 + XLOG_SIZE_OF_XLOG_SHORT_PHD as u64)
+        );
+    }
+
+    #[test]
+    pub fn test_generate_checkpoint_wal_segment() {
+        let seg_sz = pg_constants::WAL_SEGMENT_SIZE;
+        let rec_lsn = checkpoint_record_lsn(Lsn(0x1002345), seg_sz);
+        let checkpoint = CheckPoint {
+            redo: rec_lsn.0,
+            ThisTimeLineID: PG_TLI,
+            ..Default::default()
+        };
+        let seg =
+            generate_checkpoint_wal_segment(rec_lsn, Lsn(0x1002300), 42, &checkpoint).unwrap();
+        assert_eq!(seg.len(), seg_sz);
+
+        let mut page = &seg[rec_lsn.segment_offset(seg_sz) - rec_lsn.block_offset() as usize..];
+        let hdr = XLogPageHeaderData::from_bytes(&mut page).unwrap();
+        assert_eq!(hdr.xlp_magic, XLOG_PAGE_MAGIC as u16);
+        assert_eq!(hdr.xlp_pageaddr, 0x1002000);
+
+        let rec_offs = rec_lsn.segment_offset(seg_sz);
+        let record =
+            XLogRecord::from_slice(&seg[rec_offs..rec_offs + XLOG_SIZE_OF_XLOG_RECORD]).unwrap();
+        assert_eq!(record.xl_tot_len as usize, CHECKPOINT_RECORD_SIZE);
+        assert_eq!(record.xl_prev, 0x1002300);
+        let rec_end = rec_offs + record.xl_tot_len as usize;
+        let data = &seg[rec_offs + XLOG_SIZE_OF_XLOG_RECORD..rec_end];
+        let crc = crc32c_append(0, data);
+        let crc = crc32c_append(crc, &seg[rec_offs..rec_offs + XLOG_RECORD_CRC_OFFS]);
+        assert_eq!(record.xl_crc, crc);
+        assert_eq!(&data[2..], &checkpoint.encode().unwrap()[..]);
+
+        // No WAL after the record
+        assert!(seg[rec_end..].iter().all(|b| *b == 0));
+    }
+
     #[test]
     pub fn test_update_next_xid() {
         let checkpoint_buf = [0u8; std::mem::size_of::<CheckPoint>()];
@@ -3,7 +3,9 @@
 //! implementation determining how to process the queries. Currently its API
 //! is rather narrow, but we can extend it once required.
 
-use crate::pq_proto::{BeMessage, BeParameterStatusMessage, FeMessage, FeStartupPacket};
+use crate::pq_proto::{
+    BeMessage, BeParameterStatusMessage, FeMessage, FeStartupPacket, QueryError,
+};
 use crate::sock_split::{BidiStream, ReadStream, WriteStream};
 use anyhow::{bail, ensure, Context, Result};
 use bytes::{Bytes, BytesMut};
@@ -321,182 +323,288 @@ impl PostgresBackend {
         msg: FeMessage,
         unnamed_query_string: &mut Bytes,
     ) -> Result<ProcessMsgResult> {
-        // Allow only startup and password messages during auth. Otherwise client would be able to bypass auth
-        // TODO: change that to proper top-level match of protocol state with separate message handling for each state
-        if self.state < ProtoState::Established {
-            ensure!(
-                matches!(
-                    msg,
-                    FeMessage::PasswordMessage(_) | FeMessage::StartupPacket(_)
-                ),
-                "protocol violation"
-            );
-        }
-
         let have_tls = self.tls_config.is_some();
-        match msg {
-            FeMessage::StartupPacket(m) => {
-                trace!("got startup message {:?}", m);
-
-                match m {
-                    FeStartupPacket::SslRequest => {
-                        info!("SSL requested");
-
-                        self.write_message(&BeMessage::EncryptionResponse(have_tls))?;
-                        if have_tls {
-                            self.start_tls()?;
-                            self.state = ProtoState::Encrypted;
-                        }
-                    }
-                    FeStartupPacket::GssEncRequest => {
-                        info!("GSS requested");
-                        self.write_message(&BeMessage::EncryptionResponse(false))?;
-                    }
-                    FeStartupPacket::StartupMessage { .. } => {
-                        if have_tls && !matches!(self.state, ProtoState::Encrypted) {
-                            self.write_message(&BeMessage::ErrorResponse("must connect with TLS"))?;
-                            bail!("client did not connect with TLS");
-                        }
-
-                        // NB: startup() may change self.auth_type -- we are using that in proxy code
-                        // to bypass auth for new users.
-                        handler.startup(self, &m)?;
-
-                        match self.auth_type {
-                            AuthType::Trust => {
-                                self.write_message_noflush(&BeMessage::AuthenticationOk)?
-                                    .write_message_noflush(&BeParameterStatusMessage::encoding())?
-                                    // The async python driver requires a valid server_version
-                                    .write_message_noflush(&BeMessage::ParameterStatus(
-                                        BeParameterStatusMessage::ServerVersion("14.1"),
-                                    ))?
-                                    .write_message(&BeMessage::ReadyForQuery)?;
-                                self.state = ProtoState::Established;
-                            }
-                            AuthType::MD5 => {
-                                rand::thread_rng().fill(&mut self.md5_salt);
-                                self.write_message(&BeMessage::AuthenticationMD5Password(
-                                    self.md5_salt,
-                                ))?;
-                                self.state = ProtoState::Authentication;
-                            }
-                            AuthType::ZenithJWT => {
-                                self.write_message(&BeMessage::AuthenticationCleartextPassword)?;
-                                self.state = ProtoState::Authentication;
-                            }
-                        }
-                    }
-                    FeStartupPacket::CancelRequest { .. } => {
-                        return Ok(ProcessMsgResult::Break);
-                    }
+        let step = prepare_message(self.state, &mut self.buf_out, msg, unnamed_query_string)?;
+        let result = match step {
+            MessageStep::Reply => ProcessMsgResult::Continue,
+            MessageStep::Close => return Ok(ProcessMsgResult::Break),
+            MessageStep::SslRequest => {
+                info!("SSL requested");
+
+                self.write_message(&BeMessage::EncryptionResponse(have_tls))?;
+                if have_tls {
+                    self.start_tls()?;
+                    self.state = ProtoState::Encrypted;
                 }
+                ProcessMsgResult::Continue
             }
+            MessageStep::Startup(m) => {
+                if have_tls && !matches!(self.state, ProtoState::Encrypted) {
+                    self.write_message(&BeMessage::ErrorResponse("must connect with TLS"))?;
+                    bail!("client did not connect with TLS");
+                }
 
-            FeMessage::PasswordMessage(m) => {
-                trace!("got password message '{:?}'", m);
+                // NB: startup() may change self.auth_type -- we are using that in proxy code
+                // to bypass auth for new users.
+                handler.startup(self, &m)?;
+                write_startup_reply(
+                    &mut self.state,
+                    self.auth_type,
+                    &mut self.md5_salt,
+                    &mut self.buf_out,
+                )?;
+                ProcessMsgResult::Continue
+            }
+            MessageStep::CheckAuth(response) => {
+                let auth_result = match self.auth_type {
+                    AuthType::Trust => unreachable!(),
+                    AuthType::MD5 => handler.check_auth_md5(self, &response),
+                    AuthType::ZenithJWT => handler.check_auth_jwt(self, &response),
+                };
+                let reply = write_auth_reply(&mut self.state, &mut self.buf_out, auth_result);
+                self.flush()?;
+                reply?;
+                ProcessMsgResult::Continue
+            }
+            MessageStep::Query(query_string) => {
+                let result = handler.process_query(self, &query_string);
+                write_query_reply(&mut self.buf_out, &query_string, result)?
+            }
+            MessageStep::Execute(query_string) => {
+                let result = handler.process_query(self, &query_string);
+                write_execute_reply(&mut self.buf_out, &query_string, result)?;
+                ProcessMsgResult::Continue
+            }
+        };
+        self.flush()?;
+        Ok(result)
+    }
+}
 
-                assert!(self.state == ProtoState::Authentication);
+///
+/// What's left to do with a message from the client after `prepare_message()`.
+/// These are the steps that call the Handler or depend on how the connection
+/// does its I/O, so this module and [`crate::postgres_backend_async`] each do
+/// them their own way. The replies are buffered, the caller flushes them.
+///
+pub(crate) enum MessageStep {
+    /// Nothing, apart from flushing the replies.
+    Reply,
+    /// Close the connection.
+    Close,
+    /// The client asked for TLS: reply whether we support it, and start it.
+    SslRequest,
+    /// Call Handler::startup(), and then `write_startup_reply()`.
+    Startup(FeStartupPacket),
+    /// Check the password, or JWT, with the Handler, and then `write_auth_reply()`.
+    CheckAuth(Bytes),
+    /// Call Handler::process_query(), and then `write_query_reply()`.
+    Query(String),
+    /// Call Handler::process_query(), and then `write_execute_reply()`.
+    Execute(String),
+}
 
-                match self.auth_type {
-                    AuthType::Trust => unreachable!(),
-                    AuthType::MD5 => {
-                        let (_, md5_response) = m.split_last().context("protocol violation")?;
+/// Handle a message from the client as far as it can be done without the
+/// Handler, buffering the replies in 'buf_out'.
+pub(crate) fn prepare_message(
+    state: ProtoState,
+    buf_out: &mut BytesMut,
+    msg: FeMessage,
+    unnamed_query_string: &mut Bytes,
+) -> Result<MessageStep> {
+    // Allow only startup and password messages during auth. Otherwise client would be able to bypass auth
+    // TODO: change that to proper top-level match of protocol state with separate message handling for each state
+    if state < ProtoState::Established {
+        ensure!(
+            matches!(
+                msg,
+                FeMessage::PasswordMessage(_) | FeMessage::StartupPacket(_)
+            ),
+            "protocol violation"
+        );
+    }
 
-                        if let Err(e) = handler.check_auth_md5(self, md5_response) {
-                            self.write_message(&BeMessage::ErrorResponse(&e.to_string()))?;
-                            bail!("auth failed: {}", e);
-                        }
-                    }
-                    AuthType::ZenithJWT => {
-                        let (_, jwt_response) = m.split_last().context("protocol violation")?;
+    let step = match msg {
+        FeMessage::StartupPacket(m) => {
+            trace!("got startup message {:?}", m);
 
-                        if let Err(e) = handler.check_auth_jwt(self, jwt_response) {
-                            self.write_message(&BeMessage::ErrorResponse(&e.to_string()))?;
-                            bail!("auth failed: {}", e);
-                        }
-                    }
+            match m {
+                FeStartupPacket::SslRequest => MessageStep::SslRequest,
+                FeStartupPacket::GssEncRequest => {
+                    info!("GSS requested");
+                    BeMessage::write(buf_out, &BeMessage::EncryptionResponse(false))?;
+                    MessageStep::Reply
                 }
-                self.write_message_noflush(&BeMessage::AuthenticationOk)?
-                    .write_message_noflush(&BeParameterStatusMessage::encoding())?
-                    .write_message(&BeMessage::ReadyForQuery)?;
-                self.state = ProtoState::Established;
+                FeStartupPacket::StartupMessage { .. } => MessageStep::Startup(m),
+                FeStartupPacket::CancelRequest { .. } => MessageStep::Close,
             }
+        }
 
-            FeMessage::Query(m) => {
-                // remove null terminator
-                let query_string = cstr_to_str(&m.body)?;
-
-                trace!("got query {:?}", query_string);
-                // xxx distinguish fatal and recoverable errors?
-                if let Err(e) = handler.process_query(self, query_string) {
-                    // ":?" uses the alternate formatting style, which makes anyhow display the
-                    // full cause of the error, not just the top-level context + its trace.
-                    // We don't want to send that in the ErrorResponse though,
-                    // because it's not relevant to the compute node logs.
-                    if query_string.starts_with("callmemaybe") {
-                        // FIXME avoid printing a backtrace for tenant x not found errors until this is properly fixed
-                        error!("query handler for '{}' failed: {}", query_string, e);
-                    } else {
-                        error!("query handler for '{}' failed: {:?}", query_string, e);
-                    }
-                    self.write_message_noflush(&BeMessage::ErrorResponse(&e.to_string()))?;
-                    // TODO: untangle convoluted control flow
-                    if e.to_string().contains("failed to run") {
-                        return Ok(ProcessMsgResult::Break);
-                    }
-                }
-                self.write_message(&BeMessage::ReadyForQuery)?;
-            }
+        FeMessage::PasswordMessage(m) => {
+            trace!("got password message '{:?}'", m);
 
-            FeMessage::Parse(m) => {
-                *unnamed_query_string = m.query_string;
-                self.write_message(&BeMessage::ParseComplete)?;
-            }
+            assert!(state == ProtoState::Authentication);
 
-            FeMessage::Describe(_) => {
-                self.write_message_noflush(&BeMessage::ParameterDescription)?
-                    .write_message(&BeMessage::NoData)?;
-            }
+            // remove null terminator
+            let (_, response) = m.split_last().context("protocol violation")?;
+            MessageStep::CheckAuth(m.slice(..response.len()))
+        }
 
-            FeMessage::Bind(_) => {
-                self.write_message(&BeMessage::BindComplete)?;
-            }
+        FeMessage::Query(m) => {
+            // remove null terminator
+            let query_string = cstr_to_str(&m.body)?;
 
-            FeMessage::Close(_) => {
-                self.write_message(&BeMessage::CloseComplete)?;
-            }
+            trace!("got query {:?}", query_string);
+            MessageStep::Query(query_string.to_string())
+        }
 
-            FeMessage::Execute(_) => {
-                let query_string = cstr_to_str(unnamed_query_string)?;
-                trace!("got execute {:?}", query_string);
-                // xxx distinguish fatal and recoverable errors?
-                if let Err(e) = handler.process_query(self, query_string) {
-                    error!("query handler for '{}' failed: {:?}", query_string, e);
-                    self.write_message(&BeMessage::ErrorResponse(&e.to_string()))?;
-                }
-                // NOTE there is no ReadyForQuery message. This handler is used
-                // for basebackup and it uses CopyOut which doesn't require
-                // ReadyForQuery message and backend just switches back to
-                // processing mode after sending CopyDone or ErrorResponse.
-            }
+        FeMessage::Parse(m) => {
+            *unnamed_query_string = m.query_string;
+            BeMessage::write(buf_out, &BeMessage::ParseComplete)?;
+            MessageStep::Reply
+        }
 
-            FeMessage::Sync => {
-                self.write_message(&BeMessage::ReadyForQuery)?;
-            }
+        FeMessage::Describe(_) => {
+            BeMessage::write(buf_out, &BeMessage::ParameterDescription)?;
+            BeMessage::write(buf_out, &BeMessage::NoData)?;
+            MessageStep::Reply
+        }
 
-            FeMessage::Terminate => {
-                return Ok(ProcessMsgResult::Break);
-            }
+        FeMessage::Bind(_) => {
+            BeMessage::write(buf_out, &BeMessage::BindComplete)?;
+            MessageStep::Reply
+        }
 
-            // We prefer explicit pattern matching to wildcards, because
-            // this helps us spot the places where new variants are missing
-            FeMessage::CopyData(_) | FeMessage::CopyDone | FeMessage::CopyFail => {
-                bail!("unexpected message type: {:?}", msg);
-            }
+        FeMessage::Close(_) => {
+            BeMessage::write(buf_out, &BeMessage::CloseComplete)?;
+            MessageStep::Reply
+        }
+
+        FeMessage::Execute(_) => {
+            let query_string = cstr_to_str(unnamed_query_string)?;
+            trace!("got execute {:?}", query_string);
+            MessageStep::Execute(query_string.to_string())
+        }
+
+        FeMessage::Sync => {
+            BeMessage::write(buf_out, &BeMessage::ReadyForQuery)?;
+            MessageStep::Reply
+        }
+
+        FeMessage::Terminate => MessageStep::Close,
+
+        // We prefer explicit pattern matching to wildcards, because
+        // this helps us spot the places where new variants are missing
+        FeMessage::CopyData(_) | FeMessage::CopyDone | FeMessage::CopyFail => {
+            bail!("unexpected message type: {:?}", msg);
         }
+    };
+    Ok(step)
+}
+
+/// Reply to the startup message, after the Handler has seen it: either the
+/// connection is established, or the client has to authenticate.
+pub(crate) fn write_startup_reply(
+    state: &mut ProtoState,
+    auth_type: AuthType,
+    md5_salt: &mut [u8; 4],
+    buf_out: &mut BytesMut,
+) -> io::Result<()> {
+    match auth_type {
+        AuthType::Trust => {
+            BeMessage::write(buf_out, &BeMessage::AuthenticationOk)?;
+            BeMessage::write(buf_out, &BeParameterStatusMessage::encoding())?;
+            // The async python driver requires a valid server_version
+            BeMessage::write(
+                buf_out,
+                &BeMessage::ParameterStatus(BeParameterStatusMessage::ServerVersion("14.1")),
+            )?;
+            BeMessage::write(buf_out, &BeMessage::ReadyForQuery)?;
+            *state = ProtoState::Established;
+        }
+        AuthType::MD5 => {
+            rand::thread_rng().fill(md5_salt);
+            BeMessage::write(buf_out, &BeMessage::AuthenticationMD5Password(*md5_salt))?;
+            *state = ProtoState::Authentication;
+        }
+        AuthType::ZenithJWT => {
+            BeMessage::write(buf_out, &BeMessage::AuthenticationCleartextPassword)?;
+            *state = ProtoState::Authentication;
+        }
+    }
+    Ok(())
+}
+
+/// Reply to the password message, with the result of the Handler's check. If
+/// the check failed, the error is returned after buffering the ErrorResponse,
+/// and the caller should close the connection.
+pub(crate) fn write_auth_reply(
+    state: &mut ProtoState,
+    buf_out: &mut BytesMut,
+    auth_result: Result<()>,
+) -> Result<()> {
+    if let Err(e) = auth_result {
+        BeMessage::write(buf_out, &BeMessage::ErrorResponse(&e.to_string()))?;
+        bail!("auth failed: {}", e);
+    }
+    BeMessage::write(buf_out, &BeMessage::AuthenticationOk)?;
+    BeMessage::write(buf_out, &BeParameterStatusMessage::encoding())?;
+    BeMessage::write(buf_out, &BeMessage::ReadyForQuery)?;
+    *state = ProtoState::Established;
+    Ok(())
+}
+
+/// Reply to a simple query, with the result of Handler::process_query().
+pub(crate) fn write_query_reply(
+    buf_out: &mut BytesMut,
+    query_string: &str,
+    result: Result<()>,
+) -> io::Result<ProcessMsgResult> {
+    // xxx distinguish fatal and recoverable errors?
+    if let Err(e) = result {
+        // ":?" uses the alternate formatting style, which makes anyhow display the
+        // full cause of the error, not just the top-level context + its trace.
+        // We don't want to send that in the ErrorResponse though,
+        // because it's not relevant to the compute node logs.
+        if query_string.starts_with("callmemaybe") {
+            // FIXME avoid printing a backtrace for tenant x not found errors until this is properly fixed
+            error!("query handler for '{}' failed: {}", query_string, e);
+        } else {
+            error!("query handler for '{}' failed: {:?}", query_string, e);
+        }
+        BeMessage::write(
+            buf_out,
+            &BeMessage::ErrorResponseWithCode(&e.to_string(), QueryError::sqlstate(&e)),
+        )?;
+        // TODO: untangle convoluted control flow
+        if e.to_string().contains("failed to run") {
+            return Ok(ProcessMsgResult::Break);
+        }
+    }
+    BeMessage::write(buf_out, &BeMessage::ReadyForQuery)?;
+    Ok(ProcessMsgResult::Continue)
+}
 
-        Ok(ProcessMsgResult::Continue)
+/// Reply to an Execute message of the extended query protocol, with the result
+/// of Handler::process_query().
+pub(crate) fn write_execute_reply(
+    buf_out: &mut BytesMut,
+    query_string: &str,
+    result: Result<()>,
+) -> io::Result<()> {
+    // xxx distinguish fatal and recoverable errors?
+    if let Err(e) = result {
+        error!("query handler for '{}' failed: {:?}", query_string, e);
+        BeMessage::write(
+            buf_out,
+            &BeMessage::ErrorResponseWithCode(&e.to_string(), QueryError::sqlstate(&e)),
+        )?;
     }
+    // NOTE there is no ReadyForQuery message. This handler is used
+    // for basebackup and it uses CopyOut which doesn't require
+    // ReadyForQuery message and backend just switches back to
+    // processing mode after sending CopyDone or ErrorResponse.
+    Ok(())
 }
 
 // Set the flag to inform connections to cancel
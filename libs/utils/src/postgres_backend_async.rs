@@ -0,0 +1,210 @@
+//! Server-side asynchronous Postgres connection, as limited as we need.
+//! To use, create PostgresBackend and run() it, passing the Handler
+//! implementation determining how to process the queries. Currently its API
+//! is rather narrow, but we can extend it once required.
+//!
+//! This is the async counterpart of [`crate::postgres_backend`], and shares
+//! its protocol handling. Unlike the synchronous version, it doesn't support
+//! TLS: SSL requests are declined.
+
+use crate::postgres_backend::{
+    prepare_message, write_auth_reply, write_execute_reply, write_query_reply, write_startup_reply,
+    AuthType, MessageStep, ProcessMsgResult, ProtoState,
+};
+use crate::pq_proto::{BeMessage, FeMessage, FeStartupPacket};
+use anyhow::{bail, Result};
+use bytes::{Bytes, BytesMut};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tracing::*;
+
+#[async_trait::async_trait]
+pub trait Handler {
+    /// Handle single query.
+    /// postgres_backend will issue ReadyForQuery after calling this (this
+    /// might be not what we want after CopyData streaming, but currently we don't
+    /// care).
+    async fn process_query(&mut self, pgb: &mut PostgresBackend, query_string: &str) -> Result<()>;
+
+    /// Called on startup packet receival, allows to process params.
+    fn startup(&mut self, _pgb: &mut PostgresBackend, _sm: &FeStartupPacket) -> Result<()> {
+        Ok(())
+    }
+
+    /// Check auth md5
+    fn check_auth_md5(&mut self, _pgb: &mut PostgresBackend, _md5_response: &[u8]) -> Result<()> {
+        bail!("MD5 auth failed")
+    }
+
+    /// Check auth jwt
+    fn check_auth_jwt(&mut self, _pgb: &mut PostgresBackend, _jwt_response: &[u8]) -> Result<()> {
+        bail!("JWT auth failed")
+    }
+}
+
+pub struct PostgresBackend {
+    stream: BufReader<TcpStream>,
+    // Output buffer. c.f. BeMessage::write why we are using BytesMut here.
+    buf_out: BytesMut,
+
+    pub state: ProtoState,
+
+    md5_salt: [u8; 4],
+    auth_type: AuthType,
+
+    peer_addr: SocketAddr,
+}
+
+impl PostgresBackend {
+    pub fn new(socket: TcpStream, auth_type: AuthType) -> io::Result<Self> {
+        let peer_addr = socket.peer_addr()?;
+
+        Ok(Self {
+            stream: BufReader::new(socket),
+            buf_out: BytesMut::with_capacity(10 * 1024),
+            state: ProtoState::Initialization,
+            md5_salt: [0u8; 4],
+            auth_type,
+            peer_addr,
+        })
+    }
+
+    pub fn get_peer_addr(&self) -> &SocketAddr {
+        &self.peer_addr
+    }
+
+    /// Read full message or return None if connection is closed.
+    ///
+    /// Note that this is not cancellation-safe: if the returned future is
+    /// dropped in the middle of a message, the connection is left in an
+    /// undefined state and must be closed.
+    pub async fn read_message(&mut self) -> Result<Option<FeMessage>> {
+        use ProtoState::*;
+        match self.state {
+            Initialization | Encrypted => FeStartupPacket::read_fut(&mut self.stream).await,
+            Authentication | Established => FeMessage::read_fut(&mut self.stream).await,
+        }
+    }
+
+    /// Write message into internal output buffer.
+    pub fn write_message_noflush(&mut self, message: &BeMessage) -> io::Result<&mut Self> {
+        BeMessage::write(&mut self.buf_out, message)?;
+        Ok(self)
+    }
+
+    /// Flush output buffer into the socket.
+    pub async fn flush(&mut self) -> io::Result<&mut Self> {
+        self.stream.get_mut().write_all(&self.buf_out).await?;
+        self.buf_out.clear();
+        Ok(self)
+    }
+
+    /// Write message into internal buffer and flush it.
+    pub async fn write_message(&mut self, message: &BeMessage<'_>) -> io::Result<&mut Self> {
+        self.write_message_noflush(message)?;
+        self.flush().await
+    }
+
+    /// Process messages until the client disconnects, or `shutdown_watcher`
+    /// completes. The socket is shut down when we are done.
+    pub async fn run<F>(
+        mut self,
+        handler: &mut (impl Handler + Send),
+        shutdown_watcher: F,
+    ) -> Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        trace!("postgres backend to {:?} started", self.peer_addr);
+
+        tokio::pin!(shutdown_watcher);
+
+        let mut unnamed_query_string = Bytes::new();
+        let ret = loop {
+            let msg = tokio::select! {
+                biased;
+
+                _ = &mut shutdown_watcher => {
+                    // We were requested to shut down.
+                    break Ok(());
+                }
+
+                msg = self.read_message() => msg,
+            };
+
+            match msg {
+                Ok(Some(msg)) => {
+                    trace!("got message {:?}", msg);
+
+                    match self
+                        .process_message(handler, msg, &mut unnamed_query_string)
+                        .await
+                    {
+                        Ok(ProcessMsgResult::Continue) => continue,
+                        Ok(ProcessMsgResult::Break) => break Ok(()),
+                        Err(e) => break Err(e),
+                    }
+                }
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+
+        let _ = self.stream.get_mut().shutdown().await;
+        trace!("postgres backend to {:?} exited", self.peer_addr);
+        ret
+    }
+
+    async fn process_message(
+        &mut self,
+        handler: &mut (impl Handler + Send),
+        msg: FeMessage,
+        unnamed_query_string: &mut Bytes,
+    ) -> Result<ProcessMsgResult> {
+        let step = prepare_message(self.state, &mut self.buf_out, msg, unnamed_query_string)?;
+        let result = match step {
+            MessageStep::Reply => ProcessMsgResult::Continue,
+            MessageStep::Close => return Ok(ProcessMsgResult::Break),
+            MessageStep::SslRequest => {
+                info!("SSL requested, but TLS is not supported");
+                self.write_message_noflush(&BeMessage::EncryptionResponse(false))?;
+                ProcessMsgResult::Continue
+            }
+            MessageStep::Startup(m) => {
+                handler.startup(self, &m)?;
+                write_startup_reply(
+                    &mut self.state,
+                    self.auth_type,
+                    &mut self.md5_salt,
+                    &mut self.buf_out,
+                )?;
+                ProcessMsgResult::Continue
+            }
+            MessageStep::CheckAuth(response) => {
+                let auth_result = match self.auth_type {
+                    AuthType::Trust => unreachable!(),
+                    AuthType::MD5 => handler.check_auth_md5(self, &response),
+                    AuthType::ZenithJWT => handler.check_auth_jwt(self, &response),
+                };
+                let reply = write_auth_reply(&mut self.state, &mut self.buf_out, auth_result);
+                self.flush().await?;
+                reply?;
+                ProcessMsgResult::Continue
+            }
+            MessageStep::Query(query_string) => {
+                let result = handler.process_query(self, &query_string).await;
+                write_query_reply(&mut self.buf_out, &query_string, result)?
+            }
+            MessageStep::Execute(query_string) => {
+                let result = handler.process_query(self, &query_string).await;
+                write_execute_reply(&mut self.buf_out, &query_string, result)?;
+                ProcessMsgResult::Continue
+            }
+        };
+        self.flush().await?;
+        Ok(result)
+    }
+}
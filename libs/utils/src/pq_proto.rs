@@ -8,6 +8,7 @@ use bytes::{Buf, BufMut, Bytes, BytesMut};
 use postgres_protocol::PG_EPOCH;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 use std::future::Future;
 use std::io::{self, Cursor};
 use std::str;
@@ -22,6 +23,53 @@ pub const INT8_OID: Oid = 20;
 pub const INT4_OID: Oid = 23;
 pub const TEXT_OID: Oid = 25;
 
+/// SQLSTATE error code, sent to the client in ErrorResponse messages. See
+/// <https://www.postgresql.org/docs/current/errcodes-appendix.html>.
+pub type SqlState = [u8; 5];
+
+pub const SQLSTATE_INTERNAL_ERROR: &SqlState = b"XX000";
+pub const SQLSTATE_SYNTAX_ERROR: &SqlState = b"42601";
+pub const SQLSTATE_INVALID_PARAMETER_VALUE: &SqlState = b"22023";
+pub const SQLSTATE_INSUFFICIENT_PRIVILEGE: &SqlState = b"42501";
+pub const SQLSTATE_FEATURE_NOT_SUPPORTED: &SqlState = b"0A000";
+
+///
+/// An error that is reported to the client with a specific SQLSTATE code.
+///
+/// Query handlers return it, possibly wrapped in an anyhow::Error, to control
+/// the code of the ErrorResponse. Any other error is reported as an internal
+/// error.
+///
+#[derive(Debug)]
+pub struct QueryError {
+    pub code: &'static SqlState,
+    pub message: String,
+}
+
+impl QueryError {
+    pub fn new(code: &'static SqlState, message: impl Into<String>) -> Self {
+        QueryError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The SQLSTATE code to report for the given error.
+    pub fn sqlstate(err: &anyhow::Error) -> &'static SqlState {
+        err.chain()
+            .find_map(|cause| cause.downcast_ref::<QueryError>())
+            .map_or(SQLSTATE_INTERNAL_ERROR, |e| e.code)
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
 #[derive(Debug)]
 pub enum FeMessage {
     StartupPacket(FeStartupPacket),
@@ -432,6 +480,8 @@ pub enum BeMessage<'a> {
     // None means column is NULL
     DataRow(&'a [Option<&'a [u8]>]),
     ErrorResponse(&'a str),
+    // Like ErrorResponse, with the given SQLSTATE code instead of 'internal error'
+    ErrorResponseWithCode(&'a str, &'a SqlState),
     // single byte - used in response to SSLRequest/GSSENCRequest
     EncryptionResponse(bool),
     NoData,
@@ -599,6 +649,25 @@ pub fn write_cstr(s: &[u8], buf: &mut BytesMut) -> Result<(), io::Error> {
     Ok(())
 }
 
+fn write_error_response(buf: &mut BytesMut, code: &SqlState, error_msg: &str) {
+    // 'E' signalizes ErrorResponse messages
+    buf.put_u8(b'E');
+    write_body(buf, |buf| {
+        buf.put_u8(b'S'); // severity
+        write_cstr(&Bytes::from("ERROR"), buf)?;
+
+        buf.put_u8(b'C'); // SQLSTATE error code
+        write_cstr(code, buf)?;
+
+        buf.put_u8(b'M'); // the message
+        write_cstr(error_msg.as_bytes(), buf)?;
+
+        buf.put_u8(0); // terminator
+        Ok::<_, io::Error>(())
+    })
+    .unwrap();
+}
+
 // Truncate 0 from C string in Bytes and stringify it (returns slice, no allocations)
 // PG protocol strings are always C strings.
 fn cstr_to_str(b: &Bytes) -> Result<&str> {
@@ -774,23 +843,11 @@ impl<'a> BeMessage<'a> {
             BeMessage::ErrorResponse(error_msg) => {
                 // For all the errors set Severity to Error and error code to
                 // 'internal error'.
+                write_error_response(buf, SQLSTATE_INTERNAL_ERROR, error_msg);
+            }
 
-                // 'E' signalizes ErrorResponse messages
-                buf.put_u8(b'E');
-                write_body(buf, |buf| {
-                    buf.put_u8(b'S'); // severity
-                    write_cstr(&Bytes::from("ERROR"), buf)?;
-
-                    buf.put_u8(b'C'); // SQLSTATE error code
-                    write_cstr(&Bytes::from("CXX000"), buf)?;
-
-                    buf.put_u8(b'M'); // the message
-                    write_cstr(error_msg.as_bytes(), buf)?;
-
-                    buf.put_u8(0); // terminator
-                    Ok::<_, io::Error>(())
-                })
-                .unwrap();
+            BeMessage::ErrorResponseWithCode(error_msg, code) => {
+                write_error_response(buf, code, error_msg);
             }
 
             // NoticeResponse has the same format as ErrorResponse. From doc: "The frontend should display the
@@ -806,7 +863,7 @@ impl<'a> BeMessage<'a> {
                     write_cstr(&Bytes::from("NOTICE"), buf)?;
 
                     buf.put_u8(b'C'); // SQLSTATE error code
-                    write_cstr(&Bytes::from("CXX000"), buf)?;
+                    write_cstr(SQLSTATE_INTERNAL_ERROR, buf)?;
 
                     buf.put_u8(b'M'); // the message
                     write_cstr(error_msg.as_bytes(), buf)?;
@@ -1084,6 +1141,32 @@ mod tests {
         assert_eq!(zf, zf_parsed);
     }
 
+    #[test]
+    fn test_error_response_code() {
+        let mut buf = BytesMut::new();
+        BeMessage::write(
+            &mut buf,
+            &BeMessage::ErrorResponseWithCode("oops", SQLSTATE_SYNTAX_ERROR),
+        )
+        .unwrap();
+        // Skip the message tag and length
+        assert_eq!(&buf[5..], b"SERROR\0C42601\0Moops\0\0");
+
+        let mut buf = BytesMut::new();
+        BeMessage::write(&mut buf, &BeMessage::ErrorResponse("oops")).unwrap();
+        assert_eq!(&buf[5..], b"SERROR\0CXX000\0Moops\0\0");
+    }
+
+    #[test]
+    fn test_query_error_sqlstate() {
+        let err = anyhow::Error::new(QueryError::new(SQLSTATE_SYNTAX_ERROR, "bad command"))
+            .context("could not parse query");
+        assert_eq!(QueryError::sqlstate(&err), SQLSTATE_SYNTAX_ERROR);
+
+        let err = anyhow::anyhow!("something else");
+        assert_eq!(QueryError::sqlstate(&err), SQLSTATE_INTERNAL_ERROR);
+    }
+
     // Make sure that `read` is sync/async callable
     async fn _assert(stream: &mut (impl tokio::io::AsyncRead + Unpin)) {
         let _ = FeMessage::read(&mut [].as_ref());
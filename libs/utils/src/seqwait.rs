@@ -7,6 +7,7 @@ use std::mem;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Mutex;
 use std::time::Duration;
+use tokio::sync::oneshot;
 
 /// An error happened while waiting for a number
 #[derive(Debug, PartialEq, thiserror::Error)]
@@ -47,8 +48,30 @@ struct Waiter<T>
 where
     T: Ord,
 {
-    wake_num: T,              // wake me when this number arrives ...
-    wake_channel: Sender<()>, // ... by sending a message to this channel
+    wake_num: T,               // wake me when this number arrives ...
+    wake_channel: WakeChannel, // ... by sending a message to this channel
+}
+
+/// Blocking waiters are woken through a std channel, async waiters through
+/// a tokio oneshot channel.
+enum WakeChannel {
+    Sync(Sender<()>),
+    Async(oneshot::Sender<()>),
+}
+
+impl WakeChannel {
+    fn wake(self) {
+        // This can fail if there are no receivers.
+        // We don't care; discard the error.
+        match self {
+            WakeChannel::Sync(tx) => {
+                let _ = tx.send(());
+            }
+            WakeChannel::Async(tx) => {
+                let _ = tx.send(());
+            }
+        }
+    }
 }
 
 // BinaryHeap is a max-heap, and we want a min-heap. Reverse the ordering here
@@ -79,12 +102,16 @@ impl<T: Ord> Eq for Waiter<T> {}
 /// As soon as the number arrives by another caller calling
 /// [`advance`], then the waiter will be woken up.
 ///
+/// Waiting can be done either by blocking the current thread with
+/// [`wait_for`], or asynchronously with [`wait_for_timeout_async`].
+///
 /// This implementation takes a blocking Mutex on both [`wait_for`]
 /// and [`advance`], meaning there may be unexpected executor blocking
 /// due to thread scheduling unfairness. There are probably better
 /// implementations, but we can probably live with this for now.
 ///
 /// [`wait_for`]: SeqWait::wait_for
+/// [`wait_for_timeout_async`]: SeqWait::wait_for_timeout_async
 /// [`advance`]: SeqWait::advance
 ///
 /// <S> means Storage, <V> is type of counter that this storage exposes.
@@ -167,6 +194,39 @@ where
         }
     }
 
+    /// Wait for a number to arrive, without blocking the current thread.
+    ///
+    /// Same as [`SeqWait::wait_for_timeout`], but for use in async code.
+    pub async fn wait_for_timeout_async(
+        &self,
+        num: V,
+        timeout_duration: Duration,
+    ) -> Result<(), SeqWaitError> {
+        let rx = {
+            let mut internal = self.internal.lock().unwrap();
+            if internal.current.cnt_value() >= num {
+                return Ok(());
+            }
+            if internal.shutdown {
+                return Err(SeqWaitError::Shutdown);
+            }
+
+            let (tx, rx) = oneshot::channel();
+            internal.waiters.push(Waiter {
+                wake_num: num,
+                wake_channel: WakeChannel::Async(tx),
+            });
+            // Drop the lock as we exit this scope.
+            rx
+        };
+
+        match tokio::time::timeout(timeout_duration, rx).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(SeqWaitError::Shutdown),
+            Err(_) => Err(SeqWaitError::Timeout),
+        }
+    }
+
     /// Register and return a channel that will be notified when a number arrives,
     /// or None, if it has already arrived.
     fn queue_for_wait(&self, num: V) -> Result<Option<Receiver<()>>, SeqWaitError> {
@@ -182,7 +242,7 @@ where
         let (tx, rx) = channel();
         internal.waiters.push(Waiter {
             wake_num: num,
-            wake_channel: tx,
+            wake_channel: WakeChannel::Sync(tx),
         });
         // Drop the lock as we exit this scope.
         Ok(Some(rx))
@@ -217,9 +277,7 @@ where
         };
 
         for tx in wake_these {
-            // This can fail if there are no receivers.
-            // We don't care; discard the error.
-            let _ = tx.send(());
+            tx.wake();
         }
         old_value
     }
@@ -290,4 +348,28 @@ mod tests {
         let old = seq.advance(99);
         assert_eq!(old, 0)
     }
+
+    #[tokio::test]
+    async fn seqwait_async() {
+        let seq = Arc::new(SeqWait::new(0));
+        let seq2 = Arc::clone(&seq);
+        let timeout = Duration::from_secs(10);
+
+        let waiter = tokio::spawn(async move {
+            seq2.wait_for_timeout_async(42, timeout)
+                .await
+                .expect("wait_for 42");
+            seq2.wait_for_timeout_async(0, timeout)
+                .await
+                .expect("wait_for 0");
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        seq.advance(99);
+        waiter.await.unwrap();
+
+        let res = seq
+            .wait_for_timeout_async(100, Duration::from_millis(1))
+            .await;
+        assert_eq!(res, Err(SeqWaitError::Timeout));
+    }
 }
@@ -13,6 +13,7 @@ use safekeeper::defaults::{
     DEFAULT_HTTP_LISTEN_PORT as DEFAULT_SAFEKEEPER_HTTP_PORT,
     DEFAULT_PG_LISTEN_PORT as DEFAULT_SAFEKEEPER_PG_PORT,
 };
+use std::cmp::max;
 use std::collections::{BTreeSet, HashMap};
 use std::path::Path;
 use std::process::exit;
@@ -159,6 +160,17 @@ fn main() -> Result<()> {
                 .about("Create a new blank timeline")
                 .arg(tenant_id_arg.clone())
                 .arg(branch_name_arg.clone()))
+            .subcommand(App::new("config")
+                .about("Override the tenant's gc_horizon and pitr_interval for a timeline. Settings that are not given fall back to the tenant config")
+                .arg(tenant_id_arg.clone())
+                .arg(branch_name_arg.clone())
+                .arg(Arg::new("config").short('c').takes_value(true).multiple_occurrences(true).required(false)))
+            .subcommand(App::new("reset")
+                .about("Truncate the timeline history at an earlier Lsn on the safekeepers and the pageserver. All computes of the branch have to be stopped")
+                .arg(tenant_id_arg.clone())
+                .arg(branch_name_arg.clone())
+                .arg(Arg::new("lsn").long("lsn").takes_value(true)
+                    .help("Lsn to reset the timeline to").required(true)))
         ).subcommand(
             App::new("tenant")
             .setting(AppSettings::ArgRequiredElseHelp)
@@ -652,6 +664,111 @@ fn handle_timeline(timeline_match: &ArgMatches, env: &mut local_env::LocalEnv) -
                 timeline.timeline_id, last_record_lsn, tenant_id, ancestor_branch_name,
             );
         }
+        Some(("config", config_match)) => {
+            let tenant_id = get_tenant_id(config_match, env)?;
+            let branch_name = config_match
+                .value_of("branch-name")
+                .unwrap_or(DEFAULT_BRANCH_NAME);
+            let timeline_id = env
+                .get_branch_timeline_id(branch_name, tenant_id)
+                .ok_or_else(|| anyhow!("Found no timeline id for branch name '{}'", branch_name))?;
+            let timeline_conf: HashMap<_, _> = config_match
+                .values_of("config")
+                .map(|vals| vals.flat_map(|c| c.split_once(':')).collect())
+                .unwrap_or_default();
+
+            pageserver
+                .timeline_config(tenant_id, timeline_id, timeline_conf)
+                .with_context(|| format!("Timeline config failed for timeline {timeline_id}"))?;
+            println!("timeline {timeline_id} of tenant {tenant_id} successfully configured on the pageserver");
+        }
+        Some(("reset", reset_match)) => {
+            let tenant_id = get_tenant_id(reset_match, env)?;
+            let branch_name = reset_match
+                .value_of("branch-name")
+                .unwrap_or(DEFAULT_BRANCH_NAME);
+            let timeline_id = env
+                .get_branch_timeline_id(branch_name, tenant_id)
+                .ok_or_else(|| anyhow!("Found no timeline id for branch name '{}'", branch_name))?;
+            let lsn = reset_match
+                .value_of("lsn")
+                .map(Lsn::from_str)
+                .transpose()
+                .context("Failed to parse reset Lsn from the request")?
+                .ok_or_else(|| anyhow!("No reset Lsn provided"))?;
+
+            let cplane = ComputeControlPlane::load(env.clone())?;
+            if let Some(((_, node_name), node)) = cplane.nodes.iter().find(|(_, node)| {
+                node.tenant_id == tenant_id
+                    && node.timeline_id == timeline_id
+                    && node.status() != "stopped"
+            }) {
+                bail!(
+                    "Compute node '{}' of branch '{}' is {}, stop it before the reset",
+                    node_name,
+                    branch_name,
+                    node.status()
+                );
+            }
+
+            // Check all the safekeepers before changing any of them, and pick
+            // a term newer than any of theirs. All of them start that same
+            // term at the reset point, so that none of them wins an election
+            // with the old WAL.
+            let safekeepers = env
+                .safekeepers
+                .iter()
+                .map(|node| SafekeeperNode::from_env(env, node))
+                .collect::<Vec<_>>();
+            let mut max_term = 0;
+            for safekeeper in &safekeepers {
+                let status = safekeeper
+                    .timeline_status(tenant_id, timeline_id)
+                    .with_context(|| {
+                        format!(
+                            "Failed to get timeline status from safekeeper {}",
+                            safekeeper.id
+                        )
+                    })?;
+                if status.flush_lsn < lsn {
+                    bail!(
+                        "Safekeeper {} only has WAL up to {}, cannot reset to {}",
+                        safekeeper.id,
+                        status.flush_lsn,
+                        lsn
+                    );
+                }
+                max_term = max(max_term, status.acceptor_state.term);
+            }
+            let term = max_term + 1;
+
+            // Truncate the WAL on the safekeepers first, so that the pageserver
+            // doesn't get the WAL after the reset point streamed again. They
+            // also tell the start of the record before the reset point, which
+            // the pageserver doesn't know. If some of them fail, running the
+            // command again finishes the reset: truncating the WAL of the
+            // others at the same LSN again is a no-op.
+            let mut prev_record_lsn = None;
+            for (i, safekeeper) in safekeepers.iter().enumerate() {
+                let response = safekeeper
+                    .timeline_reset(tenant_id, timeline_id, lsn, term)
+                    .with_context(|| {
+                        format!(
+                            "Timeline reset failed on safekeeper {}, after resetting {} of {} safekeepers, run the command again to finish the reset",
+                            safekeeper.id,
+                            i,
+                            safekeepers.len()
+                        )
+                    })?;
+                prev_record_lsn = prev_record_lsn.or(response.prev_record_lsn);
+            }
+            pageserver
+                .timeline_reset(tenant_id, timeline_id, lsn, prev_record_lsn)
+                .with_context(|| format!("Timeline reset failed for timeline {timeline_id}"))?;
+            println!(
+                "timeline {timeline_id} of tenant {tenant_id} successfully reset to Lsn {lsn}"
+            );
+        }
         Some((sub_name, _)) => bail!("Unexpected tenant subcommand '{}'", sub_name),
         None => bail!("no tenant subcommand provided"),
     }
@@ -0,0 +1,203 @@
+//!
+//! Compare the LayerMap search structure with a plain vector of layers that
+//! is scanned linearly, which is what the LayerMap used to be.
+//!
+//! The layer map is populated with a synthetic history that looks like what
+//! compaction produces: a stack of L1 delta layers with varying key
+//! boundaries, some L0 delta layers covering the whole key space, and image
+//! layers created now and then.
+//!
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use pageserver::layered_repository::layer_map::LayerMap;
+use pageserver::layered_repository::storage_layer::{Layer, LayerDescriptor};
+use pageserver::repository::Key;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::sync::Arc;
+use utils::lsn::Lsn;
+
+const NUM_KEYS: u32 = 1_000_000;
+
+fn key(i: u32) -> Key {
+    Key::MIN.add(i)
+}
+
+fn generate_layers(rng: &mut StdRng, num_layers: usize) -> Vec<Arc<dyn Layer>> {
+    let mut layers: Vec<Arc<dyn Layer>> = Vec::new();
+    let mut lsn = Lsn(0x1000);
+    while layers.len() < num_layers {
+        let lsn_range = lsn..lsn + 0x10000;
+        if rng.gen_bool(0.1) {
+            layers.push(Arc::new(LayerDescriptor {
+                key: Key::MIN..Key::MAX,
+                lsn: lsn_range,
+                is_incremental: true,
+            }));
+        } else {
+            let mut start = 0;
+            while start < NUM_KEYS {
+                let end = std::cmp::min(start + rng.gen_range(1..NUM_KEYS / 10), NUM_KEYS);
+                layers.push(Arc::new(LayerDescriptor {
+                    key: key(start)..key(end),
+                    lsn: lsn_range.clone(),
+                    is_incremental: true,
+                }));
+                start = end;
+            }
+        }
+        lsn = lsn + 0x10000;
+
+        if rng.gen_bool(0.2) {
+            let mut start = 0;
+            while start < NUM_KEYS {
+                let end = std::cmp::min(start + NUM_KEYS / 20, NUM_KEYS);
+                layers.push(Arc::new(LayerDescriptor {
+                    key: key(start)..key(end),
+                    lsn: lsn..lsn + 1,
+                    is_incremental: false,
+                }));
+                start = end;
+            }
+            lsn = lsn + 1;
+        }
+    }
+    layers
+}
+
+/// The search algorithm of the LayerMap before it had an index: scan all the
+/// layers to find the latest image and delta layer.
+fn search_linear(
+    layers: &[Arc<dyn Layer>],
+    key: Key,
+    end_lsn: Lsn,
+) -> Option<(Arc<dyn Layer>, Lsn)> {
+    let mut latest_img: Option<Arc<dyn Layer>> = None;
+    let mut latest_img_lsn: Option<Lsn> = None;
+    for l in layers.iter() {
+        if l.is_incremental() || !l.get_key_range().contains(&key) {
+            continue;
+        }
+        let img_lsn = l.get_lsn_range().start;
+        if img_lsn >= end_lsn {
+            continue;
+        }
+        if Lsn(img_lsn.0 + 1) == end_lsn {
+            return Some((Arc::clone(l), img_lsn));
+        }
+        if img_lsn > latest_img_lsn.unwrap_or(Lsn(0)) {
+            latest_img = Some(Arc::clone(l));
+            latest_img_lsn = Some(img_lsn);
+        }
+    }
+
+    let mut latest_delta: Option<Arc<dyn Layer>> = None;
+    for l in layers.iter() {
+        if !l.is_incremental() || !l.get_key_range().contains(&key) {
+            continue;
+        }
+        if l.get_lsn_range().start >= end_lsn {
+            continue;
+        }
+        if l.get_lsn_range().end >= end_lsn {
+            latest_delta.replace(Arc::clone(l));
+            break;
+        }
+        if let Some(old_candidate) = &latest_delta {
+            if l.get_lsn_range().end > old_candidate.get_lsn_range().end {
+                latest_delta.replace(Arc::clone(l));
+            }
+        } else {
+            latest_delta.replace(Arc::clone(l));
+        }
+    }
+
+    if let Some(l) = latest_delta {
+        let lsn_floor = std::cmp::max(
+            Lsn(latest_img_lsn.unwrap_or(Lsn(0)).0 + 1),
+            l.get_lsn_range().start,
+        );
+        Some((l, lsn_floor))
+    } else {
+        latest_img.map(|l| (l, latest_img_lsn.unwrap()))
+    }
+}
+
+fn random_queries(rng: &mut StdRng, layers: &[Arc<dyn Layer>], n: usize) -> Vec<(Key, Lsn)> {
+    let max_lsn = layers.iter().map(|l| l.get_lsn_range().end).max().unwrap();
+    (0..n)
+        .map(|_| {
+            (
+                key(rng.gen_range(0..NUM_KEYS)),
+                Lsn(rng.gen_range(0..max_lsn.0)),
+            )
+        })
+        .collect()
+}
+
+fn bench_search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("layer_map_search");
+    for num_layers in [1_000, 10_000, 50_000] {
+        let mut rng = StdRng::seed_from_u64(42);
+        let layers = generate_layers(&mut rng, num_layers);
+        let queries = random_queries(&mut rng, &layers, 100);
+
+        let mut layer_map = LayerMap::default();
+        for l in layers.iter() {
+            layer_map.insert_historic(Arc::clone(l));
+        }
+
+        group.bench_with_input(
+            BenchmarkId::new("vector", num_layers),
+            &queries,
+            |b, queries| {
+                b.iter(|| {
+                    for (key, lsn) in queries {
+                        search_linear(&layers, *key, *lsn);
+                    }
+                })
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("indexed", num_layers),
+            &queries,
+            |b, queries| {
+                b.iter(|| {
+                    for (key, lsn) in queries {
+                        layer_map.search(*key, *lsn).unwrap();
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+// The index is rebuilt on the first search after a layer has been removed.
+// Measure that cost, by removing and adding back a layer before each search.
+fn bench_update(c: &mut Criterion) {
+    let mut group = c.benchmark_group("layer_map_update_and_search");
+    for num_layers in [1_000, 10_000, 50_000] {
+        let mut rng = StdRng::seed_from_u64(42);
+        let layers = generate_layers(&mut rng, num_layers);
+        let queries = random_queries(&mut rng, &layers, 1);
+        let (key, lsn) = queries[0];
+
+        let mut layer_map = LayerMap::default();
+        for l in layers.iter() {
+            layer_map.insert_historic(Arc::clone(l));
+        }
+
+        group.bench_function(BenchmarkId::from_parameter(num_layers), |b| {
+            b.iter(|| {
+                let l = Arc::clone(&layers[0]);
+                layer_map.remove_historic(Arc::clone(&l));
+                layer_map.insert_historic(l);
+                layer_map.search(key, lsn).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_search, bench_update);
+criterion_main!(benches);
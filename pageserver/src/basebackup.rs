@@ -10,18 +10,24 @@
 //! This module is responsible for creation of such tarball
 //! from data stored in object storage.
 //!
+//! It can also produce a "full" backup, which contains the relation data
+//! as well, and a WAL segment with a shutdown checkpoint record. That's a
+//! complete PostgreSQL data directory that a vanilla server can start on,
+//! without the page server.
+//!
 use anyhow::{anyhow, bail, ensure, Context, Result};
-use bytes::{BufMut, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use fail::fail_point;
 use std::fmt::Write as FmtWrite;
 use std::io;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::sync::Arc;
 use std::time::SystemTime;
 use tar::{Builder, EntryType, Header};
 use tracing::*;
 
-use crate::reltag::SlruKind;
+use crate::pgdatadir_mapping::BlockNumber;
+use crate::reltag::{RelTag, SlruKind};
 use crate::repository::Timeline;
 use crate::DatadirTimelineImpl;
 use postgres_ffi::xlog_utils::*;
@@ -39,6 +45,8 @@ where
     timeline: &'a Arc<DatadirTimelineImpl>,
     pub lsn: Lsn,
     prev_record_lsn: Lsn,
+    include_slru: bool,
+    full_backup: bool,
 
     finished: bool,
 }
@@ -58,6 +66,8 @@ where
         write: W,
         timeline: &'a Arc<DatadirTimelineImpl>,
         req_lsn: Option<Lsn>,
+        include_slru: bool,
+        full_backup: bool,
     ) -> Result<Basebackup<'a, W>> {
         // Compute postgres doesn't have any previous WAL files, but the first
         // record that it's going to write needs to include the LSN of the
@@ -73,9 +83,13 @@ where
         // an old LSN and it doesn't have any WAL of its own yet. We will set
         // prev_lsn to Lsn(0) if we cannot provide the correct value.
         let (backup_prev, backup_lsn) = if let Some(req_lsn) = req_lsn {
-            // Backup was requested at a particular LSN. Wait for it to arrive.
-            info!("waiting for {}", req_lsn);
-            timeline.tline.wait_lsn(req_lsn)?;
+            // Backup was requested at a particular LSN. The caller is
+            // responsible for waiting for it to arrive.
+            ensure!(
+                req_lsn <= timeline.tline.get_last_record_lsn(),
+                "basebackup LSN {} has not been received yet",
+                req_lsn
+            );
 
             // If the requested point is the end of the timeline, we can
             // provide prev_lsn. (get_last_record_rlsn() might return it as
@@ -94,8 +108,10 @@ where
         };
 
         info!(
-            "taking basebackup lsn={}, prev_lsn={}",
-            backup_lsn, backup_prev
+            "taking {} basebackup lsn={}, prev_lsn={}",
+            if full_backup { "full" } else { "compute" },
+            backup_lsn,
+            backup_prev
         );
 
         Ok(Basebackup {
@@ -103,6 +119,9 @@ where
             timeline,
             lsn: backup_lsn,
             prev_record_lsn: backup_prev,
+            // A full backup has no page server to fetch the SLRUs from
+            include_slru: include_slru || full_backup,
+            full_backup,
             finished: false,
         })
     }
@@ -127,19 +146,39 @@ where
         }
 
         // Gather non-relational files from object storage pages.
-        for kind in [
-            SlruKind::Clog,
-            SlruKind::MultiXactOffsets,
-            SlruKind::MultiXactMembers,
-        ] {
-            for segno in self.timeline.list_slru_segments(kind, self.lsn)? {
-                self.add_slru_segment(kind, segno)?;
+        //
+        // The SLRUs can be left out, if the compute is going to fetch the
+        // pages it needs from the page server on demand. The pg_xact and
+        // pg_multixact directories are still created above.
+        if self.include_slru {
+            for kind in [
+                SlruKind::Clog,
+                SlruKind::MultiXactOffsets,
+                SlruKind::MultiXactMembers,
+            ] {
+                for segno in self.timeline.list_slru_segments(kind, self.lsn)? {
+                    self.add_slru_segment(kind, segno)?;
+                }
             }
         }
 
         // Create tablespace directories
         for ((spcnode, dbnode), has_relmap_file) in self.timeline.list_dbdirs(self.lsn)? {
             self.add_dbdir(spcnode, dbnode, has_relmap_file)?;
+
+            // Computes fetch relation pages on demand, so only a full
+            // backup contains the relation files.
+            if self.full_backup {
+                let mut rels: Vec<RelTag> = self
+                    .timeline
+                    .list_rels(spcnode, dbnode, self.lsn)?
+                    .into_iter()
+                    .collect();
+                rels.sort();
+                for rel in rels {
+                    self.add_rel(rel)?;
+                }
+            }
         }
         for xid in self.timeline.list_twophase_files(self.lsn)? {
             self.add_twophase_file(xid)?;
@@ -190,6 +229,42 @@ where
         Ok(())
     }
 
+    //
+    // Generate relation files from repository, one file per RELSEG_SIZE
+    // segment, like PostgreSQL stores them.
+    //
+    fn add_rel(&mut self, tag: RelTag) -> anyhow::Result<()> {
+        let nblocks = self.timeline.get_rel_size(tag, self.lsn)?;
+
+        // An empty relation still has a file
+        if nblocks == 0 {
+            let header = new_tar_header(&tag.to_segfile_name(0), 0)?;
+            self.ar.append(&header, &mut io::empty())?;
+            return Ok(());
+        }
+
+        let nsegments = (nblocks + pg_constants::RELSEG_SIZE - 1) / pg_constants::RELSEG_SIZE;
+        for segno in 0..nsegments {
+            let start_blknum = segno * pg_constants::RELSEG_SIZE;
+            let end_blknum = std::cmp::min(start_blknum + pg_constants::RELSEG_SIZE, nblocks);
+            let size = (end_blknum - start_blknum) as u64 * pg_constants::BLCKSZ as u64;
+
+            let header = new_tar_header(&tag.to_segfile_name(segno), size)?;
+            let reader = RelSegmentReader {
+                timeline: self.timeline,
+                tag,
+                lsn: self.lsn,
+                next_blknum: start_blknum,
+                end_blknum,
+                page: Bytes::new(),
+            };
+            self.ar.append(&header, reader)?;
+        }
+
+        trace!("Added to basebackup rel {} nblocks {}", tag, nblocks);
+        Ok(())
+    }
+
     //
     // Include database/tablespace directories.
     //
@@ -312,6 +387,10 @@ where
         pg_control.checkPointCopy = checkpoint;
         pg_control.state = pg_constants::DB_SHUTDOWNED;
 
+        if self.full_backup {
+            return self.add_checkpoint_wal_segment(pg_control);
+        }
+
         // add zenith.signal file
         let mut zenith_signal = String::new();
         if self.prev_record_lsn == Lsn(0) {
@@ -344,6 +423,88 @@ where
         self.ar.append(&header, &wal_seg[..])?;
         Ok(())
     }
+
+    //
+    // A vanilla PostgreSQL server doesn't understand zenith.signal. It needs
+    // pg_control to point to a valid checkpoint record in WAL instead. Write
+    // a shutdown checkpoint record at the backup LSN, so that the server
+    // starts up without any recovery.
+    //
+    fn add_checkpoint_wal_segment(
+        &mut self,
+        mut pg_control: ControlFileData,
+    ) -> anyhow::Result<()> {
+        let rec_lsn = checkpoint_record_lsn(self.lsn, pg_constants::WAL_SEGMENT_SIZE);
+
+        let mut checkpoint = pg_control.checkPointCopy;
+        checkpoint.redo = rec_lsn.0;
+        pg_control.checkPoint = rec_lsn.0;
+        pg_control.checkPointCopy = checkpoint;
+
+        let pg_control_bytes = pg_control.encode();
+        let header = new_tar_header("global/pg_control", pg_control_bytes.len() as u64)?;
+        self.ar.append(&header, &pg_control_bytes[..])?;
+
+        let segno = rec_lsn.segment_number(pg_constants::WAL_SEGMENT_SIZE);
+        let wal_file_name = XLogFileName(PG_TLI, segno, pg_constants::WAL_SEGMENT_SIZE);
+        let wal_file_path = format!("pg_wal/{}", wal_file_name);
+        let header = new_tar_header(&wal_file_path, pg_constants::WAL_SEGMENT_SIZE as u64)?;
+        let wal_seg = generate_checkpoint_wal_segment(
+            rec_lsn,
+            self.prev_record_lsn,
+            pg_control.system_identifier,
+            &checkpoint,
+        )
+        .map_err(|e| anyhow!(e).context("Failed generating wal segment"))?;
+        ensure!(wal_seg.len() == pg_constants::WAL_SEGMENT_SIZE);
+        self.ar.append(&header, &wal_seg[..])?;
+        Ok(())
+    }
+}
+
+///
+/// Reads one segment of a relation from the repository, a page at a time,
+/// so that the whole segment doesn't need to be held in memory.
+///
+struct RelSegmentReader<'a> {
+    timeline: &'a Arc<DatadirTimelineImpl>,
+    tag: RelTag,
+    lsn: Lsn,
+    next_blknum: BlockNumber,
+    end_blknum: BlockNumber,
+    /// Remainder of the page that's currently being read
+    page: Bytes,
+}
+
+impl<'a> Read for RelSegmentReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.page.has_remaining() {
+            if self.next_blknum >= self.end_blknum {
+                return Ok(0);
+            }
+            let img = self
+                .timeline
+                .get_rel_page_at_lsn(self.tag, self.next_blknum, self.lsn)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            if img.len() != pg_constants::BLCKSZ as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "unexpected page size {} for rel {} blk {}",
+                        img.len(),
+                        self.tag,
+                        self.next_blknum
+                    ),
+                ));
+            }
+            self.page = img;
+            self.next_blknum += 1;
+        }
+
+        let n = std::cmp::min(buf.len(), self.page.remaining());
+        self.page.copy_to_slice(&mut buf[..n]);
+        Ok(n)
+    }
 }
 
 impl<'a, W> Drop for Basebackup<'a, W>
@@ -11,7 +11,7 @@ use daemonize::Daemonize;
 use fail::FailScenario;
 use pageserver::{
     config::{defaults::*, PageServerConf},
-    http, page_cache, page_service, profiling, tenant_mgr, thread_mgr,
+    disk_usage_eviction, http, page_cache, page_service, profiling, tenant_mgr, thread_mgr,
     thread_mgr::ThreadKind,
     timelines, virtual_file, LOG_FILE_NAME,
 };
@@ -189,6 +189,7 @@ fn main() -> anyhow::Result<()> {
 
     // Basic initialization of things that don't change after startup
     virtual_file::init(conf.max_file_descriptors);
+    virtual_file::set_io_rate_limits(conf.background_io_rate_limit, conf.foreground_io_rate_limit);
     page_cache::init(conf.page_cache_size);
 
     // Create repo and exit if init was requested
@@ -276,6 +277,23 @@ fn start_pageserver(conf: &'static PageServerConf, daemonize: bool) -> Result<()
 
     let remote_index = tenant_mgr::init_tenant_mgr(conf)?;
 
+    // Evicted layer files are downloaded back from the remote storage, so
+    // eviction is only possible with one.
+    if conf.disk_usage_eviction_threshold > 0 {
+        if conf.remote_storage_config.is_some() {
+            thread_mgr::spawn(
+                ThreadKind::DiskUsageEviction,
+                None,
+                None,
+                "disk usage eviction thread",
+                false,
+                move || disk_usage_eviction::eviction_loop(conf),
+            )?;
+        } else {
+            warn!("disk usage based eviction requires remote storage, not evicting layer files");
+        }
+    }
+
     // Spawn a new thread for the http endpoint
     // bind before launching separate thread so the error reported before startup exits
     let auth_cloned = auth.clone();
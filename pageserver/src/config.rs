@@ -39,6 +39,15 @@ pub mod defaults {
     pub const DEFAULT_PAGE_CACHE_SIZE: usize = 8192;
     pub const DEFAULT_MAX_FILE_DESCRIPTORS: usize = 100;
 
+    pub const DEFAULT_ON_DEMAND_DOWNLOAD: bool = false;
+
+    pub const DEFAULT_DISK_USAGE_EVICTION_THRESHOLD: u8 = 0;
+    pub const DEFAULT_DISK_USAGE_EVICTION_PERIOD: &str = "10 s";
+
+    // Disk I/O throttling is disabled by default.
+    pub const DEFAULT_BACKGROUND_IO_RATE_LIMIT: u64 = 0;
+    pub const DEFAULT_FOREGROUND_IO_RATE_LIMIT: u64 = 0;
+
     ///
     /// Default built-in configuration file.
     ///
@@ -57,16 +66,33 @@ pub mod defaults {
 # initial superuser role name to use when creating a new tenant
 #initial_superuser_name = '{DEFAULT_SUPERUSER}'
 
+#on_demand_download = {DEFAULT_ON_DEMAND_DOWNLOAD}
+
+# evict uploaded layer files when the disk usage goes above this percentage, 0 disables
+#disk_usage_eviction_threshold = {DEFAULT_DISK_USAGE_EVICTION_THRESHOLD}
+#disk_usage_eviction_period = '{DEFAULT_DISK_USAGE_EVICTION_PERIOD}'
+
+# disk I/O budgets in bytes per second, 0 is unlimited
+#background_io_rate_limit = {DEFAULT_BACKGROUND_IO_RATE_LIMIT}
+#foreground_io_rate_limit = {DEFAULT_FOREGROUND_IO_RATE_LIMIT}
+
 # [tenant_config]
 #checkpoint_distance = {DEFAULT_CHECKPOINT_DISTANCE} # in bytes
 #compaction_target_size = {DEFAULT_COMPACTION_TARGET_SIZE} # in bytes
 #compaction_period = '{DEFAULT_COMPACTION_PERIOD}'
 #compaction_threshold = '{DEFAULT_COMPACTION_THRESHOLD}'
+#compaction_strategy = '{DEFAULT_COMPACTION_STRATEGY}' # 'level0' or 'tiered'
+#compaction_tier_ratio = {DEFAULT_COMPACTION_TIER_RATIO}
 
 #gc_period = '{DEFAULT_GC_PERIOD}'
 #gc_horizon = {DEFAULT_GC_HORIZON}
 #image_creation_threshold = {DEFAULT_IMAGE_CREATION_THRESHOLD}
 #pitr_interval = '{DEFAULT_PITR_INTERVAL}'
+#getpage_rate_limit = {DEFAULT_GETPAGE_RATE_LIMIT} # requests per second, 0 is unlimited
+#getpage_burst = {DEFAULT_GETPAGE_BURST}
+#compression = '{DEFAULT_COMPRESSION}' # 'none', 'zstd' or 'lz4'
+#scrub_period = '{DEFAULT_SCRUB_PERIOD}'
+#scrub_rate_limit = {DEFAULT_SCRUB_RATE_LIMIT} # bytes per second, 0 is unlimited
 
 # [remote_storage]
 
@@ -109,6 +135,23 @@ pub struct PageServerConf {
 
     pub auth_validation_public_key_path: Option<PathBuf>,
     pub remote_storage_config: Option<RemoteStorageConfig>,
+    /// If `true`, timelines are attached without downloading their layer files
+    /// from the remote storage. The layers are downloaded when they're first
+    /// needed.
+    pub on_demand_download: bool,
+    /// When the usage of the disk that holds the repository goes above this
+    /// percentage, the least recently read layer files that have been
+    /// uploaded to the remote storage are evicted. 0 disables the eviction.
+    pub disk_usage_eviction_threshold: u8,
+    /// How often to check the disk usage
+    pub disk_usage_eviction_period: Duration,
+    /// Bytes per second that compaction, GC, layer flushes and scrubbing can
+    /// read and write, across all tenants. 0 means unlimited.
+    pub background_io_rate_limit: u64,
+    /// Bytes per second that the page service can read from layer files,
+    /// across all tenants, charged as one block per requested page. 0 means
+    /// unlimited.
+    pub foreground_io_rate_limit: u64,
 
     pub profiling: ProfilingConfig,
     pub default_tenant_conf: TenantConf,
@@ -179,6 +222,11 @@ struct PageServerConfigBuilder {
     //
     auth_validation_public_key_path: BuilderValue<Option<PathBuf>>,
     remote_storage_config: BuilderValue<Option<RemoteStorageConfig>>,
+    on_demand_download: BuilderValue<bool>,
+    disk_usage_eviction_threshold: BuilderValue<u8>,
+    disk_usage_eviction_period: BuilderValue<Duration>,
+    background_io_rate_limit: BuilderValue<u64>,
+    foreground_io_rate_limit: BuilderValue<u64>,
 
     id: BuilderValue<NodeId>,
 
@@ -208,6 +256,14 @@ impl Default for PageServerConfigBuilder {
             auth_type: Set(AuthType::Trust),
             auth_validation_public_key_path: Set(None),
             remote_storage_config: Set(None),
+            on_demand_download: Set(DEFAULT_ON_DEMAND_DOWNLOAD),
+            disk_usage_eviction_threshold: Set(DEFAULT_DISK_USAGE_EVICTION_THRESHOLD),
+            disk_usage_eviction_period: Set(humantime::parse_duration(
+                DEFAULT_DISK_USAGE_EVICTION_PERIOD,
+            )
+            .expect("cannot parse default disk usage eviction period")),
+            background_io_rate_limit: Set(DEFAULT_BACKGROUND_IO_RATE_LIMIT),
+            foreground_io_rate_limit: Set(DEFAULT_FOREGROUND_IO_RATE_LIMIT),
             id: NotSet,
             profiling: Set(ProfilingConfig::Disabled),
             broker_etcd_prefix: Set(etcd_broker::DEFAULT_NEON_BROKER_ETCD_PREFIX.to_string()),
@@ -268,6 +324,26 @@ impl PageServerConfigBuilder {
         self.remote_storage_config = BuilderValue::Set(remote_storage_config)
     }
 
+    pub fn on_demand_download(&mut self, on_demand_download: bool) {
+        self.on_demand_download = BuilderValue::Set(on_demand_download)
+    }
+
+    pub fn disk_usage_eviction_threshold(&mut self, disk_usage_eviction_threshold: u8) {
+        self.disk_usage_eviction_threshold = BuilderValue::Set(disk_usage_eviction_threshold)
+    }
+
+    pub fn disk_usage_eviction_period(&mut self, disk_usage_eviction_period: Duration) {
+        self.disk_usage_eviction_period = BuilderValue::Set(disk_usage_eviction_period)
+    }
+
+    pub fn background_io_rate_limit(&mut self, background_io_rate_limit: u64) {
+        self.background_io_rate_limit = BuilderValue::Set(background_io_rate_limit)
+    }
+
+    pub fn foreground_io_rate_limit(&mut self, foreground_io_rate_limit: u64) {
+        self.foreground_io_rate_limit = BuilderValue::Set(foreground_io_rate_limit)
+    }
+
     pub fn broker_endpoints(&mut self, broker_endpoints: Vec<Url>) {
         self.broker_endpoints = BuilderValue::Set(broker_endpoints)
     }
@@ -320,6 +396,21 @@ impl PageServerConfigBuilder {
             remote_storage_config: self
                 .remote_storage_config
                 .ok_or(anyhow!("missing remote_storage_config"))?,
+            on_demand_download: self
+                .on_demand_download
+                .ok_or(anyhow!("missing on_demand_download"))?,
+            disk_usage_eviction_threshold: self
+                .disk_usage_eviction_threshold
+                .ok_or(anyhow!("missing disk_usage_eviction_threshold"))?,
+            disk_usage_eviction_period: self
+                .disk_usage_eviction_period
+                .ok_or(anyhow!("missing disk_usage_eviction_period"))?,
+            background_io_rate_limit: self
+                .background_io_rate_limit
+                .ok_or(anyhow!("missing background_io_rate_limit"))?,
+            foreground_io_rate_limit: self
+                .foreground_io_rate_limit
+                .ok_or(anyhow!("missing foreground_io_rate_limit"))?,
             id: self.id.ok_or(anyhow!("missing id"))?,
             profiling: self.profiling.ok_or(anyhow!("missing profiling"))?,
             // TenantConf is handled separately
@@ -396,6 +487,26 @@ impl PageServerConf {
                 "remote_storage" => {
                     builder.remote_storage_config(Some(RemoteStorageConfig::from_toml(item)?))
                 }
+                "on_demand_download" => {
+                    builder.on_demand_download(parse_toml_bool(key, item)?)
+                }
+                "disk_usage_eviction_threshold" => {
+                    let threshold = parse_toml_u64(key, item)?;
+                    ensure!(
+                        threshold <= 100,
+                        "disk_usage_eviction_threshold is a percentage, got {threshold}"
+                    );
+                    builder.disk_usage_eviction_threshold(threshold as u8)
+                }
+                "disk_usage_eviction_period" => {
+                    builder.disk_usage_eviction_period(parse_toml_duration(key, item)?)
+                }
+                "background_io_rate_limit" => {
+                    builder.background_io_rate_limit(parse_toml_u64(key, item)?)
+                }
+                "foreground_io_rate_limit" => {
+                    builder.foreground_io_rate_limit(parse_toml_u64(key, item)?)
+                }
                 "tenant_config" => {
                     t_conf = Self::parse_toml_tenant_conf(item)?;
                 }
@@ -469,6 +580,22 @@ impl PageServerConf {
                 Some(parse_toml_u64("compaction_threshold", compaction_threshold)?.try_into()?);
         }
 
+        if let Some(compaction_strategy) = item.get("compaction_strategy") {
+            t_conf.compaction_strategy = Some(parse_toml_from_str(
+                "compaction_strategy",
+                compaction_strategy,
+            )?);
+        }
+
+        if let Some(compaction_tier_ratio) = item.get("compaction_tier_ratio") {
+            let ratio = parse_toml_u64("compaction_tier_ratio", compaction_tier_ratio)?;
+            ensure!(
+                ratio >= 2,
+                "compaction_tier_ratio must be at least 2, got {ratio}"
+            );
+            t_conf.compaction_tier_ratio = Some(ratio.try_into()?);
+        }
+
         if let Some(gc_horizon) = item.get("gc_horizon") {
             t_conf.gc_horizon = Some(parse_toml_u64("gc_horizon", gc_horizon)?);
         }
@@ -481,6 +608,27 @@ impl PageServerConf {
             t_conf.pitr_interval = Some(parse_toml_duration("pitr_interval", pitr_interval)?);
         }
 
+        if let Some(getpage_rate_limit) = item.get("getpage_rate_limit") {
+            t_conf.getpage_rate_limit =
+                Some(parse_toml_u64("getpage_rate_limit", getpage_rate_limit)?);
+        }
+
+        if let Some(getpage_burst) = item.get("getpage_burst") {
+            t_conf.getpage_burst = Some(parse_toml_u64("getpage_burst", getpage_burst)?);
+        }
+
+        if let Some(compression) = item.get("compression") {
+            t_conf.compression = Some(parse_toml_from_str("compression", compression)?);
+        }
+
+        if let Some(scrub_period) = item.get("scrub_period") {
+            t_conf.scrub_period = Some(parse_toml_duration("scrub_period", scrub_period)?);
+        }
+
+        if let Some(scrub_rate_limit) = item.get("scrub_rate_limit") {
+            t_conf.scrub_rate_limit = Some(parse_toml_u64("scrub_rate_limit", scrub_rate_limit)?);
+        }
+
         Ok(t_conf)
     }
 
@@ -505,6 +653,11 @@ impl PageServerConf {
             auth_type: AuthType::Trust,
             auth_validation_public_key_path: None,
             remote_storage_config: None,
+            on_demand_download: false,
+            disk_usage_eviction_threshold: 0,
+            disk_usage_eviction_period: Duration::from_secs(10),
+            background_io_rate_limit: 0,
+            foreground_io_rate_limit: 0,
             profiling: ProfilingConfig::Disabled,
             default_tenant_conf: TenantConf::dummy_conf(),
             broker_endpoints: Vec::new(),
@@ -534,6 +687,11 @@ fn parse_toml_u64(name: &str, item: &Item) -> Result<u64> {
     Ok(i as u64)
 }
 
+fn parse_toml_bool(name: &str, item: &Item) -> Result<bool> {
+    item.as_bool()
+        .with_context(|| format!("configure option {name} is not a boolean"))
+}
+
 fn parse_toml_duration(name: &str, item: &Item) -> Result<Duration> {
     let s = item
         .as_str()
@@ -602,6 +760,12 @@ max_file_descriptors = 333
 initial_superuser_name = 'zzzz'
 id = 10
 
+disk_usage_eviction_threshold = 90
+disk_usage_eviction_period = '222 s'
+
+background_io_rate_limit = 1048576
+foreground_io_rate_limit = 4194304
+
 "#;
 
     #[test]
@@ -635,6 +799,13 @@ id = 10
                 auth_type: AuthType::Trust,
                 auth_validation_public_key_path: None,
                 remote_storage_config: None,
+                on_demand_download: false,
+                disk_usage_eviction_threshold: defaults::DEFAULT_DISK_USAGE_EVICTION_THRESHOLD,
+                disk_usage_eviction_period: humantime::parse_duration(
+                    defaults::DEFAULT_DISK_USAGE_EVICTION_PERIOD
+                )?,
+                background_io_rate_limit: defaults::DEFAULT_BACKGROUND_IO_RATE_LIMIT,
+                foreground_io_rate_limit: defaults::DEFAULT_FOREGROUND_IO_RATE_LIMIT,
                 profiling: ProfilingConfig::Disabled,
                 default_tenant_conf: TenantConf::default(),
                 broker_endpoints: vec![broker_endpoint
@@ -679,6 +850,11 @@ id = 10
                 auth_type: AuthType::Trust,
                 auth_validation_public_key_path: None,
                 remote_storage_config: None,
+                on_demand_download: false,
+                disk_usage_eviction_threshold: 90,
+                disk_usage_eviction_period: Duration::from_secs(222),
+                background_io_rate_limit: 1048576,
+                foreground_io_rate_limit: 4194304,
                 profiling: ProfilingConfig::Disabled,
                 default_tenant_conf: TenantConf::default(),
                 broker_endpoints: vec![broker_endpoint
@@ -0,0 +1,227 @@
+//! Evict layer files from the local disk when it's filling up.
+//!
+//! The eviction thread periodically checks the usage of the file system that
+//! holds the repository. When it's above `disk_usage_eviction_threshold`
+//! percent, the thread removes the local files of the least recently read
+//! historic layers, until the usage is below the threshold again.
+//!
+//! Only layers whose files have been uploaded to the remote storage are
+//! evicted. They're replaced with RemoteLayers in the layer map, and
+//! downloaded again the next time they're needed. See
+//! `LayeredTimeline::evict_layer`.
+//!
+//! The layers evicted most recently are kept in memory, and reported by the
+//! `/v1/disk_usage_eviction` HTTP endpoint.
+use crate::config::PageServerConf;
+use crate::tenant_mgr::{self, TenantState};
+use crate::thread_mgr;
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use metrics::{register_int_counter, IntCounter};
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::*;
+use utils::zid::{ZTenantId, ZTimelineId};
+
+/// How many of the most recently evicted layers to report
+const MAX_RECENT_EVICTIONS: usize = 100;
+
+lazy_static! {
+    static ref EVICTED_LAYERS: IntCounter = register_int_counter!(
+        "pageserver_evicted_layers_total",
+        "Number of layer files evicted from the local disk"
+    )
+    .expect("failed to define a metric");
+    static ref EVICTED_LAYER_BYTES: IntCounter = register_int_counter!(
+        "pageserver_evicted_layer_bytes_total",
+        "Size of the layer files evicted from the local disk"
+    )
+    .expect("failed to define a metric");
+    static ref EVICTION_STATUS: Mutex<EvictionStatus> = Mutex::new(EvictionStatus::default());
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DiskUsage {
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+}
+
+/// A layer file that was evicted from the local disk
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+pub struct EvictedLayer {
+    #[serde_as(as = "DisplayFromStr")]
+    pub tenant_id: ZTenantId,
+    #[serde_as(as = "DisplayFromStr")]
+    pub timeline_id: ZTimelineId,
+    pub layer_file_name: String,
+    pub file_size: u64,
+    /// When the layer was last read, in seconds since the UNIX epoch
+    pub last_access: u64,
+    /// When the layer was evicted, in seconds since the UNIX epoch
+    pub evicted_at: u64,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct EvictionStatus {
+    /// Disk usage at the start of the last iteration
+    pub disk_usage: Option<DiskUsage>,
+    pub evicted_layers: u64,
+    pub evicted_bytes: u64,
+    /// The most recently evicted layers, oldest first
+    pub recent_evictions: VecDeque<EvictedLayer>,
+}
+
+/// Returns the usage of the disk and the layers evicted so far
+pub fn get_eviction_status() -> EvictionStatus {
+    EVICTION_STATUS.lock().unwrap().clone()
+}
+
+///
+/// Eviction thread's main loop
+///
+pub fn eviction_loop(conf: &'static PageServerConf) -> Result<()> {
+    info!(
+        "evicting layer files when disk usage is above {}%",
+        conf.disk_usage_eviction_threshold
+    );
+    loop {
+        // Sleep in short steps, so that we notice if the pageserver is shut down
+        let mut sleep_time = conf.disk_usage_eviction_period;
+        while !sleep_time.is_zero() && !thread_mgr::is_shutdown_requested() {
+            let step = std::cmp::min(sleep_time, Duration::from_secs(1));
+            std::thread::sleep(step);
+            sleep_time -= step;
+        }
+        if thread_mgr::is_shutdown_requested() {
+            break;
+        }
+
+        if let Err(e) = eviction_iteration(conf) {
+            error!("disk usage based eviction failed: {:?}", e);
+        }
+    }
+    trace!("eviction thread stopped");
+    Ok(())
+}
+
+///
+/// Check the disk usage, and evict layer files if it's above the threshold.
+/// Returns the number of bytes evicted.
+///
+pub fn eviction_iteration(conf: &'static PageServerConf) -> Result<u64> {
+    // The files of the layers that were in use when they were evicted are
+    // left behind, remove the ones that aren't anymore.
+    for tenant in tenant_mgr::list_tenants() {
+        if !matches!(tenant.state, TenantState::Active | TenantState::Idle) {
+            continue;
+        }
+        let repo = tenant_mgr::get_repository_for_tenant(tenant.id)?;
+        if let Err(e) = repo.remove_evicted_layer_files() {
+            error!("{:?}", e);
+        }
+    }
+
+    let usage = get_disk_usage(&conf.workdir)?;
+    EVICTION_STATUS.lock().unwrap().disk_usage = Some(usage);
+
+    let threshold_bytes = usage.total_bytes / 100 * conf.disk_usage_eviction_threshold as u64;
+    if usage.used_bytes <= threshold_bytes {
+        return Ok(0);
+    }
+    let bytes_to_free = usage.used_bytes - threshold_bytes;
+    info!(
+        "disk usage {} of {} bytes is above the threshold, evicting {} bytes",
+        usage.used_bytes, usage.total_bytes, bytes_to_free
+    );
+
+    let mut candidates = Vec::new();
+    for tenant in tenant_mgr::list_tenants() {
+        if !matches!(tenant.state, TenantState::Active | TenantState::Idle) {
+            continue;
+        }
+        let repo = tenant_mgr::get_repository_for_tenant(tenant.id)?;
+        candidates.extend(repo.eviction_candidates());
+    }
+
+    // Leave alone the layers that were read since the previous iteration,
+    // they're likely still in use. Evict the least recently read first.
+    let recently_read = SystemTime::now() - conf.disk_usage_eviction_period;
+    candidates.retain(|candidate| candidate.last_access < recently_read);
+    candidates.sort_by_key(|candidate| candidate.last_access);
+
+    let mut bytes_evicted = 0;
+    for candidate in candidates {
+        if bytes_evicted >= bytes_to_free || thread_mgr::is_shutdown_requested() {
+            break;
+        }
+        let tenant_id = candidate.layer.get_tenant_id();
+        let timeline_id = candidate.layer.get_timeline_id();
+        let layer_file_name = candidate.layer.filename().display().to_string();
+        match candidate.timeline.evict_layer(candidate.layer) {
+            Ok(true) => {}
+            // GC or compaction removed the layer in the meanwhile
+            Ok(false) => continue,
+            Err(e) => {
+                error!(
+                    "failed to evict layer {} of timeline {}: {:?}",
+                    layer_file_name, timeline_id, e
+                );
+                continue;
+            }
+        }
+        debug!(
+            "evicted layer {} of timeline {}",
+            layer_file_name, timeline_id
+        );
+
+        bytes_evicted += candidate.file_size;
+        EVICTED_LAYERS.inc();
+        EVICTED_LAYER_BYTES.inc_by(candidate.file_size);
+
+        let mut status = EVICTION_STATUS.lock().unwrap();
+        status.evicted_layers += 1;
+        status.evicted_bytes += candidate.file_size;
+        if status.recent_evictions.len() >= MAX_RECENT_EVICTIONS {
+            status.recent_evictions.pop_front();
+        }
+        status.recent_evictions.push_back(EvictedLayer {
+            tenant_id,
+            timeline_id,
+            layer_file_name,
+            file_size: candidate.file_size,
+            last_access: seconds_since_epoch(candidate.last_access),
+            evicted_at: seconds_since_epoch(SystemTime::now()),
+        });
+    }
+
+    info!("evicted {} bytes of layer files", bytes_evicted);
+    Ok(bytes_evicted)
+}
+
+fn get_disk_usage(path: &Path) -> Result<DiskUsage> {
+    let stat = nix::sys::statvfs::statvfs(path)
+        .with_context(|| format!("failed to get disk usage of {}", path.display()))?;
+
+    // The types of the fields differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    let (blocks, blocks_available, block_size) = (
+        stat.blocks() as u64,
+        stat.blocks_available() as u64,
+        stat.fragment_size() as u64,
+    );
+    Ok(DiskUsage {
+        total_bytes: blocks * block_size,
+        used_bytes: (blocks - blocks_available) * block_size,
+    })
+}
+
+fn seconds_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
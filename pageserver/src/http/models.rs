@@ -1,3 +1,6 @@
+use crate::pgdatadir_mapping::{DbLogicalSize, LsnForTimestamp};
+use crate::repository::GcLayerInfo;
+use crate::timelines::TimelineInfo;
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 use utils::{
@@ -17,6 +20,76 @@ pub struct TimelineCreateRequest {
     #[serde(default)]
     #[serde_as(as = "Option<DisplayFromStr>")]
     pub ancestor_start_lsn: Option<Lsn>,
+    /// Branch off the ancestor at this point in time, in RFC 3339 format. The LSN
+    /// is found from the commit timestamps, instead of passing `ancestor_start_lsn`.
+    #[serde(default)]
+    pub ancestor_start_timestamp: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TimelineCreateResponse {
+    #[serde(flatten)]
+    pub timeline_info: TimelineInfo,
+    /// How the `ancestor_start_timestamp` of the request was resolved to an LSN
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lsn_for_timestamp: Option<LsnForTimestamp>,
+}
+
+#[serde_as]
+#[derive(Serialize)]
+pub struct TimelineLogicalSizeResponse {
+    #[serde_as(as = "DisplayFromStr")]
+    pub lsn: Lsn,
+    /// Total size of all the databases, in bytes
+    pub size: u64,
+    pub databases: Vec<DbLogicalSize>,
+}
+
+#[derive(Serialize)]
+pub struct TimelineGcDryRunResponse {
+    pub layers_total: u64,
+    /// Number of layer files that GC would remove
+    pub layers_to_remove: u64,
+    pub layers: Vec<GcLayerInfo>,
+}
+
+/// Overrides of the tenant's GC settings for a single timeline. The request
+/// replaces all the overrides of the timeline, the settings that are left
+/// out fall back to the tenant config.
+#[derive(Serialize, Deserialize, Default)]
+pub struct TimelineConfigRequest {
+    pub gc_horizon: Option<u64>,
+    pub pitr_interval: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TimelineConfigResponse {
+    pub gc_horizon: Option<u64>,
+    pub pitr_interval: Option<String>,
+    /// The settings in effect, after falling back to the tenant config
+    pub effective_gc_horizon: u64,
+    pub effective_pitr_interval: String,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+pub struct RestorePointCreateRequest {
+    pub name: String,
+    /// The last record LSN of the timeline is used if omitted
+    #[serde(default)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub lsn: Option<Lsn>,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+pub struct TimelineResetRequest {
+    #[serde_as(as = "DisplayFromStr")]
+    pub lsn: Lsn,
+    /// Start of the last WAL record before 'lsn', as reported by the safekeepers
+    #[serde(default)]
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub prev_record_lsn: Option<Lsn>,
 }
 
 #[serde_as]
@@ -29,10 +102,17 @@ pub struct TenantCreateRequest {
     pub compaction_target_size: Option<u64>,
     pub compaction_period: Option<String>,
     pub compaction_threshold: Option<usize>,
+    pub compaction_strategy: Option<String>,
+    pub compaction_tier_ratio: Option<usize>,
     pub gc_horizon: Option<u64>,
     pub gc_period: Option<String>,
     pub image_creation_threshold: Option<usize>,
     pub pitr_interval: Option<String>,
+    pub getpage_rate_limit: Option<u64>,
+    pub getpage_burst: Option<u64>,
+    pub compression: Option<String>,
+    pub scrub_period: Option<String>,
+    pub scrub_rate_limit: Option<u64>,
 }
 
 #[serde_as]
@@ -64,10 +144,17 @@ pub struct TenantConfigRequest {
     pub compaction_target_size: Option<u64>,
     pub compaction_period: Option<String>,
     pub compaction_threshold: Option<usize>,
+    pub compaction_strategy: Option<String>,
+    pub compaction_tier_ratio: Option<usize>,
     pub gc_horizon: Option<u64>,
     pub gc_period: Option<String>,
     pub image_creation_threshold: Option<usize>,
     pub pitr_interval: Option<String>,
+    pub getpage_rate_limit: Option<u64>,
+    pub getpage_burst: Option<u64>,
+    pub compression: Option<String>,
+    pub scrub_period: Option<String>,
+    pub scrub_rate_limit: Option<u64>,
 }
 
 impl TenantConfigRequest {
@@ -78,10 +165,17 @@ impl TenantConfigRequest {
             compaction_target_size: None,
             compaction_period: None,
             compaction_threshold: None,
+            compaction_strategy: None,
+            compaction_tier_ratio: None,
             gc_horizon: None,
             gc_period: None,
             image_creation_threshold: None,
             pitr_interval: None,
+            getpage_rate_limit: None,
+            getpage_burst: None,
+            compression: None,
+            scrub_period: None,
+            scrub_rate_limit: None,
         }
     }
 }
@@ -1,21 +1,33 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::str::FromStr;
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
+use bytes::Bytes;
 use hyper::StatusCode;
-use hyper::{Body, Request, Response, Uri};
+use hyper::{header, Body, Request, Response, Uri};
+use postgres_ffi::xlog_utils::to_pg_timestamp;
 use remote_storage::GenericRemoteStorage;
 use tracing::*;
 
 use super::models::{
-    StatusResponse, TenantConfigRequest, TenantCreateRequest, TenantCreateResponse,
-    TimelineCreateRequest,
+    RestorePointCreateRequest, StatusResponse, TenantConfigRequest, TenantCreateRequest,
+    TenantCreateResponse, TimelineConfigRequest, TimelineConfigResponse, TimelineCreateRequest,
+    TimelineCreateResponse, TimelineGcDryRunResponse, TimelineLogicalSizeResponse,
+    TimelineResetRequest,
 };
-use crate::repository::Repository;
+use crate::basebackup::Basebackup;
+use crate::disk_usage_eviction;
+use crate::layered_repository::blob_io::CompressionAlgorithm;
+use crate::pgdatadir_mapping::LsnForTimestamp;
+use crate::repository::{Repository, Timeline, TimelineHasChildren};
 use crate::storage_sync;
 use crate::storage_sync::index::{RemoteIndex, RemoteTimeline};
-use crate::tenant_config::TenantConfOpt;
+use crate::tenant_config::{CompactionStrategy, TenantConfOpt, TimelineConfOpt};
+use crate::tenant_mgr::{TenantInfo, TenantState};
 use crate::timelines::{LocalTimelineInfo, RemoteTimelineInfo, TimelineInfo};
-use crate::{config::PageServerConf, tenant_mgr, timelines};
+use crate::{config::PageServerConf, tenant_mgr, tenant_size, timelines};
 use utils::{
     auth::JwtAuth,
     http::{
@@ -25,6 +37,7 @@ use utils::{
         request::parse_request_param,
         RequestExt, RouterBuilder,
     },
+    lsn::Lsn,
     zid::{ZTenantId, ZTenantTimelineId, ZTimelineId},
 };
 
@@ -90,15 +103,73 @@ async fn timeline_create_handler(mut request: Request<Body>) -> Result<Response<
 
     check_permission(&request, Some(tenant_id))?;
 
+    let ancestor_start_timestamp = request_data
+        .ancestor_start_timestamp
+        .as_deref()
+        .map(|timestamp| {
+            humantime::parse_rfc3339(timestamp).map_err(|e| {
+                ApiError::BadRequest(format!(
+                    "invalid ancestor start timestamp '{timestamp}': {e}"
+                ))
+            })
+        })
+        .transpose()?;
+    if ancestor_start_timestamp.is_some() {
+        if request_data.ancestor_start_lsn.is_some() {
+            return Err(ApiError::BadRequest(
+                "ancestor start lsn and ancestor start timestamp cannot be used together"
+                    .to_string(),
+            ));
+        }
+        if request_data.ancestor_timeline_id.is_none() {
+            return Err(ApiError::BadRequest(
+                "ancestor start timestamp requires an ancestor timeline".to_string(),
+            ));
+        }
+    }
+
     let new_timeline_info = tokio::task::spawn_blocking(move || {
-        let _enter = info_span!("/timeline_create", tenant = %tenant_id, new_timeline = ?request_data.new_timeline_id, lsn=?request_data.ancestor_start_lsn).entered();
-        timelines::create_timeline(
+        let _enter = info_span!("/timeline_create", tenant = %tenant_id, new_timeline = ?request_data.new_timeline_id, lsn=?request_data.ancestor_start_lsn, timestamp=?request_data.ancestor_start_timestamp).entered();
+
+        let mut ancestor_start_lsn = request_data.ancestor_start_lsn;
+        let mut lsn_for_timestamp = None;
+        if let (Some(timestamp), Some(ancestor_timeline_id)) =
+            (ancestor_start_timestamp, request_data.ancestor_timeline_id)
+        {
+            let ancestor_timeline =
+                tenant_mgr::get_local_timeline_with_load(tenant_id, ancestor_timeline_id)
+                    .context("Cannot branch off the timeline that's not present locally")?;
+            let result = ancestor_timeline.find_lsn_for_timestamp(to_pg_timestamp(timestamp))?;
+            info!("ancestor start timestamp resolved to {result:?}");
+            match result {
+                LsnForTimestamp::Present(lsn) | LsnForTimestamp::Future(lsn) => {
+                    ancestor_start_lsn = Some(lsn)
+                }
+                LsnForTimestamp::Past(_) => {
+                    return Err(ApiError::BadRequest(format!(
+                        "Cannot branch at {result:?}, all the commits that are still available on the ancestor timeline are newer than the timestamp"
+                    )))
+                }
+                LsnForTimestamp::NoData(_) => {
+                    return Err(ApiError::BadRequest(format!(
+                        "Cannot branch at {result:?}, no commit timestamps found on the ancestor timeline"
+                    )))
+                }
+            }
+            lsn_for_timestamp = Some(result);
+        }
+
+        let timeline_info = timelines::create_timeline(
             get_config(&request),
             tenant_id,
             request_data.new_timeline_id.map(ZTimelineId::from),
             request_data.ancestor_timeline_id.map(ZTimelineId::from),
-            request_data.ancestor_start_lsn,
-        )
+            ancestor_start_lsn,
+        )?;
+        Ok(timeline_info.map(|timeline_info| TimelineCreateResponse {
+            timeline_info,
+            lsn_for_timestamp,
+        }))
     })
     .await
     .map_err(ApiError::from_err)??;
@@ -248,6 +319,400 @@ async fn wal_receiver_get_handler(request: Request<Body>) -> Result<Response<Bod
     json_response(StatusCode::OK, wal_receiver)
 }
 
+fn get_query_param<T: FromStr>(request: &Request<Body>, name: &str) -> Result<Option<T>, ApiError> {
+    request
+        .uri()
+        .query()
+        .and_then(|v| {
+            url::form_urlencoded::parse(v.as_bytes())
+                .into_owned()
+                .find(|(param, _)| param == name)
+        })
+        .map(|(_, value)| {
+            value
+                .parse::<T>()
+                .map_err(|_| ApiError::BadRequest(format!("invalid {} '{}'", name, value)))
+        })
+        .transpose()
+}
+
+// Logical size of the timeline at the given LSN, or at the end of the timeline
+// if no LSN is given, broken down by database and relation fork.
+async fn timeline_logical_size_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_id: ZTimelineId = parse_request_param(&request, "timeline_id")?;
+    let lsn = get_query_param(&request, "lsn")?;
+
+    let timeline = tokio::task::spawn_blocking(move || {
+        tenant_mgr::get_local_timeline_with_load(tenant_id, timeline_id)
+    })
+    .await
+    .map_err(ApiError::from_err)?
+    .map_err(|e| ApiError::NotFound(format!("{:#}", e)))?;
+
+    let lsn = match lsn {
+        Some(lsn) => {
+            {
+                let latest_gc_cutoff_lsn = timeline.tline.get_latest_gc_cutoff_lsn();
+                timeline
+                    .check_lsn_is_in_scope(lsn, &latest_gc_cutoff_lsn)
+                    .map_err(|e| ApiError::BadRequest(format!("{:#}", e)))?;
+            }
+            timeline
+                .tline
+                .wait_lsn(lsn)
+                .await
+                .map_err(ApiError::from_err)?;
+            lsn
+        }
+        None => timeline.tline.get_last_record_lsn(),
+    };
+
+    let databases = tokio::task::spawn_blocking(move || {
+        let _enter =
+            info_span!("timeline_logical_size", tenant = %tenant_id, timeline = %timeline_id, lsn = %lsn)
+                .entered();
+        timeline.get_logical_size_breakdown(lsn)
+    })
+    .await
+    .map_err(ApiError::from_err)??;
+
+    json_response(
+        StatusCode::OK,
+        TimelineLogicalSizeResponse {
+            lsn,
+            size: databases.iter().map(|db| db.size).sum(),
+            databases,
+        },
+    )
+}
+
+// Report which layer files GC would remove from the timeline, and why it
+// keeps the others, without removing anything. The tenant's gc_horizon is used
+// unless one is given.
+async fn timeline_gc_dry_run_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_id: ZTimelineId = parse_request_param(&request, "timeline_id")?;
+    let gc_horizon: Option<u64> = get_query_param(&request, "gc_horizon")?;
+
+    tokio::task::spawn_blocking(move || {
+        tenant_mgr::get_local_timeline_with_load(tenant_id, timeline_id)
+    })
+    .await
+    .map_err(ApiError::from_err)?
+    .map_err(|e| ApiError::NotFound(format!("{:#}", e)))?;
+
+    let result = tokio::task::spawn_blocking(move || {
+        let _enter =
+            info_span!("timeline_gc_dry_run", tenant = %tenant_id, timeline = %timeline_id)
+                .entered();
+        let repo = tenant_mgr::get_repository_for_tenant(tenant_id)?;
+        let pitr = repo.get_pitr_interval();
+        repo.gc_iteration(Some(timeline_id), gc_horizon, pitr, false, true)
+    })
+    .await
+    .map_err(ApiError::from_err)??;
+
+    json_response(
+        StatusCode::OK,
+        TimelineGcDryRunResponse {
+            layers_total: result.layers_total,
+            layers_to_remove: result
+                .layers
+                .iter()
+                .filter(|l| l.keep_reason.is_none())
+                .count() as u64,
+            layers: result.layers,
+        },
+    )
+}
+
+async fn timeline_config_get_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_id: ZTimelineId = parse_request_param(&request, "timeline_id")?;
+
+    let timeline = tokio::task::spawn_blocking(move || {
+        tenant_mgr::get_local_timeline_with_load(tenant_id, timeline_id)
+    })
+    .await
+    .map_err(ApiError::from_err)?
+    .map_err(|e| ApiError::NotFound(format!("{:#}", e)))?;
+
+    let timeline_conf = timeline.tline.get_timeline_conf();
+    json_response(
+        StatusCode::OK,
+        TimelineConfigResponse {
+            gc_horizon: timeline_conf.gc_horizon,
+            pitr_interval: timeline_conf
+                .pitr_interval
+                .map(|d| humantime::format_duration(d).to_string()),
+            effective_gc_horizon: timeline.tline.get_gc_horizon(),
+            effective_pitr_interval: humantime::format_duration(timeline.tline.get_pitr_interval())
+                .to_string(),
+        },
+    )
+}
+
+async fn timeline_config_handler(mut request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_id: ZTimelineId = parse_request_param(&request, "timeline_id")?;
+    let request_data: TimelineConfigRequest = json_request(&mut request).await?;
+
+    let mut timeline_conf = TimelineConfOpt {
+        gc_horizon: request_data.gc_horizon,
+        ..Default::default()
+    };
+    if let Some(pitr_interval) = request_data.pitr_interval {
+        timeline_conf.pitr_interval = Some(
+            humantime::parse_duration(&pitr_interval)
+                .map_err(|e| ApiError::BadRequest(format!("invalid pitr_interval: {e}")))?,
+        );
+    }
+
+    let timeline = tokio::task::spawn_blocking(move || {
+        tenant_mgr::get_local_timeline_with_load(tenant_id, timeline_id)
+    })
+    .await
+    .map_err(ApiError::from_err)?
+    .map_err(|e| ApiError::NotFound(format!("{:#}", e)))?;
+
+    tokio::task::spawn_blocking(move || {
+        let _enter =
+            info_span!("timeline_config", tenant = %tenant_id, timeline = %timeline_id).entered();
+        timeline.tline.set_timeline_conf(timeline_conf)
+    })
+    .await
+    .map_err(ApiError::from_err)??;
+
+    json_response(StatusCode::OK, ())
+}
+
+async fn timeline_restore_point_list_handler(
+    request: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_id: ZTimelineId = parse_request_param(&request, "timeline_id")?;
+
+    let timeline = tokio::task::spawn_blocking(move || {
+        tenant_mgr::get_local_timeline_with_load(tenant_id, timeline_id)
+    })
+    .await
+    .map_err(ApiError::from_err)?
+    .map_err(|e| ApiError::NotFound(format!("{:#}", e)))?;
+
+    json_response(StatusCode::OK, timeline.tline.get_restore_points())
+}
+
+async fn timeline_restore_point_create_handler(
+    mut request: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_id: ZTimelineId = parse_request_param(&request, "timeline_id")?;
+    let request_data: RestorePointCreateRequest = json_request(&mut request).await?;
+
+    let restore_point = tokio::task::spawn_blocking(move || {
+        let _enter = info_span!("timeline_restore_point_create", tenant = %tenant_id, timeline = %timeline_id, name = %request_data.name, lsn = ?request_data.lsn).entered();
+
+        let timeline = tenant_mgr::get_local_timeline_with_load(tenant_id, timeline_id)
+            .map_err(|e| ApiError::NotFound(format!("{:#}", e)))?;
+        if timeline
+            .tline
+            .get_restore_points()
+            .iter()
+            .any(|restore_point| restore_point.name == request_data.name)
+        {
+            return Err(ApiError::Conflict(format!(
+                "restore point '{}' already exists",
+                request_data.name
+            )));
+        }
+
+        let repo = tenant_mgr::get_repository_for_tenant(tenant_id)?;
+        repo.create_restore_point(timeline_id, &request_data.name, request_data.lsn)
+            .map_err(|e| ApiError::BadRequest(format!("{:#}", e)))
+    })
+    .await
+    .map_err(ApiError::from_err)??;
+
+    json_response(StatusCode::CREATED, restore_point)
+}
+
+async fn timeline_restore_point_delete_handler(
+    request: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_id: ZTimelineId = parse_request_param(&request, "timeline_id")?;
+    let name: String = parse_request_param(&request, "name")?;
+
+    tokio::task::spawn_blocking(move || {
+        tenant_mgr::get_local_timeline_with_load(tenant_id, timeline_id)
+    })
+    .await
+    .map_err(ApiError::from_err)?
+    .map_err(|e| ApiError::NotFound(format!("{:#}", e)))?;
+
+    let deleted = tokio::task::spawn_blocking({
+        let name = name.clone();
+        move || {
+            let _enter = info_span!("timeline_restore_point_delete", tenant = %tenant_id, timeline = %timeline_id, name = %name).entered();
+            let repo = tenant_mgr::get_repository_for_tenant(tenant_id)?;
+            repo.delete_restore_point(timeline_id, &name)
+        }
+    })
+    .await
+    .map_err(ApiError::from_err)??;
+
+    match deleted {
+        Some(restore_point) => json_response(StatusCode::OK, restore_point),
+        None => Err(ApiError::NotFound(format!(
+            "Restore point '{name}' not found"
+        ))),
+    }
+}
+
+async fn timeline_reset_handler(mut request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_id: ZTimelineId = parse_request_param(&request, "timeline_id")?;
+    let request_data: TimelineResetRequest = json_request(&mut request).await?;
+
+    tokio::task::spawn_blocking(move || {
+        let _enter = info_span!("timeline_reset", tenant = %tenant_id, timeline = %timeline_id, lsn = %request_data.lsn).entered();
+        tenant_mgr::reset_timeline(
+            tenant_id,
+            timeline_id,
+            request_data.lsn,
+            request_data.prev_record_lsn,
+        )
+    })
+    .await
+    .map_err(ApiError::from_err)?
+    .map_err(|e| ApiError::BadRequest(format!("{:#}", e)))?;
+
+    json_response(StatusCode::OK, ())
+}
+
+async fn timeline_detach_ancestor_handler(
+    request: Request<Body>,
+) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_id: ZTimelineId = parse_request_param(&request, "timeline_id")?;
+
+    tokio::task::spawn_blocking(move || {
+        let _enter =
+            info_span!("timeline_detach_ancestor", tenant = %tenant_id, timeline = %timeline_id)
+                .entered();
+        tenant_mgr::detach_timeline_ancestor(tenant_id, timeline_id)
+    })
+    .await
+    .map_err(ApiError::from_err)?
+    .map_err(|e| ApiError::BadRequest(format!("{:#}", e)))?;
+
+    json_response(StatusCode::OK, ())
+}
+
+/// Size of the chunks the full backup tarball is sent in
+const FULLBACKUP_CHUNK_SIZE: usize = 64 * 1024;
+
+// HTTP equivalent of the `fullbackup` page service command. Streams a tarball
+// of the complete data directory at the given LSN, or at the end of the
+// timeline if no LSN is given.
+async fn timeline_fullbackup_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_id: ZTimelineId = parse_request_param(&request, "timeline_id")?;
+    let lsn = get_query_param(&request, "lsn")?;
+
+    let timeline = tokio::task::spawn_blocking(move || {
+        tenant_mgr::get_local_timeline_with_load(tenant_id, timeline_id)
+    })
+    .await
+    .map_err(ApiError::from_err)?
+    .map_err(|e| ApiError::NotFound(format!("{:#}", e)))?;
+
+    // Check the LSN and wait for it to arrive before starting the response,
+    // so that these errors can still be reported with a proper status code.
+    if let Some(lsn) = lsn {
+        {
+            let latest_gc_cutoff_lsn = timeline.tline.get_latest_gc_cutoff_lsn();
+            timeline
+                .check_lsn_is_in_scope(lsn, &latest_gc_cutoff_lsn)
+                .map_err(|e| ApiError::BadRequest(format!("{:#}", e)))?;
+        }
+        timeline
+            .tline
+            .wait_lsn(lsn)
+            .await
+            .map_err(ApiError::from_err)?;
+    }
+
+    let (sender, body) = Body::channel();
+    let rt = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let _enter =
+            info_span!("fullbackup", tenant = %tenant_id, timeline = %timeline_id).entered();
+
+        let mut writer =
+            io::BufWriter::with_capacity(FULLBACKUP_CHUNK_SIZE, BodySink { sender, rt });
+        let result = Basebackup::new(&mut writer, &timeline, lsn, true, true)
+            .and_then(|basebackup| basebackup.send_tarball())
+            .and_then(|()| writer.flush().context("failed to send full backup"));
+        match result {
+            Ok(()) => info!("done"),
+            Err(e) => {
+                // Abort the response, so that the client doesn't mistake a
+                // truncated tarball for a complete one.
+                error!("full backup failed: {:?}", e);
+                let (sink, _) = writer.into_parts();
+                sink.sender.abort();
+            }
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/x-tar")
+        .body(body)
+        .map_err(ApiError::from_err)
+}
+
+/// Writes the data it receives to a streaming HTTP response body.
+struct BodySink {
+    sender: hyper::body::Sender,
+    rt: tokio::runtime::Handle,
+}
+
+impl Write for BodySink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.rt
+            .block_on(self.sender.send_data(Bytes::copy_from_slice(data)))
+            .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e))?;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 async fn timeline_attach_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
     let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
     check_permission(&request, Some(tenant_id))?;
@@ -284,7 +749,7 @@ async fn timeline_attach_handler(request: Request<Body>) -> Result<Response<Body
         }
 
         remote_timeline.awaits_download = true;
-        storage_sync::schedule_layer_download(tenant_id, timeline_id);
+        schedule_timeline_download(state.conf, sync_id, remote_timeline);
         return json_response(StatusCode::ACCEPTED, ());
     } else {
         // no timeline in the index, release the lock to make the potentially lengthy download opetation
@@ -317,13 +782,35 @@ async fn timeline_attach_handler(request: Request<Body>) -> Result<Response<Body
                 ));
             }
             remote_timeline.awaits_download = true;
+            schedule_timeline_download(state.conf, sync_id, remote_timeline);
+        }
+        None => {
+            schedule_timeline_download(state.conf, sync_id, &new_timeline);
+            index_accessor.add_timeline_entry(sync_id, new_timeline);
         }
-        None => index_accessor.add_timeline_entry(sync_id, new_timeline),
     }
-    storage_sync::schedule_layer_download(tenant_id, timeline_id);
     json_response(StatusCode::ACCEPTED, ())
 }
 
+/// Schedules the download of a timeline that is being attached. With on-demand
+/// downloads enabled, only the metadata is downloaded, and the layers are
+/// downloaded when they're first accessed.
+fn schedule_timeline_download(
+    conf: &PageServerConf,
+    sync_id: ZTenantTimelineId,
+    remote_timeline: &RemoteTimeline,
+) {
+    if conf.on_demand_download {
+        storage_sync::schedule_layer_redownload(
+            sync_id.tenant_id,
+            sync_id.timeline_id,
+            remote_timeline.stored_files().clone(),
+        );
+    } else {
+        storage_sync::schedule_layer_download(sync_id.tenant_id, sync_id.timeline_id);
+    }
+}
+
 async fn try_download_index_part_data(
     state: &State,
     sync_id: ZTenantTimelineId,
@@ -368,6 +855,79 @@ async fn timeline_detach_handler(request: Request<Body>) -> Result<Response<Body
     json_response(StatusCode::OK, ())
 }
 
+async fn timeline_delete_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    let timeline_id: ZTimelineId = parse_request_param(&request, "timeline_id")?;
+    let state = get_state(&request);
+    let sync_id = ZTenantTimelineId {
+        tenant_id,
+        timeline_id,
+    };
+
+    // Branches that are not attached to this pageserver depend on the timeline too
+    let (children, remote_exists) = {
+        let index_accessor = state.remote_index.read().await;
+        let children = index_accessor
+            .all_sync_ids()
+            .filter(|id| id.tenant_id == tenant_id)
+            .filter(|id| {
+                index_accessor
+                    .timeline_entry(id)
+                    .and_then(|remote_timeline| remote_timeline.metadata.ancestor_timeline())
+                    == Some(timeline_id)
+            })
+            .map(|id| id.timeline_id)
+            .collect::<HashSet<_>>();
+        (children, index_accessor.timeline_entry(&sync_id).is_some())
+    };
+    if !children.is_empty() {
+        return Err(ApiError::Conflict(format!(
+            "Timeline {timeline_id} has child branches {children:?}, delete them first"
+        )));
+    }
+
+    let conf = state.conf;
+    let deleted_locally = tokio::task::spawn_blocking(move || {
+        let _enter =
+            info_span!("timeline_delete_handler", tenant = %tenant_id, timeline = %timeline_id)
+                .entered();
+        let repo = match tenant_mgr::get_repository_for_tenant(tenant_id) {
+            Ok(repo) => repo,
+            Err(_) => return Ok(false),
+        };
+        if repo.get_timeline(timeline_id).is_none() {
+            return Ok(false);
+        }
+        // The local branches are checked under the same lock as the removal, so
+        // that a branch created in the meanwhile doesn't lose its ancestor.
+        match tenant_mgr::detach_timeline(conf, tenant_id, timeline_id) {
+            Ok(()) => Ok(true),
+            Err(e) => match e.downcast_ref::<TimelineHasChildren>() {
+                Some(TimelineHasChildren { children, .. }) => Err(ApiError::Conflict(format!(
+                    "Timeline {timeline_id} has child branches {children:?}, delete them first"
+                ))),
+                None => Err(e.into()),
+            },
+        }
+    })
+    .await
+    .map_err(ApiError::from_err)??;
+
+    if !remote_exists && !deleted_locally {
+        return Err(ApiError::NotFound(format!(
+            "Timeline {timeline_id} not found for tenant {tenant_id}"
+        )));
+    }
+
+    if remote_exists {
+        storage_sync::schedule_timeline_delete(tenant_id, timeline_id);
+    }
+
+    json_response(StatusCode::OK, ())
+}
+
 async fn tenant_list_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
     // check for management permission
     check_permission(&request, None)?;
@@ -382,6 +942,13 @@ async fn tenant_list_handler(request: Request<Body>) -> Result<Response<Body>, A
     json_response(StatusCode::OK, response_data)
 }
 
+async fn disk_usage_eviction_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    // check for management permission
+    check_permission(&request, None)?;
+
+    json_response(StatusCode::OK, disk_usage_eviction::get_eviction_status())
+}
+
 async fn tenant_create_handler(mut request: Request<Body>) -> Result<Response<Body>, ApiError> {
     // check for management permission
     check_permission(&request, None)?;
@@ -405,12 +972,45 @@ async fn tenant_create_handler(mut request: Request<Body>) -> Result<Response<Bo
     tenant_conf.checkpoint_distance = request_data.checkpoint_distance;
     tenant_conf.compaction_target_size = request_data.compaction_target_size;
     tenant_conf.compaction_threshold = request_data.compaction_threshold;
+    tenant_conf.getpage_rate_limit = request_data.getpage_rate_limit;
+    tenant_conf.getpage_burst = request_data.getpage_burst;
 
     if let Some(compaction_period) = request_data.compaction_period {
         tenant_conf.compaction_period =
             Some(humantime::parse_duration(&compaction_period).map_err(ApiError::from_err)?);
     }
 
+    if let Some(compression) = request_data.compression {
+        tenant_conf.compression = Some(
+            compression
+                .parse::<CompressionAlgorithm>()
+                .map_err(|e| ApiError::BadRequest(format!("{:#}", e)))?,
+        );
+    }
+
+    if let Some(compaction_strategy) = request_data.compaction_strategy {
+        tenant_conf.compaction_strategy = Some(
+            compaction_strategy
+                .parse::<CompactionStrategy>()
+                .map_err(|e| ApiError::BadRequest(format!("{:#}", e)))?,
+        );
+    }
+    if let Some(ratio) = request_data.compaction_tier_ratio {
+        if ratio < 2 {
+            return Err(ApiError::BadRequest(format!(
+                "compaction_tier_ratio must be at least 2, got {}",
+                ratio
+            )));
+        }
+        tenant_conf.compaction_tier_ratio = Some(ratio);
+    }
+
+    if let Some(scrub_period) = request_data.scrub_period {
+        tenant_conf.scrub_period =
+            Some(humantime::parse_duration(&scrub_period).map_err(ApiError::from_err)?);
+    }
+    tenant_conf.scrub_rate_limit = request_data.scrub_rate_limit;
+
     let target_tenant_id = request_data
         .new_tenant_id
         .map(ZTenantId::from)
@@ -453,12 +1053,45 @@ async fn tenant_config_handler(mut request: Request<Body>) -> Result<Response<Bo
     tenant_conf.checkpoint_distance = request_data.checkpoint_distance;
     tenant_conf.compaction_target_size = request_data.compaction_target_size;
     tenant_conf.compaction_threshold = request_data.compaction_threshold;
+    tenant_conf.getpage_rate_limit = request_data.getpage_rate_limit;
+    tenant_conf.getpage_burst = request_data.getpage_burst;
 
     if let Some(compaction_period) = request_data.compaction_period {
         tenant_conf.compaction_period =
             Some(humantime::parse_duration(&compaction_period).map_err(ApiError::from_err)?);
     }
 
+    if let Some(compression) = request_data.compression {
+        tenant_conf.compression = Some(
+            compression
+                .parse::<CompressionAlgorithm>()
+                .map_err(|e| ApiError::BadRequest(format!("{:#}", e)))?,
+        );
+    }
+
+    if let Some(compaction_strategy) = request_data.compaction_strategy {
+        tenant_conf.compaction_strategy = Some(
+            compaction_strategy
+                .parse::<CompactionStrategy>()
+                .map_err(|e| ApiError::BadRequest(format!("{:#}", e)))?,
+        );
+    }
+    if let Some(ratio) = request_data.compaction_tier_ratio {
+        if ratio < 2 {
+            return Err(ApiError::BadRequest(format!(
+                "compaction_tier_ratio must be at least 2, got {}",
+                ratio
+            )));
+        }
+        tenant_conf.compaction_tier_ratio = Some(ratio);
+    }
+
+    if let Some(scrub_period) = request_data.scrub_period {
+        tenant_conf.scrub_period =
+            Some(humantime::parse_duration(&scrub_period).map_err(ApiError::from_err)?);
+    }
+    tenant_conf.scrub_rate_limit = request_data.scrub_rate_limit;
+
     tokio::task::spawn_blocking(move || {
         let _enter = info_span!("tenant_config", tenant = ?tenant_id).entered();
 
@@ -470,6 +1103,215 @@ async fn tenant_config_handler(mut request: Request<Body>) -> Result<Response<Bo
     json_response(StatusCode::OK, ())
 }
 
+async fn tenant_status_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    match tenant_mgr::get_tenant_state(tenant_id) {
+        Some(state) => json_response(
+            StatusCode::OK,
+            TenantInfo {
+                id: tenant_id,
+                state,
+            },
+        ),
+        None => Err(ApiError::NotFound(format!("Tenant {tenant_id} not found"))),
+    }
+}
+
+// Synthetic size of the tenant, with the segments of history it consists of.
+// See tenant_size.rs.
+async fn tenant_size_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_permission(&request, Some(tenant_id))?;
+
+    if tenant_mgr::get_tenant_state(tenant_id).is_none() {
+        return Err(ApiError::NotFound(format!("Tenant {tenant_id} not found")));
+    }
+
+    let tenant_size = tokio::task::spawn_blocking(move || {
+        let _enter = info_span!("tenant_size", tenant = %tenant_id).entered();
+        tenant_size::calculate_tenant_size(tenant_id)
+    })
+    .await
+    .map_err(ApiError::from_err)??;
+
+    json_response(StatusCode::OK, tenant_size)
+}
+
+async fn tenant_attach_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    // check for management permission
+    check_permission(&request, None)?;
+
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    info!("Handling tenant {tenant_id} attach");
+
+    let state = get_state(&request);
+    if state.remote_storage.is_none() {
+        return Err(ApiError::BadRequest(
+            "Cannot attach a tenant without remote storage".to_string(),
+        ));
+    }
+    if tenant_mgr::get_tenant_state(tenant_id).is_some() {
+        return Err(ApiError::Conflict(format!(
+            "Tenant {tenant_id} already exists"
+        )));
+    }
+
+    let timeline_ids = index_remote_tenant_timelines(state, tenant_id).await?;
+    if timeline_ids.is_empty() {
+        return Err(ApiError::NotFound(format!(
+            "No timelines of tenant {tenant_id} found in the remote storage"
+        )));
+    }
+
+    let conf = state.conf;
+    let remote_index = state.remote_index.clone();
+    let timeline_ids = tokio::task::spawn_blocking(move || {
+        let _enter = info_span!("tenant_attach", tenant = %tenant_id).entered();
+        tenant_mgr::attach_tenant(conf, tenant_id, &remote_index)?;
+        for timeline_id in &timeline_ids {
+            let timeline_path = conf.timeline_path(timeline_id, &tenant_id);
+            std::fs::create_dir_all(&timeline_path).with_context(|| {
+                format!(
+                    "Failed to create timeline directory '{}'",
+                    timeline_path.display()
+                )
+            })?;
+        }
+        Ok::<_, anyhow::Error>(timeline_ids)
+    })
+    .await
+    .map_err(ApiError::from_err)??;
+
+    // The tenant leaves the Attaching state when all these downloads are complete
+    let mut index_accessor = state.remote_index.write().await;
+    for timeline_id in timeline_ids {
+        let sync_id = ZTenantTimelineId {
+            tenant_id,
+            timeline_id,
+        };
+        if let Some(remote_timeline) = index_accessor.timeline_entry_mut(&sync_id) {
+            if !remote_timeline.awaits_download {
+                remote_timeline.awaits_download = true;
+                schedule_timeline_download(conf, sync_id, remote_timeline);
+            }
+        }
+    }
+
+    json_response(StatusCode::ACCEPTED, ())
+}
+
+async fn tenant_detach_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    // check for management permission
+    check_permission(&request, None)?;
+
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    check_tenant_can_be_stopped(tenant_id)?;
+
+    let conf = get_config(&request);
+    tokio::task::spawn_blocking(move || {
+        let _enter = info_span!("tenant_detach", tenant = %tenant_id).entered();
+        tenant_mgr::detach_tenant(conf, tenant_id)
+    })
+    .await
+    .map_err(ApiError::from_err)??;
+
+    json_response(StatusCode::OK, ())
+}
+
+async fn tenant_delete_handler(request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    // check for management permission
+    check_permission(&request, None)?;
+
+    let tenant_id: ZTenantId = parse_request_param(&request, "tenant_id")?;
+    let exists_locally = match check_tenant_can_be_stopped(tenant_id) {
+        Ok(()) => true,
+        Err(ApiError::NotFound(_)) => false,
+        Err(e) => return Err(e),
+    };
+
+    let state = get_state(&request);
+    let remote_timeline_ids = index_remote_tenant_timelines(state, tenant_id).await?;
+    if !exists_locally && remote_timeline_ids.is_empty() {
+        return Err(ApiError::NotFound(format!("Tenant {tenant_id} not found")));
+    }
+
+    // Stop all the timelines before their remote data is deleted, so that
+    // nothing gets uploaded for them anymore
+    if exists_locally {
+        let conf = state.conf;
+        tokio::task::spawn_blocking(move || {
+            let _enter = info_span!("tenant_delete", tenant = %tenant_id).entered();
+            tenant_mgr::delete_local_tenant(conf, tenant_id)
+        })
+        .await
+        .map_err(ApiError::from_err)??;
+    }
+
+    for timeline_id in remote_timeline_ids {
+        storage_sync::schedule_timeline_delete(tenant_id, timeline_id);
+    }
+
+    json_response(StatusCode::OK, ())
+}
+
+fn check_tenant_can_be_stopped(tenant_id: ZTenantId) -> Result<(), ApiError> {
+    match tenant_mgr::get_tenant_state(tenant_id) {
+        None => Err(ApiError::NotFound(format!("Tenant {tenant_id} not found"))),
+        Some(TenantState::Attaching) => Err(ApiError::Conflict(format!(
+            "Tenant {tenant_id} is being attached, wait for its timelines to be downloaded"
+        ))),
+        Some(TenantState::Stopping) => Err(ApiError::Conflict(format!(
+            "Tenant {tenant_id} is already being stopped"
+        ))),
+        Some(TenantState::Active | TenantState::Idle | TenantState::Broken) => Ok(()),
+    }
+}
+
+/// Adds the timelines of a tenant found in the remote storage to the remote
+/// index, downloading the index parts that are not in the index yet.
+/// Returns the ids of all the tenant's remote timelines.
+async fn index_remote_tenant_timelines(
+    state: &State,
+    tenant_id: ZTenantId,
+) -> anyhow::Result<HashSet<ZTimelineId>> {
+    let timeline_ids = match state.remote_storage.as_ref() {
+        Some(GenericRemoteStorage::Local(local_storage)) => {
+            storage_sync::list_remote_timelines(state.conf, local_storage, tenant_id).await
+        }
+        Some(GenericRemoteStorage::S3(s3_storage)) => {
+            storage_sync::list_remote_timelines(state.conf, s3_storage, tenant_id).await
+        }
+        None => return Ok(HashSet::new()),
+    }
+    .with_context(|| format!("Failed to list remote timelines of tenant {tenant_id}"))?;
+
+    for &timeline_id in &timeline_ids {
+        let sync_id = ZTenantTimelineId {
+            tenant_id,
+            timeline_id,
+        };
+        if state
+            .remote_index
+            .read()
+            .await
+            .timeline_entry(&sync_id)
+            .is_some()
+        {
+            continue;
+        }
+        // release the lock during the download, like the timeline attach does
+        if let Some(remote_timeline) = try_download_index_part_data(state, sync_id).await? {
+            let mut index_accessor = state.remote_index.write().await;
+            if index_accessor.timeline_entry(&sync_id).is_none() {
+                index_accessor.add_timeline_entry(sync_id, remote_timeline);
+            }
+        }
+    }
+
+    Ok(timeline_ids)
+}
 async fn handler_404(_: Request<Body>) -> Result<Response<Body>, ApiError> {
     json_response(
         StatusCode::NOT_FOUND,
@@ -503,6 +1345,12 @@ pub fn make_router(
         .get("/v1/tenant", tenant_list_handler)
         .post("/v1/tenant", tenant_create_handler)
         .put("/v1/tenant/config", tenant_config_handler)
+        .get("/v1/disk_usage_eviction", disk_usage_eviction_handler)
+        .get("/v1/tenant/:tenant_id", tenant_status_handler)
+        .delete("/v1/tenant/:tenant_id", tenant_delete_handler)
+        .get("/v1/tenant/:tenant_id/size", tenant_size_handler)
+        .post("/v1/tenant/:tenant_id/attach", tenant_attach_handler)
+        .post("/v1/tenant/:tenant_id/detach", tenant_detach_handler)
         .get("/v1/tenant/:tenant_id/timeline", timeline_list_handler)
         .post("/v1/tenant/:tenant_id/timeline", timeline_create_handler)
         .get(
@@ -513,6 +1361,50 @@ pub fn make_router(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/wal_receiver",
             wal_receiver_get_handler,
         )
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/fullbackup",
+            timeline_fullbackup_handler,
+        )
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/logical_size",
+            timeline_logical_size_handler,
+        )
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/gc_dry_run",
+            timeline_gc_dry_run_handler,
+        )
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/config",
+            timeline_config_get_handler,
+        )
+        .get(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/restore_point",
+            timeline_restore_point_list_handler,
+        )
+        .post(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/restore_point",
+            timeline_restore_point_create_handler,
+        )
+        .delete(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/restore_point/:name",
+            timeline_restore_point_delete_handler,
+        )
+        .put(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/config",
+            timeline_config_handler,
+        )
+        .post(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/reset",
+            timeline_reset_handler,
+        )
+        .post(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/detach_ancestor",
+            timeline_detach_ancestor_handler,
+        )
+        .delete(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id",
+            timeline_delete_handler,
+        )
         .post(
             "/v1/tenant/:tenant_id/timeline/:timeline_id/attach",
             timeline_attach_handler,
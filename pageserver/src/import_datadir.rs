@@ -4,8 +4,8 @@
 //!
 use std::fs;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
-use std::path::{Path, PathBuf};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
 
 use anyhow::{bail, ensure, Context, Result};
 use bytes::Bytes;
@@ -46,7 +46,11 @@ pub fn import_timeline_from_postgres_datadir<R: Repository>(
             None => continue,
 
             Some("pg_control") => {
-                pg_control = Some(import_control_file(&mut modification, &direntry.path())?);
+                pg_control = Some(import_control_file(
+                    &mut modification,
+                    &direntry.path(),
+                    &mut File::open(direntry.path())?,
+                )?);
             }
             Some("pg_filenode.map") => {
                 import_relmap_file(
@@ -54,6 +58,7 @@ pub fn import_timeline_from_postgres_datadir<R: Repository>(
                     pg_constants::GLOBALTABLESPACE_OID,
                     0,
                     &direntry.path(),
+                    &mut File::open(direntry.path())?,
                 )?;
             }
 
@@ -62,11 +67,14 @@ pub fn import_timeline_from_postgres_datadir<R: Repository>(
         }
     }
     for relfile in relfiles {
-        import_relfile(
+        let (mut file, len) = open_file(&relfile)?;
+        import_rel(
             &mut modification,
             &relfile,
             pg_constants::GLOBALTABLESPACE_OID,
             0,
+            &mut file,
+            len,
         )?;
     }
 
@@ -96,6 +104,7 @@ pub fn import_timeline_from_postgres_datadir<R: Repository>(
                     pg_constants::DEFAULTTABLESPACE_OID,
                     dboid,
                     &direntry.path(),
+                    &mut File::open(direntry.path())?,
                 )?,
 
                 // Load any relation files into the page server
@@ -103,30 +112,44 @@ pub fn import_timeline_from_postgres_datadir<R: Repository>(
             }
         }
         for relfile in relfiles {
-            import_relfile(
+            let (mut file, len) = open_file(&relfile)?;
+            import_rel(
                 &mut modification,
                 &relfile,
                 pg_constants::DEFAULTTABLESPACE_OID,
                 dboid,
+                &mut file,
+                len,
             )?;
         }
     }
-    for entry in fs::read_dir(path.join("pg_xact"))? {
-        let entry = entry?;
-        import_slru_file(&mut modification, SlruKind::Clog, &entry.path())?;
-    }
-    for entry in fs::read_dir(path.join("pg_multixact").join("members"))? {
-        let entry = entry?;
-        import_slru_file(&mut modification, SlruKind::MultiXactMembers, &entry.path())?;
-    }
-    for entry in fs::read_dir(path.join("pg_multixact").join("offsets"))? {
-        let entry = entry?;
-        import_slru_file(&mut modification, SlruKind::MultiXactOffsets, &entry.path())?;
+    let slru_dirs = [
+        (SlruKind::Clog, path.join("pg_xact")),
+        (
+            SlruKind::MultiXactMembers,
+            path.join("pg_multixact").join("members"),
+        ),
+        (
+            SlruKind::MultiXactOffsets,
+            path.join("pg_multixact").join("offsets"),
+        ),
+    ];
+    for (slru, dir) in slru_dirs {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let (mut file, len) = open_file(&entry.path())?;
+            import_slru(&mut modification, slru, &entry.path(), &mut file, len)?;
+        }
     }
     for entry in fs::read_dir(path.join("pg_twophase"))? {
         let entry = entry?;
-        let xid = u32::from_str_radix(&entry.path().to_string_lossy(), 16)?;
-        import_twophase_file(&mut modification, xid, &entry.path())?;
+        let xid = u32::from_str_radix(&entry.file_name().to_string_lossy(), 16)?;
+        import_twophase_file(
+            &mut modification,
+            xid,
+            &entry.path(),
+            &mut File::open(entry.path())?,
+        )?;
     }
     // TODO: Scan pg_tblspc
 
@@ -157,12 +180,215 @@ pub fn import_timeline_from_postgres_datadir<R: Repository>(
     Ok(())
 }
 
+///
+/// Import a basebackup tarball into the repository.
+///
+/// The tarball can be created by `pg_basebackup --format=tar`, or by the
+/// page server's own 'fullbackup' command. The data files are imported at
+/// 'start_lsn', which must match the backup start location in the
+/// backup_label file, or the checkpoint REDO pointer if the cluster was shut
+/// down cleanly and there is no backup_label. WAL segments included in the
+/// tarball (pg_wal/*) are written to 'wal_dir', and the WAL between
+/// 'start_lsn' and 'end_lsn' is then replayed from there, to make the
+/// imported data consistent.
+///
+/// The data pages are written to the timeline in batches as they are read,
+/// so only the metadata is held in memory until the end of the tarball.
+pub fn import_basebackup_from_tar<R: Repository, Reader: Read>(
+    tline: &mut DatadirTimeline<R>,
+    reader: Reader,
+    wal_dir: &Path,
+    start_lsn: Lsn,
+    end_lsn: Lsn,
+) -> Result<()> {
+    ensure!(
+        start_lsn <= end_lsn,
+        "end LSN {} is before start LSN {}",
+        end_lsn,
+        start_lsn
+    );
+
+    let mut pg_control: Option<ControlFileData> = None;
+    let mut backup_start_lsn: Option<Lsn> = None;
+
+    let mut modification = tline.begin_modification(start_lsn);
+    modification.init_empty()?;
+
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+
+        match entry.header().entry_type() {
+            tar::EntryType::Regular => {}
+            tar::EntryType::Directory => continue,
+            // Tablespaces are symlinks in pg_tblspc
+            other => bail!(
+                "unsupported entry type {:?} for {} in basebackup tarball",
+                other,
+                path.display()
+            ),
+        }
+        let len = entry.header().size()? as usize;
+
+        let components = path
+            .components()
+            .filter(|c| *c != Component::CurDir)
+            .map(|c| match c {
+                Component::Normal(s) => s.to_str(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .with_context(|| format!("invalid path {} in basebackup tarball", path.display()))?;
+
+        match components.as_slice() {
+            ["global", "pg_control"] => {
+                pg_control = Some(import_control_file(&mut modification, &path, &mut entry)?);
+            }
+            ["global", "pg_filenode.map"] => import_relmap_file(
+                &mut modification,
+                pg_constants::GLOBALTABLESPACE_OID,
+                0,
+                &path,
+                &mut entry,
+            )?,
+            ["global", _] => import_rel(
+                &mut modification,
+                &path,
+                pg_constants::GLOBALTABLESPACE_OID,
+                0,
+                &mut entry,
+                len,
+            )?,
+
+            ["base", "pgsql_tmp", ..] => {}
+            ["base", _, "PG_VERSION"] => {}
+            ["base", dboid, "pg_filenode.map"] => import_relmap_file(
+                &mut modification,
+                pg_constants::DEFAULTTABLESPACE_OID,
+                dboid.parse::<u32>()?,
+                &path,
+                &mut entry,
+            )?,
+            ["base", dboid, _] => import_rel(
+                &mut modification,
+                &path,
+                pg_constants::DEFAULTTABLESPACE_OID,
+                dboid.parse::<u32>()?,
+                &mut entry,
+                len,
+            )?,
+
+            ["pg_xact", _] => {
+                import_slru(&mut modification, SlruKind::Clog, &path, &mut entry, len)?
+            }
+            ["pg_multixact", "members", _] => import_slru(
+                &mut modification,
+                SlruKind::MultiXactMembers,
+                &path,
+                &mut entry,
+                len,
+            )?,
+            ["pg_multixact", "offsets", _] => import_slru(
+                &mut modification,
+                SlruKind::MultiXactOffsets,
+                &path,
+                &mut entry,
+                len,
+            )?,
+            ["pg_twophase", xid] => import_twophase_file(
+                &mut modification,
+                u32::from_str_radix(xid, 16)?,
+                &path,
+                &mut entry,
+            )?,
+
+            ["pg_wal", segname] if IsXLogFileName(segname) || IsPartialXLogFileName(segname) => {
+                trace!("spooling WAL segment {}", path.display());
+                let mut file = File::create(wal_dir.join(segname))?;
+                io::copy(&mut entry, &mut file)?;
+            }
+
+            ["backup_label"] => {
+                backup_start_lsn = Some(parse_backup_label(&mut entry)?);
+            }
+
+            _ => debug!("ignoring {} in basebackup tarball", path.display()),
+        }
+    }
+
+    let pg_control = pg_control.context("pg_control file not found in basebackup tarball")?;
+    match backup_start_lsn {
+        Some(backup_start_lsn) => ensure!(
+            backup_start_lsn == start_lsn,
+            "start LSN {} does not match the backup start location {}",
+            start_lsn,
+            backup_start_lsn
+        ),
+        None => {
+            // Without a backup_label, the data directory must be consistent as is.
+            ensure!(
+                pg_control.state == DBState_DB_SHUTDOWNED,
+                "no backup_label in basebackup tarball, and Postgres cluster was not shut down cleanly"
+            );
+            ensure!(
+                Lsn(pg_control.checkPointCopy.redo) == start_lsn,
+                "start LSN {} does not match the checkpoint REDO pointer {}",
+                start_lsn,
+                Lsn(pg_control.checkPointCopy.redo)
+            );
+        }
+    }
+
+    // We're done importing all the data files.
+    modification.commit()?;
+
+    if end_lsn > start_lsn {
+        // Stop at the first record that ends at or after 'end_lsn'
+        let endpoint = end_lsn.checked_sub(1u64).unwrap();
+        let last_lsn = import_wal(wal_dir, tline, start_lsn, endpoint)?;
+        ensure!(
+            last_lsn == end_lsn,
+            "end LSN {} is not at a WAL record boundary, the last imported record ends at {}",
+            end_lsn,
+            last_lsn
+        );
+    }
+
+    Ok(())
+}
+
+/// Extract the backup start location from a backup_label file.
+fn parse_backup_label<Reader: Read>(reader: &mut Reader) -> Result<Lsn> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+
+    // START WAL LOCATION: 0/2000028 (file 000000010000000000000002)
+    let location = contents
+        .lines()
+        .find_map(|line| line.strip_prefix("START WAL LOCATION: "))
+        .context("START WAL LOCATION not found in backup_label")?;
+    let location = location.split_whitespace().next().unwrap_or_default();
+    location
+        .parse::<Lsn>()
+        .map_err(|_| anyhow::anyhow!("invalid START WAL LOCATION '{}' in backup_label", location))
+}
+
+/// Open a file in the data directory, and return it with its length.
+fn open_file(path: &Path) -> Result<(File, usize)> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+    Ok((file, len))
+}
+
 // subroutine of import_timeline_from_postgres_datadir(), to load one relation file.
-fn import_relfile<R: Repository>(
+fn import_rel<R: Repository, Reader: Read>(
     modification: &mut DatadirModification<R>,
     path: &Path,
     spcoid: Oid,
     dboid: Oid,
+    reader: &mut Reader,
+    len: usize,
 ) -> anyhow::Result<()> {
     // Does it look like a relation file?
     trace!("importing rel file {}", path.display());
@@ -173,16 +399,17 @@ fn import_relfile<R: Repository>(
             e
         })?;
 
-    let mut file = File::open(path)?;
-    let mut buf: [u8; 8192] = [0u8; 8192];
-
-    let len = file.metadata().unwrap().len();
-    ensure!(len % pg_constants::BLCKSZ as u64 == 0);
-    let nblocks = len / pg_constants::BLCKSZ as u64;
-
-    if segno != 0 {
-        todo!();
-    }
+    ensure!(
+        len % pg_constants::BLCKSZ as usize == 0,
+        "size of relation file {} is not a multiple of block size",
+        path.display()
+    );
+    let nblocks = len / pg_constants::BLCKSZ as usize;
+    ensure!(
+        nblocks <= pg_constants::RELSEG_SIZE as usize,
+        "relation segment {} is larger than the segment size",
+        path.display()
+    );
 
     let rel = RelTag {
         spcnode: spcoid,
@@ -190,45 +417,42 @@ fn import_relfile<R: Repository>(
         relnode,
         forknum,
     };
-    modification.put_rel_creation(rel, nblocks as u32)?;
-
-    let mut blknum: u32 = segno * (1024 * 1024 * 1024 / pg_constants::BLCKSZ as u32);
-    loop {
-        let r = file.read_exact(&mut buf);
-        match r {
-            Ok(_) => {
-                modification.put_rel_page_image(rel, blknum, Bytes::copy_from_slice(&buf))?;
-            }
 
-            // TODO: UnexpectedEof is expected
-            Err(err) => match err.kind() {
-                std::io::ErrorKind::UnexpectedEof => {
-                    // reached EOF. That's expected.
-                    ensure!(blknum == nblocks as u32, "unexpected EOF");
-                    break;
-                }
-                _ => {
-                    bail!("error reading file {}: {:#}", path.display(), err);
-                }
-            },
-        };
-        blknum += 1;
+    // The segments of a relation can be imported in any order. Create the
+    // relation with the first one we see, and extend it for the others as
+    // needed.
+    let start_blknum = segno * pg_constants::RELSEG_SIZE;
+    let end_blknum = start_blknum + nblocks as u32;
+    if !modification.get_rel_exists(rel)? {
+        modification.put_rel_creation(rel, end_blknum)?;
+    } else if modification.get_rel_size(rel)? < end_blknum {
+        modification.put_rel_extend(rel, end_blknum)?;
+    }
+
+    let mut buf: [u8; 8192] = [0u8; 8192];
+    for blknum in start_blknum..end_blknum {
+        reader
+            .read_exact(&mut buf)
+            .with_context(|| format!("error reading file {}", path.display()))?;
+        modification.put_rel_page_image(rel, blknum, Bytes::copy_from_slice(&buf))?;
+        // Only writes the pages out once a batch of them has accumulated
+        modification.flush()?;
     }
 
     Ok(())
 }
 
 /// Import a relmapper (pg_filenode.map) file into the repository
-fn import_relmap_file<R: Repository>(
+fn import_relmap_file<R: Repository, Reader: Read>(
     modification: &mut DatadirModification<R>,
     spcnode: Oid,
     dbnode: Oid,
     path: &Path,
+    reader: &mut Reader,
 ) -> Result<()> {
-    let mut file = File::open(path)?;
     let mut buffer = Vec::new();
     // read the whole file
-    file.read_to_end(&mut buffer)?;
+    reader.read_to_end(&mut buffer)?;
 
     trace!("importing relmap file {}", path.display());
 
@@ -237,15 +461,15 @@ fn import_relmap_file<R: Repository>(
 }
 
 /// Import a twophase state file (pg_twophase/<xid>) into the repository
-fn import_twophase_file<R: Repository>(
+fn import_twophase_file<R: Repository, Reader: Read>(
     modification: &mut DatadirModification<R>,
     xid: TransactionId,
     path: &Path,
+    reader: &mut Reader,
 ) -> Result<()> {
-    let mut file = File::open(path)?;
     let mut buffer = Vec::new();
     // read the whole file
-    file.read_to_end(&mut buffer)?;
+    reader.read_to_end(&mut buffer)?;
 
     trace!("importing non-rel file {}", path.display());
 
@@ -258,14 +482,14 @@ fn import_twophase_file<R: Repository>(
 ///
 /// The control file is imported as is, but we also extract the checkpoint record
 /// from it and store it separated.
-fn import_control_file<R: Repository>(
+fn import_control_file<R: Repository, Reader: Read>(
     modification: &mut DatadirModification<R>,
     path: &Path,
+    reader: &mut Reader,
 ) -> Result<ControlFileData> {
-    let mut file = File::open(path)?;
     let mut buffer = Vec::new();
     // read the whole file
-    file.read_to_end(&mut buffer)?;
+    reader.read_to_end(&mut buffer)?;
 
     trace!("importing control file {}", path.display());
 
@@ -283,64 +507,48 @@ fn import_control_file<R: Repository>(
 ///
 /// Import an SLRU segment file
 ///
-fn import_slru_file<R: Repository>(
+fn import_slru<R: Repository, Reader: Read>(
     modification: &mut DatadirModification<R>,
     slru: SlruKind,
     path: &Path,
+    reader: &mut Reader,
+    len: usize,
 ) -> Result<()> {
     trace!("importing slru file {}", path.display());
 
-    let mut file = File::open(path)?;
     let mut buf: [u8; 8192] = [0u8; 8192];
     let segno = u32::from_str_radix(&path.file_name().unwrap().to_string_lossy(), 16)?;
 
-    let len = file.metadata().unwrap().len();
-    ensure!(len % pg_constants::BLCKSZ as u64 == 0); // we assume SLRU block size is the same as BLCKSZ
-    let nblocks = len / pg_constants::BLCKSZ as u64;
+    ensure!(len % pg_constants::BLCKSZ as usize == 0); // we assume SLRU block size is the same as BLCKSZ
+    let nblocks = len / pg_constants::BLCKSZ as usize;
 
-    ensure!(nblocks <= pg_constants::SLRU_PAGES_PER_SEGMENT as u64);
+    ensure!(nblocks <= pg_constants::SLRU_PAGES_PER_SEGMENT as usize);
 
     modification.put_slru_segment_creation(slru, segno, nblocks as u32)?;
 
-    let mut rpageno = 0;
-    loop {
-        let r = file.read_exact(&mut buf);
-        match r {
-            Ok(_) => {
-                modification.put_slru_page_image(
-                    slru,
-                    segno,
-                    rpageno,
-                    Bytes::copy_from_slice(&buf),
-                )?;
-            }
-
-            // TODO: UnexpectedEof is expected
-            Err(err) => match err.kind() {
-                std::io::ErrorKind::UnexpectedEof => {
-                    // reached EOF. That's expected.
-                    ensure!(rpageno == nblocks as u32, "unexpected EOF");
-                    break;
-                }
-                _ => {
-                    bail!("error reading file {}: {:#}", path.display(), err);
-                }
-            },
-        };
-        rpageno += 1;
+    for rpageno in 0..nblocks as u32 {
+        reader
+            .read_exact(&mut buf)
+            .with_context(|| format!("error reading file {}", path.display()))?;
+        modification.put_slru_page_image(slru, segno, rpageno, Bytes::copy_from_slice(&buf))?;
+        // Only writes the pages out once a batch of them has accumulated
+        modification.flush()?;
     }
 
     Ok(())
 }
 
 /// Scan PostgreSQL WAL files in given directory and load all records between
-/// 'startpoint' and 'endpoint' into the repository.
+/// 'startpoint' and 'endpoint' into the repository. The last record imported
+/// is the first one that ends after 'endpoint'.
+///
+/// Returns the end LSN of the last imported record.
 fn import_wal<R: Repository>(
     walpath: &Path,
     tline: &mut DatadirTimeline<R>,
     startpoint: Lsn,
     endpoint: Lsn,
-) -> Result<()> {
+) -> Result<Lsn> {
     let mut waldecoder = WalStreamDecoder::new(startpoint);
 
     let mut segno = startpoint.segment_number(pg_constants::WAL_SEGMENT_SIZE);
@@ -363,7 +571,13 @@ fn import_wal<R: Repository>(
         }
 
         // Slurp the WAL file
-        let mut file = File::open(&path)?;
+        let mut file = File::open(&path).with_context(|| {
+            format!(
+                "could not open WAL segment {} needed to reach {}",
+                path.display(),
+                endpoint
+            )
+        })?;
 
         if offset > 0 {
             file.seek(SeekFrom::Start(offset as u64))?;
@@ -386,6 +600,9 @@ fn import_wal<R: Repository>(
                 nrecords += 1;
 
                 trace!("imported record at {} (end {})", lsn, endpoint);
+            } else {
+                // The rest of the record is in the next segment
+                break;
             }
         }
 
@@ -401,5 +618,5 @@ fn import_wal<R: Repository>(
         info!("no WAL to import at {}", last_lsn);
     }
 
-    Ok(())
+    Ok(last_lsn)
 }
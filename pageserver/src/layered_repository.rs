@@ -21,7 +21,7 @@ use tracing::*;
 use std::cmp::{max, min, Ordering};
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fs;
 use std::fs::{File, OpenOptions};
 use std::io::Write;
@@ -33,17 +33,20 @@ use std::time::{Duration, Instant, SystemTime};
 
 use self::metadata::{metadata_path, TimelineMetadata, METADATA_FILE_NAME};
 use crate::config::PageServerConf;
-use crate::keyspace::KeySpace;
+use crate::keyspace::{KeyPartitioning, KeySpace};
 use crate::storage_sync::index::RemoteIndex;
-use crate::tenant_config::{TenantConf, TenantConfOpt};
+use crate::tenant_config::{
+    CompactionStrategy, TenantConf, TenantConfOpt, TimelineConfOpt, TIMELINE_CONFIG_NAME,
+};
 
 use crate::repository::{
-    GcResult, Repository, RepositoryTimeline, Timeline, TimelineSyncStatusUpdate, TimelineWriter,
+    GcKeepReason, GcLayerInfo, GcResult, Repository, RepositoryTimeline, Timeline,
+    TimelineHasChildren, TimelineSyncStatusUpdate, TimelineWriter,
 };
 use crate::repository::{Key, Value};
 use crate::tenant_mgr;
 use crate::thread_mgr;
-use crate::virtual_file::VirtualFile;
+use crate::virtual_file::{self, VirtualFile};
 use crate::walreceiver::IS_WAL_RECEIVER;
 use crate::walredo::WalRedoManager;
 use crate::CheckpointConfig;
@@ -58,10 +61,10 @@ use utils::{
     crashsafe_dir,
     lsn::{AtomicLsn, Lsn, RecordLsn},
     seqwait::SeqWait,
-    zid::{ZTenantId, ZTimelineId},
+    zid::{ZTenantId, ZTenantTimelineId, ZTimelineId},
 };
 
-mod blob_io;
+pub mod blob_io;
 pub mod block_io;
 mod delta_layer;
 mod disk_btree;
@@ -69,12 +72,15 @@ pub(crate) mod ephemeral_file;
 mod filename;
 mod image_layer;
 mod inmemory_layer;
-mod layer_map;
+pub mod layer_map;
 pub mod metadata;
 mod par_fsync;
-mod storage_layer;
+mod remote_layer;
+pub mod restore_points;
+pub mod storage_layer;
 
 use crate::pgdatadir_mapping::LsnForTimestamp;
+use blob_io::CompressionAlgorithm;
 use delta_layer::{DeltaLayer, DeltaLayerWriter};
 use ephemeral_file::is_ephemeral_file;
 use filename::{DeltaFileName, ImageFileName};
@@ -83,7 +89,9 @@ use inmemory_layer::InMemoryLayer;
 use layer_map::LayerMap;
 use layer_map::SearchResult;
 use postgres_ffi::xlog_utils::to_pg_timestamp;
-use storage_layer::{Layer, ValueReconstructResult, ValueReconstructState};
+use remote_layer::RemoteLayer;
+use restore_points::{RestorePoint, RESTORE_POINTS_FILE_NAME};
+use storage_layer::{range_overlaps, Layer, ValueReconstructResult, ValueReconstructState};
 
 // re-export this function so that page_cache.rs can use it.
 pub use crate::layered_repository::ephemeral_file::writeback as writeback_ephemeral_file;
@@ -147,6 +155,40 @@ lazy_static! {
     .expect("failed to define a metric");
 }
 
+// Metrics collected by the scrubber
+lazy_static! {
+    static ref SCRUBBED_LAYERS: IntCounterVec = register_int_counter_vec!(
+        "pageserver_scrubbed_layers_total",
+        "Number of layer files verified by the scrubber",
+        &["tenant_id"]
+    )
+    .expect("failed to define a metric");
+    static ref CORRUPT_LAYERS: IntCounterVec = register_int_counter_vec!(
+        "pageserver_corrupt_layers_total",
+        "Number of layer files that the scrubber found to be corrupt",
+        &["tenant_id"]
+    )
+    .expect("failed to define a metric");
+}
+
+/// Result of a scrub iteration, see `LayeredRepository::scrub_iteration`
+#[derive(Debug, Default)]
+pub struct ScrubResult {
+    pub layers_scrubbed: u64,
+    pub corrupt_layers: Vec<PathBuf>,
+    /// Corrupt layers for which a download of the remote copy was scheduled
+    pub layers_refetched: u64,
+}
+
+/// A historic layer whose file has been uploaded to the remote storage, so
+/// that the local copy can be evicted. See `LayeredRepository::eviction_candidates`
+pub struct EvictionCandidate {
+    pub timeline: Arc<LayeredTimeline>,
+    pub layer: Arc<dyn Layer>,
+    pub last_access: SystemTime,
+    pub file_size: u64,
+}
+
 /// Parts of the `.zenith/tenants/<tenantid>/timelines/<timelineid>` directory prefix.
 pub const TIMELINES_SEGMENT_NAME: &str = "timelines";
 
@@ -238,6 +280,7 @@ impl Repository for LayeredRepository {
             timelineid,
             self.tenant_id,
             Arc::clone(&self.walredo_mgr),
+            self.remote_index.clone(),
             self.upload_layers,
         );
         timeline.layers.write().unwrap().next_open_layer_at = Some(initdb_lsn);
@@ -268,9 +311,19 @@ impl Repository for LayeredRepository {
             .context("failed to load timeline for branching")?
             .ok_or_else(|| anyhow::anyhow!("unknown timeline id: {}", &src))?;
         let latest_gc_cutoff_lsn = src_timeline.get_latest_gc_cutoff_lsn();
-        src_timeline
-            .check_lsn_is_in_scope(start_lsn, &latest_gc_cutoff_lsn)
-            .context("invalid branch start lsn")?;
+        // GC keeps the history needed to branch at a restore point, even if
+        // it's behind the GC cutoff
+        let is_restore_point = src_timeline
+            .restore_points
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|restore_point| restore_point.lsn == start_lsn);
+        if !is_restore_point {
+            src_timeline
+                .check_lsn_is_in_scope(start_lsn, &latest_gc_cutoff_lsn)
+                .context("invalid branch start lsn")?;
+        }
 
         let RecordLsn {
             last: src_last,
@@ -297,7 +350,7 @@ impl Repository for LayeredRepository {
             dst_prev,
             Some(src),
             start_lsn,
-            *src_timeline.latest_gc_cutoff_lsn.read().unwrap(),
+            min(*latest_gc_cutoff_lsn, start_lsn),
             src_timeline.initdb_lsn,
         );
         crashsafe_dir::create_dir_all(self.conf.timeline_path(&dst, &self.tenant_id))?;
@@ -315,9 +368,10 @@ impl Repository for LayeredRepository {
     fn gc_iteration(
         &self,
         target_timelineid: Option<ZTimelineId>,
-        horizon: u64,
+        horizon: Option<u64>,
         pitr: Duration,
         checkpoint_before_gc: bool,
+        dry_run: bool,
     ) -> Result<GcResult> {
         let timeline_str = target_timelineid
             .map(|x| x.to_string())
@@ -326,7 +380,13 @@ impl Repository for LayeredRepository {
         STORAGE_TIME
             .with_label_values(&["gc", &self.tenant_id.to_string(), &timeline_str])
             .observe_closure_duration(|| {
-                self.gc_iteration_internal(target_timelineid, horizon, pitr, checkpoint_before_gc)
+                self.gc_iteration_internal(
+                    target_timelineid,
+                    horizon,
+                    pitr,
+                    checkpoint_before_gc,
+                    dry_run,
+                )
             })
     }
 
@@ -398,15 +458,18 @@ impl Repository for LayeredRepository {
         // check no child timelines, because detach will remove files, which will brake child branches
         // FIXME this can still be violated because we do not guarantee
         //   that all ancestors are downloaded/attached to the same pageserver
-        let num_children = timelines
+        let children = timelines
             .iter()
             .filter(|(_, entry)| entry.ancestor_timeline_id() == Some(timeline_id))
-            .count();
-
-        ensure!(
-            num_children == 0,
-            "Cannot detach timeline which has child timelines"
-        );
+            .map(|(child_id, _)| *child_id)
+            .collect::<Vec<_>>();
+        if !children.is_empty() {
+            return Err(TimelineHasChildren {
+                timeline_id,
+                children,
+            }
+            .into());
+        }
 
         ensure!(
             timelines.remove(&timeline_id).is_some(),
@@ -529,6 +592,20 @@ impl LayeredRepository {
             .unwrap_or(self.conf.default_tenant_conf.compaction_threshold)
     }
 
+    pub fn get_compaction_strategy(&self) -> CompactionStrategy {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .compaction_strategy
+            .unwrap_or(self.conf.default_tenant_conf.compaction_strategy)
+    }
+
+    pub fn get_compaction_tier_ratio(&self) -> usize {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .compaction_tier_ratio
+            .unwrap_or(self.conf.default_tenant_conf.compaction_tier_ratio)
+    }
+
     pub fn get_gc_horizon(&self) -> u64 {
         let tenant_conf = self.tenant_conf.read().unwrap();
         tenant_conf
@@ -557,6 +634,234 @@ impl LayeredRepository {
             .unwrap_or(self.conf.default_tenant_conf.pitr_interval)
     }
 
+    pub fn get_getpage_rate_limit(&self) -> u64 {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .getpage_rate_limit
+            .unwrap_or(self.conf.default_tenant_conf.getpage_rate_limit)
+    }
+
+    pub fn get_getpage_burst(&self) -> u64 {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .getpage_burst
+            .unwrap_or(self.conf.default_tenant_conf.getpage_burst)
+    }
+
+    pub fn get_compression(&self) -> CompressionAlgorithm {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .compression
+            .unwrap_or(self.conf.default_tenant_conf.compression)
+    }
+
+    pub fn get_scrub_period(&self) -> Duration {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .scrub_period
+            .unwrap_or(self.conf.default_tenant_conf.scrub_period)
+    }
+
+    pub fn get_scrub_rate_limit(&self) -> u64 {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .scrub_rate_limit
+            .unwrap_or(self.conf.default_tenant_conf.scrub_rate_limit)
+    }
+
+    ///
+    /// Verify the checksums of all the layer files of the loaded timelines.
+    /// This function is periodically called by the scrubber thread.
+    ///
+    /// 'throttle' is called with the number of bytes read as the scrub
+    /// progresses, see `Layer::scrub`. If a corrupt layer has a copy in
+    /// remote storage, it's moved aside and the copy is downloaded to
+    /// replace it.
+    ///
+    pub fn scrub_iteration(&self, throttle: &mut dyn FnMut(u64)) -> Result<ScrubResult> {
+        // Like in compaction, collect the list of timelines while holding
+        // the lock, and scrub them without it.
+        let timelines = self.timelines.lock().unwrap();
+        let timelines_to_scrub = timelines
+            .iter()
+            .filter_map(|(timelineid, timeline)| match timeline {
+                LayeredTimelineEntry::Loaded(timeline) => Some((*timelineid, Arc::clone(timeline))),
+                LayeredTimelineEntry::Unloaded { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        drop(timelines);
+
+        let tenant_id = self.tenant_id.to_string();
+        let mut result = ScrubResult::default();
+        for (timelineid, timeline) in timelines_to_scrub {
+            if thread_mgr::is_shutdown_requested() {
+                break;
+            }
+            let _entered =
+                info_span!("scrub", timeline = %timelineid, tenant = %self.tenant_id).entered();
+
+            let num_scrubbed_before = result.layers_scrubbed;
+            let corrupt_layers = timeline.scrub(throttle, &mut result);
+            SCRUBBED_LAYERS
+                .with_label_values(&[&tenant_id])
+                .inc_by(result.layers_scrubbed - num_scrubbed_before);
+
+            for layer in corrupt_layers {
+                CORRUPT_LAYERS.with_label_values(&[&tenant_id]).inc();
+                let path = layer.local_path().unwrap();
+                match self.refetch_layer(&timeline, layer) {
+                    Ok(true) => result.layers_refetched += 1,
+                    Ok(false) => warn!(
+                        "no remote copy of corrupt layer {} to download",
+                        path.display()
+                    ),
+                    Err(e) => error!(
+                        "failed to schedule download of corrupt layer {}: {:?}",
+                        path.display(),
+                        e
+                    ),
+                }
+                result.corrupt_layers.push(path);
+            }
+        }
+
+        Ok(result)
+    }
+
+    ///
+    /// Replace a corrupt layer with its copy in remote storage. Returns false
+    /// if there's no remote copy to download.
+    ///
+    fn refetch_layer(&self, timeline: &LayeredTimeline, layer: Arc<dyn Layer>) -> Result<bool> {
+        let path = layer.local_path().unwrap();
+        let sync_id = ZTenantTimelineId {
+            tenant_id: self.tenant_id,
+            timeline_id: timeline.timeline_id,
+        };
+
+        let mut index_accessor = self.remote_index.blocking_write();
+        let remote_timeline = match index_accessor.timeline_entry_mut(&sync_id) {
+            Some(remote_timeline) if remote_timeline.stored_files().contains(&path) => {
+                remote_timeline
+            }
+            _ => return Ok(false),
+        };
+        ensure!(
+            !remote_timeline.awaits_download,
+            "timeline download is already in progress"
+        );
+
+        timeline.quarantine_layer(layer)?;
+
+        // Download just this layer. The download skips the files that
+        // exist locally, and the corrupt one was moved out of the way.
+        remote_timeline.awaits_download = true;
+        let layers_to_skip = remote_timeline
+            .stored_files()
+            .iter()
+            .filter(|stored_path| **stored_path != path)
+            .cloned()
+            .collect();
+        storage_sync::schedule_layer_redownload(
+            self.tenant_id,
+            timeline.timeline_id,
+            layers_to_skip,
+        );
+        Ok(true)
+    }
+
+    ///
+    /// List the historic layers of the loaded timelines that have a local
+    /// file, which has also been uploaded to the remote storage. Used by the
+    /// disk usage based eviction to pick the layers to evict.
+    ///
+    pub fn eviction_candidates(&self) -> Vec<EvictionCandidate> {
+        let timelines = self.timelines.lock().unwrap();
+        let loaded_timelines = timelines
+            .values()
+            .filter_map(|timeline| match timeline {
+                LayeredTimelineEntry::Loaded(timeline) => Some(Arc::clone(timeline)),
+                LayeredTimelineEntry::Unloaded { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        drop(timelines);
+
+        let index_accessor = self.remote_index.blocking_read();
+
+        let mut uploaded_layers = Vec::new();
+        for timeline in loaded_timelines {
+            let sync_id = ZTenantTimelineId {
+                tenant_id: self.tenant_id,
+                timeline_id: timeline.timeline_id,
+            };
+            let stored_files = match index_accessor.timeline_entry(&sync_id) {
+                Some(remote_timeline) if !remote_timeline.awaits_download => {
+                    remote_timeline.stored_files()
+                }
+                _ => continue,
+            };
+
+            let layers = timeline.layers.read().unwrap();
+            for layer in layers.iter_historic_layers() {
+                if layer.is_remote() {
+                    continue;
+                }
+                if let (Some(path), Some(last_access)) = (layer.local_path(), layer.last_access()) {
+                    if stored_files.contains(&path) {
+                        uploaded_layers.push((
+                            Arc::clone(&timeline),
+                            Arc::clone(layer),
+                            path,
+                            last_access,
+                        ));
+                    }
+                }
+            }
+        }
+        drop(index_accessor);
+
+        // Don't hold the locks while looking up the file sizes. The file is
+        // gone if GC or compaction removed the layer in the meanwhile.
+        uploaded_layers
+            .into_iter()
+            .filter_map(|(timeline, layer, path, last_access)| {
+                let file_size = fs::metadata(&path).ok()?.len();
+                Some(EvictionCandidate {
+                    timeline,
+                    layer,
+                    last_access,
+                    file_size,
+                })
+            })
+            .collect()
+    }
+
+    ///
+    /// Remove the files of the layers evicted from the loaded timelines, that
+    /// were still in use when they were evicted and are no longer.
+    ///
+    pub fn remove_evicted_layer_files(&self) -> Result<()> {
+        let loaded_timelines = self
+            .timelines
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|timeline| match timeline {
+                LayeredTimelineEntry::Loaded(timeline) => Some(Arc::clone(timeline)),
+                LayeredTimelineEntry::Unloaded { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        for timeline in loaded_timelines {
+            timeline.remove_evicted_layer_files().with_context(|| {
+                format!(
+                    "failed to remove evicted layer files of timeline {}",
+                    timeline.timeline_id
+                )
+            })?;
+        }
+        Ok(())
+    }
+
     pub fn update_tenant_config(&self, new_tenant_conf: TenantConfOpt) -> Result<()> {
         let mut tenant_conf = self.tenant_conf.write().unwrap();
 
@@ -644,8 +949,15 @@ impl LayeredRepository {
             timeline_id,
             self.tenant_id,
             Arc::clone(&self.walredo_mgr),
+            self.remote_index.clone(),
             self.upload_layers,
         );
+        *timeline.timeline_conf.write().unwrap() =
+            Self::load_timeline_config(self.conf, timeline_id, self.tenant_id)
+                .context("failed to load timeline config")?;
+        *timeline.restore_points.lock().unwrap() =
+            restore_points::load_restore_points(self.conf, timeline_id, self.tenant_id)
+                .context("failed to load restore points")?;
         timeline
             .load_layer_map(disk_consistent_lsn)
             .context("failed to load layermap")?;
@@ -738,6 +1050,318 @@ impl LayeredRepository {
         })
     }
 
+    /// Mark an LSN of a timeline as a named restore point. GC keeps the
+    /// history needed to branch at the restore point until it's deleted.
+    /// `lsn` defaults to the last record LSN of the timeline.
+    pub fn create_restore_point(
+        &self,
+        timeline_id: ZTimelineId,
+        name: &str,
+        lsn: Option<Lsn>,
+    ) -> Result<RestorePoint> {
+        ensure!(
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')),
+            "invalid restore point name '{}', only ASCII letters, digits, '-', '_' and '.' are allowed",
+            name
+        );
+
+        // Hold the GC lock, so that GC can't remove the history behind the
+        // restore point before it's taken into account.
+        let _gc_cs = self.gc_cs.lock().unwrap();
+
+        let timeline = self
+            .get_timeline_load(timeline_id)
+            .context("failed to load timeline")?;
+
+        let last_record_lsn = timeline.get_last_record_lsn();
+        let lsn = lsn.unwrap_or(last_record_lsn);
+        ensure!(
+            lsn <= last_record_lsn,
+            "LSN {} is newer than the last record LSN {} of the timeline",
+            lsn,
+            last_record_lsn
+        );
+        let lsn = lsn.align();
+        // The history before the branch point belongs to the ancestor
+        ensure!(
+            lsn >= timeline.get_ancestor_lsn(),
+            "LSN {} is before the branch point {} of the timeline",
+            lsn,
+            timeline.get_ancestor_lsn()
+        );
+        timeline
+            .check_lsn_is_in_scope(lsn, &timeline.get_latest_gc_cutoff_lsn())
+            .context("invalid restore point lsn")?;
+
+        let mut restore_points = timeline.restore_points.lock().unwrap();
+        ensure!(
+            restore_points.iter().all(|rp| rp.name != name),
+            "restore point '{}' already exists",
+            name
+        );
+        let restore_point = RestorePoint {
+            name: name.to_string(),
+            lsn,
+            created_at: SystemTime::now(),
+        };
+        let mut new_restore_points = restore_points.clone();
+        new_restore_points.push(restore_point.clone());
+        restore_points::save_restore_points(
+            self.conf,
+            timeline_id,
+            self.tenant_id,
+            &new_restore_points,
+        )?;
+        *restore_points = new_restore_points;
+        if self.upload_layers {
+            storage_sync::schedule_index_upload(self.tenant_id, timeline_id);
+        }
+
+        info!(
+            "created restore point '{}' at {} on timeline {}",
+            name, lsn, timeline_id
+        );
+        Ok(restore_point)
+    }
+
+    /// Delete a restore point, releasing the history that it pins. Returns
+    /// the deleted restore point, or None if there's no restore point with
+    /// that name.
+    pub fn delete_restore_point(
+        &self,
+        timeline_id: ZTimelineId,
+        name: &str,
+    ) -> Result<Option<RestorePoint>> {
+        let timeline = self
+            .get_timeline_load(timeline_id)
+            .context("failed to load timeline")?;
+
+        let mut restore_points = timeline.restore_points.lock().unwrap();
+        let pos = match restore_points.iter().position(|rp| rp.name == name) {
+            Some(pos) => pos,
+            None => return Ok(None),
+        };
+        let mut new_restore_points = restore_points.clone();
+        let restore_point = new_restore_points.remove(pos);
+        restore_points::save_restore_points(
+            self.conf,
+            timeline_id,
+            self.tenant_id,
+            &new_restore_points,
+        )?;
+        *restore_points = new_restore_points;
+        if self.upload_layers {
+            storage_sync::schedule_index_upload(self.tenant_id, timeline_id);
+        }
+
+        info!(
+            "deleted restore point '{}' at {} on timeline {}",
+            name, restore_point.lsn, timeline_id
+        );
+        Ok(Some(restore_point))
+    }
+
+    /// Reset a timeline to an earlier LSN, keeping its ID: all the history
+    /// after 'lsn' is removed, locally and from the remote storage.
+    ///
+    /// The caller must stop the WAL receiver of the timeline first, and make
+    /// sure that the safekeepers don't have the WAL after 'lsn' anymore, or
+    /// it would be streamed and ingested again. The in-memory timeline object
+    /// is replaced, so the caller must also drop any references to it.
+    ///
+    /// 'prev_record_lsn' is the start of the last record before 'lsn'. The
+    /// pageserver only knows it for the last record LSN, and computes can't
+    /// start in read-write mode without it.
+    pub fn reset_timeline(
+        &self,
+        timeline_id: ZTimelineId,
+        lsn: Lsn,
+        prev_record_lsn: Option<Lsn>,
+    ) -> Result<()> {
+        // Hold the GC lock, so that GC doesn't run on the timeline, and no
+        // branches are created from it while it's reset.
+        let _gc_cs = self.gc_cs.lock().unwrap();
+
+        let timeline = {
+            let mut timelines = self.timelines.lock().unwrap();
+            // Child timelines keep a reference to the in-memory timeline
+            // object, which is replaced
+            ensure!(
+                timelines
+                    .values()
+                    .all(|entry| entry.ancestor_timeline_id() != Some(timeline_id)),
+                "Cannot reset timeline which has child timelines"
+            );
+            self.get_timeline_load_internal(timeline_id, &mut timelines)
+                .context("failed to load timeline")?
+                .with_context(|| format!("unknown timeline id: {}", timeline_id))?
+        };
+
+        ensure!(lsn.is_aligned(), "LSN {} is not aligned", lsn);
+        let last_record_lsn = timeline.get_last_record_lsn();
+        ensure!(
+            lsn <= last_record_lsn,
+            "LSN {} is newer than the last record LSN {} of the timeline",
+            lsn,
+            last_record_lsn
+        );
+        ensure!(
+            lsn >= timeline.get_ancestor_lsn(),
+            "LSN {} is before the branch point {} of the timeline",
+            lsn,
+            timeline.get_ancestor_lsn()
+        );
+        ensure!(
+            prev_record_lsn.is_some()
+                || lsn == last_record_lsn
+                || lsn == timeline.get_ancestor_lsn(),
+            "previous record LSN is required to reset the timeline to {}",
+            lsn
+        );
+        timeline
+            .check_lsn_is_in_scope(lsn, &timeline.get_latest_gc_cutoff_lsn())
+            .context("invalid reset lsn")?;
+        if let Some(restore_point) = timeline
+            .get_restore_points()
+            .into_iter()
+            .find(|rp| rp.lsn > lsn)
+        {
+            bail!(
+                "restore point '{}' at {} is after the reset LSN, delete it first",
+                restore_point.name,
+                restore_point.lsn
+            );
+        }
+
+        let (metadata, removed_layers) = timeline.reset_to(lsn, prev_record_lsn)?;
+        if self.upload_layers {
+            storage_sync::schedule_layer_delete(self.tenant_id, timeline_id, removed_layers);
+        }
+
+        // The in-memory state of the timeline, like its last record LSN,
+        // can't go back, load it again from the new metadata.
+        self.timelines.lock().unwrap().insert(
+            timeline_id,
+            LayeredTimelineEntry::Unloaded {
+                id: timeline_id,
+                metadata,
+            },
+        );
+
+        info!("reset timeline {} to {}", timeline_id, lsn);
+        Ok(())
+    }
+
+    /// Turn a branch into an independent root timeline: everything it reads
+    /// from its ancestors at the branch point is materialized as image layers
+    /// of its own, and the ancestor is removed from its metadata. After that,
+    /// the ancestor's GC and deletion don't affect the timeline anymore.
+    ///
+    /// 'keyspace' must cover all the keys in use at the branch point. Like
+    /// for [`Self::reset_timeline`], the caller must stop the WAL receiver of
+    /// the timeline first and drop any references to the timeline object.
+    pub fn detach_ancestor(&self, timeline_id: ZTimelineId, keyspace: &KeySpace) -> Result<()> {
+        // Hold the GC lock, so that the ancestor's GC doesn't run while the
+        // branch point data is copied.
+        let _gc_cs = self.gc_cs.lock().unwrap();
+
+        let timeline = {
+            let mut timelines = self.timelines.lock().unwrap();
+            // Child timelines keep a reference to the in-memory timeline
+            // object, which is replaced
+            ensure!(
+                timelines
+                    .values()
+                    .all(|entry| entry.ancestor_timeline_id() != Some(timeline_id)),
+                "Cannot detach the ancestor of a timeline which has child timelines"
+            );
+            self.get_timeline_load_internal(timeline_id, &mut timelines)
+                .context("failed to load timeline")?
+                .with_context(|| format!("unknown timeline id: {}", timeline_id))?
+        };
+        let ancestor_id = timeline
+            .get_ancestor_timeline_id()
+            .with_context(|| format!("timeline {} has no ancestor", timeline_id))?;
+
+        let metadata = timeline.detach_from_ancestor(keyspace)?;
+
+        self.timelines.lock().unwrap().insert(
+            timeline_id,
+            LayeredTimelineEntry::Unloaded {
+                id: timeline_id,
+                metadata,
+            },
+        );
+
+        info!(
+            "detached timeline {} from its ancestor {} at {}",
+            timeline_id,
+            ancestor_id,
+            timeline.get_ancestor_lsn()
+        );
+        Ok(())
+    }
+
+    /// Locate and load the timeline's overrides of the tenant config.
+    /// A timeline without a config file has no overrides.
+    pub fn load_timeline_config(
+        conf: &'static PageServerConf,
+        timelineid: ZTimelineId,
+        tenantid: ZTenantId,
+    ) -> anyhow::Result<TimelineConfOpt> {
+        let target_config_path = TimelineConfOpt::path(conf, timelineid, tenantid);
+        if !target_config_path.exists() {
+            return Ok(Default::default());
+        }
+
+        info!("load timeline config from {}", target_config_path.display());
+        let config = fs::read_to_string(&target_config_path)?;
+        toml_edit::easy::from_str(&config).with_context(|| {
+            format!(
+                "Failed to parse timeline config file '{}'",
+                target_config_path.display()
+            )
+        })
+    }
+
+    /// Overwrite the timeline's config file. The new contents are written to
+    /// a temporary file first, so that a crash can't leave a half-written
+    /// config behind.
+    pub fn persist_timeline_config(
+        conf: &'static PageServerConf,
+        timelineid: ZTimelineId,
+        tenantid: ZTenantId,
+        timeline_conf: TimelineConfOpt,
+    ) -> anyhow::Result<()> {
+        let _enter = info_span!("saving timeline config").entered();
+        let target_config_path = TimelineConfOpt::path(conf, timelineid, tenantid);
+        info!("save timeline config to {}", target_config_path.display());
+
+        let mut conf_content =
+            r#"# This file contains the timeline's overrides of the tenant config.
+#  It is read in case of pageserver restart.
+
+"#
+            .to_string();
+        conf_content += &toml_edit::easy::to_string(&timeline_conf)?;
+
+        let temp_path = target_config_path.with_extension("temp");
+        let mut file = File::create(&temp_path)
+            .with_context(|| format!("Failed to create file '{}'", temp_path.display()))?;
+        file.write_all(conf_content.as_bytes())
+            .and_then(|()| file.sync_all())
+            .with_context(|| format!("Failed to write file '{}'", temp_path.display()))?;
+        fs::rename(&temp_path, &target_config_path).with_context(|| {
+            format!(
+                "Failed to write timeline config file into path '{}'",
+                target_config_path.display()
+            )
+        })
+    }
+
     /// Save timeline metadata to file
     pub fn save_metadata(
         conf: &'static PageServerConf,
@@ -801,12 +1425,13 @@ impl LayeredRepository {
     fn gc_iteration_internal(
         &self,
         target_timelineid: Option<ZTimelineId>,
-        horizon: u64,
+        horizon: Option<u64>,
         pitr: Duration,
         checkpoint_before_gc: bool,
+        dry_run: bool,
     ) -> Result<GcResult> {
         let _span_guard =
-            info_span!("gc iteration", tenant = %self.tenant_id, timeline = ?target_timelineid)
+            info_span!("gc iteration", tenant = %self.tenant_id, timeline = ?target_timelineid, dry_run)
                 .entered();
         let mut totals: GcResult = Default::default();
         let now = Instant::now();
@@ -862,25 +1487,40 @@ impl LayeredRepository {
                 }
             }
 
-            if let Some(cutoff) = timeline.get_last_record_lsn().checked_sub(horizon) {
+            // The timeline's own settings, if any, take precedence over the
+            // tenant-wide ones. A horizon passed in explicitly wins over both.
+            let timeline_conf = timeline.get_timeline_conf();
+            let horizon = horizon.unwrap_or_else(|| timeline.get_gc_horizon());
+            let pitr = timeline_conf.pitr_interval.unwrap_or(pitr);
+
+            // A timeline shorter than the horizon is not collected at all. A dry
+            // run still reports its layers, as needed by the horizon.
+            let cutoff = match timeline.get_last_record_lsn().checked_sub(horizon) {
+                None if dry_run => Some(Lsn(0)),
+                cutoff => cutoff,
+            };
+            if let Some(cutoff) = cutoff {
                 drop(timelines);
-                let branchpoints: Vec<Lsn> = all_branchpoints
+                let mut branchpoints: Vec<Lsn> = all_branchpoints
                     .range((
                         Included((timelineid, Lsn(0))),
                         Included((timelineid, Lsn(u64::MAX))),
                     ))
                     .map(|&x| x.1)
                     .collect();
+                // Restore points pin the history like the branch points
+                branchpoints.extend(timeline.get_restore_points().iter().map(|rp| rp.lsn));
 
                 // If requested, force flush all in-memory layers to disk first,
                 // so that they too can be garbage collected. That's
                 // used in tests, so we want as deterministic results as possible.
-                if checkpoint_before_gc {
+                // A dry run doesn't change anything on disk.
+                if checkpoint_before_gc && !dry_run {
                     timeline.checkpoint(CheckpointConfig::Forced)?;
                     info!("timeline {} checkpoint_before_gc done", timelineid);
                 }
                 timeline.update_gc_info(branchpoints, cutoff, pitr);
-                let result = timeline.gc()?;
+                let result = timeline.gc(dry_run)?;
 
                 totals += result;
                 timelines = self.timelines.lock().unwrap();
@@ -899,6 +1539,11 @@ impl LayeredRepository {
 pub struct LayeredTimeline {
     conf: &'static PageServerConf,
     tenant_conf: Arc<RwLock<TenantConfOpt>>,
+    // Overrides of the tenant config for this timeline only
+    timeline_conf: RwLock<TimelineConfOpt>,
+
+    // Named restore points, see restore_points.rs
+    restore_points: Mutex<Vec<RestorePoint>>,
 
     tenant_id: ZTenantId,
     timeline_id: ZTimelineId,
@@ -961,6 +1606,18 @@ pub struct LayeredTimeline {
     /// Used to ensure that there is only one thread
     layer_flush_lock: Mutex<()>,
 
+    /// Serializes the downloads of remote layers, see
+    /// [`LayeredTimeline::download_remote_layer`].
+    layer_download_lock: Mutex<()>,
+
+    /// Layers replaced with RemoteLayers by [`LayeredTimeline::evict_layer`],
+    /// whose files are not removed yet because they were still in use.
+    evicted_layers: Mutex<Vec<Arc<dyn Layer>>>,
+
+    // Provides the list of the timeline's layer files in the remote storage,
+    // to create remote layers for the files that are not present locally.
+    remote_index: RemoteIndex,
+
     // Prevent concurrent compactions.
     // Compactions are normally performed by one thread. But compaction can also be manually
     // requested by admin (that's used in tests). These forced compactions run in a different
@@ -990,9 +1647,8 @@ pub struct LayeredTimeline {
 struct GcInfo {
     /// Specific LSNs that are needed.
     ///
-    /// Currently, this includes all points where child branches have
-    /// been forked off from. In the future, could also include
-    /// explicit user-defined snapshot points.
+    /// This includes all points where child branches have been forked
+    /// off from, and the named restore points of the timeline.
     retain_lsns: Vec<Lsn>,
 
     /// In addition to 'retain_lsns', keep everything newer than this
@@ -1011,6 +1667,7 @@ struct GcInfo {
 }
 
 /// Public interface functions
+#[async_trait::async_trait]
 impl Timeline for LayeredTimeline {
     fn get_ancestor_lsn(&self) -> Lsn {
         self.ancestor_lsn
@@ -1023,7 +1680,7 @@ impl Timeline for LayeredTimeline {
     }
 
     /// Wait until WAL has been received up to the given LSN.
-    fn wait_lsn(&self, lsn: Lsn) -> anyhow::Result<()> {
+    async fn wait_lsn(&self, lsn: Lsn) -> anyhow::Result<()> {
         // This should never be called from the WAL receiver thread, because that could lead
         // to a deadlock.
         ensure!(
@@ -1031,15 +1688,16 @@ impl Timeline for LayeredTimeline {
             "wait_lsn called by WAL receiver thread"
         );
 
-        self.wait_lsn_time_histo.observe_closure_duration(
-            || self.last_record_lsn
-                .wait_for_timeout(lsn, self.conf.wait_lsn_timeout)
-                .with_context(|| {
-                    format!(
-                        "Timed out while waiting for WAL record at LSN {} to arrive, last_record_lsn {} disk consistent LSN={}",
-                        lsn, self.get_last_record_lsn(), self.get_disk_consistent_lsn()
-                    )
-                }))?;
+        let _timer = self.wait_lsn_time_histo.start_timer();
+        self.last_record_lsn
+            .wait_for_timeout_async(lsn, self.conf.wait_lsn_timeout)
+            .await
+            .with_context(|| {
+                format!(
+                    "Timed out while waiting for WAL record at LSN {} to arrive, last_record_lsn {} disk consistent LSN={}",
+                    lsn, self.get_last_record_lsn(), self.get_disk_consistent_lsn()
+                )
+            })?;
 
         Ok(())
     }
@@ -1159,13 +1817,575 @@ impl LayeredTimeline {
             .unwrap_or(self.conf.default_tenant_conf.compaction_threshold)
     }
 
-    fn get_image_creation_threshold(&self) -> usize {
+    fn get_compaction_strategy(&self) -> CompactionStrategy {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .compaction_strategy
+            .unwrap_or(self.conf.default_tenant_conf.compaction_strategy)
+    }
+
+    fn get_compaction_tier_ratio(&self) -> usize {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .compaction_tier_ratio
+            .unwrap_or(self.conf.default_tenant_conf.compaction_tier_ratio)
+    }
+
+    fn get_image_creation_threshold(&self) -> usize {
         let tenant_conf = self.tenant_conf.read().unwrap();
         tenant_conf
             .image_creation_threshold
             .unwrap_or(self.conf.default_tenant_conf.image_creation_threshold)
     }
 
+    fn get_compression(&self) -> CompressionAlgorithm {
+        let tenant_conf = self.tenant_conf.read().unwrap();
+        tenant_conf
+            .compression
+            .unwrap_or(self.conf.default_tenant_conf.compression)
+    }
+
+    pub fn get_timeline_conf(&self) -> TimelineConfOpt {
+        *self.timeline_conf.read().unwrap()
+    }
+
+    /// Replace the timeline's overrides of the tenant config, and persist
+    /// them so that they survive a restart. Unset settings fall back to the
+    /// tenant config again.
+    pub fn set_timeline_conf(&self, timeline_conf: TimelineConfOpt) -> Result<()> {
+        let mut current = self.timeline_conf.write().unwrap();
+        LayeredRepository::persist_timeline_config(
+            self.conf,
+            self.timeline_id,
+            self.tenant_id,
+            timeline_conf,
+        )?;
+        *current = timeline_conf;
+
+        if self.upload_layers.load(atomic::Ordering::Relaxed) {
+            storage_sync::schedule_index_upload(self.tenant_id, self.timeline_id);
+        }
+        Ok(())
+    }
+
+    pub fn get_restore_points(&self) -> Vec<RestorePoint> {
+        self.restore_points.lock().unwrap().clone()
+    }
+
+    /// GC horizon of the timeline, taking its overrides into account
+    pub fn get_gc_horizon(&self) -> u64 {
+        self.get_timeline_conf().gc_horizon.unwrap_or_else(|| {
+            let tenant_conf = self.tenant_conf.read().unwrap();
+            tenant_conf
+                .gc_horizon
+                .unwrap_or(self.conf.default_tenant_conf.gc_horizon)
+        })
+    }
+
+    /// PITR interval of the timeline, taking its overrides into account
+    pub fn get_pitr_interval(&self) -> Duration {
+        self.get_timeline_conf().pitr_interval.unwrap_or_else(|| {
+            let tenant_conf = self.tenant_conf.read().unwrap();
+            tenant_conf
+                .pitr_interval
+                .unwrap_or(self.conf.default_tenant_conf.pitr_interval)
+        })
+    }
+
+    ///
+    /// Verify the checksums of all the historic layers. Returns the layers
+    /// that failed verification.
+    ///
+    fn scrub(
+        &self,
+        throttle: &mut dyn FnMut(u64),
+        result: &mut ScrubResult,
+    ) -> Vec<Arc<dyn Layer>> {
+        // Don't hold the lock while reading the files. Remote layers have no
+        // local file to verify.
+        let layers_to_scrub = self
+            .layers
+            .read()
+            .unwrap()
+            .iter_historic_layers()
+            .filter(|l| !l.is_remote())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut corrupt_layers = Vec::new();
+        for layer in layers_to_scrub {
+            if thread_mgr::is_shutdown_requested() {
+                break;
+            }
+            if let Err(e) = layer.scrub(throttle) {
+                // GC or compaction might have removed the file while we were
+                // reading it.
+                #[allow(clippy::vtable_address_comparisons)]
+                let removed = !self
+                    .layers
+                    .read()
+                    .unwrap()
+                    .iter_historic_layers()
+                    .any(|l| Arc::ptr_eq(l, &layer));
+                if removed {
+                    continue;
+                }
+                error!(
+                    "layer {} failed verification: {:#}",
+                    layer.filename().display(),
+                    e
+                );
+                corrupt_layers.push(layer);
+            }
+            result.layers_scrubbed += 1;
+        }
+        corrupt_layers
+    }
+
+    ///
+    /// Move the file of a corrupt layer aside, and replace the layer in the
+    /// layer map with a new one that opens the file again on first access.
+    /// Until a good copy of the file has been downloaded, reads that need
+    /// the layer fail.
+    ///
+    fn quarantine_layer(&self, layer: Arc<dyn Layer>) -> Result<()> {
+        let mut layers = self.layers.write().unwrap();
+
+        #[allow(clippy::vtable_address_comparisons)]
+        let present = layers
+            .iter_historic_layers()
+            .any(|l| Arc::ptr_eq(l, &layer));
+        ensure!(present, "layer was removed from the layer map concurrently");
+
+        let new_layer = self.open_layer_file(&layer.filename())?;
+
+        // The file is already gone if this is a retry after a failed download
+        let path = layer.local_path().unwrap();
+        if path.exists() {
+            rename_to_backup(path)?;
+        }
+
+        layers.remove_historic(layer);
+        layers.insert_historic(new_layer);
+        Ok(())
+    }
+
+    ///
+    /// Create an ImageLayer or DeltaLayer for a layer file in the timeline
+    /// directory. The file is opened on first access.
+    ///
+    fn open_layer_file(&self, fname: &Path) -> Result<Arc<dyn Layer>> {
+        let fname = fname.to_string_lossy();
+        if let Some(imgfilename) = ImageFileName::parse_str(&fname) {
+            Ok(Arc::new(ImageLayer::new(
+                self.conf,
+                self.timeline_id,
+                self.tenant_id,
+                &imgfilename,
+            )))
+        } else if let Some(deltafilename) = DeltaFileName::parse_str(&fname) {
+            Ok(Arc::new(DeltaLayer::new(
+                self.conf,
+                self.timeline_id,
+                self.tenant_id,
+                &deltafilename,
+            )))
+        } else {
+            bail!("unexpected layer file name {}", fname);
+        }
+    }
+
+    ///
+    /// Download the file of a remote layer, and replace the layer in the
+    /// layer map with one that reads the downloaded file. Returns the new
+    /// layer.
+    ///
+    fn download_remote_layer(&self, layer: Arc<dyn Layer>) -> Result<Arc<dyn Layer>> {
+        // Only one download at a time, so that two readers that need the
+        // same layer don't both download it.
+        let _download_guard = self.layer_download_lock.lock().unwrap();
+
+        // Another thread might have downloaded the layer while we were
+        // waiting for the lock.
+        let fname = layer.filename();
+        let layer = {
+            let layers = self.layers.read().unwrap();
+            let current = layers
+                .iter_historic_layers()
+                .find(|l| l.filename() == fname)
+                .with_context(|| {
+                    format!(
+                        "remote layer {} was removed from the layer map",
+                        fname.display()
+                    )
+                })?;
+            if !current.is_remote() {
+                return Ok(Arc::clone(current));
+            }
+            Arc::clone(current)
+        };
+
+        let path = layer.local_path().unwrap();
+        if self.keep_evicted_layer_file(&fname) {
+            info!("reusing the file of evicted layer {}", fname.display());
+        } else {
+            info!("downloading remote layer {}", fname.display());
+            storage_sync::download_layer(&path)
+                .with_context(|| format!("failed to download remote layer {}", fname.display()))?;
+        }
+        let new_layer = self.open_layer_file(&fname)?;
+
+        let mut layers = self.layers.write().unwrap();
+        #[allow(clippy::vtable_address_comparisons)]
+        let present = layers
+            .iter_historic_layers()
+            .any(|l| Arc::ptr_eq(l, &layer));
+        if !present {
+            // GC removed the layer while we were downloading it
+            drop(layers);
+            fs::remove_file(&path)?;
+            bail!(
+                "remote layer {} was removed from the layer map",
+                fname.display()
+            );
+        }
+        layers.remove_historic(layer);
+        layers.insert_historic(Arc::clone(&new_layer));
+        Ok(new_layer)
+    }
+
+    ///
+    /// Replace a layer that has been uploaded to the remote storage with a
+    /// RemoteLayer in the layer map, so that the file is downloaded again on
+    /// next access, and remove the local file. Returns false if the layer was
+    /// already removed from the layer map.
+    ///
+    /// Readers that got the layer from the layer map earlier may still be
+    /// using it, and reopen the file. The file is removed only once they're
+    /// done, by this or a later call, or by the next eviction iteration, so
+    /// pass in the last reference the caller holds.
+    ///
+    pub fn evict_layer(&self, layer: Arc<dyn Layer>) -> Result<bool> {
+        let mut layers = self.layers.write().unwrap();
+
+        #[allow(clippy::vtable_address_comparisons)]
+        let present = layers
+            .iter_historic_layers()
+            .any(|l| Arc::ptr_eq(l, &layer));
+        if !present || layer.is_remote() {
+            drop(layers);
+            drop(layer);
+            self.remove_evicted_layer_files()?;
+            return Ok(false);
+        }
+
+        let fname = layer.filename();
+        let fname_str = fname.to_string_lossy();
+        let remote_layer = if let Some(imgfilename) = ImageFileName::parse_str(&fname_str) {
+            RemoteLayer::new_img(self.conf, self.timeline_id, self.tenant_id, &imgfilename)
+        } else if let Some(deltafilename) = DeltaFileName::parse_str(&fname_str) {
+            RemoteLayer::new_delta(self.conf, self.timeline_id, self.tenant_id, &deltafilename)
+        } else {
+            bail!("unexpected layer file name {}", fname_str);
+        };
+
+        layers.remove_historic(Arc::clone(&layer));
+        layers.insert_historic(Arc::new(remote_layer));
+        drop(layers);
+
+        self.evicted_layers.lock().unwrap().push(layer);
+        self.remove_evicted_layer_files()?;
+        Ok(true)
+    }
+
+    ///
+    /// Remove the files of the evicted layers that are no longer in use.
+    ///
+    fn remove_evicted_layer_files(&self) -> Result<()> {
+        let mut evicted_layers = self.evicted_layers.lock().unwrap();
+        // The layers are not in the layer map anymore, so nothing can get a new
+        // reference to them. Once the list holds the only one, the file can go.
+        while let Some(pos) = evicted_layers
+            .iter()
+            .position(|l| Arc::strong_count(l) == 1)
+        {
+            let layer = evicted_layers.swap_remove(pos);
+            fs::remove_file(layer.local_path().unwrap())?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// If the file of an evicted layer has not been removed yet, keep it
+    /// instead. Returns true if the file is still there.
+    ///
+    fn keep_evicted_layer_file(&self, fname: &Path) -> bool {
+        let mut evicted_layers = self.evicted_layers.lock().unwrap();
+        let len_before = evicted_layers.len();
+        evicted_layers.retain(|l| l.filename() != fname);
+        evicted_layers.len() != len_before
+    }
+
+    ///
+    /// Truncate the history of the timeline at 'lsn': remove the layers
+    /// that only contain newer page versions, and rewrite the delta layers
+    /// that cross 'lsn' without them. The in-memory layers are dropped, the
+    /// WAL between the new 'disk_consistent_lsn' and 'lsn' is streamed again
+    /// from the safekeepers.
+    ///
+    /// The new layers are written first, then the remote timeline is
+    /// rewritten, if it's uploaded, then the new metadata is saved, and only
+    /// then the old layer files are removed. If the pageserver stops before
+    /// the metadata is saved, the new layers are moved aside on the next
+    /// load, as they are after 'disk_consistent_lsn'.
+    ///
+    /// Returns the new metadata and the paths of the removed layer files,
+    /// which are left in the remote storage. The timeline object must not be
+    /// used after this.
+    ///
+    fn reset_to(
+        &self,
+        lsn: Lsn,
+        prev_record_lsn: Option<Lsn>,
+    ) -> Result<(TimelineMetadata, HashSet<PathBuf>)> {
+        let _compaction_cs = self.compaction_cs.lock().unwrap();
+        let _write_guard = self.write_lock.lock().unwrap();
+        let _flush_guard = self.layer_flush_lock.lock().unwrap();
+
+        let disk_consistent_lsn = self.disk_consistent_lsn.load();
+        let sync_id = ZTenantTimelineId {
+            tenant_id: self.tenant_id,
+            timeline_id: self.timeline_id,
+        };
+        let upload_layers = self.upload_layers.load(atomic::Ordering::Relaxed);
+        if upload_layers {
+            // Uploads only ever move the remote metadata forward, an upload
+            // that is still in the queue would undo the reset.
+            let remote_lsn = self
+                .remote_index
+                .blocking_read()
+                .timeline_entry(&sync_id)
+                .map(|remote_timeline| remote_timeline.metadata.disk_consistent_lsn());
+            ensure!(
+                remote_lsn == Some(disk_consistent_lsn),
+                "timeline is still being uploaded to the remote storage, retry later"
+            );
+        }
+
+        // The layers that cross the reset point need to be present locally
+        // to be rewritten.
+        let crossing_layers = self
+            .layers
+            .read()
+            .unwrap()
+            .iter_historic_layers()
+            .filter(|l| l.get_lsn_range().start <= lsn && l.get_lsn_range().end > lsn + 1)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut rewritten_layers = Vec::with_capacity(crossing_layers.len());
+        let mut new_layers = Vec::new();
+        for layer in crossing_layers {
+            let layer = if layer.is_remote() {
+                self.download_remote_layer(layer)?
+            } else {
+                layer
+            };
+
+            let key_range = layer.get_key_range();
+            let mut writer: Option<DeltaLayerWriter> = None;
+            for x in layer.iter() {
+                let (key, value_lsn, value) = x?;
+                if value_lsn > lsn {
+                    continue;
+                }
+                if writer.is_none() {
+                    writer = Some(DeltaLayerWriter::new(
+                        self.conf,
+                        self.timeline_id,
+                        self.tenant_id,
+                        key_range.start,
+                        layer.get_lsn_range().start..lsn + 1,
+                        self.get_compression(),
+                    )?);
+                }
+                writer.as_mut().unwrap().put_value(key, value_lsn, value)?;
+            }
+            if let Some(writer) = writer {
+                new_layers.push(writer.finish(key_range.end)?);
+            }
+            rewritten_layers.push(layer.filename());
+        }
+
+        if !new_layers.is_empty() {
+            let mut layer_paths: Vec<PathBuf> = new_layers.iter().map(|l| l.path()).collect();
+            layer_paths.push(self.conf.timeline_path(&self.timeline_id, &self.tenant_id));
+            par_fsync::par_fsync(&layer_paths)?;
+        }
+
+        let layers_to_remove = self
+            .layers
+            .read()
+            .unwrap()
+            .iter_historic_layers()
+            .filter(|l| l.get_lsn_range().start > lsn || rewritten_layers.contains(&l.filename()))
+            .cloned()
+            .collect::<Vec<_>>();
+        let removed_layer_paths = layers_to_remove
+            .iter()
+            .filter_map(|l| l.local_path())
+            .collect::<HashSet<_>>();
+        let new_layer_paths = new_layers.iter().map(|l| l.path()).collect::<HashSet<_>>();
+
+        // The layer files only have the history up to 'disk_consistent_lsn'.
+        // If the reset point is after it, the rest is ingested again.
+        let new_disk_consistent_lsn = min(disk_consistent_lsn, lsn);
+        let RecordLsn {
+            last: last_record_lsn,
+            prev: last_prev_record_lsn,
+        } = self.last_record_lsn.load();
+        let ondisk_prev_record_lsn = if new_disk_consistent_lsn == last_record_lsn {
+            Some(last_prev_record_lsn)
+        } else if new_disk_consistent_lsn == lsn {
+            prev_record_lsn
+        } else {
+            None
+        };
+        let metadata = TimelineMetadata::new(
+            new_disk_consistent_lsn,
+            ondisk_prev_record_lsn,
+            self.ancestor_timeline
+                .as_ref()
+                .map(LayeredTimelineEntry::timeline_id),
+            self.ancestor_lsn,
+            *self.latest_gc_cutoff_lsn.read().unwrap(),
+            self.initdb_lsn,
+        );
+        if upload_layers {
+            storage_sync::rewrite_remote_timeline(
+                self.conf,
+                &self.remote_index,
+                sync_id,
+                &removed_layer_paths,
+                &new_layer_paths,
+                &metadata,
+            )
+            .context("failed to rewrite the remote timeline")?;
+        }
+        LayeredRepository::save_metadata(
+            self.conf,
+            self.timeline_id,
+            self.tenant_id,
+            &metadata,
+            false,
+        )?;
+
+        let mut layers = self.layers.write().unwrap();
+        layers.open_layer = None;
+        layers.frozen_layers.clear();
+        for l in layers_to_remove {
+            // The new metadata is saved already, a leftover file is moved
+            // aside on the next load.
+            if let Err(e) = l.delete() {
+                warn!(
+                    "failed to remove layer file {}: {:#}",
+                    l.filename().display(),
+                    e
+                );
+            }
+            layers.remove_historic(l);
+        }
+        for l in new_layers {
+            layers.insert_historic(Arc::new(l));
+        }
+        drop(layers);
+
+        Ok((metadata, removed_layer_paths))
+    }
+
+    ///
+    /// Make the timeline independent of its ancestor: create image layers at
+    /// the branch point for the given 'keyspace', which must cover all the
+    /// keys in use at 'ancestor_lsn', and save metadata without the ancestor.
+    /// The history before the branch point becomes unavailable, like after
+    /// GC, so the GC cutoff is moved to the branch point.
+    ///
+    /// If the timeline is uploaded, the remote timeline is rewritten before
+    /// the new metadata is saved locally.
+    ///
+    /// Returns the new metadata. The timeline object must not be used after
+    /// this.
+    ///
+    fn detach_from_ancestor(&self, keyspace: &KeySpace) -> Result<TimelineMetadata> {
+        let _compaction_cs = self.compaction_cs.lock().unwrap();
+
+        let ancestor_lsn = self.ancestor_lsn;
+        let RecordLsn {
+            last: last_record_lsn,
+            prev: prev_record_lsn,
+        } = self.last_record_lsn.load();
+        // Computes are started at the branch point with no previous record,
+        // which is only allowed at the ancestor LSN.
+        ensure!(
+            last_record_lsn != ancestor_lsn || prev_record_lsn != Lsn(0),
+            "timeline has no WAL after its branch point {}",
+            ancestor_lsn
+        );
+
+        let mut new_layer_paths = HashSet::new();
+        for part in keyspace
+            .partition(self.get_compaction_target_size())
+            .parts
+            .iter()
+        {
+            new_layer_paths.insert(self.create_image_layer(part, ancestor_lsn)?);
+        }
+
+        let _write_guard = self.write_lock.lock().unwrap();
+        let _flush_guard = self.layer_flush_lock.lock().unwrap();
+
+        let disk_consistent_lsn = self.disk_consistent_lsn.load();
+        let RecordLsn {
+            last: last_record_lsn,
+            prev: prev_record_lsn,
+        } = self.last_record_lsn.load();
+        let ondisk_prev_record_lsn = if disk_consistent_lsn == last_record_lsn {
+            Some(prev_record_lsn)
+        } else {
+            None
+        };
+        let metadata = TimelineMetadata::new(
+            disk_consistent_lsn,
+            ondisk_prev_record_lsn,
+            None,
+            Lsn(0),
+            max(*self.latest_gc_cutoff_lsn.read().unwrap(), ancestor_lsn),
+            self.initdb_lsn,
+        );
+        if self.upload_layers.load(atomic::Ordering::Relaxed) {
+            storage_sync::rewrite_remote_timeline(
+                self.conf,
+                &self.remote_index,
+                ZTenantTimelineId {
+                    tenant_id: self.tenant_id,
+                    timeline_id: self.timeline_id,
+                },
+                &HashSet::new(),
+                &new_layer_paths,
+                &metadata,
+            )
+            .context("failed to rewrite the remote timeline")?;
+        }
+        LayeredRepository::save_metadata(
+            self.conf,
+            self.timeline_id,
+            self.tenant_id,
+            &metadata,
+            false,
+        )?;
+
+        Ok(metadata)
+    }
+
     /// Open a Timeline handle.
     ///
     /// Loads the metadata for the timeline into memory, but not the layer map.
@@ -1178,6 +2398,7 @@ impl LayeredTimeline {
         timeline_id: ZTimelineId,
         tenant_id: ZTenantId,
         walredo_mgr: Arc<dyn WalRedoManager + Send + Sync>,
+        remote_index: RemoteIndex,
         upload_layers: bool,
     ) -> LayeredTimeline {
         let reconstruct_time_histo = RECONSTRUCT_TIME
@@ -1217,6 +2438,8 @@ impl LayeredTimeline {
         LayeredTimeline {
             conf,
             tenant_conf,
+            timeline_conf: RwLock::new(TimelineConfOpt::default()),
+            restore_points: Mutex::new(Vec::new()),
             timeline_id,
             tenant_id,
             layers: RwLock::new(LayerMap::default()),
@@ -1247,6 +2470,9 @@ impl LayeredTimeline {
 
             write_lock: Mutex::new(()),
             layer_flush_lock: Mutex::new(()),
+            layer_download_lock: Mutex::new(()),
+            evicted_layers: Mutex::new(Vec::new()),
+            remote_index,
             compaction_cs: Mutex::new(()),
 
             gc_info: RwLock::new(GcInfo {
@@ -1265,6 +2491,10 @@ impl LayeredTimeline {
     /// Returns all timeline-related files that were found and loaded.
     ///
     fn load_layer_map(&self, disk_consistent_lsn: Lsn) -> anyhow::Result<()> {
+        // The layer files in the remote storage that are not present locally
+        // are added as remote layers, and downloaded when they're needed.
+        let mut remote_files = self.list_remote_layer_files();
+
         let mut layers = self.layers.write().unwrap();
         let mut num_layers = 0;
 
@@ -1276,6 +2506,7 @@ impl LayeredTimeline {
             let direntry = direntry?;
             let fname = direntry.file_name();
             let fname = fname.to_string_lossy();
+            remote_files.remove(&direntry.path());
 
             if let Some(imgfilename) = ImageFileName::parse_str(&fname) {
                 // create an ImageLayer struct for each image file.
@@ -1318,8 +2549,20 @@ impl LayeredTimeline {
                 trace!("found layer {}", layer.filename().display());
                 layers.insert_historic(Arc::new(layer));
                 num_layers += 1;
-            } else if fname == METADATA_FILE_NAME || fname.ends_with(".old") {
+            } else if fname == METADATA_FILE_NAME
+                || fname == TIMELINE_CONFIG_NAME
+                || fname == RESTORE_POINTS_FILE_NAME
+                || fname.ends_with(".old")
+            {
                 // ignore these
+            } else if fname == format!("{TIMELINE_CONFIG_NAME}.temp")
+                || fname == format!("{RESTORE_POINTS_FILE_NAME}.temp")
+            {
+                // Left behind by a crash while the timeline config or the
+                // restore points were being persisted, the files themselves
+                // are intact
+                trace!("deleting leftover temp file in timeline dir: {}", fname);
+                fs::remove_file(direntry.path())?;
             } else if is_ephemeral_file(&fname) {
                 // Delete any old ephemeral files
                 trace!("deleting old ephemeral file in timeline dir: {}", fname);
@@ -1329,16 +2572,64 @@ impl LayeredTimeline {
             }
         }
 
+        let mut num_remote_layers = 0;
+        for path in remote_files {
+            let fname = path.file_name().unwrap().to_string_lossy();
+            let layer = if let Some(imgfilename) = ImageFileName::parse_str(&fname) {
+                if imgfilename.lsn > disk_consistent_lsn {
+                    warn!(
+                        "found future remote image layer {} on timeline {} disk_consistent_lsn is {}",
+                        imgfilename, self.timeline_id, disk_consistent_lsn
+                    );
+                    continue;
+                }
+                RemoteLayer::new_img(self.conf, self.timeline_id, self.tenant_id, &imgfilename)
+            } else if let Some(deltafilename) = DeltaFileName::parse_str(&fname) {
+                if deltafilename.lsn_range.end > disk_consistent_lsn + 1 {
+                    warn!(
+                        "found future remote delta layer {} on timeline {} disk_consistent_lsn is {}",
+                        deltafilename, self.timeline_id, disk_consistent_lsn
+                    );
+                    continue;
+                }
+                RemoteLayer::new_delta(self.conf, self.timeline_id, self.tenant_id, &deltafilename)
+            } else {
+                warn!("unrecognized remote layer file name: {}", fname);
+                continue;
+            };
+
+            trace!("found remote layer {}", layer.filename().display());
+            layers.insert_historic(Arc::new(layer));
+            num_remote_layers += 1;
+        }
+
         layers.next_open_layer_at = Some(Lsn(disk_consistent_lsn.0) + 1);
 
         info!(
-            "loaded layer map with {} layers at {}",
-            num_layers, disk_consistent_lsn
+            "loaded layer map with {} layers ({} remote) at {}",
+            num_layers + num_remote_layers,
+            num_remote_layers,
+            disk_consistent_lsn
         );
 
         Ok(())
     }
 
+    /// List the layer files of the timeline in the remote storage. Like
+    /// [`storage_sync::download_layer`], this must not be called on a runtime
+    /// worker thread.
+    fn list_remote_layer_files(&self) -> HashSet<PathBuf> {
+        let sync_id = ZTenantTimelineId {
+            tenant_id: self.tenant_id,
+            timeline_id: self.timeline_id,
+        };
+        self.remote_index
+            .blocking_read()
+            .timeline_entry(&sync_id)
+            .map(|remote_timeline| remote_timeline.stored_files().clone())
+            .unwrap_or_default()
+    }
+
     ///
     /// Get a handle to a Layer for reading.
     ///
@@ -1464,6 +2755,15 @@ impl LayeredTimeline {
             if let Some(SearchResult { lsn_floor, layer }) = layers.search(key, cont_lsn)? {
                 //info!("CHECKING for {} at {} on historic layer {}", key, cont_lsn, layer.filename().display());
 
+                // Download the layer file if it's not present locally. The
+                // downloaded layer covers the same key and LSN range.
+                let layer = if layer.is_remote() {
+                    drop(layers);
+                    timeline.download_remote_layer(layer)?
+                } else {
+                    layer
+                };
+
                 let lsn_floor = max(cached_lsn + 1, lsn_floor);
                 result = layer.get_value_reconstruct_data(
                     key,
@@ -1636,7 +2936,10 @@ impl LayeredTimeline {
                     Some(self.timeline_id),
                     "layer flush thread",
                     false,
-                    move || self_clone.flush_frozen_layers(false),
+                    move || {
+                        virtual_file::set_background_io();
+                        self_clone.flush_frozen_layers(false)
+                    },
                 )?;
             }
         }
@@ -1690,7 +2993,7 @@ impl LayeredTimeline {
 
     /// Flush one frozen in-memory layer to disk, as a new delta layer.
     fn flush_frozen_layer(&self, frozen_layer: Arc<InMemoryLayer>) -> Result<()> {
-        let new_delta = frozen_layer.write_to_disk()?;
+        let new_delta = frozen_layer.write_to_disk(self.get_compression())?;
         let new_delta_path = new_delta.path();
 
         // Sync the new layer to disk.
@@ -1831,6 +3134,9 @@ impl LayeredTimeline {
         // collect any page versions that are no longer needed because
         // of the new image layers we created in step 2.
         //
+        // 4. With the tiered compaction strategy, merge runs of level1
+        // delta files into larger runs, see compact_tiered().
+        //
         // TODO: This high level strategy hasn't been implemented yet.
         // Below are functions compact_level0() and create_image_layers()
         // but they are a bit ad hoc and don't quite work like it's explained
@@ -1870,6 +3176,9 @@ impl LayeredTimeline {
             // 3. Compact
             let timer = self.compact_time_histo.start_timer();
             self.compact_level0(target_file_size)?;
+            if self.get_compaction_strategy() == CompactionStrategy::Tiered {
+                self.compact_tiered(&partitioning, target_file_size)?;
+            }
             timer.stop_and_record();
         } else {
             debug!("Could not compact because no partitioning specified yet");
@@ -1909,13 +3218,22 @@ impl LayeredTimeline {
     fn create_image_layer(&self, partition: &KeySpace, lsn: Lsn) -> anyhow::Result<PathBuf> {
         let img_range =
             partition.ranges.first().unwrap().start..partition.ranges.last().unwrap().end;
-        let mut image_layer_writer =
-            ImageLayerWriter::new(self.conf, self.timeline_id, self.tenant_id, &img_range, lsn)?;
+        let mut image_layer_writer = ImageLayerWriter::new(
+            self.conf,
+            self.timeline_id,
+            self.tenant_id,
+            &img_range,
+            lsn,
+            self.get_compression(),
+        )?;
 
         for range in &partition.ranges {
             let mut key = range.start;
             while key < range.end {
                 let img = self.get(key, lsn)?;
+                // The reads were done under the layer map lock, wait for the
+                // I/O budget only now that it's released
+                virtual_file::pay_io_debt();
                 image_layer_writer.put_image(key, &img)?;
                 key = key.next();
             }
@@ -2007,10 +3325,122 @@ impl LayeredTimeline {
         // we don't accidentally use it later in the function.
         drop(level0_deltas);
 
-        // This iterator walks through all key-value pairs from all the layers
-        // we're compacting, in key, LSN order.
-        let all_values_iter = deltas_to_compact
-            .iter()
+        self.merge_delta_layers(deltas_to_compact, lsn_range, target_file_size, &[])
+    }
+
+    ///
+    /// Merge runs of Level 1 delta layers into larger runs.
+    ///
+    /// A run is the set of Level 1 delta layers with the same LSN range, as
+    /// produced by one compaction. A lookup has to visit one layer from each
+    /// run above the latest image of the page, so the number of runs is what
+    /// drives read amplification on a large timeline.
+    ///
+    /// Each run belongs to a size tier, based on how much WAL it covers, see
+    /// compaction_tier(). When 'compaction_tier_ratio' adjacent runs are in
+    /// the same tier, they are merged into one run of the next tier. That
+    /// keeps the number of runs logarithmic in the amount of WAL, while each
+    /// page version is rewritten only once per tier.
+    ///
+    fn compact_tiered(&self, partitioning: &KeyPartitioning, target_file_size: u64) -> Result<()> {
+        let ratio = self.get_compaction_tier_ratio();
+        let base = self.get_checkpoint_distance();
+
+        // Group the Level 1 delta layers into runs. Remember the LSN ranges
+        // of the Level 0 layers too, they must not overlap with a merge.
+        let layers = self.layers.read().unwrap();
+        let mut runs: BTreeMap<(Lsn, Lsn), Vec<Arc<dyn Layer>>> = BTreeMap::new();
+        let mut level0_lsn_ranges = Vec::new();
+        for l in layers.iter_historic_layers() {
+            if !l.is_incremental() {
+                continue;
+            }
+            let lsn_range = l.get_lsn_range();
+            if l.get_key_range() == (Key::MIN..Key::MAX) {
+                level0_lsn_ranges.push(lsn_range);
+            } else {
+                runs.entry((lsn_range.start, lsn_range.end))
+                    .or_default()
+                    .push(Arc::clone(l));
+            }
+        }
+        drop(layers);
+
+        let run_lsn_ranges: Vec<Range<Lsn>> =
+            runs.keys().map(|(start, end)| *start..*end).collect();
+        let runs_to_merge =
+            match find_runs_to_merge(&run_lsn_ranges, &level0_lsn_ranges, base, ratio) {
+                Some(runs_to_merge) => runs_to_merge,
+                None => return Ok(()),
+            };
+        let lsn_range = Range {
+            start: run_lsn_ranges[runs_to_merge.start].start,
+            end: run_lsn_ranges[runs_to_merge.end - 1].end,
+        };
+        let deltas_to_compact: Vec<Arc<dyn Layer>> = runs
+            .into_values()
+            .skip(runs_to_merge.start)
+            .take(runs_to_merge.len())
+            .flatten()
+            .collect();
+
+        info!(
+            "Starting tiered compaction in LSN range {}-{} for {} runs in tier {} ({} layers)",
+            lsn_range.start,
+            lsn_range.end,
+            runs_to_merge.len(),
+            compaction_tier(&run_lsn_ranges[runs_to_merge.start], base, ratio),
+            deltas_to_compact.len()
+        );
+
+        // Cut the new layers at the partition boundaries, so that a new
+        // image layer over a partition makes whole delta layers obsolete.
+        let key_boundaries: Vec<Key> = partitioning
+            .parts
+            .iter()
+            .filter_map(|part| part.ranges.first())
+            .map(|range| range.start)
+            .collect();
+
+        self.merge_delta_layers(
+            deltas_to_compact,
+            lsn_range,
+            target_file_size,
+            &key_boundaries,
+        )
+    }
+
+    ///
+    /// Merge the given delta layers into a new set of delta layers covering
+    /// 'lsn_range', and replace the old layers with them.
+    ///
+    /// The new layers are cut when they grow larger than 'target_file_size',
+    /// and also at each of the 'key_boundaries', so that they line up with
+    /// the key partitioning used for image layers.
+    ///
+    fn merge_delta_layers(
+        &self,
+        deltas_to_compact: Vec<Arc<dyn Layer>>,
+        lsn_range: Range<Lsn>,
+        target_file_size: u64,
+        key_boundaries: &[Key],
+    ) -> Result<()> {
+        // The layers need to be present locally to be read.
+        let deltas_to_compact = deltas_to_compact
+            .into_iter()
+            .map(|l| {
+                if l.is_remote() {
+                    self.download_remote_layer(l)
+                } else {
+                    Ok(l)
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // This iterator walks through all key-value pairs from all the layers
+        // we're compacting, in key, LSN order.
+        let all_values_iter = deltas_to_compact
+            .iter()
             .map(|l| l.iter())
             .kmerge_by(|a, b| {
                 if let Ok((a_key, a_lsn, _)) = a {
@@ -2029,23 +3459,28 @@ impl LayeredTimeline {
             });
 
         // Merge the contents of all the input delta layers into a new set
-        // of delta layers, based on the current partitioning.
-        //
-        // TODO: this actually divides the layers into fixed-size chunks, not
-        // based on the partitioning.
+        // of delta layers. The layers are divided into fixed-size chunks,
+        // and also at the given key boundaries.
         //
         // TODO: we should also opportunistically materialize and
         // garbage collect what we can.
         let mut new_layers = Vec::new();
         let mut prev_key: Option<Key> = None;
         let mut writer: Option<DeltaLayerWriter> = None;
+        let mut next_boundary = key_boundaries.iter().peekable();
         for x in all_values_iter {
             let (key, lsn, value) = x?;
 
+            // Did we cross a key boundary since the previous key?
+            let mut crossed_boundary = false;
+            while next_boundary.next_if(|b| **b <= key).is_some() {
+                crossed_boundary = true;
+            }
+
             if let Some(prev_key) = prev_key {
                 if key != prev_key && writer.is_some() {
                     let size = writer.as_mut().unwrap().size();
-                    if size > target_file_size {
+                    if size > target_file_size || crossed_boundary {
                         new_layers.push(writer.take().unwrap().finish(prev_key.next())?);
                         writer = None;
                     }
@@ -2059,6 +3494,7 @@ impl LayeredTimeline {
                     self.tenant_id,
                     key,
                     lsn_range.clone(),
+                    self.get_compression(),
                 )?);
             }
 
@@ -2156,7 +3592,10 @@ impl LayeredTimeline {
     /// within a layer file. We can only remove the whole file if it's fully
     /// obsolete.
     ///
-    fn gc(&self) -> Result<GcResult> {
+    /// With `dry_run`, nothing is removed and the GC cutoff is not moved.
+    /// Instead, the decision about every layer is reported in `GcResult::layers`.
+    ///
+    fn gc(&self, dry_run: bool) -> Result<GcResult> {
         let now = SystemTime::now();
         let mut result: GcResult = Default::default();
         let disk_consistent_lsn = self.get_disk_consistent_lsn();
@@ -2190,6 +3629,9 @@ impl LayeredTimeline {
                     LsnForTimestamp::Past(lsn) => {
                         debug!("past({})", lsn);
                     }
+                    LsnForTimestamp::NoData(lsn) => {
+                        debug!("nodata({})", lsn);
+                    }
                 }
                 debug!("pitr_cutoff_lsn = {:?}", pitr_cutoff_lsn)
             }
@@ -2201,8 +3643,8 @@ impl LayeredTimeline {
 
         let new_gc_cutoff = Lsn::min(cutoff, pitr_cutoff_lsn);
 
-        // Nothing to GC. Return early.
-        if *self.get_latest_gc_cutoff_lsn() >= new_gc_cutoff {
+        // Nothing to GC. Return early. A dry run still reports the layers.
+        if !dry_run && *self.get_latest_gc_cutoff_lsn() >= new_gc_cutoff {
             info!(
                 "Nothing to GC for timeline {}. cutoff_lsn {}",
                 self.timeline_id, new_gc_cutoff
@@ -2215,7 +3657,9 @@ impl LayeredTimeline {
 
         // We need to ensure that no one branches at a point before latest_gc_cutoff_lsn.
         // See branch_timeline() for details.
-        *self.latest_gc_cutoff_lsn.write().unwrap() = new_gc_cutoff;
+        if !dry_run {
+            *self.latest_gc_cutoff_lsn.write().unwrap() = new_gc_cutoff;
+        }
 
         info!("GC starting");
 
@@ -2253,6 +3697,11 @@ impl LayeredTimeline {
                     cutoff
                 );
                 result.layers_needed_by_cutoff += 1;
+                if dry_run {
+                    result
+                        .layers
+                        .push(self.gc_layer_info(l, Some(GcKeepReason::Horizon)));
+                }
                 continue 'outer;
             }
 
@@ -2264,6 +3713,11 @@ impl LayeredTimeline {
                     pitr_cutoff_lsn
                 );
                 result.layers_needed_by_pitr += 1;
+                if dry_run {
+                    result
+                        .layers
+                        .push(self.gc_layer_info(l, Some(GcKeepReason::Pitr)));
+                }
                 continue 'outer;
             }
 
@@ -2282,6 +3736,11 @@ impl LayeredTimeline {
                         l.is_incremental(),
                     );
                     result.layers_needed_by_branches += 1;
+                    if dry_run {
+                        result
+                            .layers
+                            .push(self.gc_layer_info(l, Some(GcKeepReason::BranchPoint)));
+                    }
                     continue 'outer;
                 }
             }
@@ -2313,6 +3772,15 @@ impl LayeredTimeline {
                     l.filename().display()
                 );
                 result.layers_not_updated += 1;
+                if dry_run {
+                    let reason =
+                        if layers.newer_layer_exists(&l.get_key_range(), l.get_lsn_range().end) {
+                            GcKeepReason::NewerImageMissing
+                        } else {
+                            GcKeepReason::Latest
+                        };
+                    result.layers.push(self.gc_layer_info(l, Some(reason)));
+                }
                 continue 'outer;
             }
 
@@ -2322,9 +3790,21 @@ impl LayeredTimeline {
                 l.filename().display(),
                 l.is_incremental(),
             );
+            if dry_run {
+                result.layers.push(self.gc_layer_info(l, None));
+            }
             layers_to_remove.push(Arc::clone(l));
         }
 
+        if dry_run {
+            info!(
+                "GC dry run done, {} layers would be removed",
+                layers_to_remove.len()
+            );
+            result.elapsed = now.elapsed()?;
+            return Ok(result);
+        }
+
         // Actually delete the layers from disk and remove them from the map.
         // (couldn't do this in the loop above, because you cannot modify a collection
         // while iterating it. BTreeMap::retain() would be another option)
@@ -2350,6 +3830,18 @@ impl LayeredTimeline {
         Ok(result)
     }
 
+    fn gc_layer_info(
+        &self,
+        layer: &Arc<dyn Layer>,
+        keep_reason: Option<GcKeepReason>,
+    ) -> GcLayerInfo {
+        GcLayerInfo {
+            timeline_id: self.timeline_id,
+            layer_file_name: layer.filename().display().to_string(),
+            keep_reason,
+        }
+    }
+
     ///
     /// Reconstruct a value, using the given base image and WAL records in 'data'.
     ///
@@ -2449,6 +3941,60 @@ fn layer_traversal_error(
     Err(msg_iter.fold(err, |err, msg| err.context(msg)))
 }
 
+/// Size tier of a run of delta layers covering 'lsn_range', for tiered
+/// compaction. Tier N covers at least 'base' * 'ratio'^N bytes of WAL, and
+/// less than 'base' * 'ratio'^(N+1).
+fn compaction_tier(lsn_range: &Range<Lsn>, base: u64, ratio: usize) -> u32 {
+    let wal_size = lsn_range.end.0 - lsn_range.start.0;
+    let ratio = max(ratio, 2) as u64;
+    let mut limit = max(base, 1).saturating_mul(ratio);
+    let mut tier = 0;
+    while wal_size >= limit && limit != u64::MAX {
+        tier += 1;
+        limit = limit.saturating_mul(ratio);
+    }
+    tier
+}
+
+/// Helper function for compact_tiered() to find the oldest sequence of
+/// 'ratio' adjacent runs in the same tier. 'runs' are the LSN ranges of the
+/// runs in LSN order, and 'others' the LSN ranges of any other delta layers.
+/// The runs must follow each other without gaps, and nothing else may
+/// overlap with the merged LSN range.
+///
+/// Returns the indexes of the runs to merge.
+fn find_runs_to_merge(
+    runs: &[Range<Lsn>],
+    others: &[Range<Lsn>],
+    base: u64,
+    ratio: usize,
+) -> Option<Range<usize>> {
+    if ratio < 2 || runs.len() < ratio {
+        return None;
+    }
+    (0..=runs.len() - ratio)
+        .map(|i| i..i + ratio)
+        .find(|candidate| {
+            let candidate_runs = &runs[candidate.clone()];
+            let tier = compaction_tier(&candidate_runs[0], base, ratio);
+            if !candidate_runs
+                .iter()
+                .all(|r| compaction_tier(r, base, ratio) == tier)
+            {
+                return false;
+            }
+            if !candidate_runs.windows(2).all(|w| w[0].end == w[1].start) {
+                return false;
+            }
+            let merged = candidate_runs[0].start..candidate_runs[ratio - 1].end;
+            !runs[..candidate.start]
+                .iter()
+                .chain(&runs[candidate.end..])
+                .chain(others)
+                .any(|r| range_overlaps(r, &merged))
+        })
+}
+
 struct LayeredTimelineWriter<'a> {
     tl: &'a LayeredTimeline,
     _write_guard: MutexGuard<'a, ()>,
@@ -2550,6 +4096,7 @@ pub mod tests {
     use super::*;
     use crate::keyspace::KeySpaceAccum;
     use crate::repository::repo_harness::*;
+    use postgres_ffi::pg_constants;
     use rand::{thread_rng, Rng};
 
     #[test]
@@ -2590,6 +4137,526 @@ pub mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_timeline_config() -> Result<()> {
+        let harness = RepoHarness::create("test_timeline_config")?;
+        let timeline_conf = TimelineConfOpt {
+            gc_horizon: Some(0x1000),
+            pitr_interval: Some(Duration::ZERO),
+        };
+
+        #[allow(non_snake_case)]
+        let TEST_KEY: Key = Key::from_hex("112222222233333333444444445500000001").unwrap();
+
+        {
+            let repo = harness.load();
+            let tline = repo.create_empty_timeline(TIMELINE_ID, Lsn(0))?;
+            assert_eq!(tline.get_timeline_conf(), TimelineConfOpt::default());
+            assert_eq!(tline.get_gc_horizon(), repo.get_gc_horizon());
+
+            for lsn in [Lsn(0x10), Lsn(0x20)] {
+                let writer = tline.writer();
+                writer.put(
+                    TEST_KEY,
+                    lsn,
+                    Value::Image(TEST_IMG(&format!("foo at {lsn}"))),
+                )?;
+                writer.finish_write(lsn);
+                drop(writer);
+                tline.checkpoint(CheckpointConfig::Forced)?;
+            }
+
+            tline.set_timeline_conf(timeline_conf)?;
+            assert_eq!(tline.get_gc_horizon(), 0x1000);
+            assert_eq!(tline.get_pitr_interval(), Duration::ZERO);
+
+            // Without an explicit horizon, GC uses the timeline's, which
+            // keeps all the layers
+            let result = repo.gc_iteration(Some(TIMELINE_ID), None, Duration::ZERO, false, true)?;
+            assert!(!result.layers.is_empty());
+            assert!(result
+                .layers
+                .iter()
+                .all(|l| l.keep_reason == Some(GcKeepReason::Horizon)));
+
+            // An explicit horizon takes precedence over the timeline's
+            let result =
+                repo.gc_iteration(Some(TIMELINE_ID), Some(0), Duration::ZERO, false, true)?;
+            assert!(result
+                .layers
+                .iter()
+                .any(|l| l.keep_reason != Some(GcKeepReason::Horizon)));
+        }
+
+        // The overrides survive a restart
+        let repo = harness.load();
+        let tline = repo.get_timeline_load(TIMELINE_ID)?;
+        assert_eq!(tline.get_timeline_conf(), timeline_conf);
+
+        // Without overrides, the tenant config applies again
+        tline.set_timeline_conf(TimelineConfOpt::default())?;
+        assert_eq!(tline.get_gc_horizon(), repo.get_gc_horizon());
+        drop(tline);
+        drop(repo);
+        let repo = harness.load();
+        let tline = repo.get_timeline_load(TIMELINE_ID)?;
+        assert_eq!(tline.get_timeline_conf(), TimelineConfOpt::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_point() -> Result<()> {
+        let harness = RepoHarness::create("test_restore_point")?;
+
+        #[allow(non_snake_case)]
+        let TEST_KEY: Key = Key::from_hex("112222222233333333444444445500000001").unwrap();
+
+        {
+            let repo = harness.load();
+            let tline = repo.create_empty_timeline(TIMELINE_ID, Lsn(0))?;
+            for lsn in [Lsn(0x10), Lsn(0x20), Lsn(0x30)] {
+                let writer = tline.writer();
+                writer.put(
+                    TEST_KEY,
+                    lsn,
+                    Value::Image(TEST_IMG(&format!("foo at {lsn}"))),
+                )?;
+                writer.finish_write(lsn);
+                drop(writer);
+                tline.checkpoint(CheckpointConfig::Forced)?;
+            }
+
+            let restore_point =
+                repo.create_restore_point(TIMELINE_ID, "before-0x20", Some(Lsn(0x10)))?;
+            assert_eq!(restore_point.lsn, Lsn(0x10));
+            assert_eq!(tline.get_restore_points(), vec![restore_point]);
+
+            // Names are unique and safe to use in URLs, and the LSN must exist
+            assert!(repo
+                .create_restore_point(TIMELINE_ID, "before-0x20", None)
+                .is_err());
+            assert!(repo.create_restore_point(TIMELINE_ID, "a/b", None).is_err());
+            assert!(repo
+                .create_restore_point(TIMELINE_ID, "future", Some(Lsn(0x40)))
+                .is_err());
+
+            // GC moves the cutoff past the restore point, but it's still
+            // possible to branch from it, and only from it
+            repo.gc_iteration(Some(TIMELINE_ID), Some(0), Duration::ZERO, true, false)?;
+            assert!(*tline.get_latest_gc_cutoff_lsn() > Lsn(0x20));
+            assert!(repo
+                .branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Lsn(0x20))
+                .is_err());
+            repo.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Lsn(0x10))?;
+            let new_tline = repo.get_timeline_load(NEW_TIMELINE_ID)?;
+            assert_eq!(new_tline.get(TEST_KEY, Lsn(0x10))?, TEST_IMG("foo at 0/10"));
+        }
+
+        // The restore points survive a restart
+        let repo = harness.load();
+        let tline = repo.get_timeline_load(TIMELINE_ID)?;
+        assert_eq!(tline.get_restore_points().len(), 1);
+        assert_eq!(
+            repo.delete_restore_point(TIMELINE_ID, "before-0x20")?
+                .map(|rp| rp.lsn),
+            Some(Lsn(0x10))
+        );
+        assert!(repo
+            .delete_restore_point(TIMELINE_ID, "before-0x20")?
+            .is_none());
+        assert!(tline.get_restore_points().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reset_timeline() -> Result<()> {
+        let harness = RepoHarness::create("test_reset_timeline")?;
+
+        #[allow(non_snake_case)]
+        let TEST_KEY: Key = Key::from_hex("112222222233333333444444445500000001").unwrap();
+
+        {
+            let repo = harness.load();
+            let tline = repo.create_empty_timeline(TIMELINE_ID, Lsn(0))?;
+            // The second layer file crosses the reset point
+            for lsns in [vec![Lsn(0x10)], vec![Lsn(0x20), Lsn(0x28)], vec![Lsn(0x30)]] {
+                let writer = tline.writer();
+                for lsn in lsns {
+                    writer.put(
+                        TEST_KEY,
+                        lsn,
+                        Value::Image(TEST_IMG(&format!("foo at {lsn}"))),
+                    )?;
+                    writer.finish_write(lsn);
+                }
+                drop(writer);
+                tline.checkpoint(CheckpointConfig::Forced)?;
+            }
+
+            // The LSN must be within the timeline history, and the history
+            // after it must not be in use
+            assert!(repo.reset_timeline(TIMELINE_ID, Lsn(0x40), None).is_err());
+            assert!(repo
+                .reset_timeline(TIMELINE_ID, Lsn(0x20), None)
+                .unwrap_err()
+                .to_string()
+                .contains("previous record LSN is required"));
+            repo.create_restore_point(TIMELINE_ID, "after-0x20", Some(Lsn(0x28)))?;
+            assert!(repo
+                .reset_timeline(TIMELINE_ID, Lsn(0x20), Some(Lsn(0x18)))
+                .is_err());
+            repo.delete_restore_point(TIMELINE_ID, "after-0x20")?;
+            repo.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Lsn(0x30))?;
+            assert!(repo
+                .reset_timeline(TIMELINE_ID, Lsn(0x20), Some(Lsn(0x18)))
+                .is_err());
+            repo.detach_timeline(NEW_TIMELINE_ID)?;
+
+            drop(tline);
+            repo.reset_timeline(TIMELINE_ID, Lsn(0x20), Some(Lsn(0x18)))?;
+            let tline = repo.get_timeline_load(TIMELINE_ID)?;
+            assert_eq!(tline.get_last_record_lsn(), Lsn(0x20));
+            assert_eq!(tline.get_prev_record_lsn(), Lsn(0x18));
+            assert_eq!(tline.get(TEST_KEY, Lsn(0x20))?, TEST_IMG("foo at 0/20"));
+            assert_eq!(tline.get(TEST_KEY, Lsn(0x10))?, TEST_IMG("foo at 0/10"));
+
+            // New WAL is ingested on top of the reset point
+            let writer = tline.writer();
+            writer.put(TEST_KEY, Lsn(0x30), Value::Image(TEST_IMG("bar at 0/30")))?;
+            writer.finish_write(Lsn(0x30));
+            drop(writer);
+            tline.checkpoint(CheckpointConfig::Forced)?;
+        }
+
+        // The removed history doesn't come back after a restart
+        let repo = harness.load();
+        let tline = repo.get_timeline_load(TIMELINE_ID)?;
+        assert_eq!(tline.get_last_record_lsn(), Lsn(0x30));
+        assert_eq!(tline.get(TEST_KEY, Lsn(0x30))?, TEST_IMG("bar at 0/30"));
+        assert_eq!(tline.get(TEST_KEY, Lsn(0x2f))?, TEST_IMG("foo at 0/20"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detach_ancestor() -> Result<()> {
+        let harness = RepoHarness::create("test_detach_ancestor")?;
+
+        #[allow(non_snake_case)]
+        let TEST_KEY: Key = Key::from_hex("112222222233333333444444445500000001").unwrap();
+        #[allow(non_snake_case)]
+        let TEST_KEY2: Key = Key::from_hex("112222222233333333444444445500000002").unwrap();
+
+        {
+            let repo = harness.load();
+            let tline = repo.create_empty_timeline(TIMELINE_ID, Lsn(0))?;
+            for lsn in [Lsn(0x10), Lsn(0x20)] {
+                let writer = tline.writer();
+                writer.put(
+                    TEST_KEY,
+                    lsn,
+                    Value::Image(TEST_IMG(&format!("foo at {lsn}"))),
+                )?;
+                writer.finish_write(lsn);
+                drop(writer);
+                tline.checkpoint(CheckpointConfig::Forced)?;
+            }
+
+            repo.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Lsn(0x20))?;
+            let new_tline = repo.get_timeline_load(NEW_TIMELINE_ID)?;
+            let writer = new_tline.writer();
+            writer.put(TEST_KEY2, Lsn(0x30), Value::Image(TEST_IMG("bar at 0/30")))?;
+            writer.finish_write(Lsn(0x30));
+            drop(writer);
+            new_tline.checkpoint(CheckpointConfig::Forced)?;
+            drop(new_tline);
+
+            let mut keyspace = KeySpaceAccum::new();
+            keyspace.add_key(TEST_KEY);
+            let keyspace = keyspace.to_keyspace();
+
+            // Only branches can be detached
+            assert!(repo.detach_ancestor(TIMELINE_ID, &keyspace).is_err());
+
+            repo.detach_ancestor(NEW_TIMELINE_ID, &keyspace)?;
+            let new_tline = repo.get_timeline_load(NEW_TIMELINE_ID)?;
+            assert_eq!(new_tline.get_ancestor_timeline_id(), None);
+            assert_eq!(new_tline.get_ancestor_lsn(), Lsn(0));
+            assert_eq!(*new_tline.get_latest_gc_cutoff_lsn(), Lsn(0x20));
+            assert!(repo.detach_ancestor(NEW_TIMELINE_ID, &keyspace).is_err());
+
+            // The former parent has no children anymore
+            repo.detach_timeline(TIMELINE_ID)?;
+        }
+
+        // The branch doesn't need the data of its former parent
+        std::fs::remove_dir_all(harness.timeline_path(&TIMELINE_ID))?;
+        let repo = harness.load();
+        let new_tline = repo.get_timeline_load(NEW_TIMELINE_ID)?;
+        assert_eq!(new_tline.get(TEST_KEY, Lsn(0x30))?, TEST_IMG("foo at 0/20"));
+        assert_eq!(
+            new_tline.get(TEST_KEY2, Lsn(0x30))?,
+            TEST_IMG("bar at 0/30")
+        );
+        assert!(new_tline
+            .check_lsn_is_in_scope(Lsn(0x10), &new_tline.get_latest_gc_cutoff_lsn())
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_runs_to_merge() {
+        let runs = |bounds: &[u64]| -> Vec<Range<Lsn>> {
+            bounds.windows(2).map(|w| Lsn(w[0])..Lsn(w[1])).collect()
+        };
+
+        assert_eq!(compaction_tier(&(Lsn(0)..Lsn(99)), 100, 4), 0);
+        assert_eq!(compaction_tier(&(Lsn(0)..Lsn(399)), 100, 4), 0);
+        assert_eq!(compaction_tier(&(Lsn(0)..Lsn(400)), 100, 4), 1);
+        assert_eq!(compaction_tier(&(Lsn(0)..Lsn(1600)), 100, 4), 2);
+
+        // Not enough runs
+        let tier0 = runs(&[0, 100, 200, 300]);
+        assert_eq!(find_runs_to_merge(&tier0, &[], 100, 4), None);
+
+        // The oldest runs of the same tier are merged first
+        let mixed = runs(&[0, 400, 500, 600, 700, 800, 900]);
+        assert_eq!(find_runs_to_merge(&mixed, &[], 100, 4), Some(1..5));
+
+        // Runs with a gap in between cannot be merged
+        let gap = runs(&[0, 100, 200, 300, 400]);
+        let gap = [&gap[..2], &gap[3..], &runs(&[400, 500, 600])].concat();
+        assert_eq!(find_runs_to_merge(&gap, &[], 100, 4), None);
+
+        // Neither can runs that overlap with another delta layer
+        let tier0 = runs(&[0, 100, 200, 300, 400, 500]);
+        assert_eq!(
+            find_runs_to_merge(&tier0, &[Lsn(350)..Lsn(450)], 100, 4),
+            None
+        );
+        assert_eq!(
+            find_runs_to_merge(&tier0, &[Lsn(500)..Lsn(600)], 100, 4),
+            Some(0..4)
+        );
+    }
+
+    #[test]
+    fn test_tiered_compaction() -> Result<()> {
+        let mut harness = RepoHarness::create("test_tiered_compaction")?;
+        harness.tenant_conf.checkpoint_distance = 1024;
+        harness.tenant_conf.compaction_threshold = 1;
+        harness.tenant_conf.compaction_strategy = CompactionStrategy::Tiered;
+        harness.tenant_conf.compaction_tier_ratio = 4;
+        let repo = harness.load();
+        let tline = repo.create_empty_timeline(TIMELINE_ID, Lsn(0))?;
+
+        const NUM_KEYS: u32 = 100;
+        const NUM_GENERATIONS: usize = 16;
+
+        let mut test_key = Key::from_hex("012222222233333333444444445500000000").unwrap();
+        let mut keyspace = KeySpaceAccum::new();
+        for blknum in 0..NUM_KEYS {
+            test_key.field6 = blknum;
+            keyspace.add_key(test_key);
+        }
+        // Four partitions of 25 keys each
+        let partitioning = keyspace
+            .to_keyspace()
+            .partition(25 * pg_constants::BLCKSZ as u64);
+        assert_eq!(partitioning.parts.len(), 4);
+
+        // Update all the keys in each generation, and compact each generation
+        // into a run of L1 delta layers.
+        let mut lsn = Lsn(0);
+        let mut generation_lsns = Vec::new();
+        for generation in 0..NUM_GENERATIONS {
+            for blknum in 0..NUM_KEYS {
+                lsn = Lsn(lsn.0 + 0x10);
+                test_key.field6 = blknum;
+                let writer = tline.writer();
+                writer.put(
+                    test_key,
+                    lsn,
+                    Value::Image(TEST_IMG(&format!(
+                        "{} in generation {}",
+                        blknum, generation
+                    ))),
+                )?;
+                writer.finish_write(lsn);
+            }
+            generation_lsns.push(lsn);
+            tline.checkpoint(CheckpointConfig::Forced)?;
+            tline.compact_level0(u64::MAX)?;
+        }
+
+        let read_amplification = |blknum: u32| -> Result<usize> {
+            let mut key = test_key;
+            key.field6 = blknum;
+            tline
+                .layers
+                .read()
+                .unwrap()
+                .read_amplification(key, lsn + 1)
+        };
+        let count_deltas = || {
+            tline
+                .layers
+                .read()
+                .unwrap()
+                .iter_historic_layers()
+                .filter(|l| l.is_incremental())
+                .count()
+        };
+
+        // One run per generation, each a single layer
+        assert_eq!(read_amplification(0)?, NUM_GENERATIONS);
+        assert_eq!(read_amplification(NUM_KEYS - 1)?, NUM_GENERATIONS);
+        assert_eq!(count_deltas(), NUM_GENERATIONS);
+
+        // Four merges into tier 1, and one more into tier 2
+        for _ in 0..5 {
+            tline.compact_tiered(&partitioning, u64::MAX)?;
+        }
+        assert_eq!(read_amplification(0)?, 1);
+        assert_eq!(read_amplification(NUM_KEYS - 1)?, 1);
+        // The merged run is cut at the partition boundaries
+        assert_eq!(count_deltas(), partitioning.parts.len());
+
+        // Nothing more to do
+        tline.compact_tiered(&partitioning, u64::MAX)?;
+        assert_eq!(count_deltas(), partitioning.parts.len());
+
+        // All page versions are still there
+        for (generation, generation_lsn) in generation_lsns.iter().enumerate() {
+            for blknum in 0..NUM_KEYS {
+                test_key.field6 = blknum;
+                assert_eq!(
+                    tline.get(test_key, *generation_lsn)?,
+                    TEST_IMG(&format!("{} in generation {}", blknum, generation))
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_scrub_corrupt_layer() -> Result<()> {
+        let harness = RepoHarness::create("test_scrub_corrupt_layer")?;
+        let repo = harness.load();
+        let tline = repo.create_empty_timeline(TIMELINE_ID, Lsn(0))?;
+
+        #[allow(non_snake_case)]
+        let TEST_KEY: Key = Key::from_hex("112222222233333333444444445500000001").unwrap();
+
+        let writer = tline.writer();
+        writer.put(TEST_KEY, Lsn(0x10), Value::Image(TEST_IMG("foo at 0x10")))?;
+        writer.finish_write(Lsn(0x10));
+        drop(writer);
+        tline.checkpoint(CheckpointConfig::Forced)?;
+
+        let result = repo.scrub_iteration(&mut |_| {})?;
+        assert_eq!(result.layers_scrubbed, 1);
+        assert!(result.corrupt_layers.is_empty());
+
+        // Flip a bit in the first block of values
+        let layer_path = fs::read_dir(harness.timeline_path(&TIMELINE_ID))?
+            .map(|entry| entry.unwrap().path())
+            .find(|path| {
+                DeltaFileName::parse_str(&path.file_name().unwrap().to_string_lossy()).is_some()
+            })
+            .expect("no delta layer was created");
+        let mut contents = fs::read(&layer_path)?;
+        contents[page_cache::PAGE_SZ + 10] ^= 1;
+        fs::write(&layer_path, contents)?;
+
+        let result = repo.scrub_iteration(&mut |_| {})?;
+        assert_eq!(result.corrupt_layers, vec![layer_path]);
+        // There's no remote storage to fetch a good copy from
+        assert_eq!(result.layers_refetched, 0);
+
+        // Reads from the layer fail too, rather than returning garbage
+        let err = tline.get(TEST_KEY, Lsn(0x10)).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("checksum mismatch"),
+            "{:#}",
+            err
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_evict_layer() -> Result<()> {
+        let harness = RepoHarness::create("test_evict_layer")?;
+        let repo = harness.load();
+        let tline = repo.create_empty_timeline(TIMELINE_ID, Lsn(0))?;
+
+        #[allow(non_snake_case)]
+        let TEST_KEY: Key = Key::from_hex("112222222233333333444444445500000001").unwrap();
+
+        let writer = tline.writer();
+        writer.put(TEST_KEY, Lsn(0x10), Value::Image(TEST_IMG("foo at 0x10")))?;
+        writer.finish_write(Lsn(0x10));
+        drop(writer);
+        tline.checkpoint(CheckpointConfig::Forced)?;
+
+        // Nothing has been uploaded yet
+        assert!(repo.eviction_candidates().is_empty());
+
+        let layer_paths = fs::read_dir(harness.timeline_path(&TIMELINE_ID))?
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.file_name().unwrap() != METADATA_FILE_NAME)
+            .collect::<Vec<_>>();
+        let mut remote_timeline = crate::storage_sync::index::RemoteTimeline::new(load_metadata(
+            harness.conf,
+            TIMELINE_ID,
+            harness.tenant_id,
+        )?);
+        remote_timeline.add_timeline_layers(layer_paths.clone());
+        let sync_id = ZTenantTimelineId {
+            tenant_id: harness.tenant_id,
+            timeline_id: TIMELINE_ID,
+        };
+        repo.get_remote_index()
+            .blocking_write()
+            .add_timeline_entry(sync_id, remote_timeline);
+
+        let candidates = repo.eviction_candidates();
+        assert_eq!(candidates.len(), layer_paths.len());
+        for candidate in candidates {
+            let path = candidate.layer.local_path().unwrap();
+
+            // The file stays while a reader still holds the layer
+            let reader_ref = Arc::clone(&candidate.layer);
+            assert!(candidate.timeline.evict_layer(candidate.layer)?);
+            assert!(path.exists());
+
+            // Already evicted
+            assert!(!candidate.timeline.evict_layer(Arc::clone(&reader_ref))?);
+            assert!(path.exists());
+
+            // The next eviction iteration removes it once the reader is done
+            drop(reader_ref);
+            repo.remove_evicted_layer_files()?;
+            assert!(!path.exists());
+        }
+        assert!(repo.eviction_candidates().is_empty());
+
+        // There's no remote storage to download the evicted layer from
+        let err = tline.get(TEST_KEY, Lsn(0x10)).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("No remote storage configured"),
+            "{:#}",
+            err
+        );
+
+        Ok(())
+    }
+
     // Target file size in the unit tests. In production, the target
     // file size is much larger, maybe 1 GB. But a small size makes it
     // much faster to exercise all the logic for creating the files,
@@ -2683,7 +4750,7 @@ pub mod tests {
             tline.update_gc_info(Vec::new(), cutoff, Duration::ZERO);
             tline.checkpoint(CheckpointConfig::Forced)?;
             tline.compact()?;
-            tline.gc()?;
+            tline.gc(false)?;
         }
 
         Ok(())
@@ -2753,7 +4820,7 @@ pub mod tests {
             tline.update_gc_info(Vec::new(), cutoff, Duration::ZERO);
             tline.checkpoint(CheckpointConfig::Forced)?;
             tline.compact()?;
-            tline.gc()?;
+            tline.gc(false)?;
         }
 
         Ok(())
@@ -2830,7 +4897,7 @@ pub mod tests {
             tline.update_gc_info(Vec::new(), cutoff, Duration::ZERO);
             tline.checkpoint(CheckpointConfig::Forced)?;
             tline.compact()?;
-            tline.gc()?;
+            tline.gc(false)?;
         }
 
         Ok(())
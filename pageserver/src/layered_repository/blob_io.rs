@@ -8,13 +8,85 @@
 //! bit set. This way, we can detect whether it's 1- or 4-byte header
 //! by peeking at the first byte.
 //!
+//! Blobs with a 4-byte header can be compressed. The second highest bit
+//! of the header is set for compressed blobs, and the length is the
+//! length of the compressed data. The compression algorithm is not
+//! stored in the blob itself; the file format that contains the blobs
+//! is responsible for tracking it. Short blobs are never compressed.
+//!
 //! len <  128: 0XXXXXXX
-//! len >= 128: 1XXXXXXX XXXXXXXX XXXXXXXX XXXXXXXX
+//! len >= 128: 1CXXXXXX XXXXXXXX XXXXXXXX XXXXXXXX
+//!
+//! Before storage format version 4, there was no compression, and the
+//! C bit was part of the length. No blob was ever that large, though,
+//! so the old files can be read with the new format.
 //!
 use crate::layered_repository::block_io::{BlockCursor, BlockReader};
 use crate::page_cache::PAGE_SZ;
+use serde::{Deserialize, Serialize};
 use std::cmp::min;
+use std::fmt;
 use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+/// Largest blob that can be stored, limited by the size of the length header
+const MAX_BLOB_LEN: usize = 0x3fff_ffff;
+
+/// Compression algorithm used for the blobs in a layer file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionAlgorithm {
+    None,
+    Zstd,
+    Lz4,
+}
+
+impl CompressionAlgorithm {
+    fn compress(&self, srcbuf: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        match self {
+            CompressionAlgorithm::None => Ok(None),
+            CompressionAlgorithm::Zstd => {
+                zstd::bulk::compress(srcbuf, zstd::DEFAULT_COMPRESSION_LEVEL).map(Some)
+            }
+            CompressionAlgorithm::Lz4 => Ok(Some(lz4_flex::compress_prepend_size(srcbuf))),
+        }
+    }
+
+    fn decompress(&self, srcbuf: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            CompressionAlgorithm::None => Err(Error::new(
+                ErrorKind::InvalidData,
+                "compressed blob in a file without compression",
+            )),
+            CompressionAlgorithm::Zstd => zstd::stream::decode_all(srcbuf),
+            CompressionAlgorithm::Lz4 => lz4_flex::decompress_size_prepended(srcbuf)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+        }
+    }
+}
+
+impl FromStr for CompressionAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "none" => Ok(CompressionAlgorithm::None),
+            "zstd" => Ok(CompressionAlgorithm::Zstd),
+            "lz4" => Ok(CompressionAlgorithm::Lz4),
+            _ => anyhow::bail!("unknown compression algorithm '{}'", s),
+        }
+    }
+}
+
+impl fmt::Display for CompressionAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CompressionAlgorithm::None => "none",
+            CompressionAlgorithm::Zstd => "zstd",
+            CompressionAlgorithm::Lz4 => "lz4",
+        })
+    }
+}
 
 /// For reading
 pub trait BlobCursor {
@@ -26,23 +98,53 @@ pub trait BlobCursor {
     }
 
     /// Read blob into the given buffer. Any previous contents in the buffer
-    /// are overwritten.
+    /// are overwritten. Fails if the blob is compressed.
     fn read_blob_into_buf(
         &mut self,
         offset: u64,
         dstbuf: &mut Vec<u8>,
-    ) -> Result<(), std::io::Error>;
+    ) -> Result<(), std::io::Error> {
+        if self.read_raw_blob_into_buf(offset, dstbuf)? {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("unexpected compressed blob at offset {}", offset),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Read a blob that might be compressed with 'compression', and return
+    /// the uncompressed data.
+    fn read_blob_decompressed(
+        &mut self,
+        offset: u64,
+        compression: CompressionAlgorithm,
+    ) -> Result<Vec<u8>, std::io::Error> {
+        let mut buf = Vec::new();
+        if self.read_raw_blob_into_buf(offset, &mut buf)? {
+            buf = compression.decompress(&buf)?;
+        }
+        Ok(buf)
+    }
+
+    /// Read the stored contents of a blob into the given buffer, without
+    /// decompressing it. Returns true if the blob is compressed.
+    fn read_raw_blob_into_buf(
+        &mut self,
+        offset: u64,
+        dstbuf: &mut Vec<u8>,
+    ) -> Result<bool, std::io::Error>;
 }
 
 impl<'a, R> BlobCursor for BlockCursor<R>
 where
     R: BlockReader,
 {
-    fn read_blob_into_buf(
+    fn read_raw_blob_into_buf(
         &mut self,
         offset: u64,
         dstbuf: &mut Vec<u8>,
-    ) -> Result<(), std::io::Error> {
+    ) -> Result<bool, std::io::Error> {
         let mut blknum = (offset / PAGE_SZ as u64) as u32;
         let mut off = (offset % PAGE_SZ as u64) as usize;
 
@@ -50,6 +152,7 @@ where
 
         // peek at the first byte, to determine if it's a 1- or 4-byte length
         let first_len_byte = buf[off];
+        let compressed = first_len_byte & 0xc0 == 0xc0;
         let len: usize = if first_len_byte < 0x80 {
             // 1-byte length header
             off += 1;
@@ -69,7 +172,7 @@ where
                 len_buf.copy_from_slice(&buf[off..off + 4]);
                 off += 4;
             }
-            len_buf[0] &= 0x7f;
+            len_buf[0] &= 0x3f;
             u32::from_be_bytes(len_buf) as usize
         };
 
@@ -91,7 +194,7 @@ where
             remain -= this_blk_len;
             off += this_blk_len;
         }
-        Ok(())
+        Ok(compressed)
     }
 }
 
@@ -114,16 +217,20 @@ where
 {
     inner: W,
     offset: u64,
+    compression: CompressionAlgorithm,
 }
 
 impl<W> WriteBlobWriter<W>
 where
     W: std::io::Write,
 {
-    pub fn new(inner: W, start_offset: u64) -> Self {
+    /// Create a new writer. Blobs are compressed with 'compression', if
+    /// that makes them smaller.
+    pub fn new(inner: W, start_offset: u64, compression: CompressionAlgorithm) -> Self {
         WriteBlobWriter {
             inner,
             offset: start_offset,
+            compression,
         }
     }
 
@@ -153,21 +260,136 @@ where
             let len_buf = srcbuf.len() as u8;
             self.inner.write_all(&[len_buf])?;
             self.offset += 1;
-        } else {
-            // Write a 4-byte length header
-            if srcbuf.len() > 0x7fff_ffff {
-                return Err(Error::new(
-                    ErrorKind::Other,
-                    format!("blob too large ({} bytes)", srcbuf.len()),
-                ));
-            }
-            let mut len_buf = ((srcbuf.len()) as u32).to_be_bytes();
-            len_buf[0] |= 0x80;
-            self.inner.write_all(&len_buf)?;
-            self.offset += 4;
+            self.inner.write_all(srcbuf)?;
+            self.offset += srcbuf.len() as u64;
+            return Ok(offset);
+        }
+
+        // Compress the blob, unless that doesn't make it any smaller
+        let compressed = self
+            .compression
+            .compress(srcbuf)?
+            .filter(|compressed| compressed.len() < srcbuf.len());
+        let data = compressed.as_deref().unwrap_or(srcbuf);
+
+        // Write a 4-byte length header
+        if data.len() > MAX_BLOB_LEN {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("blob too large ({} bytes)", data.len()),
+            ));
+        }
+        let mut len_buf = (data.len() as u32).to_be_bytes();
+        len_buf[0] |= 0x80;
+        if compressed.is_some() {
+            len_buf[0] |= 0x40;
         }
-        self.inner.write_all(srcbuf)?;
-        self.offset += srcbuf.len() as u64;
+        self.inner.write_all(&len_buf)?;
+        self.offset += 4;
+        self.inner.write_all(data)?;
+        self.offset += data.len() as u64;
         Ok(offset)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    /// A BlockReader over an in-memory buffer
+    struct TestDisk(Vec<u8>);
+
+    impl BlockReader for TestDisk {
+        type BlockLease = std::rc::Rc<[u8; PAGE_SZ]>;
+
+        fn read_blk(&self, blknum: u32) -> io::Result<Self::BlockLease> {
+            let mut buf = [0u8; PAGE_SZ];
+            let start = blknum as usize * PAGE_SZ;
+            let end = min(start + PAGE_SZ, self.0.len());
+            buf[..end - start].copy_from_slice(&self.0[start..end]);
+            Ok(std::rc::Rc::new(buf))
+        }
+    }
+
+    fn test_blobs() -> Vec<Vec<u8>> {
+        vec![
+            Vec::new(),
+            b"foo".to_vec(),
+            vec![0xAA; 127],
+            vec![0xAA; 128],
+            // not compressible
+            (0..10000).map(|i| (i * 7919 % 251) as u8).collect(),
+            // compressible, and spans several pages
+            vec![0; 3 * PAGE_SZ],
+            b"bar".repeat(1000),
+        ]
+    }
+
+    fn write_blobs(
+        blobs: &[Vec<u8>],
+        compression: CompressionAlgorithm,
+    ) -> io::Result<(TestDisk, Vec<u64>)> {
+        let mut writer = WriteBlobWriter::new(Vec::new(), 0, compression);
+        let mut offsets = Vec::new();
+        for blob in blobs {
+            offsets.push(writer.write_blob(blob)?);
+        }
+        Ok((TestDisk(writer.into_inner()), offsets))
+    }
+
+    #[test]
+    fn roundtrip() -> io::Result<()> {
+        let blobs = test_blobs();
+        for compression in [
+            CompressionAlgorithm::None,
+            CompressionAlgorithm::Zstd,
+            CompressionAlgorithm::Lz4,
+        ] {
+            let (disk, offsets) = write_blobs(&blobs, compression)?;
+            let mut cursor = disk.block_cursor();
+            for (blob, offset) in blobs.iter().zip(offsets) {
+                assert_eq!(&cursor.read_blob_decompressed(offset, compression)?, blob);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn compressed_blobs_are_smaller() -> io::Result<()> {
+        let blobs = test_blobs();
+        let (uncompressed, _) = write_blobs(&blobs, CompressionAlgorithm::None)?;
+        let (compressed, offsets) = write_blobs(&blobs, CompressionAlgorithm::Zstd)?;
+        assert!(compressed.0.len() < uncompressed.0.len());
+
+        // Incompressible and short blobs are stored as is
+        let mut cursor = compressed.block_cursor();
+        for (blob, offset) in blobs.iter().zip(offsets) {
+            let mut buf = Vec::new();
+            let is_compressed = cursor.read_raw_blob_into_buf(offset, &mut buf)?;
+            assert_eq!(is_compressed, &buf != blob);
+            if !is_compressed {
+                assert_eq!(&cursor.read_blob(offset)?, blob);
+            } else {
+                assert!(cursor.read_blob(offset).is_err());
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn read_old_format() -> io::Result<()> {
+        // A blob with a 4-byte header, as written before compression support
+        let blob = vec![0x55; 1000];
+        let mut file = (blob.len() as u32 | 0x8000_0000).to_be_bytes().to_vec();
+        file.extend_from_slice(&blob);
+        let disk = TestDisk(file);
+        let mut cursor = disk.block_cursor();
+        assert_eq!(cursor.read_blob(0)?, blob);
+        assert_eq!(
+            cursor.read_blob_decompressed(0, CompressionAlgorithm::None)?,
+            blob
+        );
+        Ok(())
+    }
+}
@@ -1,11 +1,29 @@
 //!
 //! Low-level Block-oriented I/O functions
 //!
+//! Layer files written with format version 5 or later carry a CRC-32C
+//! checksum of each block. The checksums are computed by ChecksumWriter as
+//! the file is written, and stored in a table at the end of the file:
+//!
+//! ```text
+//! +-----------+--------------------------+---------------------------+
+//! | blk 0     | blks 1..N                | blk N..                   |
+//! | (summary) | (values and index)       | (checksum table)          |
+//! +-----------+--------------------------+---------------------------+
+//! ```
+//!
+//! The table has one big-endian u32 for each of the blocks 0..N. N is
+//! recorded in the summary. FileBlockReader verifies each block against the
+//! table when it's read into the page cache, and 'scrub' verifies all of
+//! them directly from disk.
+//!
 
 use crate::page_cache;
 use crate::page_cache::{ReadBufResult, PAGE_SZ};
 use bytes::Bytes;
 use lazy_static::lazy_static;
+use metrics::{register_int_counter, IntCounter};
+use std::io::{Error, ErrorKind, Seek, SeekFrom, Write};
 use std::ops::{Deref, DerefMut};
 use std::os::unix::fs::FileExt;
 use std::sync::atomic::AtomicU64;
@@ -121,6 +139,14 @@ lazy_static! {
     static ref NEXT_ID: AtomicU64 = AtomicU64::new(1);
 }
 
+lazy_static! {
+    static ref CHECKSUM_ERRORS: IntCounter = register_int_counter!(
+        "pageserver_layer_checksum_errors_total",
+        "Number of layer file blocks that failed checksum verification"
+    )
+    .expect("failed to define a metric");
+}
+
 /// An adapter for reading a (virtual) file using the page cache.
 ///
 /// The file is assumed to be immutable. This doesn't provide any functions
@@ -130,6 +156,10 @@ pub struct FileBlockReader<F> {
 
     /// Unique ID of this file, used as key in the page cache.
     file_id: u64,
+
+    /// Checksums of the blocks, if the file has them. Blocks beyond the end
+    /// of this are not verified.
+    checksums: Option<Vec<u32>>,
 }
 
 impl<F> FileBlockReader<F>
@@ -139,7 +169,68 @@ where
     pub fn new(file: F) -> Self {
         let file_id = NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
 
-        FileBlockReader { file_id, file }
+        FileBlockReader {
+            file_id,
+            file,
+            checksums: None,
+        }
+    }
+
+    ///
+    /// Load the checksum table that starts at block 'checksums_start_blk',
+    /// and verify all blocks read from now on against it.
+    ///
+    /// Blocks that are already in the page cache are not verified again, so
+    /// this should be called before reading anything other than block 0.
+    ///
+    pub fn load_checksums(&mut self, checksums_start_blk: u32) -> Result<(), std::io::Error> {
+        let mut buf = vec![0u8; checksums_start_blk as usize * 4];
+        self.file
+            .read_exact_at(&mut buf, checksums_start_blk as u64 * PAGE_SZ as u64)?;
+        let checksums = buf
+            .chunks_exact(4)
+            .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+            .collect();
+        self.checksums = Some(checksums);
+        Ok(())
+    }
+
+    /// Check the contents of a block against its checksum, if we have one.
+    pub fn verify_blk(&self, blknum: u32, buf: &[u8]) -> Result<(), std::io::Error> {
+        let expected = match self.checksums.as_ref().and_then(|c| c.get(blknum as usize)) {
+            Some(expected) => *expected,
+            None => return Ok(()),
+        };
+        let actual = crc32c::crc32c(buf);
+        if actual != expected {
+            CHECKSUM_ERRORS.inc();
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "checksum mismatch in block {}: expected {:08X}, got {:08X}",
+                    blknum, expected, actual
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    ///
+    /// Read all the checksummed blocks directly from disk, bypassing the page
+    /// cache, and verify them. 'throttle' is called with the number of bytes
+    /// read after each block, and can sleep to limit the I/O rate.
+    ///
+    /// Files without checksums are not read at all.
+    ///
+    pub fn scrub(&self, throttle: &mut dyn FnMut(u64)) -> Result<(), std::io::Error> {
+        let num_blocks = self.checksums.as_ref().map_or(0, |c| c.len());
+        let mut buf = [0u8; PAGE_SZ];
+        for blknum in 0..num_blocks as u32 {
+            self.fill_buffer(&mut buf, blknum)?;
+            self.verify_blk(blknum, &buf)?;
+            throttle(PAGE_SZ as u64);
+        }
+        Ok(())
     }
 
     /// Read a page from the underlying file into given buffer.
@@ -162,8 +253,11 @@ where
             match cache.read_immutable_buf(self.file_id, blknum) {
                 ReadBufResult::Found(guard) => break Ok(guard),
                 ReadBufResult::NotFound(mut write_guard) => {
-                    // Read the page from disk into the buffer
+                    // Read the page from disk into the buffer. If it doesn't
+                    // pass verification, the guard is dropped without marking
+                    // it valid, which removes the buffer from the cache.
                     self.fill_buffer(write_guard.deref_mut(), blknum)?;
+                    self.verify_blk(blknum, write_guard.deref())?;
                     write_guard.mark_valid();
 
                     // Swap for read lock
@@ -187,6 +281,90 @@ pub trait BlockWriter {
     fn write_blk(&mut self, buf: Bytes) -> Result<u32, std::io::Error>;
 }
 
+///
+/// A writer that computes the checksums of the blocks written through it.
+///
+/// Block 0 is reserved for the summary, which is written last: the underlying
+/// writer must be positioned at the start of block 1 when this is created.
+/// Call 'finish' to write the summary and the checksum table.
+///
+#[derive(Debug)]
+pub struct ChecksumWriter<W> {
+    inner: W,
+    /// Checksums of the completed blocks, starting from block 1
+    checksums: Vec<u32>,
+    /// Checksum of the data written to the current block so far
+    crc: u32,
+    /// Number of bytes written to the current block so far
+    pos: usize,
+}
+
+impl<W: Write + Seek> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChecksumWriter {
+            inner,
+            checksums: Vec::new(),
+            crc: 0,
+            pos: 0,
+        }
+    }
+
+    /// Number of the block that the next write goes to
+    pub fn next_blk(&self) -> u32 {
+        (1 + self.checksums.len()) as u32
+    }
+
+    /// Pad the current block with zeros, so that the next write starts at a
+    /// block boundary.
+    pub fn pad_to_block(&mut self) -> Result<(), std::io::Error> {
+        if self.pos > 0 {
+            let zeros = [0u8; PAGE_SZ];
+            self.write_all(&zeros[self.pos..])?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Write 'summary_blk' to block 0, and the checksum table after the last
+    /// block. The summary must record 'next_blk()' as the start of the table;
+    /// call 'pad_to_block' first. Returns the underlying writer.
+    ///
+    pub fn finish(mut self, summary_blk: &[u8; PAGE_SZ]) -> Result<W, std::io::Error> {
+        assert!(self.pos == 0, "last block was not padded");
+
+        let mut table = Vec::with_capacity((1 + self.checksums.len()) * 4);
+        table.extend_from_slice(&crc32c::crc32c(summary_blk).to_be_bytes());
+        for crc in self.checksums.iter() {
+            table.extend_from_slice(&crc.to_be_bytes());
+        }
+        self.inner.write_all(&table)?;
+
+        self.inner.seek(SeekFrom::Start(0))?;
+        self.inner.write_all(summary_blk)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        // Don't cross a block boundary in one call
+        let len = std::cmp::min(buf.len(), PAGE_SZ - self.pos);
+        let written = self.inner.write(&buf[..len])?;
+        self.crc = crc32c::crc32c_append(self.crc, &buf[..written]);
+        self.pos += written;
+        if self.pos == PAGE_SZ {
+            self.checksums.push(self.crc);
+            self.crc = 0;
+            self.pos = 0;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.inner.flush()
+    }
+}
+
 ///
 /// A simple in-memory buffer of blocks.
 ///
@@ -216,3 +394,57 @@ impl Default for BlockBuf {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn write_test_file(path: &std::path::Path, num_blocks: usize) -> u32 {
+        let mut cursor = Cursor::new(Vec::new());
+        cursor.seek(SeekFrom::Start(PAGE_SZ as u64)).unwrap();
+        let mut writer = ChecksumWriter::new(cursor);
+        for i in 0..num_blocks {
+            // Write in odd-sized pieces, to exercise the block boundaries
+            let data: Vec<u8> = (0..PAGE_SZ / 2).map(|j| (i + j) as u8).collect();
+            writer.write_all(&data).unwrap();
+        }
+        writer.pad_to_block().unwrap();
+        let checksums_start_blk = writer.next_blk();
+
+        let mut summary_blk = [0u8; PAGE_SZ];
+        summary_blk[0..4].copy_from_slice(&checksums_start_blk.to_be_bytes());
+        let cursor = writer.finish(&summary_blk).unwrap();
+        std::fs::write(path, cursor.into_inner()).unwrap();
+        checksums_start_blk
+    }
+
+    #[test]
+    fn checksums() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("layer");
+        let checksums_start_blk = write_test_file(&path, 7);
+        // 7 half blocks, padded to 4 blocks, plus the summary
+        assert_eq!(checksums_start_blk, 5);
+
+        let mut reader = FileBlockReader::new(std::fs::File::open(&path)?);
+        reader.load_checksums(checksums_start_blk)?;
+        let mut scrubbed = 0;
+        reader.scrub(&mut |bytes| scrubbed += bytes)?;
+        assert_eq!(scrubbed, 5 * PAGE_SZ as u64);
+
+        // Flip a bit in block 3, and check that it's caught
+        let mut contents = std::fs::read(&path)?;
+        contents[3 * PAGE_SZ + 100] ^= 0x10;
+        std::fs::write(&path, &contents)?;
+
+        let reader = FileBlockReader {
+            checksums: reader.checksums,
+            ..FileBlockReader::new(std::fs::File::open(&path)?)
+        };
+        let err = reader.scrub(&mut |_| {}).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("block 3"), "{}", err);
+        Ok(())
+    }
+}
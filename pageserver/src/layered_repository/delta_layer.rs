@@ -21,21 +21,26 @@
 //! and it contains basic information about the layer, and offsets to the other
 //! parts. The "index" is a B-tree, mapping from Key and LSN to an offset in the
 //! "values" part.  The actual page images and WAL records are stored in the
-//! "values" part.
+//! "values" part. Files written with format version 5 or later also have a
+//! table of block checksums at the end, see block_io.rs.
 //!
 use crate::config::PageServerConf;
-use crate::layered_repository::blob_io::{BlobCursor, BlobWriter, WriteBlobWriter};
-use crate::layered_repository::block_io::{BlockBuf, BlockCursor, BlockReader, FileBlockReader};
+use crate::layered_repository::blob_io::{
+    BlobCursor, BlobWriter, CompressionAlgorithm, WriteBlobWriter,
+};
+use crate::layered_repository::block_io::{
+    BlockBuf, BlockCursor, BlockReader, ChecksumWriter, FileBlockReader,
+};
 use crate::layered_repository::disk_btree::{DiskBtreeBuilder, DiskBtreeReader, VisitDirection};
 use crate::layered_repository::filename::{DeltaFileName, PathOrConf};
 use crate::layered_repository::storage_layer::{
-    Layer, ValueReconstructResult, ValueReconstructState,
+    Layer, LayerAccessTime, ValueReconstructResult, ValueReconstructState,
 };
 use crate::page_cache::{PageReadGuard, PAGE_SZ};
 use crate::repository::{Key, Value, KEY_SIZE};
 use crate::virtual_file::VirtualFile;
 use crate::walrecord;
-use crate::{DELTA_FILE_MAGIC, STORAGE_FORMAT_VERSION};
+use crate::{DELTA_FILE_MAGIC, MIN_SUPPORTED_STORAGE_FORMAT_VERSION, STORAGE_FORMAT_VERSION};
 use anyhow::{bail, ensure, Context, Result};
 use rand::{distributions::Alphanumeric, Rng};
 use serde::{Deserialize, Serialize};
@@ -46,6 +51,7 @@ use std::ops::Range;
 use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
 use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
 use tracing::*;
 
 use utils::{
@@ -75,6 +81,16 @@ struct Summary {
     index_start_blk: u32,
     /// Block within the 'index', where the B-tree root page is stored
     index_root_blk: u32,
+
+    /// Compression algorithm used for the values. Files written before format
+    /// version 4 don't have this field, but the zero padding after the summary
+    /// deserializes as CompressionAlgorithm::None.
+    compression: CompressionAlgorithm,
+
+    /// Block number where the table of block checksums begins. This is also
+    /// the number of blocks covered by the table. Zero in files written
+    /// before format version 5, which have no checksums.
+    checksums_start_blk: u32,
 }
 
 impl From<&DeltaLayer> for Summary {
@@ -90,6 +106,8 @@ impl From<&DeltaLayer> for Summary {
 
             index_start_blk: 0,
             index_root_blk: 0,
+            compression: CompressionAlgorithm::None,
+            checksums_start_blk: 0,
         }
     }
 }
@@ -180,6 +198,9 @@ pub struct DeltaLayer {
     pub key_range: Range<Key>,
     pub lsn_range: Range<Lsn>,
 
+    /// When the layer was last read, see `Layer::last_access`
+    access_time: LayerAccessTime,
+
     inner: RwLock<DeltaLayerInner>,
 }
 
@@ -190,6 +211,7 @@ pub struct DeltaLayerInner {
     // values copied from summary
     index_start_blk: u32,
     index_root_blk: u32,
+    compression: CompressionAlgorithm,
 
     /// Reader object for reading blocks from the file. (None if not loaded yet)
     file: Option<FileBlockReader<VirtualFile>>,
@@ -230,6 +252,7 @@ impl Layer for DeltaLayer {
         let mut need_image = true;
 
         ensure!(self.key_range.contains(&key));
+        self.access_time.touch();
 
         {
             // Open the file and lock the metadata in memory
@@ -263,12 +286,14 @@ impl Layer for DeltaLayer {
             // Ok, 'offsets' now contains the offsets of all the entries we need to read
             let mut cursor = file.block_cursor();
             for (entry_lsn, pos) in offsets {
-                let buf = cursor.read_blob(pos).with_context(|| {
-                    format!(
-                        "Failed to read blob from virtual file {}",
-                        file.file.path.display()
-                    )
-                })?;
+                let buf = cursor
+                    .read_blob_decompressed(pos, inner.compression)
+                    .with_context(|| {
+                        format!(
+                            "Failed to read blob from virtual file {}",
+                            file.file.path.display()
+                        )
+                    })?;
                 let val = Value::des(&buf).with_context(|| {
                     format!(
                         "Failed to deserialize file blob from virtual file {}",
@@ -322,6 +347,24 @@ impl Layer for DeltaLayer {
         Ok(())
     }
 
+    fn scrub(&self, throttle: &mut dyn FnMut(u64)) -> Result<()> {
+        // Use a separate file handle, so that the blocks are read from disk
+        // rather than the page cache.
+        let path = self.path();
+        let file = VirtualFile::open(&path)
+            .with_context(|| format!("Failed to open file '{}'", path.display()))?;
+        let mut file = FileBlockReader::new(file);
+
+        let mut summary_blk = [0u8; PAGE_SZ];
+        file.file.read_exact_at(&mut summary_blk, 0)?;
+        let summary = Summary::des_prefix(&summary_blk)?;
+        if summary.checksums_start_blk != 0 {
+            file.load_checksums(summary.checksums_start_blk)?;
+            file.scrub(throttle)?;
+        }
+        Ok(())
+    }
+
     fn is_incremental(&self) -> bool {
         true
     }
@@ -330,6 +373,14 @@ impl Layer for DeltaLayer {
         false
     }
 
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn last_access(&self) -> Option<SystemTime> {
+        Some(self.access_time.get())
+    }
+
     /// debugging function to print out the contents of the layer
     fn dump(&self, verbose: bool) -> Result<()> {
         println!(
@@ -366,7 +417,7 @@ impl Layer for DeltaLayer {
 
         // A subroutine to dump a single blob
         let mut dump_blob = |blob_ref: BlobRef| -> anyhow::Result<String> {
-            let buf = cursor.read_blob(blob_ref.pos())?;
+            let buf = cursor.read_blob_decompressed(blob_ref.pos(), inner.compression)?;
             let val = Value::des(&buf)?;
             let desc = match val {
                 Value::Image(img) => {
@@ -488,12 +539,21 @@ impl DeltaLayer {
         let file = inner.file.as_mut().unwrap();
         let summary_blk = file.read_blk(0)?;
         let actual_summary = Summary::des_prefix(summary_blk.as_ref())?;
+        ensure!(
+            (MIN_SUPPORTED_STORAGE_FORMAT_VERSION..=STORAGE_FORMAT_VERSION)
+                .contains(&actual_summary.format_version),
+            "unsupported format version {}",
+            actual_summary.format_version
+        );
 
         match &self.path_or_conf {
             PathOrConf::Conf(_) => {
                 let mut expected_summary = Summary::from(self);
+                expected_summary.format_version = actual_summary.format_version;
                 expected_summary.index_start_blk = actual_summary.index_start_blk;
                 expected_summary.index_root_blk = actual_summary.index_root_blk;
+                expected_summary.compression = actual_summary.compression;
+                expected_summary.checksums_start_blk = actual_summary.checksums_start_blk;
                 if actual_summary != expected_summary {
                     bail!("in-file summary does not match expected summary. actual = {:?} expected = {:?}", actual_summary, expected_summary);
                 }
@@ -514,6 +574,16 @@ impl DeltaLayer {
 
         inner.index_start_blk = actual_summary.index_start_blk;
         inner.index_root_blk = actual_summary.index_root_blk;
+        inner.compression = actual_summary.compression;
+
+        // Verify the rest of the file as it's read. The summary block was
+        // read before we had the checksums, so check it now.
+        if actual_summary.checksums_start_blk != 0 {
+            let file = inner.file.as_mut().unwrap();
+            file.load_checksums(actual_summary.checksums_start_blk)
+                .with_context(|| format!("Failed to read checksums from '{}'", path.display()))?;
+            file.verify_blk(0, summary_blk.as_ref())?;
+        }
 
         debug!("loaded from {}", &path.display());
 
@@ -534,11 +604,13 @@ impl DeltaLayer {
             tenantid,
             key_range: filename.key_range.clone(),
             lsn_range: filename.lsn_range.clone(),
+            access_time: LayerAccessTime::new(),
             inner: RwLock::new(DeltaLayerInner {
                 loaded: false,
                 file: None,
                 index_start_blk: 0,
                 index_root_blk: 0,
+                compression: CompressionAlgorithm::None,
             }),
         }
     }
@@ -561,11 +633,13 @@ impl DeltaLayer {
             tenantid: summary.tenantid,
             key_range: summary.key_range,
             lsn_range: summary.lsn_range,
+            access_time: LayerAccessTime::new(),
             inner: RwLock::new(DeltaLayerInner {
                 loaded: false,
                 file: None,
                 index_start_blk: 0,
                 index_root_blk: 0,
+                compression: CompressionAlgorithm::None,
             }),
         })
     }
@@ -610,12 +684,14 @@ pub struct DeltaLayerWriter {
 
     tree: DiskBtreeBuilder<BlockBuf, DELTA_KEY_SIZE>,
 
-    blob_writer: WriteBlobWriter<BufWriter<VirtualFile>>,
+    blob_writer: WriteBlobWriter<BufWriter<ChecksumWriter<VirtualFile>>>,
+    compression: CompressionAlgorithm,
 }
 
 impl DeltaLayerWriter {
     ///
-    /// Start building a new delta layer.
+    /// Start building a new delta layer. The values are compressed with
+    /// 'compression'.
     ///
     pub fn new(
         conf: &'static PageServerConf,
@@ -623,6 +699,7 @@ impl DeltaLayerWriter {
         tenantid: ZTenantId,
         key_start: Key,
         lsn_range: Range<Lsn>,
+        compression: CompressionAlgorithm,
     ) -> Result<DeltaLayerWriter> {
         // Create the file initially with a temporary filename. We don't know
         // the end key yet, so we cannot form the final filename yet. We will
@@ -635,8 +712,8 @@ impl DeltaLayerWriter {
         let mut file = VirtualFile::create(&path)?;
         // make room for the header block
         file.seek(SeekFrom::Start(PAGE_SZ as u64))?;
-        let buf_writer = BufWriter::new(file);
-        let blob_writer = WriteBlobWriter::new(buf_writer, PAGE_SZ as u64);
+        let buf_writer = BufWriter::new(ChecksumWriter::new(file));
+        let blob_writer = WriteBlobWriter::new(buf_writer, PAGE_SZ as u64, compression);
 
         // Initialize the b-tree index builder
         let block_buf = BlockBuf::new();
@@ -651,6 +728,7 @@ impl DeltaLayerWriter {
             lsn_range,
             tree: tree_builder,
             blob_writer,
+            compression,
         })
     }
 
@@ -684,13 +762,14 @@ impl DeltaLayerWriter {
             ((self.blob_writer.size() + PAGE_SZ as u64 - 1) / PAGE_SZ as u64) as u32;
 
         let buf_writer = self.blob_writer.into_inner();
-        let mut file = buf_writer.into_inner()?;
+        let mut writer = buf_writer.into_inner()?;
 
         // Write out the index
         let (index_root_blk, block_buf) = self.tree.finish()?;
-        file.seek(SeekFrom::Start(index_start_blk as u64 * PAGE_SZ as u64))?;
+        writer.pad_to_block()?;
+        assert_eq!(writer.next_blk(), index_start_blk);
         for buf in block_buf.blocks {
-            file.write_all(buf.as_ref())?;
+            writer.write_all(buf.as_ref())?;
         }
 
         // Fill in the summary on blk 0
@@ -703,9 +782,12 @@ impl DeltaLayerWriter {
             lsn_range: self.lsn_range.clone(),
             index_start_blk,
             index_root_blk,
+            compression: self.compression,
+            checksums_start_blk: writer.next_blk(),
         };
-        file.seek(SeekFrom::Start(0))?;
-        Summary::ser_into(&summary, &mut file)?;
+        let mut summary_blk = [0u8; PAGE_SZ];
+        summary.ser_into_slice(&mut summary_blk)?;
+        let file = writer.finish(&summary_blk)?;
 
         // Note: Because we opened the file in write-only mode, we cannot
         // reuse the same VirtualFile for reading later. That's why we don't
@@ -716,11 +798,13 @@ impl DeltaLayerWriter {
             timelineid: self.timelineid,
             key_range: self.key_start..key_end,
             lsn_range: self.lsn_range.clone(),
+            access_time: LayerAccessTime::new(),
             inner: RwLock::new(DeltaLayerInner {
                 loaded: false,
                 file: None,
                 index_start_blk,
                 index_root_blk,
+                compression: self.compression,
             }),
         };
 
@@ -758,6 +842,7 @@ struct DeltaValueIter<'a> {
     all_offsets: Vec<(DeltaKey, BlobRef)>,
     next_idx: usize,
     reader: BlockCursor<Adapter<'a>>,
+    compression: CompressionAlgorithm,
 }
 
 struct Adapter<'a>(RwLockReadGuard<'a, DeltaLayerInner>);
@@ -800,6 +885,7 @@ impl<'a> DeltaValueIter<'a> {
         let iter = DeltaValueIter {
             all_offsets,
             next_idx: 0,
+            compression: inner.compression,
             reader: BlockCursor::new(Adapter(inner)),
         };
 
@@ -813,7 +899,9 @@ impl<'a> DeltaValueIter<'a> {
             let key = delta_key.key();
             let lsn = delta_key.lsn();
 
-            let buf = self.reader.read_blob(blob_ref.pos())?;
+            let buf = self
+                .reader
+                .read_blob_decompressed(blob_ref.pos(), self.compression)?;
             let val = Value::des(&buf)?;
             self.next_idx += 1;
             Ok(Some((key, lsn, val)))
@@ -203,6 +203,14 @@ impl BlobWriter for EphemeralFile {
             buf[off] = srcbuf.len() as u8;
             off += 1;
         } else {
+            // The second highest bit of the header marks compressed blobs,
+            // so it must not be part of the length.
+            if srcbuf.len() > 0x3fff_ffff {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("blob too large ({} bytes)", srcbuf.len()),
+                ));
+            }
             let mut len_buf = u32::to_be_bytes(srcbuf.len() as u32);
             len_buf[0] |= 0x80;
             let thislen = PAGE_SZ - off;
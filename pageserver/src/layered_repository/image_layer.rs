@@ -18,19 +18,23 @@
 //! beginning of the file, and it contains basic information about the
 //! layer, and offsets to the other parts. The "index" is a B-tree,
 //! mapping from Key to an offset in the "values" part.  The
-//! actual page images are stored in the "values" part.
+//! actual page images are stored in the "values" part. Files written
+//! with format version 5 or later also have a table of block checksums
+//! at the end, see block_io.rs.
 use crate::config::PageServerConf;
-use crate::layered_repository::blob_io::{BlobCursor, BlobWriter, WriteBlobWriter};
-use crate::layered_repository::block_io::{BlockBuf, BlockReader, FileBlockReader};
+use crate::layered_repository::blob_io::{
+    BlobCursor, BlobWriter, CompressionAlgorithm, WriteBlobWriter,
+};
+use crate::layered_repository::block_io::{BlockBuf, BlockReader, ChecksumWriter, FileBlockReader};
 use crate::layered_repository::disk_btree::{DiskBtreeBuilder, DiskBtreeReader, VisitDirection};
 use crate::layered_repository::filename::{ImageFileName, PathOrConf};
 use crate::layered_repository::storage_layer::{
-    Layer, ValueReconstructResult, ValueReconstructState,
+    Layer, LayerAccessTime, ValueReconstructResult, ValueReconstructState,
 };
 use crate::page_cache::PAGE_SZ;
 use crate::repository::{Key, Value, KEY_SIZE};
 use crate::virtual_file::VirtualFile;
-use crate::{IMAGE_FILE_MAGIC, STORAGE_FORMAT_VERSION};
+use crate::{IMAGE_FILE_MAGIC, MIN_SUPPORTED_STORAGE_FORMAT_VERSION, STORAGE_FORMAT_VERSION};
 use anyhow::{bail, ensure, Context, Result};
 use bytes::Bytes;
 use hex;
@@ -40,8 +44,10 @@ use std::fs;
 use std::io::Write;
 use std::io::{Seek, SeekFrom};
 use std::ops::Range;
+use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
 use std::sync::{RwLock, RwLockReadGuard};
+use std::time::SystemTime;
 use tracing::*;
 
 use utils::{
@@ -71,6 +77,16 @@ struct Summary {
     index_start_blk: u32,
     /// Block within the 'index', where the B-tree root page is stored
     index_root_blk: u32,
+
+    /// Compression algorithm used for the page images. Files written before
+    /// format version 4 don't have this field, but the zero padding after the
+    /// summary deserializes as CompressionAlgorithm::None.
+    compression: CompressionAlgorithm,
+
+    /// Block number where the table of block checksums begins. This is also
+    /// the number of blocks covered by the table. Zero in files written
+    /// before format version 5, which have no checksums.
+    checksums_start_blk: u32,
     // the 'values' part starts after the summary header, on block 1.
 }
 
@@ -86,6 +102,8 @@ impl From<&ImageLayer> for Summary {
 
             index_start_blk: 0,
             index_root_blk: 0,
+            compression: CompressionAlgorithm::None,
+            checksums_start_blk: 0,
         }
     }
 }
@@ -106,6 +124,9 @@ pub struct ImageLayer {
     // This entry contains an image of all pages as of this LSN
     pub lsn: Lsn,
 
+    /// When the layer was last read, see `Layer::last_access`
+    access_time: LayerAccessTime,
+
     inner: RwLock<ImageLayerInner>,
 }
 
@@ -116,6 +137,7 @@ pub struct ImageLayerInner {
     // values copied from summary
     index_start_blk: u32,
     index_root_blk: u32,
+    compression: CompressionAlgorithm,
 
     /// Reader object for reading blocks from the file. (None if not loaded yet)
     file: Option<FileBlockReader<VirtualFile>>,
@@ -157,6 +179,7 @@ impl Layer for ImageLayer {
         assert!(self.key_range.contains(&key));
         assert!(lsn_range.start >= self.lsn);
         assert!(lsn_range.end >= self.lsn);
+        self.access_time.touch();
 
         let inner = self.load()?;
 
@@ -166,13 +189,16 @@ impl Layer for ImageLayer {
         let mut keybuf: [u8; KEY_SIZE] = [0u8; KEY_SIZE];
         key.write_to_byte_slice(&mut keybuf);
         if let Some(offset) = tree_reader.get(&keybuf)? {
-            let blob = file.block_cursor().read_blob(offset).with_context(|| {
-                format!(
-                    "failed to read value from data file {} at offset {}",
-                    self.filename().display(),
-                    offset
-                )
-            })?;
+            let blob = file
+                .block_cursor()
+                .read_blob_decompressed(offset, inner.compression)
+                .with_context(|| {
+                    format!(
+                        "failed to read value from data file {} at offset {}",
+                        self.filename().display(),
+                        offset
+                    )
+                })?;
             let value = Bytes::from(blob);
 
             reconstruct_state.img = Some((self.lsn, value));
@@ -192,6 +218,24 @@ impl Layer for ImageLayer {
         Ok(())
     }
 
+    fn scrub(&self, throttle: &mut dyn FnMut(u64)) -> Result<()> {
+        // Use a separate file handle, so that the blocks are read from disk
+        // rather than the page cache.
+        let path = self.path();
+        let file = VirtualFile::open(&path)
+            .with_context(|| format!("Failed to open file '{}'", path.display()))?;
+        let mut file = FileBlockReader::new(file);
+
+        let mut summary_blk = [0u8; PAGE_SZ];
+        file.file.read_exact_at(&mut summary_blk, 0)?;
+        let summary = Summary::des_prefix(&summary_blk)?;
+        if summary.checksums_start_blk != 0 {
+            file.load_checksums(summary.checksums_start_blk)?;
+            file.scrub(throttle)?;
+        }
+        Ok(())
+    }
+
     fn is_incremental(&self) -> bool {
         false
     }
@@ -200,6 +244,14 @@ impl Layer for ImageLayer {
         false
     }
 
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn last_access(&self) -> Option<SystemTime> {
+        Some(self.access_time.get())
+    }
+
     /// debugging function to print out the contents of the layer
     fn dump(&self, verbose: bool) -> Result<()> {
         println!(
@@ -304,12 +356,21 @@ impl ImageLayer {
         let file = inner.file.as_mut().unwrap();
         let summary_blk = file.read_blk(0)?;
         let actual_summary = Summary::des_prefix(summary_blk.as_ref())?;
+        ensure!(
+            (MIN_SUPPORTED_STORAGE_FORMAT_VERSION..=STORAGE_FORMAT_VERSION)
+                .contains(&actual_summary.format_version),
+            "unsupported format version {}",
+            actual_summary.format_version
+        );
 
         match &self.path_or_conf {
             PathOrConf::Conf(_) => {
                 let mut expected_summary = Summary::from(self);
+                expected_summary.format_version = actual_summary.format_version;
                 expected_summary.index_start_blk = actual_summary.index_start_blk;
                 expected_summary.index_root_blk = actual_summary.index_root_blk;
+                expected_summary.compression = actual_summary.compression;
+                expected_summary.checksums_start_blk = actual_summary.checksums_start_blk;
 
                 if actual_summary != expected_summary {
                     bail!("in-file summary does not match expected summary. actual = {:?} expected = {:?}", actual_summary, expected_summary);
@@ -331,6 +392,16 @@ impl ImageLayer {
 
         inner.index_start_blk = actual_summary.index_start_blk;
         inner.index_root_blk = actual_summary.index_root_blk;
+        inner.compression = actual_summary.compression;
+
+        // Verify the rest of the file as it's read. The summary block was
+        // read before we had the checksums, so check it now.
+        if actual_summary.checksums_start_blk != 0 {
+            let file = inner.file.as_mut().unwrap();
+            file.load_checksums(actual_summary.checksums_start_blk)
+                .with_context(|| format!("Failed to read checksums from '{}'", path.display()))?;
+            file.verify_blk(0, summary_blk.as_ref())?;
+        }
         inner.loaded = true;
         Ok(())
     }
@@ -348,11 +419,13 @@ impl ImageLayer {
             tenantid,
             key_range: filename.key_range.clone(),
             lsn: filename.lsn,
+            access_time: LayerAccessTime::new(),
             inner: RwLock::new(ImageLayerInner {
                 loaded: false,
                 file: None,
                 index_start_blk: 0,
                 index_root_blk: 0,
+                compression: CompressionAlgorithm::None,
             }),
         }
     }
@@ -375,11 +448,13 @@ impl ImageLayer {
             tenantid: summary.tenantid,
             key_range: summary.key_range,
             lsn: summary.lsn,
+            access_time: LayerAccessTime::new(),
             inner: RwLock::new(ImageLayerInner {
                 file: None,
                 loaded: false,
                 index_start_blk: 0,
                 index_root_blk: 0,
+                compression: CompressionAlgorithm::None,
             }),
         })
     }
@@ -421,8 +496,9 @@ pub struct ImageLayerWriter {
     key_range: Range<Key>,
     lsn: Lsn,
 
-    blob_writer: WriteBlobWriter<VirtualFile>,
+    blob_writer: WriteBlobWriter<ChecksumWriter<VirtualFile>>,
     tree: DiskBtreeBuilder<BlockBuf, KEY_SIZE>,
+    compression: CompressionAlgorithm,
 }
 
 impl ImageLayerWriter {
@@ -432,6 +508,7 @@ impl ImageLayerWriter {
         tenantid: ZTenantId,
         key_range: &Range<Key>,
         lsn: Lsn,
+        compression: CompressionAlgorithm,
     ) -> anyhow::Result<ImageLayerWriter> {
         // Create the file initially with a temporary filename.
         // We'll atomically rename it to the final name when we're done.
@@ -448,7 +525,8 @@ impl ImageLayerWriter {
         let mut file = VirtualFile::create(&path)?;
         // make room for the header block
         file.seek(SeekFrom::Start(PAGE_SZ as u64))?;
-        let blob_writer = WriteBlobWriter::new(file, PAGE_SZ as u64);
+        let blob_writer =
+            WriteBlobWriter::new(ChecksumWriter::new(file), PAGE_SZ as u64, compression);
 
         // Initialize the b-tree index builder
         let block_buf = BlockBuf::new();
@@ -463,6 +541,7 @@ impl ImageLayerWriter {
             lsn,
             tree: tree_builder,
             blob_writer,
+            compression,
         };
 
         Ok(writer)
@@ -488,13 +567,14 @@ impl ImageLayerWriter {
         let index_start_blk =
             ((self.blob_writer.size() + PAGE_SZ as u64 - 1) / PAGE_SZ as u64) as u32;
 
-        let mut file = self.blob_writer.into_inner();
+        let mut writer = self.blob_writer.into_inner();
 
         // Write out the index
-        file.seek(SeekFrom::Start(index_start_blk as u64 * PAGE_SZ as u64))?;
+        writer.pad_to_block()?;
+        assert_eq!(writer.next_blk(), index_start_blk);
         let (index_root_blk, block_buf) = self.tree.finish()?;
         for buf in block_buf.blocks {
-            file.write_all(buf.as_ref())?;
+            writer.write_all(buf.as_ref())?;
         }
 
         // Fill in the summary on blk 0
@@ -507,9 +587,12 @@ impl ImageLayerWriter {
             lsn: self.lsn,
             index_start_blk,
             index_root_blk,
+            compression: self.compression,
+            checksums_start_blk: writer.next_blk(),
         };
-        file.seek(SeekFrom::Start(0))?;
-        Summary::ser_into(&summary, &mut file)?;
+        let mut summary_blk = [0u8; PAGE_SZ];
+        summary.ser_into_slice(&mut summary_blk)?;
+        let file = writer.finish(&summary_blk)?;
 
         // Note: Because we open the file in write-only mode, we cannot
         // reuse the same VirtualFile for reading later. That's why we don't
@@ -520,11 +603,13 @@ impl ImageLayerWriter {
             tenantid: self.tenantid,
             key_range: self.key_range.clone(),
             lsn: self.lsn,
+            access_time: LayerAccessTime::new(),
             inner: RwLock::new(ImageLayerInner {
                 loaded: false,
                 file: None,
                 index_start_blk,
                 index_root_blk,
+                compression: self.compression,
             }),
         };
 
@@ -5,7 +5,7 @@
 //! its position in the file, is kept in memory, though.
 //!
 use crate::config::PageServerConf;
-use crate::layered_repository::blob_io::{BlobCursor, BlobWriter};
+use crate::layered_repository::blob_io::{BlobCursor, BlobWriter, CompressionAlgorithm};
 use crate::layered_repository::block_io::BlockReader;
 use crate::layered_repository::delta_layer::{DeltaLayer, DeltaLayerWriter};
 use crate::layered_repository::ephemeral_file::EphemeralFile;
@@ -29,6 +29,7 @@ use std::fmt::Write as _;
 use std::ops::Range;
 use std::path::PathBuf;
 use std::sync::RwLock;
+use std::time::SystemTime;
 
 pub struct InMemoryLayer {
     conf: &'static PageServerConf,
@@ -171,6 +172,10 @@ impl Layer for InMemoryLayer {
         bail!("can't delete an InMemoryLayer")
     }
 
+    fn scrub(&self, _throttle: &mut dyn FnMut(u64)) -> Result<()> {
+        Ok(())
+    }
+
     fn is_incremental(&self) -> bool {
         // in-memory layer is always considered incremental.
         true
@@ -180,6 +185,14 @@ impl Layer for InMemoryLayer {
         true
     }
 
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn last_access(&self) -> Option<SystemTime> {
+        None
+    }
+
     /// debugging function to print out the contents of the layer
     fn dump(&self, verbose: bool) -> Result<()> {
         let inner = self.inner.read().unwrap();
@@ -309,8 +322,9 @@ impl InMemoryLayer {
 
     /// Write this frozen in-memory layer to disk.
     ///
-    /// Returns a new delta layer with all the same data as this in-memory layer
-    pub fn write_to_disk(&self) -> Result<DeltaLayer> {
+    /// Returns a new delta layer with all the same data as this in-memory layer.
+    /// The values are compressed with 'compression'.
+    pub fn write_to_disk(&self, compression: CompressionAlgorithm) -> Result<DeltaLayer> {
         // Grab the lock in read-mode. We hold it over the I/O, but because this
         // layer is not writeable anymore, no one should be trying to acquire the
         // write lock on it, so we shouldn't block anyone. There's one exception
@@ -328,6 +342,7 @@ impl InMemoryLayer {
             self.tenantid,
             Key::MIN,
             self.start_lsn..inner.end_lsn.unwrap(),
+            compression,
         )?;
 
         let mut buf = Vec::new();
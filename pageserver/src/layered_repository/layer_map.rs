@@ -9,7 +9,17 @@
 //! are frozen, and it is split up into new image and delta layers and the
 //! corresponding files are written to disk.
 //!
+//! To find the layer that holds a given key and LSN quickly, the on-disk
+//! layers are indexed by segment trees over the key space, see
+//! [`segment_tree`]. The index is built when it's first needed. Layers added
+//! after that are searched linearly until there are too many of them, and
+//! then the index is rebuilt. Removed layers are taken out of the index
+//! without rebuilding it.
+//!
+
+mod segment_tree;
 
+use self::segment_tree::LayerSegmentTree;
 use crate::layered_repository::storage_layer::Layer;
 use crate::layered_repository::storage_layer::{range_eq, range_overlaps};
 use crate::layered_repository::InMemoryLayer;
@@ -17,12 +27,17 @@ use crate::repository::Key;
 use anyhow::Result;
 use lazy_static::lazy_static;
 use metrics::{register_int_gauge, IntGauge};
+use once_cell::sync::OnceCell;
 use std::collections::VecDeque;
 use std::ops::Range;
 use std::sync::Arc;
 use tracing::*;
 use utils::lsn::Lsn;
 
+/// Max number of historic layers added since the index was built, before it
+/// is rebuilt.
+const MAX_UNINDEXED_LAYERS: usize = 32;
+
 lazy_static! {
     static ref NUM_ONDISK_LAYERS: IntGauge =
         register_int_gauge!("pageserver_ondisk_layers", "Number of layers on-disk")
@@ -53,15 +68,107 @@ pub struct LayerMap {
     ///
     pub frozen_layers: VecDeque<Arc<InMemoryLayer>>,
 
-    /// All the historic layers are kept here
+    /// All the historic layers are kept here. The index refers to the layers
+    /// by their position, so a removed layer that is covered by the index
+    /// leaves a None behind, until the index is rebuilt.
+    historic_layers: Vec<Option<Arc<dyn Layer>>>,
+
+    /// Search index over 'historic_layers'. Built on first use. It covers the
+    /// layers that existed at that time, the ones added after it are at the
+    /// end of 'historic_layers'.
+    historic_index: OnceCell<HistoricIndex>,
+}
+
+///
+/// Search structures over the historic layers. The layers are identified by
+/// their position in LayerMap::historic_layers.
+///
+struct HistoricIndex {
+    /// Number of layers covered by the index
+    num_layers: usize,
+
+    images: LayerSegmentTree,
+    deltas: LayerSegmentTree,
+
+    /// (end LSN, position) of all delta layers, sorted by end LSN
+    deltas_by_end_lsn: Vec<(Lsn, usize)>,
 
-    /// TODO: This is a placeholder implementation of a data structure
-    /// to hold information about all the layer files on disk and in
-    /// S3. Currently, it's just a vector and all operations perform a
-    /// linear scan over it.  That obviously becomes slow as the
-    /// number of layers grows. I'm imagining that an R-tree or some
-    /// other 2D data structure would be the long-term solution here.
-    historic_layers: Vec<Arc<dyn Layer>>,
+    /// (key, start LSN) for the start and end keys of all layers, sorted by key
+    key_bounds: Vec<(Key, Lsn)>,
+}
+
+impl HistoricIndex {
+    fn build(layers: &[Option<Arc<dyn Layer>>]) -> Self {
+        let descs = || {
+            layers.iter().enumerate().filter_map(|(pos, l)| {
+                l.as_ref().map(|l| {
+                    (
+                        pos,
+                        l.is_incremental(),
+                        l.get_key_range(),
+                        l.get_lsn_range(),
+                    )
+                })
+            })
+        };
+        let images = LayerSegmentTree::build(
+            descs()
+                .filter(|(_, is_incremental, ..)| !is_incremental)
+                .map(|(pos, _, key_range, lsn_range)| (pos, key_range, lsn_range)),
+        );
+        let deltas = LayerSegmentTree::build(
+            descs()
+                .filter(|(_, is_incremental, ..)| *is_incremental)
+                .map(|(pos, _, key_range, lsn_range)| (pos, key_range, lsn_range)),
+        );
+
+        let mut deltas_by_end_lsn: Vec<(Lsn, usize)> = descs()
+            .filter(|(_, is_incremental, ..)| *is_incremental)
+            .map(|(pos, _, _, lsn_range)| (lsn_range.end, pos))
+            .collect();
+        deltas_by_end_lsn.sort_unstable();
+
+        let mut key_bounds: Vec<(Key, Lsn)> = descs()
+            .flat_map(|(_, _, key_range, lsn_range)| {
+                [
+                    (key_range.start, lsn_range.start),
+                    (key_range.end, lsn_range.start),
+                ]
+            })
+            .collect();
+        key_bounds.sort_unstable();
+
+        HistoricIndex {
+            num_layers: layers.len(),
+            images,
+            deltas,
+            deltas_by_end_lsn,
+            key_bounds,
+        }
+    }
+
+    /// Remove the layer at 'pos' from the search structures
+    fn remove(&mut self, pos: usize, layer: &dyn Layer) {
+        let key_range = layer.get_key_range();
+        let lsn_range = layer.get_lsn_range();
+        if layer.is_incremental() {
+            self.deltas.remove(pos, &key_range, &lsn_range);
+            let i = self
+                .deltas_by_end_lsn
+                .binary_search(&(lsn_range.end, pos))
+                .expect("delta layer not found in the index");
+            self.deltas_by_end_lsn.remove(i);
+        } else {
+            self.images.remove(pos, &key_range, &lsn_range);
+        }
+        for bound in [key_range.start, key_range.end] {
+            let i = self
+                .key_bounds
+                .binary_search(&(bound, lsn_range.start))
+                .expect("layer key bound not found in the index");
+            self.key_bounds.remove(i);
+        }
+    }
 }
 
 /// Return value of LayerMap::search
@@ -83,71 +190,23 @@ impl LayerMap {
     /// layer.
     ///
     pub fn search(&self, key: Key, end_lsn: Lsn) -> Result<Option<SearchResult>> {
-        // linear search
         // Find the latest image layer that covers the given key
-        let mut latest_img: Option<Arc<dyn Layer>> = None;
-        let mut latest_img_lsn: Option<Lsn> = None;
-        for l in self.historic_layers.iter() {
-            if l.is_incremental() {
-                continue;
-            }
-            if !l.get_key_range().contains(&key) {
-                continue;
-            }
-            let img_lsn = l.get_lsn_range().start;
-
-            if img_lsn >= end_lsn {
-                // too new
-                continue;
-            }
+        let latest_img = self.find_latest_image(key, end_lsn).map(Arc::clone);
+        let latest_img_lsn = latest_img.as_ref().map(|l| l.get_lsn_range().start);
+        if let Some(img_lsn) = latest_img_lsn {
             if Lsn(img_lsn.0 + 1) == end_lsn {
                 // found exact match
                 return Ok(Some(SearchResult {
-                    layer: Arc::clone(l),
+                    layer: latest_img.unwrap(),
                     lsn_floor: img_lsn,
                 }));
             }
-            if img_lsn > latest_img_lsn.unwrap_or(Lsn(0)) {
-                latest_img = Some(Arc::clone(l));
-                latest_img_lsn = Some(img_lsn);
-            }
         }
 
-        // Search the delta layers
-        let mut latest_delta: Option<Arc<dyn Layer>> = None;
-        for l in self.historic_layers.iter() {
-            if !l.is_incremental() {
-                continue;
-            }
-            if !l.get_key_range().contains(&key) {
-                continue;
-            }
-
-            if l.get_lsn_range().start >= end_lsn {
-                // too new
-                continue;
-            }
-
-            if l.get_lsn_range().end >= end_lsn {
-                // this layer contains the requested point in the key/lsn space.
-                // No need to search any further
-                trace!(
-                    "found layer {} for request on {key} at {end_lsn}",
-                    l.filename().display(),
-                );
-                latest_delta.replace(Arc::clone(l));
-                break;
-            }
-            // this layer's end LSN is smaller than the requested point. If there's
-            // nothing newer, this is what we need to return. Remember this.
-            if let Some(old_candidate) = &latest_delta {
-                if l.get_lsn_range().end > old_candidate.get_lsn_range().end {
-                    latest_delta.replace(Arc::clone(l));
-                }
-            } else {
-                latest_delta.replace(Arc::clone(l));
-            }
-        }
+        // Search the delta layers. If there is a layer that contains the
+        // requested point in the key/lsn space, this finds it. Otherwise, this
+        // finds the layer with the greatest end LSN below the requested point.
+        let latest_delta = self.find_latest_delta(key, end_lsn).map(Arc::clone);
         if let Some(l) = latest_delta {
             trace!(
                 "found (old) layer {} for request on {key} at {end_lsn}",
@@ -177,7 +236,13 @@ impl LayerMap {
     /// Insert an on-disk layer
     ///
     pub fn insert_historic(&mut self, layer: Arc<dyn Layer>) {
-        self.historic_layers.push(layer);
+        self.historic_layers.push(Some(layer));
+        if self.unindexed_layers().count() > MAX_UNINDEXED_LAYERS {
+            // Throw away the index, and the holes left by the removed layers
+            // with it. It is rebuilt on the next search.
+            self.historic_index.take();
+            self.historic_layers.retain(Option::is_some);
+        }
         NUM_ONDISK_LAYERS.inc();
     }
 
@@ -187,20 +252,96 @@ impl LayerMap {
     /// This should be called when the corresponding file on disk has been deleted.
     ///
     pub fn remove_historic(&mut self, layer: Arc<dyn Layer>) {
-        let len_before = self.historic_layers.len();
-
         // FIXME: ptr_eq might fail to return true for 'dyn'
         // references.  Clippy complains about this. In practice it
-        // seems to work, the assertion below would be triggered
+        // seems to work, the expect() below would be triggered
         // otherwise but this ought to be fixed.
         #[allow(clippy::vtable_address_comparisons)]
-        self.historic_layers
-            .retain(|other| !Arc::ptr_eq(other, &layer));
-
-        assert_eq!(self.historic_layers.len(), len_before - 1);
+        let pos = self
+            .historic_layers
+            .iter()
+            .position(|other| other.as_ref().map_or(false, |o| Arc::ptr_eq(o, &layer)))
+            .expect("layer not found in the layer map");
+
+        match self.historic_index.get_mut() {
+            Some(index) if pos < index.num_layers => {
+                index.remove(pos, layer.as_ref());
+                self.historic_layers[pos] = None;
+            }
+            _ => {
+                // Not covered by the index, nothing refers to its position
+                self.historic_layers.remove(pos);
+            }
+        }
         NUM_ONDISK_LAYERS.dec();
     }
 
+    fn historic_index(&self) -> &HistoricIndex {
+        self.historic_index
+            .get_or_init(|| HistoricIndex::build(&self.historic_layers))
+    }
+
+    /// Get the layer at a position returned by the index
+    fn indexed_layer(&self, pos: usize) -> &Arc<dyn Layer> {
+        self.historic_layers[pos]
+            .as_ref()
+            .expect("index refers to a removed layer")
+    }
+
+    /// Historic layers that are not covered by the index
+    fn unindexed_layers(&self) -> impl Iterator<Item = &Arc<dyn Layer>> {
+        let indexed = match self.historic_index.get() {
+            Some(index) => index.num_layers,
+            None => self.historic_layers.len(),
+        };
+        self.historic_layers[indexed..].iter().flatten()
+    }
+
+    /// Find the image layer that covers 'key', with the greatest LSN below 'end_lsn'
+    fn find_latest_image(&self, key: Key, end_lsn: Lsn) -> Option<&Arc<dyn Layer>> {
+        let index = self.historic_index();
+        let mut result = index
+            .images
+            .find_latest_start(key, end_lsn)
+            .map(|pos| self.indexed_layer(pos));
+
+        for l in self.unindexed_layers() {
+            if l.is_incremental()
+                || !l.get_key_range().contains(&key)
+                || l.get_lsn_range().start >= end_lsn
+            {
+                continue;
+            }
+            if result.map_or(true, |r| l.get_lsn_range().start > r.get_lsn_range().start) {
+                result = Some(l);
+            }
+        }
+        result
+    }
+
+    /// Find the delta layer that covers 'key' and starts below 'end_lsn', with
+    /// the greatest end LSN.
+    fn find_latest_delta(&self, key: Key, end_lsn: Lsn) -> Option<&Arc<dyn Layer>> {
+        let index = self.historic_index();
+        let mut result = index
+            .deltas
+            .find_latest_end(key, end_lsn)
+            .map(|pos| self.indexed_layer(pos));
+
+        for l in self.unindexed_layers() {
+            if !l.is_incremental()
+                || !l.get_key_range().contains(&key)
+                || l.get_lsn_range().start >= end_lsn
+            {
+                continue;
+            }
+            if result.map_or(true, |r| l.get_lsn_range().end > r.get_lsn_range().end) {
+                result = Some(l);
+            }
+        }
+        result
+    }
+
     /// Is there a newer image layer for given key- and LSN-range?
     ///
     /// This is used for garbage collection, to determine if an old layer can
@@ -212,64 +353,37 @@ impl LayerMap {
     ) -> Result<bool> {
         let mut range_remain = key_range.clone();
 
+        // Walk through the key range, jumping to the end of an image layer
+        // within the LSN range that covers the current position.
         loop {
-            let mut made_progress = false;
-            for l in self.historic_layers.iter() {
-                if l.is_incremental() {
-                    continue;
-                }
-                let img_lsn = l.get_lsn_range().start;
-                if !l.is_incremental()
-                    && l.get_key_range().contains(&range_remain.start)
-                    && lsn_range.contains(&img_lsn)
-                {
-                    made_progress = true;
-                    let img_key_end = l.get_key_range().end;
-
-                    if img_key_end >= range_remain.end {
-                        return Ok(true);
-                    }
-                    range_remain.start = img_key_end;
-                }
+            let l = match self.find_latest_image(range_remain.start, lsn_range.end) {
+                Some(l) => l,
+                None => return Ok(false),
+            };
+            if l.get_lsn_range().start < lsn_range.start {
+                return Ok(false);
             }
 
-            if !made_progress {
-                return Ok(false);
+            let img_key_end = l.get_key_range().end;
+            if img_key_end >= range_remain.end {
+                return Ok(true);
             }
+            range_remain.start = img_key_end;
         }
     }
 
-    pub fn iter_historic_layers(&self) -> impl Iterator<Item = &Arc<dyn Layer>> {
-        self.historic_layers.iter()
+    ///
+    /// Is there a historic layer with data at or after `lsn`, for any part of
+    /// the key range?
+    ///
+    pub fn newer_layer_exists(&self, key_range: &Range<Key>, lsn: Lsn) -> bool {
+        self.iter_historic_layers().any(|l| {
+            l.get_lsn_range().start >= lsn && range_overlaps(&l.get_key_range(), key_range)
+        })
     }
 
-    /// Find the last image layer that covers 'key', ignoring any image layers
-    /// newer than 'lsn'.
-    fn find_latest_image(&self, key: Key, lsn: Lsn) -> Option<Arc<dyn Layer>> {
-        let mut candidate_lsn = Lsn(0);
-        let mut candidate = None;
-        for l in self.historic_layers.iter() {
-            if l.is_incremental() {
-                continue;
-            }
-
-            if !l.get_key_range().contains(&key) {
-                continue;
-            }
-
-            let this_lsn = l.get_lsn_range().start;
-            if this_lsn > lsn {
-                continue;
-            }
-            if this_lsn < candidate_lsn {
-                // our previous candidate was better
-                continue;
-            }
-            candidate_lsn = this_lsn;
-            candidate = Some(Arc::clone(l));
-        }
-
-        candidate
+    pub fn iter_historic_layers(&self) -> impl Iterator<Item = &Arc<dyn Layer>> {
+        self.historic_layers.iter().flatten()
     }
 
     ///
@@ -285,17 +399,28 @@ impl LayerMap {
         key_range: &Range<Key>,
         lsn: Lsn,
     ) -> Result<Vec<(Range<Key>, Option<Arc<dyn Layer>>)>> {
+        let key_bounds = &self.historic_index().key_bounds;
+        let first = key_bounds.partition_point(|(key, _)| *key < key_range.start);
+
         let mut points = vec![key_range.start];
-        for l in self.historic_layers.iter() {
+        for (key, start_lsn) in key_bounds[first..].iter() {
+            if *key >= key_range.end {
+                break;
+            }
+            if *start_lsn <= lsn {
+                points.push(*key);
+            }
+        }
+        for l in self.unindexed_layers() {
             if l.get_lsn_range().start > lsn {
                 continue;
             }
             let range = l.get_key_range();
             if key_range.contains(&range.start) {
-                points.push(l.get_key_range().start);
+                points.push(range.start);
             }
             if key_range.contains(&range.end) {
-                points.push(l.get_key_range().end);
+                points.push(range.end);
             }
         }
         points.push(key_range.end);
@@ -309,7 +434,9 @@ impl LayerMap {
         let mut start = *points.first().unwrap();
         let mut ranges = Vec::new();
         for end in points[1..].iter() {
-            let img = self.find_latest_image(start, lsn);
+            // Find the last image layer that covers 'start', ignoring any
+            // image layers newer than 'lsn'.
+            let img = self.find_latest_image(start, lsn + 1).map(Arc::clone);
 
             ranges.push((start..*end, img));
 
@@ -320,12 +447,21 @@ impl LayerMap {
 
     /// Count how many L1 delta layers there are that overlap with the
     /// given key and LSN range.
+    ///
+    /// Only the delta layers that end after the start of the LSN range are
+    /// looked at. The LSN range is normally the range after the latest image
+    /// layer, so that's a small fraction of all the layers.
     pub fn count_deltas(&self, key_range: &Range<Key>, lsn_range: &Range<Lsn>) -> Result<usize> {
+        let deltas_by_end_lsn = &self.historic_index().deltas_by_end_lsn;
+        let first = deltas_by_end_lsn.partition_point(|(end_lsn, _)| *end_lsn <= lsn_range.start);
+
+        let indexed_deltas = deltas_by_end_lsn[first..]
+            .iter()
+            .map(|(_, pos)| self.indexed_layer(*pos));
+        let unindexed_deltas = self.unindexed_layers().filter(|l| l.is_incremental());
+
         let mut result = 0;
-        for l in self.historic_layers.iter() {
-            if !l.is_incremental() {
-                continue;
-            }
+        for l in indexed_deltas.chain(unindexed_deltas) {
             if !range_overlaps(&l.get_lsn_range(), lsn_range) {
                 continue;
             }
@@ -346,10 +482,28 @@ impl LayerMap {
         Ok(result)
     }
 
+    /// Count the historic layers that a lookup of 'key' at 'end_lsn' visits,
+    /// down to the latest image of the key.
+    ///
+    /// This is the worst case: a real lookup stops earlier if it finds a WAL
+    /// record that initializes the page.
+    pub fn read_amplification(&self, key: Key, end_lsn: Lsn) -> Result<usize> {
+        let mut lsn = end_lsn;
+        let mut result = 0;
+        while let Some(SearchResult { layer, lsn_floor }) = self.search(key, lsn)? {
+            result += 1;
+            if !layer.is_incremental() || lsn_floor >= lsn {
+                break;
+            }
+            lsn = lsn_floor;
+        }
+        Ok(result)
+    }
+
     /// Return all L0 delta layers
     pub fn get_level0_deltas(&self) -> Result<Vec<Arc<dyn Layer>>> {
         let mut deltas = Vec::new();
-        for l in self.historic_layers.iter() {
+        for l in self.iter_historic_layers() {
             if !l.is_incremental() {
                 continue;
             }
@@ -377,10 +531,248 @@ impl LayerMap {
         }
 
         println!("historic_layers:");
-        for layer in self.historic_layers.iter() {
+        for layer in self.iter_historic_layers() {
             layer.dump(verbose)?;
         }
         println!("End dump LayerMap");
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layered_repository::storage_layer::LayerDescriptor;
+    use rand::{Rng, SeedableRng};
+    use std::path::PathBuf;
+
+    fn key(i: u32) -> Key {
+        Key::MIN.add(i)
+    }
+
+    /// Generate a layer history that looks like what compaction produces: each
+    /// "generation" of WAL is either one L0 delta layer covering all keys, or
+    /// a set of L1 delta layers with random key boundaries. Now and then, image
+    /// layers are created.
+    fn generate_layers(rng: &mut impl Rng, generations: u64) -> Vec<Arc<dyn Layer>> {
+        let mut layers: Vec<Arc<dyn Layer>> = Vec::new();
+        for g in 0..generations {
+            let lsn = Lsn(g * 100 + 10)..Lsn(g * 100 + 110);
+            if rng.gen_bool(0.2) {
+                layers.push(Arc::new(LayerDescriptor {
+                    key: Key::MIN..Key::MAX,
+                    lsn,
+                    is_incremental: true,
+                }));
+            } else {
+                let mut start = 0;
+                while start < 1000 {
+                    let end = rng.gen_range(start + 1..=1000);
+                    layers.push(Arc::new(LayerDescriptor {
+                        key: key(start)..key(end),
+                        lsn: lsn.clone(),
+                        is_incremental: true,
+                    }));
+                    start = end;
+                }
+            }
+            if rng.gen_bool(0.2) {
+                let img_lsn = Lsn(g * 100 + 110 - rng.gen_range(1..50));
+                let mut start = rng.gen_range(0..500);
+                while start < 1000 {
+                    let end = rng.gen_range(start + 1..=1000);
+                    layers.push(Arc::new(LayerDescriptor {
+                        key: key(start)..key(end),
+                        lsn: img_lsn..img_lsn + 1,
+                        is_incremental: false,
+                    }));
+                    start = end + rng.gen_range(0..100);
+                }
+            }
+        }
+        layers
+    }
+
+    /// Find the layer for 'key' at 'end_lsn', by scanning all layers
+    fn search_linear(layers: &[Arc<dyn Layer>], key: Key, end_lsn: Lsn) -> Option<(PathBuf, Lsn)> {
+        let covering = |l: &&Arc<dyn Layer>| {
+            l.get_key_range().contains(&key) && l.get_lsn_range().start < end_lsn
+        };
+        let latest_img = layers
+            .iter()
+            .filter(|l| !l.is_incremental())
+            .filter(covering)
+            .max_by_key(|l| l.get_lsn_range().start);
+        let latest_delta = layers
+            .iter()
+            .filter(|l| l.is_incremental())
+            .filter(covering)
+            .max_by_key(|l| l.get_lsn_range().end);
+
+        match (latest_img, latest_delta) {
+            (Some(img), _) if img.get_lsn_range().end == end_lsn => {
+                Some((img.filename(), img.get_lsn_range().start))
+            }
+            (img, Some(delta)) => {
+                let img_end = img.map_or(Lsn(1), |img| img.get_lsn_range().end);
+                let lsn_floor = std::cmp::max(img_end, delta.get_lsn_range().start);
+                Some((delta.filename(), lsn_floor))
+            }
+            (Some(img), None) => Some((img.filename(), img.get_lsn_range().start)),
+            (None, None) => None,
+        }
+    }
+
+    fn check_search(map: &LayerMap, layers: &[Arc<dyn Layer>], rng: &mut impl Rng) {
+        for _ in 0..1000 {
+            let k = key(rng.gen_range(0..1010));
+            let lsn = Lsn(rng.gen_range(0..5100));
+            let result = map
+                .search(k, lsn)
+                .unwrap()
+                .map(|r| (r.layer.filename(), r.lsn_floor));
+            assert_eq!(result, search_linear(layers, k, lsn), "key {k} lsn {lsn}");
+        }
+    }
+
+    #[test]
+    fn search_matches_linear_scan() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let mut layers = generate_layers(&mut rng, 50);
+
+        // Insert the layers in batches, and search in between. The layers
+        // inserted after a search are not covered by the index until there
+        // are enough of them.
+        let mut map = LayerMap::default();
+        for (i, batch) in layers.chunks(20).enumerate() {
+            for l in batch {
+                map.insert_historic(Arc::clone(l));
+            }
+            check_search(&map, &layers[..i * 20 + batch.len()], &mut rng);
+        }
+
+        // Remove some layers, and check that the index is updated
+        for _ in 0..layers.len() / 2 {
+            let l = layers.swap_remove(rng.gen_range(0..layers.len()));
+            map.remove_historic(l);
+        }
+        check_search(&map, &layers, &mut rng);
+    }
+
+    #[test]
+    fn image_coverage_and_deltas() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let layers = generate_layers(&mut rng, 50);
+
+        // Leave some layers out of the index
+        let mut map = LayerMap::default();
+        let (indexed, unindexed) = layers.split_at(layers.len() - 10);
+        for l in indexed {
+            map.insert_historic(Arc::clone(l));
+        }
+        map.search(key(0), Lsn(100)).unwrap();
+        for l in unindexed {
+            map.insert_historic(Arc::clone(l));
+        }
+
+        for _ in 0..100 {
+            let start = rng.gen_range(0..1000);
+            let key_range = key(start)..key(rng.gen_range(start + 1..=1000));
+            let lsn = Lsn(rng.gen_range(0..5100));
+
+            let coverage = map.image_coverage(&key_range, lsn).unwrap();
+            assert_eq!(coverage.first().unwrap().0.start, key_range.start);
+            assert_eq!(coverage.last().unwrap().0.end, key_range.end);
+            for (range, img) in coverage {
+                let expected = layers
+                    .iter()
+                    .filter(|l| !l.is_incremental())
+                    .filter(|l| l.get_key_range().contains(&range.start))
+                    .filter(|l| l.get_lsn_range().start <= lsn)
+                    .max_by_key(|l| l.get_lsn_range().start);
+                assert_eq!(img.map(|l| l.filename()), expected.map(|l| l.filename()));
+
+                let img_lsn = Lsn(rng.gen_range(0..lsn.0 + 1));
+                let lsn_range = img_lsn..lsn;
+                let expected = layers
+                    .iter()
+                    .filter(|l| l.is_incremental())
+                    .filter(|l| range_overlaps(&l.get_lsn_range(), &lsn_range))
+                    .filter(|l| range_overlaps(&l.get_key_range(), &range))
+                    .filter(|l| l.get_key_range() != (Key::MIN..Key::MAX))
+                    .count();
+                assert_eq!(map.count_deltas(&range, &lsn_range).unwrap(), expected);
+            }
+
+            let lsn_range = Lsn(rng.gen_range(0..lsn.0 + 1))..lsn;
+            let mut covered = vec![false; (start..1000).len()];
+            for l in layers.iter() {
+                if !l.is_incremental() && lsn_range.contains(&l.get_lsn_range().start) {
+                    let range = l.get_key_range();
+                    for k in start..1000 {
+                        if range.contains(&key(k)) {
+                            covered[(k - start) as usize] = true;
+                        }
+                    }
+                }
+            }
+            let num_keys = (key_range.end.field6 - key_range.start.field6) as usize;
+            let expected = covered[..num_keys].iter().all(|c| *c);
+            assert_eq!(
+                map.image_layer_exists(&key_range, &lsn_range).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn read_amplification_of_runs() {
+        let run = |lsn: Range<Lsn>| -> Vec<Arc<dyn Layer>> {
+            vec![
+                Arc::new(LayerDescriptor {
+                    key: key(0)..key(500),
+                    lsn: lsn.clone(),
+                    is_incremental: true,
+                }),
+                Arc::new(LayerDescriptor {
+                    key: key(500)..key(1000),
+                    lsn,
+                    is_incremental: true,
+                }),
+            ]
+        };
+
+        let mut map = LayerMap::default();
+        map.insert_historic(Arc::new(LayerDescriptor {
+            key: key(0)..key(1000),
+            lsn: Lsn(10)..Lsn(11),
+            is_incremental: false,
+        }));
+        let mut runs = Vec::new();
+        for r in 0..8 {
+            let layers = run(Lsn(100 * r + 100)..Lsn(100 * r + 200));
+            for l in layers.iter() {
+                map.insert_historic(Arc::clone(l));
+            }
+            runs.push(layers);
+        }
+
+        // One layer from each run, and the image layer
+        assert_eq!(map.read_amplification(key(100), Lsn(900)).unwrap(), 9);
+        assert_eq!(map.read_amplification(key(700), Lsn(450)).unwrap(), 5);
+        assert_eq!(map.read_amplification(key(1000), Lsn(900)).unwrap(), 0);
+
+        // Merge the first four runs into one
+        for l in runs.drain(..4).flatten() {
+            map.remove_historic(l);
+        }
+        for l in run(Lsn(100)..Lsn(500)) {
+            map.insert_historic(l);
+        }
+        assert_eq!(map.read_amplification(key(100), Lsn(900)).unwrap(), 6);
+        assert_eq!(map.read_amplification(key(700), Lsn(450)).unwrap(), 2);
+
+        // An exact image match needs no deltas
+        assert_eq!(map.read_amplification(key(100), Lsn(11)).unwrap(), 1);
+    }
+}
@@ -0,0 +1,364 @@
+//!
+//! A static segment tree over the key space, used by the LayerMap to find the
+//! layers that cover a given key.
+//!
+//! The tree is built over the "elementary" key ranges delimited by the start
+//! and end keys of all the layers. Each layer is stored in the O(log n) nodes
+//! whose key ranges together make up the layer's key range, and the layers
+//! stored in each node are sorted by the start of their LSN range. To find the
+//! layers that cover a key, we walk down from the root to the leaf containing
+//! the key, and binary search the layers of each node on the way. A lookup is
+//! therefore O(log^2 n).
+//!
+//! Layers cannot be added to a tree once it has been built, but they can be
+//! removed. Removing a layer takes O(n) time in the worst case, as the
+//! layers stored in the same nodes are shifted down. The LayerMap builds a
+//! new tree when enough layers have been added.
+//!
+
+use crate::repository::Key;
+use std::ops::Range;
+use utils::lsn::Lsn;
+
+pub struct LayerSegmentTree {
+    /// Start and end keys of all the layers, sorted and deduplicated. Leaf 'i'
+    /// of the tree covers keys 'bounds[i]..bounds[i + 1]'.
+    bounds: Vec<Key>,
+
+    /// The children of node 'i' are nodes '2 * i + 1' and '2 * i + 2'. The
+    /// layers stored in node 'i' are 'entries[node_start[i]..node_end[i]]'.
+    /// They cover the node's whole key range, but not the parent's, and are
+    /// sorted by LSN range. The space up to 'node_start[i + 1]' is left over
+    /// from removed layers.
+    node_start: Vec<usize>,
+    node_end: Vec<usize>,
+    entries: Vec<Entry>,
+
+    /// For each entry, the position of the entry with the greatest end LSN
+    /// among the entries of the same node up to and including it.
+    max_end: Vec<usize>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    lsn_range: Range<Lsn>,
+    /// Caller-provided identifier of the layer
+    id: usize,
+}
+
+impl LayerSegmentTree {
+    ///
+    /// Build a tree from (id, key range, LSN range) triples. The ids are
+    /// returned from the searches, they are typically the layers' positions
+    /// in some other container.
+    ///
+    pub fn build(layers: impl IntoIterator<Item = (usize, Range<Key>, Range<Lsn>)>) -> Self {
+        let layers: Vec<_> = layers
+            .into_iter()
+            .filter(|(_, key_range, _)| key_range.start < key_range.end)
+            .collect();
+
+        let mut bounds: Vec<Key> = layers
+            .iter()
+            .flat_map(|(_, key_range, _)| [key_range.start, key_range.end])
+            .collect();
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        let num_leaves = bounds.len().saturating_sub(1);
+        let num_nodes = 4 * num_leaves;
+        let leaf_range = |key_range: &Range<Key>| {
+            bounds.binary_search(&key_range.start).unwrap()
+                ..bounds.binary_search(&key_range.end).unwrap()
+        };
+
+        // Count the entries in each node first, so that all the entries can be
+        // stored in one vector.
+        let mut node_start = vec![0; num_nodes + 1];
+        for (_, key_range, _) in layers.iter() {
+            decompose(0, 0..num_leaves, &leaf_range(key_range), &mut |node| {
+                node_start[node + 1] += 1
+            });
+        }
+        for node in 0..num_nodes {
+            node_start[node + 1] += node_start[node];
+        }
+
+        let mut next_pos = node_start.clone();
+        let empty = Entry {
+            lsn_range: Lsn(0)..Lsn(0),
+            id: 0,
+        };
+        let mut entries = vec![empty; node_start[num_nodes]];
+        for (id, key_range, lsn_range) in layers.iter() {
+            decompose(0, 0..num_leaves, &leaf_range(key_range), &mut |node| {
+                entries[next_pos[node]] = Entry {
+                    lsn_range: lsn_range.clone(),
+                    id: *id,
+                };
+                next_pos[node] += 1;
+            });
+        }
+
+        let mut max_end = Vec::with_capacity(entries.len());
+        for node in 0..num_nodes {
+            let node_entries = &mut entries[node_start[node]..node_start[node + 1]];
+            node_entries.sort_unstable_by_key(sort_key);
+
+            let mut max_pos = 0;
+            for (pos, e) in node_entries.iter().enumerate() {
+                if e.lsn_range.end >= node_entries[max_pos].lsn_range.end {
+                    max_pos = pos;
+                }
+                max_end.push(node_start[node] + max_pos);
+            }
+        }
+
+        LayerSegmentTree {
+            bounds,
+            node_end: node_start[1..].to_vec(),
+            node_start,
+            entries,
+            max_end,
+        }
+    }
+
+    ///
+    /// Remove a layer that was passed to 'build' with the same id, key range
+    /// and LSN range.
+    ///
+    pub fn remove(&mut self, id: usize, key_range: &Range<Key>, lsn_range: &Range<Lsn>) {
+        if key_range.start >= key_range.end {
+            return;
+        }
+        let leaves = self.bounds.binary_search(&key_range.start).unwrap()
+            ..self.bounds.binary_search(&key_range.end).unwrap();
+        let removed = Entry {
+            lsn_range: lsn_range.clone(),
+            id,
+        };
+
+        let mut nodes = Vec::new();
+        decompose(0, 0..self.bounds.len() - 1, &leaves, &mut |node| {
+            nodes.push(node)
+        });
+        for node in nodes {
+            let start = self.node_start[node];
+            let end = self.node_end[node];
+            let pos = start
+                + self.entries[start..end]
+                    .binary_search_by_key(&sort_key(&removed), sort_key)
+                    .expect("layer not found in the segment tree");
+            self.entries[pos..end].rotate_left(1);
+            self.node_end[node] = end - 1;
+
+            // Recompute the running maximum for the entries after the removed one
+            for p in pos..end - 1 {
+                let prev_max = if p == start { p } else { self.max_end[p - 1] };
+                self.max_end[p] =
+                    if self.entries[p].lsn_range.end >= self.entries[prev_max].lsn_range.end {
+                        p
+                    } else {
+                        prev_max
+                    };
+            }
+        }
+    }
+
+    /// Call 'f' for every node on the path from the root to the leaf containing
+    /// 'key', with the range of the node's entries.
+    fn visit_path(&self, key: Key, mut f: impl FnMut(Range<usize>)) {
+        if self.bounds.len() < 2 || key < self.bounds[0] || key >= *self.bounds.last().unwrap() {
+            return;
+        }
+        let leaf = self.bounds.partition_point(|b| *b <= key) - 1;
+
+        let mut node = 0;
+        let mut node_range = 0..self.bounds.len() - 1;
+        loop {
+            f(self.node_start[node]..self.node_end[node]);
+            if node_range.end - node_range.start == 1 {
+                break;
+            }
+            let mid = (node_range.start + node_range.end) / 2;
+            if leaf < mid {
+                node = 2 * node + 1;
+                node_range.end = mid;
+            } else {
+                node = 2 * node + 2;
+                node_range.start = mid;
+            }
+        }
+    }
+
+    /// Return the number of the node's entries that start below 'end_lsn'
+    fn count_below(&self, node_entries: &Range<usize>, end_lsn: Lsn) -> usize {
+        self.entries[node_entries.clone()].partition_point(|e| e.lsn_range.start < end_lsn)
+    }
+
+    ///
+    /// Of the layers that cover 'key' and start below 'end_lsn', find the one
+    /// with the greatest start LSN.
+    ///
+    pub fn find_latest_start(&self, key: Key, end_lsn: Lsn) -> Option<usize> {
+        let mut best: Option<&Entry> = None;
+        self.visit_path(key, |node_entries| {
+            let n = self.count_below(&node_entries, end_lsn);
+            if n > 0 {
+                let candidate = &self.entries[node_entries.start + n - 1];
+                if best.map_or(true, |b| sort_key(candidate) > sort_key(b)) {
+                    best = Some(candidate);
+                }
+            }
+        });
+        best.map(|e| e.id)
+    }
+
+    ///
+    /// Of the layers that cover 'key' and start below 'end_lsn', find the one
+    /// with the greatest end LSN.
+    ///
+    pub fn find_latest_end(&self, key: Key, end_lsn: Lsn) -> Option<usize> {
+        let mut best: Option<&Entry> = None;
+        self.visit_path(key, |node_entries| {
+            let n = self.count_below(&node_entries, end_lsn);
+            if n > 0 {
+                let candidate = &self.entries[self.max_end[node_entries.start + n - 1]];
+                if best.map_or(true, |b| candidate.lsn_range.end > b.lsn_range.end) {
+                    best = Some(candidate);
+                }
+            }
+        });
+        best.map(|e| e.id)
+    }
+}
+
+/// Call 'f' for each of the nodes that together make up the range of 'leaves'
+fn decompose(
+    node: usize,
+    node_range: Range<usize>,
+    leaves: &Range<usize>,
+    f: &mut impl FnMut(usize),
+) {
+    if leaves.start <= node_range.start && node_range.end <= leaves.end {
+        f(node);
+        return;
+    }
+    let mid = (node_range.start + node_range.end) / 2;
+    if leaves.start < mid {
+        decompose(2 * node + 1, node_range.start..mid, leaves, f);
+    }
+    if leaves.end > mid {
+        decompose(2 * node + 2, mid..node_range.end, leaves, f);
+    }
+}
+
+fn sort_key(e: &Entry) -> (Lsn, Lsn, usize) {
+    (e.lsn_range.start, e.lsn_range.end, e.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    fn key(i: u32) -> Key {
+        Key::MIN.add(i)
+    }
+
+    fn brute_force(
+        layers: &[(usize, Range<Key>, Range<Lsn>)],
+        key: Key,
+        end_lsn: Lsn,
+    ) -> Vec<&(usize, Range<Key>, Range<Lsn>)> {
+        layers
+            .iter()
+            .filter(|(_, key_range, lsn_range)| {
+                key_range.contains(&key) && lsn_range.start < end_lsn
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_tree() {
+        let tree = LayerSegmentTree::build(Vec::new());
+        assert_eq!(tree.find_latest_start(key(0), Lsn(100)), None);
+        assert_eq!(tree.find_latest_end(key(0), Lsn(100)), None);
+    }
+
+    #[test]
+    fn search() {
+        let tree = LayerSegmentTree::build(vec![
+            (0, key(0)..key(100), Lsn(10)..Lsn(20)),
+            (1, key(0)..key(50), Lsn(20)..Lsn(30)),
+            (2, key(50)..key(100), Lsn(20)..Lsn(25)),
+            (3, key(10)..key(20), Lsn(5)..Lsn(40)),
+        ]);
+
+        assert_eq!(tree.find_latest_start(key(5), Lsn(10)), None);
+        assert_eq!(tree.find_latest_start(key(5), Lsn(11)), Some(0));
+        assert_eq!(tree.find_latest_start(key(5), Lsn(100)), Some(1));
+        assert_eq!(tree.find_latest_start(key(99), Lsn(100)), Some(2));
+        assert_eq!(tree.find_latest_start(key(100), Lsn(100)), None);
+
+        assert_eq!(tree.find_latest_start(key(15), Lsn(10)), Some(3));
+        assert_eq!(tree.find_latest_end(key(15), Lsn(10)), Some(3));
+        assert_eq!(tree.find_latest_start(key(15), Lsn(100)), Some(1));
+        assert_eq!(tree.find_latest_end(key(15), Lsn(100)), Some(3));
+        assert_eq!(tree.find_latest_end(key(25), Lsn(100)), Some(1));
+    }
+
+    fn check_random_searches(
+        tree: &LayerSegmentTree,
+        layers: &[(usize, Range<Key>, Range<Lsn>)],
+        rng: &mut impl Rng,
+    ) {
+        let by_id = |id: usize| layers.iter().find(|l| l.0 == id).unwrap();
+        for _ in 0..1000 {
+            let k = key(rng.gen_range(0..1010));
+            let lsn = Lsn(rng.gen_range(0..1010));
+            let matching = brute_force(layers, k, lsn);
+
+            let latest_start = matching.iter().map(|l| l.2.start).max();
+            assert_eq!(
+                tree.find_latest_start(k, lsn).map(|id| by_id(id).2.start),
+                latest_start
+            );
+            let latest_end = matching.iter().map(|l| l.2.end).max();
+            assert_eq!(
+                tree.find_latest_end(k, lsn).map(|id| by_id(id).2.end),
+                latest_end
+            );
+        }
+    }
+
+    #[test]
+    fn search_random() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+
+        for _ in 0..20 {
+            let mut layers: Vec<_> = (0..rng.gen_range(1..200))
+                .map(|id| {
+                    let start_key = rng.gen_range(0..1000);
+                    let end_key = rng.gen_range(start_key + 1..=1000);
+                    let start_lsn = rng.gen_range(0..1000);
+                    let end_lsn = rng.gen_range(start_lsn + 1..=1000);
+                    (
+                        id,
+                        key(start_key)..key(end_key),
+                        Lsn(start_lsn)..Lsn(end_lsn),
+                    )
+                })
+                .collect();
+            let mut tree = LayerSegmentTree::build(layers.clone());
+            check_random_searches(&tree, &layers, &mut rng);
+
+            // Remove half of the layers, and check that the searches skip them
+            for _ in 0..layers.len() / 2 {
+                let (id, key_range, lsn_range) = layers.swap_remove(rng.gen_range(0..layers.len()));
+                tree.remove(id, &key_range, &lsn_range);
+            }
+            check_random_searches(&tree, &layers, &mut rng);
+        }
+    }
+}
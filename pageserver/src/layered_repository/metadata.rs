@@ -17,7 +17,7 @@ use utils::{
 };
 
 use crate::config::PageServerConf;
-use crate::STORAGE_FORMAT_VERSION;
+use crate::{MIN_SUPPORTED_STORAGE_FORMAT_VERSION, STORAGE_FORMAT_VERSION};
 
 /// We assume that a write of up to METADATA_MAX_SIZE bytes is atomic.
 ///
@@ -108,8 +108,11 @@ impl TimelineMetadata {
             "metadata bytes size is wrong"
         );
         let hdr = TimelineMetadataHeader::des(&metadata_bytes[0..METADATA_HDR_SIZE])?;
+        // The metadata file format hasn't changed since the oldest supported
+        // version, only the layer file format has.
         ensure!(
-            hdr.format_version == STORAGE_FORMAT_VERSION,
+            (MIN_SUPPORTED_STORAGE_FORMAT_VERSION..=STORAGE_FORMAT_VERSION)
+                .contains(&hdr.format_version),
             "format version mismatch"
         );
         let metadata_size = hdr.size as usize;
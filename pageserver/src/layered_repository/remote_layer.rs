@@ -0,0 +1,157 @@
+//! A RemoteLayer is a placeholder for an image or delta layer whose file
+//! exists only in the remote storage. It knows the key and LSN range of the
+//! layer from the file name, but holds no data.
+//!
+//! The timeline downloads the file and replaces the RemoteLayer in the layer
+//! map with a regular ImageLayer or DeltaLayer, when a read first needs the
+//! layer. See LayeredTimeline::download_remote_layer.
+use crate::config::PageServerConf;
+use crate::layered_repository::filename::{DeltaFileName, ImageFileName};
+use crate::layered_repository::storage_layer::{
+    Layer, ValueReconstructResult, ValueReconstructState,
+};
+use crate::repository::{Key, Value};
+use anyhow::{anyhow, bail, Result};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use utils::{
+    lsn::Lsn,
+    zid::{ZTenantId, ZTimelineId},
+};
+
+pub struct RemoteLayer {
+    tenantid: ZTenantId,
+    timelineid: ZTimelineId,
+    key_range: Range<Key>,
+    lsn_range: Range<Lsn>,
+    is_incremental: bool,
+
+    /// Path that the layer file is downloaded to. It's also the key of the
+    /// file in the remote index.
+    path: PathBuf,
+}
+
+impl RemoteLayer {
+    pub fn new_img(
+        conf: &'static PageServerConf,
+        timelineid: ZTimelineId,
+        tenantid: ZTenantId,
+        fname: &ImageFileName,
+    ) -> RemoteLayer {
+        RemoteLayer {
+            tenantid,
+            timelineid,
+            key_range: fname.key_range.clone(),
+            lsn_range: fname.lsn..fname.lsn + 1,
+            is_incremental: false,
+            path: conf
+                .timeline_path(&timelineid, &tenantid)
+                .join(fname.to_string()),
+        }
+    }
+
+    pub fn new_delta(
+        conf: &'static PageServerConf,
+        timelineid: ZTimelineId,
+        tenantid: ZTenantId,
+        fname: &DeltaFileName,
+    ) -> RemoteLayer {
+        RemoteLayer {
+            tenantid,
+            timelineid,
+            key_range: fname.key_range.clone(),
+            lsn_range: fname.lsn_range.clone(),
+            is_incremental: true,
+            path: conf
+                .timeline_path(&timelineid, &tenantid)
+                .join(fname.to_string()),
+        }
+    }
+}
+
+impl Layer for RemoteLayer {
+    fn get_tenant_id(&self) -> ZTenantId {
+        self.tenantid
+    }
+
+    fn get_timeline_id(&self) -> ZTimelineId {
+        self.timelineid
+    }
+
+    fn get_key_range(&self) -> Range<Key> {
+        self.key_range.clone()
+    }
+
+    fn get_lsn_range(&self) -> Range<Lsn> {
+        self.lsn_range.clone()
+    }
+
+    fn filename(&self) -> PathBuf {
+        PathBuf::from(self.path.file_name().unwrap())
+    }
+
+    /// The file doesn't exist locally until it's downloaded, but GC and
+    /// compaction use the path to remove the remote copy of the layer.
+    fn local_path(&self) -> Option<PathBuf> {
+        Some(self.path.clone())
+    }
+
+    fn get_value_reconstruct_data(
+        &self,
+        _key: Key,
+        _lsn_range: Range<Lsn>,
+        _reconstruct_data: &mut ValueReconstructState,
+    ) -> Result<ValueReconstructResult> {
+        bail!(
+            "remote layer {} needs to be downloaded before reading",
+            self.filename().display()
+        )
+    }
+
+    fn is_incremental(&self) -> bool {
+        self.is_incremental
+    }
+
+    fn is_in_memory(&self) -> bool {
+        false
+    }
+
+    fn is_remote(&self) -> bool {
+        true
+    }
+
+    fn last_access(&self) -> Option<SystemTime> {
+        None
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<(Key, Lsn, Value)>> + '_> {
+        Box::new(std::iter::once(Err(anyhow!(
+            "remote layer {} needs to be downloaded before reading",
+            self.filename().display()
+        ))))
+    }
+
+    /// There's no local file to remove
+    fn delete(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn scrub(&self, _throttle: &mut dyn FnMut(u64)) -> Result<()> {
+        Ok(())
+    }
+
+    fn dump(&self, _verbose: bool) -> Result<()> {
+        println!(
+            "----- remote layer for ten {} tli {} keys {}-{} lsn {}-{} ----",
+            self.tenantid,
+            self.timelineid,
+            self.key_range.start,
+            self.key_range.end,
+            self.lsn_range.start,
+            self.lsn_range.end
+        );
+        Ok(())
+    }
+}
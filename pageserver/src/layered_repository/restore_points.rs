@@ -0,0 +1,154 @@
+//! Named restore points of a timeline.
+//!
+//! A restore point marks an LSN of a timeline that a branch can be created
+//! from later, for example right before a schema migration. GC keeps all the
+//! history needed to reconstruct the timeline at a restore point, the same
+//! way as it does for the branch points of child timelines, until the
+//! restore point is deleted.
+//!
+//! The restore points are stored in a file in the timeline directory, next to
+//! the metadata file, and in the timeline's remote index part, so that they
+//! survive an attach to another pageserver.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use utils::{
+    lsn::Lsn,
+    zid::{ZTenantId, ZTimelineId},
+};
+
+use crate::config::PageServerConf;
+
+/// The name of the file with the restore points of a timeline.
+pub const RESTORE_POINTS_FILE_NAME: &str = "restore_points";
+
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RestorePoint {
+    pub name: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub lsn: Lsn,
+    #[serde(with = "humantime_serde")]
+    pub created_at: SystemTime,
+}
+
+/// Contents of the restore points file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RestorePointsFile {
+    #[serde(default)]
+    restore_point: Vec<RestorePoint>,
+}
+
+pub fn restore_points_path(
+    conf: &'static PageServerConf,
+    timelineid: ZTimelineId,
+    tenantid: ZTenantId,
+) -> PathBuf {
+    conf.timeline_path(&timelineid, &tenantid)
+        .join(RESTORE_POINTS_FILE_NAME)
+}
+
+/// Load the restore points of a timeline. A timeline without the file has
+/// no restore points.
+pub fn load_restore_points(
+    conf: &'static PageServerConf,
+    timelineid: ZTimelineId,
+    tenantid: ZTenantId,
+) -> Result<Vec<RestorePoint>> {
+    let path = restore_points_path(conf, timelineid, tenantid);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read restore points file '{}'", path.display()))?;
+    let file: RestorePointsFile = toml_edit::easy::from_str(&content)
+        .with_context(|| format!("Failed to parse restore points file '{}'", path.display()))?;
+    Ok(file.restore_point)
+}
+
+/// Overwrite the restore points file of a timeline.
+///
+/// The new contents are written to a temporary file first, so that a crash
+/// can't leave a half-written file behind, and lose the restore points.
+pub fn save_restore_points(
+    conf: &'static PageServerConf,
+    timelineid: ZTimelineId,
+    tenantid: ZTenantId,
+    restore_points: &[RestorePoint],
+) -> Result<()> {
+    let path = restore_points_path(conf, timelineid, tenantid);
+    let temp_path = path.with_extension("temp");
+
+    let mut content = r#"# This file contains the named restore points of the timeline.
+# GC keeps the history needed to branch from them.
+
+"#
+    .to_string();
+    content += &toml_edit::easy::to_string(&RestorePointsFile {
+        restore_point: restore_points.to_vec(),
+    })?;
+
+    let mut file = fs::File::create(&temp_path)
+        .with_context(|| format!("Failed to create file '{}'", temp_path.display()))?;
+    file.write_all(content.as_bytes())
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("Failed to write file '{}'", temp_path.display()))?;
+    fs::rename(&temp_path, &path).with_context(|| {
+        format!(
+            "Failed to rename '{}' to '{}'",
+            temp_path.display(),
+            path.display()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repository::repo_harness::{RepoHarness, TIMELINE_ID};
+    use std::time::Duration;
+
+    #[test]
+    fn restore_points_roundtrip() -> Result<()> {
+        let harness = RepoHarness::create("restore_points_roundtrip")?;
+        fs::create_dir_all(harness.timeline_path(&TIMELINE_ID))?;
+
+        // No file means no restore points
+        assert!(load_restore_points(harness.conf, TIMELINE_ID, harness.tenant_id)?.is_empty());
+
+        let restore_points = vec![
+            RestorePoint {
+                name: "before-migration-42".to_string(),
+                lsn: Lsn(0x10),
+                created_at: SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000),
+            },
+            RestorePoint {
+                name: "nightly".to_string(),
+                lsn: Lsn(0x20),
+                created_at: SystemTime::UNIX_EPOCH + Duration::from_secs(2_000_000),
+            },
+        ];
+        save_restore_points(
+            harness.conf,
+            TIMELINE_ID,
+            harness.tenant_id,
+            &restore_points,
+        )?;
+        assert_eq!(
+            load_restore_points(harness.conf, TIMELINE_ID, harness.tenant_id)?,
+            restore_points
+        );
+
+        save_restore_points(harness.conf, TIMELINE_ID, harness.tenant_id, &[])?;
+        assert!(load_restore_points(harness.conf, TIMELINE_ID, harness.tenant_id)?.is_empty());
+
+        Ok(())
+    }
+}
@@ -2,12 +2,15 @@
 //! Common traits and structs for layers
 //!
 
+use crate::layered_repository::filename::{DeltaFileName, ImageFileName};
 use crate::repository::{Key, Value};
 use crate::walrecord::ZenithWalRecord;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use bytes::Bytes;
 use std::ops::Range;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
 
 use utils::{
     lsn::Lsn,
@@ -136,12 +139,149 @@ pub trait Layer: Send + Sync {
     /// Returns true for layers that are represented in memory.
     fn is_in_memory(&self) -> bool;
 
+    /// Returns true for layers whose file exists only in the remote storage,
+    /// and needs to be downloaded before the layer can be read.
+    fn is_remote(&self) -> bool;
+
+    /// Returns when the layer was last read. Only layers with a local file,
+    /// which can be evicted to free up disk space, track their accesses.
+    fn last_access(&self) -> Option<SystemTime>;
+
     /// Iterate through all keys and values stored in the layer
     fn iter(&self) -> Box<dyn Iterator<Item = Result<(Key, Lsn, Value)>> + '_>;
 
     /// Permanently remove this layer from disk.
     fn delete(&self) -> Result<()>;
 
+    ///
+    /// Re-read the layer file from disk and verify the checksums of all its
+    /// blocks. 'throttle' is called with the number of bytes read as the
+    /// scrub progresses. Layers that are not stored on disk, or were written
+    /// before checksums were added, have nothing to verify.
+    ///
+    fn scrub(&self, throttle: &mut dyn FnMut(u64)) -> Result<()>;
+
     /// Dump summary of the contents of the layer to stdout
     fn dump(&self, verbose: bool) -> Result<()>;
 }
+
+/// Time of the last read of a layer file, used to choose the layers to evict
+/// when the disk fills up. It's stored as milliseconds since the UNIX epoch,
+/// so that reads can update it without taking a lock.
+pub struct LayerAccessTime(AtomicU64);
+
+impl LayerAccessTime {
+    /// Layers start out as accessed at the time they're created or loaded.
+    pub fn new() -> Self {
+        let access_time = LayerAccessTime(AtomicU64::new(0));
+        access_time.touch();
+        access_time
+    }
+
+    pub fn touch(&self) {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        self.0.store(now.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(self.0.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for LayerAccessTime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A layer that has a key and LSN range, but no data. It is used to
+/// populate a LayerMap in tests and benchmarks, without any files.
+#[derive(Clone, Debug)]
+pub struct LayerDescriptor {
+    pub key: Range<Key>,
+    pub lsn: Range<Lsn>,
+    pub is_incremental: bool,
+}
+
+impl Layer for LayerDescriptor {
+    fn get_tenant_id(&self) -> ZTenantId {
+        ZTenantId::from([0u8; 16])
+    }
+
+    fn get_timeline_id(&self) -> ZTimelineId {
+        ZTimelineId::from([0u8; 16])
+    }
+
+    fn get_key_range(&self) -> Range<Key> {
+        self.key.clone()
+    }
+
+    fn get_lsn_range(&self) -> Range<Lsn> {
+        self.lsn.clone()
+    }
+
+    fn filename(&self) -> PathBuf {
+        let name = if self.is_incremental {
+            DeltaFileName {
+                key_range: self.key.clone(),
+                lsn_range: self.lsn.clone(),
+            }
+            .to_string()
+        } else {
+            ImageFileName {
+                key_range: self.key.clone(),
+                lsn: self.lsn.start,
+            }
+            .to_string()
+        };
+        PathBuf::from(name)
+    }
+
+    fn local_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    fn get_value_reconstruct_data(
+        &self,
+        _key: Key,
+        _lsn_range: Range<Lsn>,
+        _reconstruct_data: &mut ValueReconstructState,
+    ) -> Result<ValueReconstructResult> {
+        bail!("layer descriptor {} has no data", self.filename().display())
+    }
+
+    fn is_incremental(&self) -> bool {
+        self.is_incremental
+    }
+
+    fn is_in_memory(&self) -> bool {
+        false
+    }
+
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    fn last_access(&self) -> Option<SystemTime> {
+        None
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<(Key, Lsn, Value)>> + '_> {
+        Box::new(std::iter::empty())
+    }
+
+    fn delete(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn scrub(&self, _throttle: &mut dyn FnMut(u64)) -> Result<()> {
+        Ok(())
+    }
+
+    fn dump(&self, _verbose: bool) -> Result<()> {
+        println!("----- layer descriptor {} ----", self.filename().display());
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 pub mod basebackup;
 pub mod config;
+pub mod disk_usage_eviction;
 pub mod http;
 pub mod import_datadir;
 pub mod keyspace;
@@ -13,8 +14,10 @@ pub mod repository;
 pub mod storage_sync;
 pub mod tenant_config;
 pub mod tenant_mgr;
+pub mod tenant_size;
 pub mod tenant_threads;
 pub mod thread_mgr;
+pub mod throttle;
 pub mod timelines;
 pub mod virtual_file;
 pub mod walingest;
@@ -24,7 +27,6 @@ pub mod walredo;
 
 use lazy_static::lazy_static;
 use tracing::info;
-use utils::postgres_backend;
 
 use crate::thread_mgr::ThreadKind;
 use metrics::{register_int_gauge_vec, IntGaugeVec};
@@ -37,7 +39,13 @@ use pgdatadir_mapping::DatadirTimeline;
 /// This is embedded in the metadata file, and also in the header of all the
 /// layer files. If you make any backwards-incompatible changes to the storage
 /// format, bump this!
-pub const STORAGE_FORMAT_VERSION: u16 = 3;
+///
+/// Version 4 added optional compression of the blobs in layer files.
+/// Version 5 added checksums of the blocks in layer files.
+pub const STORAGE_FORMAT_VERSION: u16 = 5;
+
+/// Oldest storage format version that can still be read
+pub const MIN_SUPPORTED_STORAGE_FORMAT_VERSION: u16 = 3;
 
 // Magic constants used to identify different kinds of files
 pub const IMAGE_FILE_MAGIC: u16 = 0x5A60;
@@ -69,12 +77,11 @@ pub type DatadirTimelineImpl = DatadirTimeline<RepositoryImpl>;
 
 pub fn shutdown_pageserver(exit_code: i32) {
     // Shut down the libpq endpoint thread. This prevents new connections from
-    // being accepted.
+    // being accepted, and closes the existing page service connections.
     thread_mgr::shutdown_threads(Some(ThreadKind::LibpqEndpointListener), None, None);
 
-    // Shut down any page service threads.
-    postgres_backend::set_pgbackend_shutdown_requested();
-    thread_mgr::shutdown_threads(Some(ThreadKind::PageRequestHandler), None, None);
+    // Stop evicting layer files before the tenants are shut down.
+    thread_mgr::shutdown_threads(Some(ThreadKind::DiskUsageEviction), None, None);
 
     // Shut down all the tenants. This flushes everything to disk and kills
     // the checkpoint and GC threads.
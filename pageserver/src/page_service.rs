@@ -2,55 +2,159 @@
 //! The Page Service listens for client connections and serves their GetPage@LSN
 //! requests.
 //
-//   It is possible to connect here using usual psql/pgbench/libpq. Following
-// commands are supported now:
-//     *status* -- show actual info about this pageserver,
+//   It is possible to connect here using usual psql/pgbench/libpq. The most
+// important commands are:
 //     *pagestream* -- enter mode where smgr and pageserver talk with their
 //  custom protocol.
-//     *callmemaybe <zenith timelineid> $url* -- ask pageserver to start walreceiver on $url
+//     *basebackup* -- send a tarball to start a compute node
+//     *callmemaybe* -- ask pageserver to start walreceiver
+//
+// The *help* command lists all of them. See page_service/command.rs for the
+// command grammar.
 //
 
 use anyhow::{bail, ensure, Context, Result};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use lazy_static::lazy_static;
-use regex::Regex;
 use std::io;
 use std::net::TcpListener;
 use std::str;
 use std::str::FromStr;
 use std::sync::{Arc, RwLockReadGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
 use tracing::*;
 use utils::{
     auth::{self, Claims, JwtAuth, Scope},
     lsn::Lsn,
-    postgres_backend::{self, is_socket_read_timed_out, AuthType, PostgresBackend},
-    pq_proto::{BeMessage, FeMessage, RowDescriptor, SINGLE_COL_ROWDESC},
+    postgres_backend::AuthType,
+    postgres_backend_async::{self, PostgresBackend},
+    pq_proto::{
+        BeMessage, FeMessage, QueryError, RowDescriptor, SINGLE_COL_ROWDESC,
+        SQLSTATE_FEATURE_NOT_SUPPORTED, SQLSTATE_INSUFFICIENT_PRIVILEGE,
+        SQLSTATE_INVALID_PARAMETER_VALUE,
+    },
     zid::{ZTenantId, ZTimelineId},
 };
 
+mod command;
+
+use self::command::{PageServiceCommand, COMMANDS};
 use crate::basebackup;
 use crate::config::{PageServerConf, ProfilingConfig};
-use crate::pgdatadir_mapping::{DatadirTimeline, LsnForTimestamp};
+use crate::pgdatadir_mapping::{BlockNumber, DatadirTimeline, LsnForTimestamp};
 use crate::profiling::profpoint_start;
-use crate::reltag::RelTag;
+use crate::reltag::{RelTag, SlruKind};
 use crate::repository::Repository;
 use crate::repository::Timeline;
 use crate::tenant_mgr;
-use crate::thread_mgr;
-use crate::thread_mgr::ThreadKind;
+use crate::thread_mgr::{self, RegisteredTask, ThreadKind};
+use crate::timelines;
+use crate::virtual_file::{self, IoBudgetMetrics, IoClass};
 use crate::walreceiver;
-use crate::CheckpointConfig;
-use metrics::{register_histogram_vec, HistogramVec};
+use crate::{CheckpointConfig, DatadirTimelineImpl};
+use metrics::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
 use postgres_ffi::xlog_utils::to_pg_timestamp;
 
 use postgres_ffi::pg_constants;
 
+/// Oldest pagestream protocol version that the server still speaks. Version 1
+/// is the original protocol, used by clients that don't pass a version in the
+/// `pagestream` command.
+const PAGESTREAM_MIN_PROTOCOL_VERSION: u32 = 1;
+/// Newest pagestream protocol version that the server speaks.
+const PAGESTREAM_MAX_PROTOCOL_VERSION: u32 = 2;
+
+/// Capability flag: the client may send batched GetPages requests.
+const PAGESTREAM_CAP_GET_PAGES: u64 = 1 << 0;
+/// Capability flag: the client may fetch individual SLRU pages, instead of
+/// relying on the SLRU segments included in the basebackup.
+const PAGESTREAM_CAP_GET_SLRU_PAGE: u64 = 1 << 1;
+/// All the capability flags this server knows about.
+const PAGESTREAM_SUPPORTED_CAPABILITIES: u64 =
+    PAGESTREAM_CAP_GET_PAGES | PAGESTREAM_CAP_GET_SLRU_PAGE;
+
+///
+/// Pagestream protocol version and capabilities agreed upon with the client
+/// at the start of the `pagestream` command.
+///
+/// Version 1 has no handshake and no capabilities. From version 2 onwards the
+/// client passes the version and the capabilities it wants, and the server
+/// replies with a Handshake message carrying the capabilities that were
+/// actually granted, before any other message.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PagestreamProtocol {
+    version: u32,
+    capabilities: u64,
+}
+
+impl PagestreamProtocol {
+    const V1: PagestreamProtocol = PagestreamProtocol {
+        version: 1,
+        capabilities: 0,
+    };
+
+    fn negotiate(version: u32, requested_capabilities: u64) -> Result<Self> {
+        ensure!(
+            (PAGESTREAM_MIN_PROTOCOL_VERSION..=PAGESTREAM_MAX_PROTOCOL_VERSION).contains(&version),
+            "unsupported pagestream protocol version {}, this pageserver supports versions {} to {}",
+            version,
+            PAGESTREAM_MIN_PROTOCOL_VERSION,
+            PAGESTREAM_MAX_PROTOCOL_VERSION
+        );
+        if version == 1 {
+            return Ok(Self::V1);
+        }
+        Ok(PagestreamProtocol {
+            version,
+            capabilities: requested_capabilities & PAGESTREAM_SUPPORTED_CAPABILITIES,
+        })
+    }
+
+    fn has_capability(&self, capability: u64) -> bool {
+        self.capabilities & capability != 0
+    }
+}
+
+//
+// Pagestream messages are wrapped in libpq CopyData. The tags 0-3 and 100-104
+// are the original protocol, and correspond to the ZenithMessageTag enum in
+// pagestore_client.h. The later additions aren't in the C client yet, so
+// their wire format is defined here. All integers are in network byte order.
+//
+// Requests:
+//
+//   4 GetPages     latest u8, lsn u64, nblocks u32, and then nblocks times
+//                  spcnode u32, dbnode u32, relnode u32, forknum u8, blkno u32
+//
+//   5 GetSlruPage  latest u8, lsn u64, kind u8, segno u32, blkno u32. The kind
+//                  is 0 for pg_xact, 1 for pg_multixact/members and 2 for
+//                  pg_multixact/offsets.
+//
+// Responses:
+//
+//   105 GetPages   nblocks u32, and then for each block in the order of the
+//                  request, either 0 followed by the 8 kB page, or 1 followed
+//                  by a null-terminated error message
+//
+//   106 GetSlruPage
+//                  the 8 kB page
+//
+//   110 Handshake  version u32, capabilities u64. Sent once, before anything
+//                  else, to clients that asked for protocol version 2 or later
+//                  in the `pagestream` command. See PagestreamProtocol.
+//
+
 // Wrapped in libpq CopyData
+#[derive(Debug)]
 enum PagestreamFeMessage {
     Exists(PagestreamExistsRequest),
     Nblocks(PagestreamNblocksRequest),
     GetPage(PagestreamGetPageRequest),
     DbSize(PagestreamDbSizeRequest),
+    GetPages(PagestreamGetPagesRequest),
+    GetSlruPage(PagestreamGetSlruPageRequest),
 }
 
 // Wrapped in libpq CopyData
@@ -60,6 +164,9 @@ enum PagestreamBeMessage {
     GetPage(PagestreamGetPageResponse),
     Error(PagestreamErrorResponse),
     DbSize(PagestreamDbSizeResponse),
+    GetPages(PagestreamGetPagesResponse),
+    Handshake(PagestreamHandshakeResponse),
+    GetSlruPage(PagestreamGetSlruPageResponse),
 }
 
 #[derive(Debug)]
@@ -91,6 +198,23 @@ struct PagestreamDbSizeRequest {
     dbnode: u32,
 }
 
+/// Request for several pages at once, all at the same LSN.
+#[derive(Debug)]
+struct PagestreamGetPagesRequest {
+    latest: bool,
+    lsn: Lsn,
+    pages: Vec<(RelTag, BlockNumber)>,
+}
+
+#[derive(Debug)]
+struct PagestreamGetSlruPageRequest {
+    latest: bool,
+    lsn: Lsn,
+    kind: SlruKind,
+    segno: u32,
+    blkno: BlockNumber,
+}
+
 #[derive(Debug)]
 struct PagestreamExistsResponse {
     exists: bool,
@@ -116,8 +240,41 @@ struct PagestreamDbSizeResponse {
     db_size: i64,
 }
 
+/// One entry per requested block, in the order of the request. A failure to
+/// read one block doesn't fail the whole batch.
+#[derive(Debug)]
+struct PagestreamGetPagesResponse {
+    pages: Vec<Result<Bytes, String>>,
+}
+
+#[derive(Debug)]
+struct PagestreamGetSlruPageResponse {
+    page: Bytes,
+}
+
+#[derive(Debug)]
+struct PagestreamHandshakeResponse {
+    protocol: PagestreamProtocol,
+}
+
+/// Upper limit on the number of blocks in a single GetPages request.
+const MAX_GET_PAGES_BATCH: usize = 1024;
+
+/// Size of the 'latest' flag, LSN and number of blocks at the start of a
+/// GetPages request, after the tag.
+const GET_PAGES_HEADER_SIZE: usize = 1 + 8 + 4;
+
+/// Size of one (RelTag, blkno) entry in a GetPages request.
+const GET_PAGES_ENTRY_SIZE: usize = 4 + 4 + 4 + 1 + 4;
+
+/// Size of a GetSlruPage request, after the tag.
+const GET_SLRU_PAGE_REQUEST_SIZE: usize = 1 + 8 + 1 + 4 + 4;
+
 impl PagestreamFeMessage {
-    fn parse(mut body: Bytes) -> anyhow::Result<PagestreamFeMessage> {
+    fn parse(
+        mut body: Bytes,
+        protocol: &PagestreamProtocol,
+    ) -> anyhow::Result<PagestreamFeMessage> {
         // TODO these gets can fail
 
         // these correspond to the ZenithMessageTag enum in pagestore_client.h
@@ -162,13 +319,94 @@ impl PagestreamFeMessage {
                 lsn: Lsn::from(body.get_u64()),
                 dbnode: body.get_u32(),
             })),
-            _ => bail!("unknown smgr message tag: {},'{:?}'", msg_tag, body),
+            4 => {
+                ensure!(
+                    protocol.has_capability(PAGESTREAM_CAP_GET_PAGES),
+                    "GetPages request was not negotiated for pagestream protocol version {} with capabilities {:#x}",
+                    protocol.version,
+                    protocol.capabilities
+                );
+                ensure!(
+                    body.remaining() >= GET_PAGES_HEADER_SIZE,
+                    "invalid GetPages request length {}",
+                    body.remaining()
+                );
+                let latest = body.get_u8() != 0;
+                let lsn = Lsn::from(body.get_u64());
+                let nblocks = body.get_u32() as usize;
+                ensure!(
+                    nblocks <= MAX_GET_PAGES_BATCH,
+                    "too many blocks in GetPages request: {}, max {}",
+                    nblocks,
+                    MAX_GET_PAGES_BATCH
+                );
+                ensure!(
+                    body.remaining() == nblocks * GET_PAGES_ENTRY_SIZE,
+                    "invalid GetPages request length {} for {} blocks",
+                    body.remaining(),
+                    nblocks
+                );
+                let mut pages = Vec::with_capacity(nblocks);
+                for _ in 0..nblocks {
+                    let rel = RelTag {
+                        spcnode: body.get_u32(),
+                        dbnode: body.get_u32(),
+                        relnode: body.get_u32(),
+                        forknum: body.get_u8(),
+                    };
+                    let blkno = body.get_u32();
+                    pages.push((rel, blkno));
+                }
+                Ok(PagestreamFeMessage::GetPages(PagestreamGetPagesRequest {
+                    latest,
+                    lsn,
+                    pages,
+                }))
+            }
+            5 => {
+                ensure!(
+                    protocol.has_capability(PAGESTREAM_CAP_GET_SLRU_PAGE),
+                    "GetSlruPage request was not negotiated for pagestream protocol version {} with capabilities {:#x}",
+                    protocol.version,
+                    protocol.capabilities
+                );
+                ensure!(
+                    body.remaining() == GET_SLRU_PAGE_REQUEST_SIZE,
+                    "invalid GetSlruPage request length {}",
+                    body.remaining()
+                );
+                Ok(PagestreamFeMessage::GetSlruPage(
+                    PagestreamGetSlruPageRequest {
+                        latest: body.get_u8() != 0,
+                        lsn: Lsn::from(body.get_u64()),
+                        kind: slru_kind_from_tag(body.get_u8())?,
+                        segno: body.get_u32(),
+                        blkno: body.get_u32(),
+                    },
+                ))
+            }
+            _ => bail!(
+                "unknown smgr message tag for pagestream protocol version {}: {},'{:?}'",
+                protocol.version,
+                msg_tag,
+                body
+            ),
         }
     }
 }
 
+// SLRU kinds in GetSlruPage requests, see the wire format above
+fn slru_kind_from_tag(tag: u8) -> Result<SlruKind> {
+    Ok(match tag {
+        0 => SlruKind::Clog,
+        1 => SlruKind::MultiXactMembers,
+        2 => SlruKind::MultiXactOffsets,
+        _ => bail!("unknown SLRU kind: {}", tag),
+    })
+}
+
 impl PagestreamBeMessage {
-    fn serialize(&self) -> Bytes {
+    fn serialize(&self, protocol: &PagestreamProtocol) -> Result<Bytes> {
         let mut bytes = BytesMut::new();
 
         match self {
@@ -196,9 +434,53 @@ impl PagestreamBeMessage {
                 bytes.put_u8(104); /* tag from pagestore_client.h */
                 bytes.put_i64(resp.db_size);
             }
+
+            Self::GetPages(resp) => {
+                ensure!(
+                    protocol.has_capability(PAGESTREAM_CAP_GET_PAGES),
+                    "GetPages response is not part of pagestream protocol version {}",
+                    protocol.version
+                );
+                bytes.put_u8(105); /* see the wire format above */
+                bytes.put_u32(resp.pages.len() as u32);
+                for page in resp.pages.iter() {
+                    match page {
+                        Ok(page) => {
+                            bytes.put_u8(0);
+                            bytes.put(&page[..]);
+                        }
+                        Err(message) => {
+                            bytes.put_u8(1);
+                            bytes.put(message.as_bytes());
+                            bytes.put_u8(0); // null terminator
+                        }
+                    }
+                }
+            }
+
+            Self::GetSlruPage(resp) => {
+                ensure!(
+                    protocol.has_capability(PAGESTREAM_CAP_GET_SLRU_PAGE),
+                    "GetSlruPage response is not part of pagestream protocol version {}",
+                    protocol.version
+                );
+                bytes.put_u8(106); /* see the wire format above */
+                bytes.put(&resp.page[..]);
+            }
+
+            Self::Handshake(resp) => {
+                ensure!(
+                    protocol.version >= 2,
+                    "Handshake is not part of pagestream protocol version {}",
+                    protocol.version
+                );
+                bytes.put_u8(110); /* see the wire format above */
+                bytes.put_u32(resp.protocol.version);
+                bytes.put_u64(resp.protocol.capabilities);
+            }
         }
 
-        bytes.into()
+        Ok(bytes.into())
     }
 }
 
@@ -216,17 +498,25 @@ pub fn thread_main(
     auth_type: AuthType,
 ) -> anyhow::Result<()> {
     listener.set_nonblocking(true)?;
-    let basic_rt = tokio::runtime::Builder::new_current_thread()
-        .enable_io()
+
+    // Each connection is served by a task on this runtime. Reading pages still
+    // involves blocking I/O and WAL redo, so it needs to be multi-threaded.
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .thread_name("page service worker")
+        .enable_all()
         .build()?;
 
     let tokio_listener = {
-        let _guard = basic_rt.enter();
+        let _guard = runtime.enter();
         tokio::net::TcpListener::from_std(listener)
     }?;
 
+    // The connection tasks watch this channel, to learn when the listener
+    // thread has been requested to shut down.
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+
     // Wait for a new connection to arrive, or for server shutdown.
-    while let Some(res) = basic_rt.block_on(async {
+    while let Some(res) = runtime.block_on(async {
         let shutdown_watcher = thread_mgr::shutdown_watcher();
         tokio::select! {
             biased;
@@ -243,25 +533,26 @@ pub fn thread_main(
     }) {
         match res {
             Ok((socket, peer_addr)) => {
-                // Connection established. Spawn a new thread to handle it.
+                // Connection established. Spawn a new task to handle it.
                 debug!("accepted connection from {}", peer_addr);
                 let local_auth = auth.clone();
-
-                // PageRequestHandler threads are not associated with any particular
-                // timeline in the thread manager. In practice most connections will
-                // only deal with a particular timeline, but we don't know which one
-                // yet.
-                if let Err(err) = thread_mgr::spawn(
-                    ThreadKind::PageRequestHandler,
-                    None,
-                    None,
-                    "serving Page Service thread",
-                    false,
-                    move || page_service_conn_main(conf, local_auth, socket, auth_type),
-                ) {
-                    // Thread creation failed. Log the error and continue.
-                    error!("could not spawn page service thread: {:?}", err);
-                }
+                let shutdown_rx = shutdown_rx.clone();
+
+                // The tasks are not associated with any particular timeline.
+                // In practice most connections will only deal with a particular
+                // timeline, but we don't know which one yet. A connection is
+                // registered with its timeline when it enters pagestream mode.
+                runtime.spawn(
+                    async move {
+                        if let Err(err) =
+                            page_service_conn_main(conf, local_auth, socket, auth_type, shutdown_rx)
+                                .await
+                        {
+                            error!("page service connection failed: {:?}", err);
+                        }
+                    }
+                    .instrument(info_span!("page_service_conn", peer = %peer_addr)),
+                );
             }
             Err(err) => {
                 // accept() failed. Log the error, and loop back to retry on next connection.
@@ -272,16 +563,25 @@ pub fn thread_main(
 
     debug!("page_service loop terminated");
 
+    // Tell all the connections to close, and give them some time to finish
+    // the requests that are already in progress.
+    let _ = shutdown_tx.send(());
+    runtime.shutdown_timeout(CONNECTION_SHUTDOWN_TIMEOUT);
+
     Ok(())
 }
 
-fn page_service_conn_main(
+/// How long to wait for the connections to close, on shutdown.
+const CONNECTION_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn page_service_conn_main(
     conf: &'static PageServerConf,
     auth: Option<Arc<JwtAuth>>,
     socket: tokio::net::TcpStream,
     auth_type: AuthType,
+    shutdown_rx: watch::Receiver<()>,
 ) -> anyhow::Result<()> {
-    // Immediately increment the gauge, then create a job to decrement it on thread exit.
+    // Immediately increment the gauge, then create a job to decrement it on task exit.
     // One of the pros of `defer!` is that this will *most probably*
     // get called, even in presence of panics.
     let gauge = crate::LIVE_CONNECTIONS_COUNT.with_label_values(&["page_service"]);
@@ -290,22 +590,16 @@ fn page_service_conn_main(
         gauge.dec();
     }
 
-    // We use Tokio to accept the connection, but the rest of the code works with a
-    // regular socket. Convert.
-    let socket = socket
-        .into_std()
-        .context("could not convert tokio::net:TcpStream to std::net::TcpStream")?;
-    socket
-        .set_nonblocking(false)
-        .context("could not put socket to blocking mode")?;
-
     socket
         .set_nodelay(true)
         .context("could not set TCP_NODELAY")?;
 
-    let mut conn_handler = PageServerHandler::new(conf, auth);
-    let pgbackend = PostgresBackend::new(socket, auth_type, None, true)?;
-    match pgbackend.run(&mut conn_handler) {
+    let mut conn_handler = PageServerHandler::new(conf, auth, shutdown_rx.clone());
+    let pgbackend = PostgresBackend::new(socket, auth_type)?;
+    match pgbackend
+        .run(&mut conn_handler, wait_for_shutdown(shutdown_rx))
+        .await
+    {
         Ok(()) => {
             // we've been requested to shut down
             Ok(())
@@ -330,11 +624,40 @@ fn page_service_conn_main(
     }
 }
 
+/// Completes when the page service is requested to shut down.
+async fn wait_for_shutdown(mut shutdown_rx: watch::Receiver<()>) {
+    // An error means that the sender is gone, which also means shutdown.
+    let _ = shutdown_rx.changed().await;
+}
+
+/// Completes when the page service is requested to shut down, or 'task' is.
+async fn wait_for_task_shutdown(shutdown_rx: watch::Receiver<()>, task: &RegisteredTask) {
+    tokio::select! {
+        _ = wait_for_shutdown(shutdown_rx) => {}
+        _ = task.shutdown_watcher() => {}
+    }
+}
+
+///
+/// Get a local timeline, loading it if it's not loaded yet.
+///
+/// Loading the layer map reads the remote storage index with a runtime of its
+/// own, which cannot be started on a runtime worker thread, so do it in
+/// block_in_place.
+///
+fn get_local_timeline_with_load(
+    tenant_id: ZTenantId,
+    timeline_id: ZTimelineId,
+) -> anyhow::Result<Arc<DatadirTimelineImpl>> {
+    tokio::task::block_in_place(|| tenant_mgr::get_local_timeline_with_load(tenant_id, timeline_id))
+}
+
 #[derive(Debug)]
 struct PageServerHandler {
     conf: &'static PageServerConf,
     auth: Option<Arc<JwtAuth>>,
     claims: Option<Claims>,
+    shutdown_rx: watch::Receiver<()>,
 }
 
 const TIME_BUCKETS: &[f64] = &[
@@ -353,98 +676,251 @@ lazy_static! {
         TIME_BUCKETS.into()
     )
     .expect("failed to define a metric");
+    static ref GETPAGE_THROTTLED_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "pageserver_getpage_throttled_requests_total",
+        "Number of GetPage@LSN requests delayed by the per-tenant rate limit",
+        &["tenant_id"]
+    )
+    .expect("failed to define a metric");
+    static ref GETPAGE_THROTTLE_WAIT_TIME: HistogramVec = register_histogram_vec!(
+        "pageserver_getpage_throttle_wait_seconds",
+        "Time GetPage@LSN requests spent waiting for the per-tenant rate limit",
+        &["tenant_id"],
+        vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]
+    )
+    .expect("failed to define a metric");
+}
+
+impl PagestreamFeMessage {
+    /// Name of the request, for the SMGR_QUERY_TIME metric
+    fn smgr_query_type(&self) -> &'static str {
+        match self {
+            PagestreamFeMessage::Exists(_) => "get_rel_exists",
+            PagestreamFeMessage::Nblocks(_) => "get_rel_size",
+            PagestreamFeMessage::GetPage(_) => "get_page_at_lsn",
+            PagestreamFeMessage::DbSize(_) => "get_db_size",
+            PagestreamFeMessage::GetPages(_) => "get_pages_at_lsn",
+            PagestreamFeMessage::GetSlruPage(_) => "get_slru_page_at_lsn",
+        }
+    }
+
+    /// The 'latest' flag and LSN of the request. See `wait_or_get_last_lsn`.
+    fn request_lsn(&self) -> (bool, Lsn) {
+        match self {
+            PagestreamFeMessage::Exists(req) => (req.latest, req.lsn),
+            PagestreamFeMessage::Nblocks(req) => (req.latest, req.lsn),
+            PagestreamFeMessage::GetPage(req) => (req.latest, req.lsn),
+            PagestreamFeMessage::DbSize(req) => (req.latest, req.lsn),
+            PagestreamFeMessage::GetPages(req) => (req.latest, req.lsn),
+            PagestreamFeMessage::GetSlruPage(req) => (req.latest, req.lsn),
+        }
+    }
+
+    /// Number of pages the request reads, charged against the tenant's
+    /// GetPage@LSN rate limit. Metadata requests are not throttled.
+    fn getpage_cost(&self) -> u64 {
+        match self {
+            PagestreamFeMessage::Exists(_) => 0,
+            PagestreamFeMessage::Nblocks(_) => 0,
+            PagestreamFeMessage::DbSize(_) => 0,
+            PagestreamFeMessage::GetPage(_) => 1,
+            PagestreamFeMessage::GetPages(req) => req.pages.len() as u64,
+            PagestreamFeMessage::GetSlruPage(_) => 1,
+        }
+    }
 }
 
 impl PageServerHandler {
-    pub fn new(conf: &'static PageServerConf, auth: Option<Arc<JwtAuth>>) -> Self {
+    pub fn new(
+        conf: &'static PageServerConf,
+        auth: Option<Arc<JwtAuth>>,
+        shutdown_rx: watch::Receiver<()>,
+    ) -> Self {
         PageServerHandler {
             conf,
             auth,
             claims: None,
+            shutdown_rx,
         }
     }
 
-    fn handle_pagerequests(
+    async fn handle_pagerequests(
         &self,
         pgb: &mut PostgresBackend,
         timelineid: ZTimelineId,
         tenantid: ZTenantId,
+        protocol: PagestreamProtocol,
     ) -> anyhow::Result<()> {
-        let _enter = info_span!("pagestream", timeline = %timelineid, tenant = %tenantid).entered();
+        // Register the connection with the timeline, so that it's closed when
+        // the timeline is detached, deleted or replaced. Otherwise it would
+        // keep serving pages from the old timeline object.
+        let task = thread_mgr::register_task(
+            ThreadKind::PageRequestHandler,
+            Some(tenantid),
+            Some(timelineid),
+            "pagestream connection",
+        );
 
         // Check that the timeline exists
-        let timeline = tenant_mgr::get_local_timeline_with_load(tenantid, timelineid)
+        let timeline = get_local_timeline_with_load(tenantid, timelineid)
             .context("Cannot load local timeline")?;
+        let repo = tenant_mgr::get_repository_for_tenant(tenantid)?;
+        let throttle = tenant_mgr::get_getpage_throttle(tenantid)?;
 
         /* switch client to COPYBOTH */
-        pgb.write_message(&BeMessage::CopyBothResponse)?;
+        pgb.write_message(&BeMessage::CopyBothResponse).await?;
+
+        // Version 1 clients don't expect a handshake, newer ones need to learn
+        // which capabilities were granted.
+        if protocol.version >= 2 {
+            let handshake =
+                PagestreamBeMessage::Handshake(PagestreamHandshakeResponse { protocol });
+            pgb.write_message(&BeMessage::CopyData(&handshake.serialize(&protocol)?))
+                .await?;
+        }
 
-        while !thread_mgr::is_shutdown_requested() {
-            let msg = pgb.read_message();
+        let tenant_id = tenantid.to_string();
+        let timeline_id = timelineid.to_string();
+        let foreground_io = IoBudgetMetrics::new(IoClass::Foreground, &tenant_id);
 
-            let profiling_guard = profpoint_start(self.conf, ProfilingConfig::PageRequests);
-            match msg {
-                Ok(message) => {
-                    if let Some(message) = message {
-                        trace!("query: {:?}", message);
-
-                        let copy_data_bytes = match message {
-                            FeMessage::CopyData(bytes) => bytes,
-                            _ => continue,
-                        };
-
-                        let zenith_fe_msg = PagestreamFeMessage::parse(copy_data_bytes)?;
-                        let tenant_id = tenantid.to_string();
-                        let timeline_id = timelineid.to_string();
-
-                        let response = match zenith_fe_msg {
-                            PagestreamFeMessage::Exists(req) => SMGR_QUERY_TIME
-                                .with_label_values(&["get_rel_exists", &tenant_id, &timeline_id])
-                                .observe_closure_duration(|| {
-                                    self.handle_get_rel_exists_request(timeline.as_ref(), &req)
-                                }),
-                            PagestreamFeMessage::Nblocks(req) => SMGR_QUERY_TIME
-                                .with_label_values(&["get_rel_size", &tenant_id, &timeline_id])
-                                .observe_closure_duration(|| {
-                                    self.handle_get_nblocks_request(timeline.as_ref(), &req)
-                                }),
-                            PagestreamFeMessage::GetPage(req) => SMGR_QUERY_TIME
-                                .with_label_values(&["get_page_at_lsn", &tenant_id, &timeline_id])
-                                .observe_closure_duration(|| {
-                                    self.handle_get_page_at_lsn_request(timeline.as_ref(), &req)
-                                }),
-                            PagestreamFeMessage::DbSize(req) => SMGR_QUERY_TIME
-                                .with_label_values(&["get_db_size", &tenant_id, &timeline_id])
-                                .observe_closure_duration(|| {
-                                    self.handle_db_size_request(timeline.as_ref(), &req)
-                                }),
-                        };
-
-                        let response = response.unwrap_or_else(|e| {
-                            // print the all details to the log with {:#}, but for the client the
-                            // error message is enough
-                            error!("error reading relation or page version: {:?}", e);
-                            PagestreamBeMessage::Error(PagestreamErrorResponse {
-                                message: e.to_string(),
-                            })
-                        });
-
-                        pgb.write_message(&BeMessage::CopyData(&response.serialize()))?;
-                    } else {
-                        break;
+        loop {
+            let message = tokio::select! {
+                biased;
+
+                _ = wait_for_task_shutdown(self.shutdown_rx.clone(), &task) => {
+                    // We were requested to shut down.
+                    break;
+                }
+
+                message = pgb.read_message() => message?,
+            };
+
+            let message = match message {
+                Some(message) => message,
+                None => break,
+            };
+            trace!("query: {:?}", message);
+
+            let copy_data_bytes = match message {
+                FeMessage::CopyData(bytes) => bytes,
+                _ => continue,
+            };
+
+            let zenith_fe_msg = PagestreamFeMessage::parse(copy_data_bytes, &protocol)?;
+
+            // Admission control: if the tenant is over its GetPage@LSN rate
+            // limit, hold the request back until it's its turn. The limits are
+            // re-read for every request, so that config changes apply to
+            // existing connections.
+            let cost = zenith_fe_msg.getpage_cost();
+            if cost > 0 {
+                let wait = throttle.acquire(
+                    repo.get_getpage_rate_limit(),
+                    repo.get_getpage_burst(),
+                    cost,
+                    Instant::now(),
+                );
+                if !wait.is_zero() {
+                    GETPAGE_THROTTLED_REQUESTS
+                        .with_label_values(&[&tenant_id])
+                        .inc();
+                    GETPAGE_THROTTLE_WAIT_TIME
+                        .with_label_values(&[&tenant_id])
+                        .observe(wait.as_secs_f64());
+                    tokio::select! {
+                        biased;
+
+                        _ = wait_for_task_shutdown(self.shutdown_rx.clone(), &task) => break,
+                        _ = tokio::time::sleep(wait) => {}
                     }
                 }
-                Err(e) => {
-                    if !is_socket_read_timed_out(&e) {
-                        return Err(e);
+
+                // Then wait for the pageserver-wide foreground I/O budget, here
+                // rather than in the blocking reads, which would park the
+                // runtime worker. Every page is charged as one block read.
+                let wait = virtual_file::charge_foreground_io(
+                    &foreground_io,
+                    cost * pg_constants::BLCKSZ as u64,
+                );
+                if !wait.is_zero() {
+                    tokio::select! {
+                        biased;
+
+                        _ = wait_for_task_shutdown(self.shutdown_rx.clone(), &task) => break,
+                        _ = tokio::time::sleep(wait) => {}
                     }
                 }
             }
-            drop(profiling_guard);
+
+            let _timer = SMGR_QUERY_TIME
+                .with_label_values(&[zenith_fe_msg.smgr_query_type(), &tenant_id, &timeline_id])
+                .start_timer();
+
+            // Wait for the WAL to arrive, without blocking the thread. The
+            // rest of the request does blocking I/O and WAL redo, so tell the
+            // runtime to move other tasks off this worker thread meanwhile.
+            let (latest, req_lsn) = zenith_fe_msg.request_lsn();
+            let lsn = tokio::select! {
+                biased;
+
+                _ = wait_for_task_shutdown(self.shutdown_rx.clone(), &task) => break,
+                lsn = Self::wait_or_get_last_lsn(timeline.as_ref(), req_lsn, latest) => lsn,
+            };
+            let response = match lsn {
+                Ok(lsn) => tokio::task::block_in_place(|| {
+                    let _profiling_guard =
+                        profpoint_start(self.conf, ProfilingConfig::PageRequests);
+                    self.handle_request_at_lsn(timeline.as_ref(), zenith_fe_msg, lsn)
+                }),
+                Err(e) => Err(e),
+            };
+
+            let response = response.unwrap_or_else(|e| {
+                // print the all details to the log with {:#}, but for the client the
+                // error message is enough
+                error!("error reading relation or page version: {:?}", e);
+                PagestreamBeMessage::Error(PagestreamErrorResponse {
+                    message: e.to_string(),
+                })
+            });
+
+            pgb.write_message(&BeMessage::CopyData(&response.serialize(&protocol)?))
+                .await?;
+        }
+
+        if task.is_shutdown_requested() {
+            // Make the client reconnect, to find out what became of the timeline
+            bail!("timeline {} was detached or replaced", timelineid);
         }
         Ok(())
     }
 
+    fn handle_request_at_lsn<R: Repository>(
+        &self,
+        timeline: &DatadirTimeline<R>,
+        request: PagestreamFeMessage,
+        lsn: Lsn,
+    ) -> Result<PagestreamBeMessage> {
+        match request {
+            PagestreamFeMessage::Exists(req) => {
+                self.handle_get_rel_exists_request(timeline, &req, lsn)
+            }
+            PagestreamFeMessage::Nblocks(req) => {
+                self.handle_get_nblocks_request(timeline, &req, lsn)
+            }
+            PagestreamFeMessage::GetPage(req) => {
+                self.handle_get_page_at_lsn_request(timeline, &req, lsn)
+            }
+            PagestreamFeMessage::DbSize(req) => self.handle_db_size_request(timeline, &req, lsn),
+            PagestreamFeMessage::GetPages(req) => {
+                self.handle_get_pages_at_lsn_request(timeline, &req, lsn)
+            }
+            PagestreamFeMessage::GetSlruPage(req) => {
+                self.handle_get_slru_page_at_lsn_request(timeline, &req, lsn)
+            }
+        }
+    }
+
     /// Helper function to handle the LSN from client request.
     ///
     /// Each GetPage (and Exists and Nblocks) request includes information about
@@ -456,12 +932,12 @@ impl PageServerHandler {
     ///
     /// In either case, if the page server hasn't received the WAL up to the
     /// requested LSN yet, we will wait for it to arrive. The return value is
-    /// the LSN that should be used to look up the page versions.
-    fn wait_or_get_last_lsn<R: Repository>(
+    /// the LSN that should be used to look up the page versions. The caller
+    /// must still check it against the GC cutoff, see `check_gc_cutoff`.
+    async fn wait_or_get_last_lsn<R: Repository>(
         timeline: &DatadirTimeline<R>,
         mut lsn: Lsn,
         latest: bool,
-        latest_gc_cutoff_lsn: &RwLockReadGuard<Lsn>,
     ) -> Result<Lsn> {
         if latest {
             // Latest page version was requested. If LSN is given, it is a hint
@@ -485,7 +961,7 @@ impl PageServerHandler {
             if lsn <= last_record_lsn {
                 lsn = last_record_lsn;
             } else {
-                timeline.tline.wait_lsn(lsn)?;
+                timeline.tline.wait_lsn(lsn).await?;
                 // Since we waited for 'lsn' to arrive, that is now the last
                 // record LSN. (Or close enough for our purposes; the
                 // last-record LSN can advance immediately after we return
@@ -495,25 +971,32 @@ impl PageServerHandler {
             if lsn == Lsn(0) {
                 bail!("invalid LSN(0) in request");
             }
-            timeline.tline.wait_lsn(lsn)?;
+            timeline.tline.wait_lsn(lsn).await?;
         }
+        Ok(lsn)
+    }
+
+    /// Check that the data at 'lsn' hasn't been garbage collected yet. The
+    /// caller must hold the guard while it reads the data.
+    fn check_gc_cutoff(lsn: Lsn, latest_gc_cutoff_lsn: &RwLockReadGuard<Lsn>) -> Result<()> {
         ensure!(
             lsn >= **latest_gc_cutoff_lsn,
             "tried to request a page version that was garbage collected. requested at {} gc cutoff {}",
             lsn, **latest_gc_cutoff_lsn
         );
-        Ok(lsn)
+        Ok(())
     }
 
     fn handle_get_rel_exists_request<R: Repository>(
         &self,
         timeline: &DatadirTimeline<R>,
         req: &PagestreamExistsRequest,
+        lsn: Lsn,
     ) -> Result<PagestreamBeMessage> {
         let _enter = info_span!("get_rel_exists", rel = %req.rel, req_lsn = %req.lsn).entered();
 
         let latest_gc_cutoff_lsn = timeline.tline.get_latest_gc_cutoff_lsn();
-        let lsn = Self::wait_or_get_last_lsn(timeline, req.lsn, req.latest, &latest_gc_cutoff_lsn)?;
+        Self::check_gc_cutoff(lsn, &latest_gc_cutoff_lsn)?;
 
         let exists = timeline.get_rel_exists(req.rel, lsn)?;
 
@@ -526,10 +1009,11 @@ impl PageServerHandler {
         &self,
         timeline: &DatadirTimeline<R>,
         req: &PagestreamNblocksRequest,
+        lsn: Lsn,
     ) -> Result<PagestreamBeMessage> {
         let _enter = info_span!("get_nblocks", rel = %req.rel, req_lsn = %req.lsn).entered();
         let latest_gc_cutoff_lsn = timeline.tline.get_latest_gc_cutoff_lsn();
-        let lsn = Self::wait_or_get_last_lsn(timeline, req.lsn, req.latest, &latest_gc_cutoff_lsn)?;
+        Self::check_gc_cutoff(lsn, &latest_gc_cutoff_lsn)?;
 
         let n_blocks = timeline.get_rel_size(req.rel, lsn)?;
 
@@ -542,10 +1026,11 @@ impl PageServerHandler {
         &self,
         timeline: &DatadirTimeline<R>,
         req: &PagestreamDbSizeRequest,
+        lsn: Lsn,
     ) -> Result<PagestreamBeMessage> {
         let _enter = info_span!("get_db_size", dbnode = %req.dbnode, req_lsn = %req.lsn).entered();
         let latest_gc_cutoff_lsn = timeline.tline.get_latest_gc_cutoff_lsn();
-        let lsn = Self::wait_or_get_last_lsn(timeline, req.lsn, req.latest, &latest_gc_cutoff_lsn)?;
+        Self::check_gc_cutoff(lsn, &latest_gc_cutoff_lsn)?;
 
         let all_rels = timeline.list_rels(pg_constants::DEFAULTTABLESPACE_OID, req.dbnode, lsn)?;
         let mut total_blocks: i64 = 0;
@@ -568,11 +1053,12 @@ impl PageServerHandler {
         &self,
         timeline: &DatadirTimeline<R>,
         req: &PagestreamGetPageRequest,
+        lsn: Lsn,
     ) -> Result<PagestreamBeMessage> {
         let _enter = info_span!("get_page", rel = %req.rel, blkno = &req.blkno, req_lsn = %req.lsn)
             .entered();
         let latest_gc_cutoff_lsn = timeline.tline.get_latest_gc_cutoff_lsn();
-        let lsn = Self::wait_or_get_last_lsn(timeline, req.lsn, req.latest, &latest_gc_cutoff_lsn)?;
+        Self::check_gc_cutoff(lsn, &latest_gc_cutoff_lsn)?;
         /*
         // Add a 1s delay to some requests. The delayed causes the requests to
         // hit the race condition from github issue #1047 more easily.
@@ -588,39 +1074,156 @@ impl PageServerHandler {
         }))
     }
 
-    fn handle_basebackup_request(
+    fn handle_get_pages_at_lsn_request<R: Repository>(
+        &self,
+        timeline: &DatadirTimeline<R>,
+        req: &PagestreamGetPagesRequest,
+        lsn: Lsn,
+    ) -> Result<PagestreamBeMessage> {
+        let _enter =
+            info_span!("get_pages", nblocks = req.pages.len(), req_lsn = %req.lsn).entered();
+        // All the blocks are read at the same LSN, so we only need to check it once.
+        let latest_gc_cutoff_lsn = timeline.tline.get_latest_gc_cutoff_lsn();
+        Self::check_gc_cutoff(lsn, &latest_gc_cutoff_lsn)?;
+
+        let pages = req
+            .pages
+            .iter()
+            .map(|&(rel, blkno)| {
+                timeline.get_rel_page_at_lsn(rel, blkno, lsn).map_err(|e| {
+                    error!(
+                        "error reading page {} blk {} at {}: {:?}",
+                        rel, blkno, lsn, e
+                    );
+                    e.to_string()
+                })
+            })
+            .collect();
+
+        Ok(PagestreamBeMessage::GetPages(PagestreamGetPagesResponse {
+            pages,
+        }))
+    }
+
+    fn handle_get_slru_page_at_lsn_request<R: Repository>(
+        &self,
+        timeline: &DatadirTimeline<R>,
+        req: &PagestreamGetSlruPageRequest,
+        lsn: Lsn,
+    ) -> Result<PagestreamBeMessage> {
+        let _enter = info_span!("get_slru_page", kind = ?req.kind, segno = req.segno, blkno = req.blkno, req_lsn = %req.lsn)
+            .entered();
+        let latest_gc_cutoff_lsn = timeline.tline.get_latest_gc_cutoff_lsn();
+        Self::check_gc_cutoff(lsn, &latest_gc_cutoff_lsn)?;
+
+        let page = timeline.get_slru_page_at_lsn(req.kind, req.segno, req.blkno, lsn)?;
+
+        Ok(PagestreamBeMessage::GetSlruPage(
+            PagestreamGetSlruPageResponse { page },
+        ))
+    }
+
+    async fn handle_basebackup_request(
         &self,
         pgb: &mut PostgresBackend,
         timelineid: ZTimelineId,
         lsn: Option<Lsn>,
         tenantid: ZTenantId,
+        include_slru: bool,
+        full_backup: bool,
     ) -> anyhow::Result<()> {
-        let span = info_span!("basebackup", timeline = %timelineid, tenant = %tenantid, lsn = field::Empty);
-        let _enter = span.enter();
-        info!("starting");
+        let span = info_span!("basebackup", timeline = %timelineid, tenant = %tenantid, lsn = field::Empty, full_backup);
+        async {
+            info!("starting");
 
-        // check that the timeline exists
-        let timeline = tenant_mgr::get_local_timeline_with_load(tenantid, timelineid)
-            .context("Cannot load local timeline")?;
-        let latest_gc_cutoff_lsn = timeline.tline.get_latest_gc_cutoff_lsn();
-        if let Some(lsn) = lsn {
-            timeline
-                .check_lsn_is_in_scope(lsn, &latest_gc_cutoff_lsn)
-                .context("invalid basebackup lsn")?;
-        }
+            // check that the timeline exists
+            let timeline = get_local_timeline_with_load(tenantid, timelineid)
+                .context("Cannot load local timeline")?;
+            if let Some(lsn) = lsn {
+                {
+                    let latest_gc_cutoff_lsn = timeline.tline.get_latest_gc_cutoff_lsn();
+                    timeline
+                        .check_lsn_is_in_scope(lsn, &latest_gc_cutoff_lsn)
+                        .context("invalid basebackup lsn")?;
+                }
+                // Backup was requested at a particular LSN. Wait for it to arrive.
+                info!("waiting for {}", lsn);
+                timeline.tline.wait_lsn(lsn).await?;
+            }
 
-        // switch client to COPYOUT
-        pgb.write_message(&BeMessage::CopyOutResponse)?;
+            // switch client to COPYOUT
+            pgb.write_message(&BeMessage::CopyOutResponse).await?;
 
-        /* Send a tarball of the latest layer on the timeline */
-        {
-            let mut writer = CopyDataSink { pgb };
+            /* Send a tarball of the latest layer on the timeline */
+            //
+            // Building the tarball does a lot of blocking I/O, so tell the
+            // runtime to move other tasks off this worker thread meanwhile.
+            let rt = tokio::runtime::Handle::current();
+            tokio::task::block_in_place(|| -> anyhow::Result<()> {
+                // Hold the GC cutoff lock, so that GC doesn't remove the data
+                // while we're reading it.
+                let latest_gc_cutoff_lsn = timeline.tline.get_latest_gc_cutoff_lsn();
+                if let Some(lsn) = lsn {
+                    timeline
+                        .check_lsn_is_in_scope(lsn, &latest_gc_cutoff_lsn)
+                        .context("invalid basebackup lsn")?;
+                }
 
-            let basebackup = basebackup::Basebackup::new(&mut writer, &timeline, lsn)?;
-            span.record("lsn", &basebackup.lsn.to_string().as_str());
-            basebackup.send_tarball()?;
+                let mut writer = CopyDataSink { pgb: &mut *pgb, rt };
+                let basebackup = basebackup::Basebackup::new(
+                    &mut writer,
+                    &timeline,
+                    lsn,
+                    include_slru,
+                    full_backup,
+                )?;
+                Span::current().record("lsn", &basebackup.lsn.to_string().as_str());
+                basebackup.send_tarball()
+            })?;
+            pgb.write_message(&BeMessage::CopyDone).await?;
+            info!("done");
+
+            Ok::<(), anyhow::Error>(())
         }
-        pgb.write_message(&BeMessage::CopyDone)?;
+        .instrument(span)
+        .await
+    }
+
+    async fn handle_import_basebackup(
+        &self,
+        pgb: &mut PostgresBackend,
+        tenantid: ZTenantId,
+        timelineid: ZTimelineId,
+        start_lsn: Lsn,
+        end_lsn: Lsn,
+    ) -> anyhow::Result<()> {
+        info!("starting");
+
+        // switch client to COPYIN
+        pgb.write_message(&BeMessage::CopyInResponse).await?;
+
+        // Importing does a lot of blocking I/O, so tell the runtime to move
+        // other tasks off this worker thread meanwhile.
+        let rt = tokio::runtime::Handle::current();
+        tokio::task::block_in_place(|| -> anyhow::Result<()> {
+            let mut reader = CopyInReader::new(&mut *pgb, rt);
+            let result = timelines::import_timeline_from_tar(
+                self.conf,
+                tenantid,
+                timelineid,
+                start_lsn,
+                end_lsn,
+                &mut reader,
+            );
+
+            // The tar reader stops at the end-of-archive marker, and on error
+            // we stop reading altogether. Read the rest of the COPY data, up
+            // to CopyDone, so that the client sees the result of the command.
+            let drained = io::copy(&mut reader, &mut io::sink());
+            result?;
+            drained?;
+            Ok(())
+        })?;
         info!("done");
 
         Ok(())
@@ -641,10 +1244,12 @@ impl PageServerHandler {
             .as_ref()
             .expect("claims presence already checked");
         auth::check_permission(claims, tenantid)
+            .map_err(|e| QueryError::new(SQLSTATE_INSUFFICIENT_PRIVILEGE, e.to_string()).into())
     }
 }
 
-impl postgres_backend::Handler for PageServerHandler {
+#[async_trait::async_trait]
+impl postgres_backend_async::Handler for PageServerHandler {
     fn check_auth_jwt(
         &mut self,
         _pgb: &mut PostgresBackend,
@@ -674,261 +1279,324 @@ impl postgres_backend::Handler for PageServerHandler {
         Ok(())
     }
 
-    fn process_query(
+    async fn process_query(
         &mut self,
         pgb: &mut PostgresBackend,
         query_string: &str,
     ) -> anyhow::Result<()> {
         debug!("process query {:?}", query_string);
 
-        if query_string.starts_with("pagestream ") {
-            let (_, params_raw) = query_string.split_at("pagestream ".len());
-            let params = params_raw.split(' ').collect::<Vec<_>>();
-            ensure!(
-                params.len() == 2,
-                "invalid param number for pagestream command"
-            );
-            let tenantid = ZTenantId::from_str(params[0])?;
-            let timelineid = ZTimelineId::from_str(params[1])?;
-
-            self.check_permission(Some(tenantid))?;
-
-            self.handle_pagerequests(pgb, timelineid, tenantid)?;
-        } else if query_string.starts_with("basebackup ") {
-            let (_, params_raw) = query_string.split_at("basebackup ".len());
-            let params = params_raw.split_whitespace().collect::<Vec<_>>();
-
-            ensure!(
-                params.len() >= 2,
-                "invalid param number for basebackup command"
-            );
-
-            let tenantid = ZTenantId::from_str(params[0])?;
-            let timelineid = ZTimelineId::from_str(params[1])?;
-
-            self.check_permission(Some(tenantid))?;
-
-            let lsn = if params.len() == 3 {
-                Some(Lsn::from_str(params[2])?)
-            } else {
-                None
-            };
-
-            // Check that the timeline exists
-            self.handle_basebackup_request(pgb, timelineid, lsn, tenantid)?;
-            pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
-        } else if query_string.starts_with("callmemaybe ") {
-            // callmemaybe <zenith tenantid as hex string> <zenith timelineid as hex string> <connstr>
-            // TODO lazy static
-            let re = Regex::new(r"^callmemaybe ([[:xdigit:]]+) ([[:xdigit:]]+) (.*)$").unwrap();
-            let caps = re
-                .captures(query_string)
-                .with_context(|| format!("invalid callmemaybe: '{}'", query_string))?;
-
-            let tenantid = ZTenantId::from_str(caps.get(1).unwrap().as_str())?;
-            let timelineid = ZTimelineId::from_str(caps.get(2).unwrap().as_str())?;
-            let connstr = caps.get(3).unwrap().as_str().to_owned();
+        match PageServiceCommand::parse(query_string)? {
+            PageServiceCommand::PageStream {
+                tenant_id,
+                timeline_id,
+                protocol_version,
+                capabilities,
+            } => {
+                let protocol = match protocol_version {
+                    Some(version) => {
+                        PagestreamProtocol::negotiate(version, capabilities).map_err(|e| {
+                            QueryError::new(SQLSTATE_FEATURE_NOT_SUPPORTED, e.to_string())
+                        })?
+                    }
+                    None => PagestreamProtocol::V1,
+                };
 
-            self.check_permission(Some(tenantid))?;
+                self.check_permission(Some(tenant_id))?;
 
-            let _enter =
-                info_span!("callmemaybe", timeline = %timelineid, tenant = %tenantid).entered();
+                self.handle_pagerequests(pgb, timeline_id, tenant_id, protocol)
+                    .instrument(info_span!("pagestream", timeline = %timeline_id, tenant = %tenant_id, protocol_version = protocol.version))
+                    .await?;
+            }
+            PageServiceCommand::BaseBackup {
+                tenant_id,
+                timeline_id,
+                lsn,
+                include_slru,
+            } => {
+                // With --no-slru, the compute is expected to fetch SLRU pages on
+                // demand with GetSlruPage requests.
+                self.check_permission(Some(tenant_id))?;
+
+                self.handle_basebackup_request(
+                    pgb,
+                    timeline_id,
+                    lsn,
+                    tenant_id,
+                    include_slru,
+                    false,
+                )
+                .await?;
+                pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+            }
+            PageServiceCommand::FullBackup {
+                tenant_id,
+                timeline_id,
+                lsn,
+            } => {
+                // Like basebackup, but the tarball is a complete data directory,
+                // including relation files, that vanilla PostgreSQL can start on.
+                self.check_permission(Some(tenant_id))?;
+
+                self.handle_basebackup_request(pgb, timeline_id, lsn, tenant_id, true, true)
+                    .await?;
+                pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+            }
+            PageServiceCommand::ImportBasebackup {
+                tenant_id,
+                timeline_id,
+                start_lsn,
+                end_lsn,
+            } => {
+                // Create a new timeline from a basebackup tarball, which the
+                // client sends with COPY. The data files are imported at
+                // start_lsn, the start of the backup, and the WAL included in the
+                // tarball is replayed up to end_lsn.
+                self.check_permission(Some(tenant_id))?;
+
+                self.handle_import_basebackup(pgb, tenant_id, timeline_id, start_lsn, end_lsn)
+                    .instrument(
+                        info_span!("import basebackup", timeline = %timeline_id, tenant = %tenant_id),
+                    )
+                    .await?;
+                pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+            }
+            PageServiceCommand::CallMeMaybe {
+                tenant_id,
+                timeline_id,
+                connstr,
+            } => {
+                self.check_permission(Some(tenant_id))?;
 
-            // Check that the timeline exists
-            tenant_mgr::get_local_timeline_with_load(tenantid, timelineid)
-                .context("Cannot load local timeline")?;
+                let _enter =
+                    info_span!("callmemaybe", timeline = %timeline_id, tenant = %tenant_id)
+                        .entered();
 
-            walreceiver::launch_wal_receiver(self.conf, tenantid, timelineid, &connstr)?;
+                // Check that the timeline exists
+                get_local_timeline_with_load(tenant_id, timeline_id)
+                    .context("Cannot load local timeline")?;
 
-            pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
-        } else if query_string.to_ascii_lowercase().starts_with("set ") {
-            // important because psycopg2 executes "SET datestyle TO 'ISO'"
-            // on connect
-            pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
-        } else if query_string.starts_with("failpoints ") {
-            ensure!(fail::has_failpoints(), "Cannot manage failpoints because pageserver was compiled without failpoints support");
+                walreceiver::launch_wal_receiver(self.conf, tenant_id, timeline_id, &connstr)?;
 
-            let (_, failpoints) = query_string.split_at("failpoints ".len());
+                pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+            }
+            PageServiceCommand::Set => {
+                pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+            }
+            PageServiceCommand::Failpoints(failpoints) => {
+                if !fail::has_failpoints() {
+                    return Err(QueryError::new(
+                        SQLSTATE_FEATURE_NOT_SUPPORTED,
+                        "Cannot manage failpoints because pageserver was compiled without failpoints support",
+                    )
+                    .into());
+                }
 
-            for failpoint in failpoints.split(';') {
-                if let Some((name, actions)) = failpoint.split_once('=') {
+                for (name, actions) in failpoints {
                     info!("cfg failpoint: {} {}", name, actions);
 
                     // We recognize one extra "action" that's not natively recognized
                     // by the failpoints crate: exit, to immediately kill the process
-                    if actions == "exit" {
+                    let result = if actions == "exit" {
                         fail::cfg_callback(name, || {
                             info!("Exit requested by failpoint");
                             std::process::exit(1);
                         })
-                        .unwrap();
                     } else {
-                        fail::cfg(name, actions).unwrap();
+                        fail::cfg(name, &actions)
+                    };
+                    result.map_err(|e| {
+                        QueryError::new(
+                            SQLSTATE_INVALID_PARAMETER_VALUE,
+                            format!("invalid failpoint actions '{}': {}", actions, e),
+                        )
+                    })?;
+                }
+                pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+            }
+            PageServiceCommand::Show { tenant_id } => {
+                let repo = tenant_mgr::get_repository_for_tenant(tenant_id)?;
+                pgb.write_message_noflush(&BeMessage::RowDescription(&[
+                    RowDescriptor::int8_col(b"checkpoint_distance"),
+                    RowDescriptor::int8_col(b"compaction_target_size"),
+                    RowDescriptor::int8_col(b"compaction_period"),
+                    RowDescriptor::int8_col(b"compaction_threshold"),
+                    RowDescriptor::text_col(b"compaction_strategy"),
+                    RowDescriptor::int8_col(b"compaction_tier_ratio"),
+                    RowDescriptor::int8_col(b"gc_horizon"),
+                    RowDescriptor::int8_col(b"gc_period"),
+                    RowDescriptor::int8_col(b"image_creation_threshold"),
+                    RowDescriptor::int8_col(b"pitr_interval"),
+                    RowDescriptor::int8_col(b"getpage_rate_limit"),
+                    RowDescriptor::int8_col(b"getpage_burst"),
+                    RowDescriptor::text_col(b"compression"),
+                    RowDescriptor::int8_col(b"scrub_period"),
+                    RowDescriptor::int8_col(b"scrub_rate_limit"),
+                ]))?
+                .write_message_noflush(&BeMessage::DataRow(&[
+                    Some(repo.get_checkpoint_distance().to_string().as_bytes()),
+                    Some(repo.get_compaction_target_size().to_string().as_bytes()),
+                    Some(
+                        repo.get_compaction_period()
+                            .as_secs()
+                            .to_string()
+                            .as_bytes(),
+                    ),
+                    Some(repo.get_compaction_threshold().to_string().as_bytes()),
+                    Some(repo.get_compaction_strategy().to_string().as_bytes()),
+                    Some(repo.get_compaction_tier_ratio().to_string().as_bytes()),
+                    Some(repo.get_gc_horizon().to_string().as_bytes()),
+                    Some(repo.get_gc_period().as_secs().to_string().as_bytes()),
+                    Some(repo.get_image_creation_threshold().to_string().as_bytes()),
+                    Some(repo.get_pitr_interval().as_secs().to_string().as_bytes()),
+                    Some(repo.get_getpage_rate_limit().to_string().as_bytes()),
+                    Some(repo.get_getpage_burst().to_string().as_bytes()),
+                    Some(repo.get_compression().to_string().as_bytes()),
+                    Some(repo.get_scrub_period().as_secs().to_string().as_bytes()),
+                    Some(repo.get_scrub_rate_limit().to_string().as_bytes()),
+                ]))?
+                .write_message(&BeMessage::CommandComplete(b"SELECT 1"))
+                .await?;
+            }
+            PageServiceCommand::DoGc {
+                tenant_id,
+                timeline_id,
+                gc_horizon,
+                dry_run,
+            } => {
+                // Run GC immediately on given timeline.
+                // FIXME: This is just for tests. See test_runner/batch_others/test_gc.py.
+                // This probably should require special authentication or a global flag to
+                // enable, I don't think we want to or need to allow regular clients to invoke
+                // GC.
+                let repo = tenant_mgr::get_repository_for_tenant(tenant_id)?;
+
+                // Use tenant's pitr setting
+                let pitr = repo.get_pitr_interval();
+                let result = tokio::task::block_in_place(|| {
+                    repo.gc_iteration(Some(timeline_id), gc_horizon, pitr, true, dry_run)
+                })?;
+                if dry_run {
+                    // One row per layer, with the reason to keep it, or NULL
+                    // if it would be removed
+                    pgb.write_message_noflush(&BeMessage::RowDescription(&[
+                        RowDescriptor::text_col(b"layer"),
+                        RowDescriptor::text_col(b"keep_reason"),
+                    ]))?;
+                    for layer in result.layers.iter() {
+                        let keep_reason = layer.keep_reason.map(|r| format!("{:?}", r));
+                        pgb.write_message_noflush(&BeMessage::DataRow(&[
+                            Some(layer.layer_file_name.as_bytes()),
+                            keep_reason.as_ref().map(|r| r.as_bytes()),
+                        ]))?;
                     }
+                    pgb.write_message(&BeMessage::CommandComplete(
+                        format!("SELECT {}", result.layers.len()).as_bytes(),
+                    ))
+                    .await?;
                 } else {
-                    bail!("Invalid failpoints format");
+                    pgb.write_message_noflush(&BeMessage::RowDescription(&[
+                        RowDescriptor::int8_col(b"layers_total"),
+                        RowDescriptor::int8_col(b"layers_needed_by_cutoff"),
+                        RowDescriptor::int8_col(b"layers_needed_by_pitr"),
+                        RowDescriptor::int8_col(b"layers_needed_by_branches"),
+                        RowDescriptor::int8_col(b"layers_not_updated"),
+                        RowDescriptor::int8_col(b"layers_removed"),
+                        RowDescriptor::int8_col(b"elapsed"),
+                    ]))?
+                    .write_message_noflush(&BeMessage::DataRow(&[
+                        Some(result.layers_total.to_string().as_bytes()),
+                        Some(result.layers_needed_by_cutoff.to_string().as_bytes()),
+                        Some(result.layers_needed_by_pitr.to_string().as_bytes()),
+                        Some(result.layers_needed_by_branches.to_string().as_bytes()),
+                        Some(result.layers_not_updated.to_string().as_bytes()),
+                        Some(result.layers_removed.to_string().as_bytes()),
+                        Some(result.elapsed.as_millis().to_string().as_bytes()),
+                    ]))?
+                    .write_message(&BeMessage::CommandComplete(b"SELECT 1"))
+                    .await?;
                 }
             }
-            pgb.write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
-        } else if query_string.starts_with("show ") {
-            // show <tenant_id>
-            let (_, params_raw) = query_string.split_at("show ".len());
-            let params = params_raw.split(' ').collect::<Vec<_>>();
-            ensure!(params.len() == 1, "invalid param number for config command");
-            let tenantid = ZTenantId::from_str(params[0])?;
-            let repo = tenant_mgr::get_repository_for_tenant(tenantid)?;
-            pgb.write_message_noflush(&BeMessage::RowDescription(&[
-                RowDescriptor::int8_col(b"checkpoint_distance"),
-                RowDescriptor::int8_col(b"compaction_target_size"),
-                RowDescriptor::int8_col(b"compaction_period"),
-                RowDescriptor::int8_col(b"compaction_threshold"),
-                RowDescriptor::int8_col(b"gc_horizon"),
-                RowDescriptor::int8_col(b"gc_period"),
-                RowDescriptor::int8_col(b"image_creation_threshold"),
-                RowDescriptor::int8_col(b"pitr_interval"),
-            ]))?
-            .write_message_noflush(&BeMessage::DataRow(&[
-                Some(repo.get_checkpoint_distance().to_string().as_bytes()),
-                Some(repo.get_compaction_target_size().to_string().as_bytes()),
-                Some(
-                    repo.get_compaction_period()
-                        .as_secs()
-                        .to_string()
-                        .as_bytes(),
-                ),
-                Some(repo.get_compaction_threshold().to_string().as_bytes()),
-                Some(repo.get_gc_horizon().to_string().as_bytes()),
-                Some(repo.get_gc_period().as_secs().to_string().as_bytes()),
-                Some(repo.get_image_creation_threshold().to_string().as_bytes()),
-                Some(repo.get_pitr_interval().as_secs().to_string().as_bytes()),
-            ]))?
-            .write_message(&BeMessage::CommandComplete(b"SELECT 1"))?;
-        } else if query_string.starts_with("do_gc ") {
-            // Run GC immediately on given timeline.
-            // FIXME: This is just for tests. See test_runner/batch_others/test_gc.py.
-            // This probably should require special authentication or a global flag to
-            // enable, I don't think we want to or need to allow regular clients to invoke
-            // GC.
-
-            // do_gc <tenant_id> <timeline_id> <gc_horizon>
-            let re = Regex::new(r"^do_gc ([[:xdigit:]]+)\s([[:xdigit:]]+)($|\s)([[:digit:]]+)?")
-                .unwrap();
-
-            let caps = re
-                .captures(query_string)
-                .with_context(|| format!("invalid do_gc: '{}'", query_string))?;
-
-            let tenantid = ZTenantId::from_str(caps.get(1).unwrap().as_str())?;
-            let timelineid = ZTimelineId::from_str(caps.get(2).unwrap().as_str())?;
-
-            let repo = tenant_mgr::get_repository_for_tenant(tenantid)?;
-
-            let gc_horizon: u64 = caps
-                .get(4)
-                .map(|h| h.as_str().parse())
-                .unwrap_or_else(|| Ok(repo.get_gc_horizon()))?;
-
-            let repo = tenant_mgr::get_repository_for_tenant(tenantid)?;
-            // Use tenant's pitr setting
-            let pitr = repo.get_pitr_interval();
-            let result = repo.gc_iteration(Some(timelineid), gc_horizon, pitr, true)?;
-            pgb.write_message_noflush(&BeMessage::RowDescription(&[
-                RowDescriptor::int8_col(b"layers_total"),
-                RowDescriptor::int8_col(b"layers_needed_by_cutoff"),
-                RowDescriptor::int8_col(b"layers_needed_by_pitr"),
-                RowDescriptor::int8_col(b"layers_needed_by_branches"),
-                RowDescriptor::int8_col(b"layers_not_updated"),
-                RowDescriptor::int8_col(b"layers_removed"),
-                RowDescriptor::int8_col(b"elapsed"),
-            ]))?
-            .write_message_noflush(&BeMessage::DataRow(&[
-                Some(result.layers_total.to_string().as_bytes()),
-                Some(result.layers_needed_by_cutoff.to_string().as_bytes()),
-                Some(result.layers_needed_by_pitr.to_string().as_bytes()),
-                Some(result.layers_needed_by_branches.to_string().as_bytes()),
-                Some(result.layers_not_updated.to_string().as_bytes()),
-                Some(result.layers_removed.to_string().as_bytes()),
-                Some(result.elapsed.as_millis().to_string().as_bytes()),
-            ]))?
-            .write_message(&BeMessage::CommandComplete(b"SELECT 1"))?;
-        } else if query_string.starts_with("compact ") {
-            // Run compaction immediately on given timeline.
-            // FIXME This is just for tests. Don't expect this to be exposed to
-            // the users or the api.
-
-            // compact <tenant_id> <timeline_id>
-            let re = Regex::new(r"^compact ([[:xdigit:]]+)\s([[:xdigit:]]+)($|\s)?").unwrap();
-
-            let caps = re
-                .captures(query_string)
-                .with_context(|| format!("Invalid compact: '{}'", query_string))?;
-
-            let tenantid = ZTenantId::from_str(caps.get(1).unwrap().as_str())?;
-            let timelineid = ZTimelineId::from_str(caps.get(2).unwrap().as_str())?;
-            let timeline = tenant_mgr::get_local_timeline_with_load(tenantid, timelineid)
-                .context("Couldn't load timeline")?;
-            timeline.tline.compact()?;
-
-            pgb.write_message_noflush(&SINGLE_COL_ROWDESC)?
-                .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
-        } else if query_string.starts_with("checkpoint ") {
-            // Run checkpoint immediately on given timeline.
-
-            // checkpoint <tenant_id> <timeline_id>
-            let re = Regex::new(r"^checkpoint ([[:xdigit:]]+)\s([[:xdigit:]]+)($|\s)?").unwrap();
-
-            let caps = re
-                .captures(query_string)
-                .with_context(|| format!("invalid checkpoint command: '{}'", query_string))?;
-
-            let tenantid = ZTenantId::from_str(caps.get(1).unwrap().as_str())?;
-            let timelineid = ZTimelineId::from_str(caps.get(2).unwrap().as_str())?;
-
-            let timeline = tenant_mgr::get_local_timeline_with_load(tenantid, timelineid)
-                .context("Cannot load local timeline")?;
-
-            timeline.tline.checkpoint(CheckpointConfig::Forced)?;
-
-            // Also compact it.
-            //
-            // FIXME: This probably shouldn't be part of a "checkpoint" command, but a
-            // separate operation. Update the tests if you change this.
-            timeline.tline.compact()?;
-
-            pgb.write_message_noflush(&SINGLE_COL_ROWDESC)?
-                .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
-        } else if query_string.starts_with("get_lsn_by_timestamp ") {
-            // Locate LSN of last transaction with timestamp less or equal than sppecified
-            // TODO lazy static
-            let re = Regex::new(r"^get_lsn_by_timestamp ([[:xdigit:]]+) ([[:xdigit:]]+) '(.*)'$")
-                .unwrap();
-            let caps = re
-                .captures(query_string)
-                .with_context(|| format!("invalid get_lsn_by_timestamp: '{}'", query_string))?;
-
-            let tenantid = ZTenantId::from_str(caps.get(1).unwrap().as_str())?;
-            let timelineid = ZTimelineId::from_str(caps.get(2).unwrap().as_str())?;
-            let timeline = tenant_mgr::get_local_timeline_with_load(tenantid, timelineid)
-                .context("Cannot load local timeline")?;
-
-            let timestamp = humantime::parse_rfc3339(caps.get(3).unwrap().as_str())?;
-            let timestamp_pg = to_pg_timestamp(timestamp);
-
-            pgb.write_message_noflush(&BeMessage::RowDescription(&[RowDescriptor::text_col(
-                b"lsn",
-            )]))?;
-            let result = match timeline.find_lsn_for_timestamp(timestamp_pg)? {
-                LsnForTimestamp::Present(lsn) => format!("{}", lsn),
-                LsnForTimestamp::Future(_lsn) => "future".into(),
-                LsnForTimestamp::Past(_lsn) => "past".into(),
-            };
-            pgb.write_message_noflush(&BeMessage::DataRow(&[Some(result.as_bytes())]))?;
-            pgb.write_message(&BeMessage::CommandComplete(b"SELECT 1"))?;
-        } else {
-            bail!("unknown command");
+            PageServiceCommand::Compact {
+                tenant_id,
+                timeline_id,
+            } => {
+                // Run compaction immediately on given timeline.
+                // FIXME This is just for tests. Don't expect this to be exposed to
+                // the users or the api.
+                let timeline = get_local_timeline_with_load(tenant_id, timeline_id)
+                    .context("Couldn't load timeline")?;
+                tokio::task::block_in_place(|| timeline.tline.compact())?;
+
+                pgb.write_message_noflush(&SINGLE_COL_ROWDESC)?
+                    .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+            }
+            PageServiceCommand::Checkpoint {
+                tenant_id,
+                timeline_id,
+            } => {
+                // Run checkpoint immediately on given timeline.
+                let timeline = get_local_timeline_with_load(tenant_id, timeline_id)
+                    .context("Cannot load local timeline")?;
+
+                tokio::task::block_in_place(|| -> anyhow::Result<()> {
+                    timeline.tline.checkpoint(CheckpointConfig::Forced)?;
+
+                    // Also compact it.
+                    //
+                    // FIXME: This probably shouldn't be part of a "checkpoint" command, but a
+                    // separate operation. Update the tests if you change this.
+                    timeline.tline.compact()
+                })?;
+
+                pgb.write_message_noflush(&SINGLE_COL_ROWDESC)?
+                    .write_message_noflush(&BeMessage::CommandComplete(b"SELECT 1"))?;
+            }
+            PageServiceCommand::GetLsnByTimestamp {
+                tenant_id,
+                timeline_id,
+                timestamp,
+            } => {
+                // Locate LSN of last transaction with timestamp less or equal than sppecified
+                let timeline = get_local_timeline_with_load(tenant_id, timeline_id)
+                    .context("Cannot load local timeline")?;
+
+                let timestamp_pg = to_pg_timestamp(timestamp);
+
+                pgb.write_message_noflush(&BeMessage::RowDescription(&[RowDescriptor::text_col(
+                    b"lsn",
+                )]))?;
+                // This reads pages, which may need to be downloaded first
+                let result = match tokio::task::block_in_place(|| {
+                    timeline.find_lsn_for_timestamp(timestamp_pg)
+                })? {
+                    LsnForTimestamp::Present(lsn) => format!("{}", lsn),
+                    LsnForTimestamp::Future(_lsn) => "future".into(),
+                    LsnForTimestamp::Past(_lsn) => "past".into(),
+                    LsnForTimestamp::NoData(_lsn) => "nodata".into(),
+                };
+                pgb.write_message_noflush(&BeMessage::DataRow(&[Some(result.as_bytes())]))?;
+                pgb.write_message(&BeMessage::CommandComplete(b"SELECT 1"))
+                    .await?;
+            }
+            PageServiceCommand::Help => {
+                pgb.write_message_noflush(&BeMessage::RowDescription(&[
+                    RowDescriptor::text_col(b"command"),
+                    RowDescriptor::text_col(b"description"),
+                ]))?;
+                for help in COMMANDS {
+                    pgb.write_message_noflush(&BeMessage::DataRow(&[
+                        Some(help.synopsis.as_bytes()),
+                        Some(help.description.as_bytes()),
+                    ]))?;
+                }
+                pgb.write_message_noflush(&BeMessage::CommandComplete(
+                    format!("SELECT {}", COMMANDS.len()).as_bytes(),
+                ))?;
+            }
         }
 
-        pgb.flush()?;
+        pgb.flush().await?;
 
         Ok(())
     }
@@ -940,6 +1608,7 @@ impl postgres_backend::Handler for PageServerHandler {
 ///
 struct CopyDataSink<'a> {
     pgb: &'a mut PostgresBackend,
+    rt: tokio::runtime::Handle,
 }
 
 impl<'a> io::Write for CopyDataSink<'a> {
@@ -950,7 +1619,8 @@ impl<'a> io::Write for CopyDataSink<'a> {
         // the length cannot exceed u32.
         // FIXME: flush isn't really required, but makes it easier
         // to view in wireshark
-        self.pgb.write_message(&BeMessage::CopyData(data))?;
+        self.rt
+            .block_on(self.pgb.write_message(&BeMessage::CopyData(data)))?;
         trace!("CopyData sent for {} bytes!", data.len());
 
         Ok(data.len())
@@ -960,3 +1630,184 @@ impl<'a> io::Write for CopyDataSink<'a> {
         Ok(())
     }
 }
+
+///
+/// A std::io::Read implementation that returns the data of the CopyData
+/// messages sent by the client, until CopyDone.
+///
+struct CopyInReader<'a> {
+    pgb: &'a mut PostgresBackend,
+    rt: tokio::runtime::Handle,
+    buf: Bytes,
+    done: bool,
+}
+
+impl<'a> CopyInReader<'a> {
+    fn new(pgb: &'a mut PostgresBackend, rt: tokio::runtime::Handle) -> Self {
+        CopyInReader {
+            pgb,
+            rt,
+            buf: Bytes::new(),
+            done: false,
+        }
+    }
+}
+
+impl<'a> io::Read for CopyInReader<'a> {
+    fn read(&mut self, data: &mut [u8]) -> io::Result<usize> {
+        while self.buf.is_empty() {
+            if self.done {
+                return Ok(0);
+            }
+            let msg = self
+                .rt
+                .block_on(self.pgb.read_message())
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+            match msg {
+                Some(FeMessage::CopyData(bytes)) => self.buf = bytes,
+                Some(FeMessage::CopyDone) => self.done = true,
+                Some(FeMessage::CopyFail) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "client failed the COPY",
+                    ));
+                }
+                Some(msg) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("unexpected message during COPY: {:?}", msg),
+                    ));
+                }
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "client disconnected during COPY",
+                    ));
+                }
+            }
+        }
+
+        let n = data.len().min(self.buf.len());
+        data[..n].copy_from_slice(&self.buf[..n]);
+        self.buf.advance(n);
+        trace!("CopyData received {} bytes", n);
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_get_pages_request() -> Result<()> {
+        let rel = RelTag {
+            spcnode: 1663,
+            dbnode: 13010,
+            relnode: 16384,
+            forknum: 0,
+        };
+
+        let mut buf = BytesMut::new();
+        buf.put_u8(4);
+        buf.put_u8(1);
+        buf.put_u64(0x16B9188);
+        buf.put_u32(2);
+        for blkno in [0, 42] {
+            buf.put_u32(rel.spcnode);
+            buf.put_u32(rel.dbnode);
+            buf.put_u32(rel.relnode);
+            buf.put_u8(rel.forknum);
+            buf.put_u32(blkno);
+        }
+
+        let protocol = PagestreamProtocol::negotiate(2, PAGESTREAM_CAP_GET_PAGES)?;
+
+        // GetPages has to be negotiated first
+        assert!(PagestreamFeMessage::parse(buf.clone().freeze(), &PagestreamProtocol::V1).is_err());
+
+        match PagestreamFeMessage::parse(buf.clone().freeze(), &protocol)? {
+            PagestreamFeMessage::GetPages(req) => {
+                assert!(req.latest);
+                assert_eq!(req.lsn, Lsn(0x16B9188));
+                assert_eq!(req.pages, vec![(rel, 0), (rel, 42)]);
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        // A truncated request is an error, not a panic
+        buf.truncate(buf.len() - 1);
+        assert!(PagestreamFeMessage::parse(buf.clone().freeze(), &protocol).is_err());
+        for len in (1..=GET_PAGES_HEADER_SIZE).rev() {
+            buf.truncate(len);
+            assert!(PagestreamFeMessage::parse(buf.clone().freeze(), &protocol).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_get_slru_page_request() -> Result<()> {
+        let mut buf = BytesMut::new();
+        buf.put_u8(5);
+        buf.put_u8(0);
+        buf.put_u64(0x16B9188);
+        buf.put_u8(1);
+        buf.put_u32(3);
+        buf.put_u32(7);
+
+        let protocol = PagestreamProtocol::negotiate(2, PAGESTREAM_CAP_GET_SLRU_PAGE)?;
+        match PagestreamFeMessage::parse(buf.clone().freeze(), &protocol)? {
+            PagestreamFeMessage::GetSlruPage(req) => {
+                assert!(!req.latest);
+                assert_eq!(req.lsn, Lsn(0x16B9188));
+                assert_eq!(req.kind, SlruKind::MultiXactMembers);
+                assert_eq!((req.segno, req.blkno), (3, 7));
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        // A truncated request is an error, not a panic
+        buf.truncate(buf.len() - 1);
+        assert!(PagestreamFeMessage::parse(buf.freeze(), &protocol).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn serialize_get_pages_response() -> Result<()> {
+        let protocol = PagestreamProtocol::negotiate(2, PAGESTREAM_CAP_GET_PAGES)?;
+        let page = Bytes::from_static(&[0xAB; 8192]);
+        let resp = PagestreamBeMessage::GetPages(PagestreamGetPagesResponse {
+            pages: vec![Ok(page), Err("oops".to_string())],
+        });
+        assert!(resp.serialize(&PagestreamProtocol::V1).is_err());
+        let mut bytes = resp.serialize(&protocol)?;
+        assert_eq!(bytes.get_u8(), 105);
+        assert_eq!(bytes.get_u32(), 2);
+        assert_eq!(bytes.get_u8(), 0);
+        bytes.advance(8192);
+        assert_eq!(bytes.get_u8(), 1);
+        assert_eq!(&bytes[..], b"oops\0");
+        Ok(())
+    }
+
+    #[test]
+    fn negotiate_protocol() -> Result<()> {
+        assert_eq!(
+            PagestreamProtocol::negotiate(1, u64::MAX)?,
+            PagestreamProtocol::V1
+        );
+
+        // Unknown capabilities are silently dropped
+        let protocol = PagestreamProtocol::negotiate(2, u64::MAX)?;
+        assert_eq!(protocol.capabilities, PAGESTREAM_SUPPORTED_CAPABILITIES);
+        let protocol = PagestreamProtocol::negotiate(2, 0)?;
+        assert!(!protocol.has_capability(PAGESTREAM_CAP_GET_PAGES));
+
+        // Unknown versions are rejected
+        assert!(PagestreamProtocol::negotiate(0, 0).is_err());
+        assert!(PagestreamProtocol::negotiate(PAGESTREAM_MAX_PROTOCOL_VERSION + 1, 0).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,593 @@
+//!
+//! Parser for the commands accepted by the page service.
+//!
+//! A command is a single line. The first word names the command (two words
+//! for `import basebackup`), and the rest are its arguments, separated by
+//! whitespace. A few commands take a free-form last argument that extends to
+//! the end of the line.
+//!
+//! Malformed commands are reported with SQLSTATE 42601 (syntax_error), and
+//! arguments that don't parse with 22023 (invalid_parameter_value).
+//!
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use utils::{
+    lsn::Lsn,
+    pq_proto::{QueryError, SQLSTATE_INVALID_PARAMETER_VALUE, SQLSTATE_SYNTAX_ERROR},
+    zid::{ZTenantId, ZTimelineId},
+};
+
+/// A parsed page service command.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PageServiceCommand {
+    PageStream {
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+        /// Clients that don't pass a protocol version speak version 1
+        protocol_version: Option<u32>,
+        capabilities: u64,
+    },
+    BaseBackup {
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+        lsn: Option<Lsn>,
+        include_slru: bool,
+    },
+    FullBackup {
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+        lsn: Option<Lsn>,
+    },
+    ImportBasebackup {
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+        start_lsn: Lsn,
+        end_lsn: Lsn,
+    },
+    CallMeMaybe {
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+        connstr: String,
+    },
+    /// (name, actions) pairs
+    Failpoints(Vec<(String, String)>),
+    Show {
+        tenant_id: ZTenantId,
+    },
+    DoGc {
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+        gc_horizon: Option<u64>,
+        dry_run: bool,
+    },
+    Compact {
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+    },
+    Checkpoint {
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+    },
+    GetLsnByTimestamp {
+        tenant_id: ZTenantId,
+        timeline_id: ZTimelineId,
+        timestamp: SystemTime,
+    },
+    /// SET commands are accepted and ignored. This is important because
+    /// psycopg2 executes "SET datestyle TO 'ISO'" on connect.
+    Set,
+    Help,
+}
+
+/// Description of a command, for `help` and error messages.
+pub struct CommandHelp {
+    pub name: &'static str,
+    pub synopsis: &'static str,
+    pub description: &'static str,
+}
+
+pub const COMMANDS: &[CommandHelp] = &[
+    CommandHelp {
+        name: "pagestream",
+        synopsis: "pagestream <tenant_id> <timeline_id> [<protocol_version> [<capabilities>]]",
+        description: "Enter the pagestream sub-protocol, to serve GetPage@LSN requests",
+    },
+    CommandHelp {
+        name: "basebackup",
+        synopsis: "basebackup <tenant_id> <timeline_id> [<lsn>] [--no-slru]",
+        description: "Send a tarball to start a compute node at the LSN, or at the end of WAL",
+    },
+    CommandHelp {
+        name: "fullbackup",
+        synopsis: "fullbackup <tenant_id> <timeline_id> [<lsn>]",
+        description: "Send a tarball of a complete data directory at the LSN",
+    },
+    CommandHelp {
+        name: "import basebackup",
+        synopsis: "import basebackup <tenant_id> <timeline_id> <start_lsn> <end_lsn>",
+        description: "Create a new timeline from a basebackup tarball, sent with COPY",
+    },
+    CommandHelp {
+        name: "callmemaybe",
+        synopsis: "callmemaybe <tenant_id> <timeline_id> <connstr>",
+        description: "Start receiving WAL for the timeline from the safekeeper at connstr",
+    },
+    CommandHelp {
+        name: "failpoints",
+        synopsis: "failpoints <name>=<actions>[;<name>=<actions>...]",
+        description: "Configure failpoints, if compiled with failpoints support",
+    },
+    CommandHelp {
+        name: "show",
+        synopsis: "show <tenant_id>",
+        description: "Show the configuration of the tenant",
+    },
+    CommandHelp {
+        name: "do_gc",
+        synopsis: "do_gc <tenant_id> <timeline_id> [<gc_horizon>] [--dry-run]",
+        description:
+            "Run garbage collection on the timeline, or list what it would remove and keep",
+    },
+    CommandHelp {
+        name: "compact",
+        synopsis: "compact <tenant_id> <timeline_id>",
+        description: "Run compaction on the timeline",
+    },
+    CommandHelp {
+        name: "checkpoint",
+        synopsis: "checkpoint <tenant_id> <timeline_id>",
+        description: "Flush the in-memory data of the timeline to disk, and compact it",
+    },
+    CommandHelp {
+        name: "get_lsn_by_timestamp",
+        synopsis: "get_lsn_by_timestamp <tenant_id> <timeline_id> '<timestamp>'",
+        description: "Find the LSN of the last commit at or before the RFC 3339 timestamp",
+    },
+    CommandHelp {
+        name: "set",
+        synopsis: "set ...",
+        description: "Accepted and ignored, for compatibility with client libraries",
+    },
+    CommandHelp {
+        name: "help",
+        synopsis: "help",
+        description: "Show this list of commands",
+    },
+];
+
+impl PageServiceCommand {
+    pub fn parse(query: &str) -> Result<Self, QueryError> {
+        let mut args = Args::new(query);
+        let name = match args.next() {
+            Some(name) => name,
+            None => return Err(syntax_error("empty command")),
+        };
+
+        if name.eq_ignore_ascii_case("set") {
+            return Ok(PageServiceCommand::Set);
+        }
+
+        let command = match name {
+            "pagestream" => {
+                args.command = "pagestream";
+                let tenant_id = args.tenant_id()?;
+                let timeline_id = args.timeline_id()?;
+                let protocol_version = args.next().map(|v| args.parse(v)).transpose()?;
+                let capabilities = match args.next() {
+                    Some(c) => args.parse(c)?,
+                    None => 0,
+                };
+                PageServiceCommand::PageStream {
+                    tenant_id,
+                    timeline_id,
+                    protocol_version,
+                    capabilities,
+                }
+            }
+            "basebackup" => {
+                args.command = "basebackup";
+                let tenant_id = args.tenant_id()?;
+                let timeline_id = args.timeline_id()?;
+                let mut lsn = None;
+                let mut include_slru = true;
+                while let Some(arg) = args.next() {
+                    match arg {
+                        "--no-slru" => include_slru = false,
+                        _ if lsn.is_none() => lsn = Some(args.parse(arg)?),
+                        _ => {
+                            let msg = format!("unexpected argument '{}'", arg);
+                            return Err(args.usage_error(msg));
+                        }
+                    }
+                }
+                PageServiceCommand::BaseBackup {
+                    tenant_id,
+                    timeline_id,
+                    lsn,
+                    include_slru,
+                }
+            }
+            "fullbackup" => {
+                args.command = "fullbackup";
+                PageServiceCommand::FullBackup {
+                    tenant_id: args.tenant_id()?,
+                    timeline_id: args.timeline_id()?,
+                    lsn: args.next().map(|lsn| args.parse(lsn)).transpose()?,
+                }
+            }
+            "import" => {
+                args.command = "import basebackup";
+                if args.next() != Some("basebackup") {
+                    return Err(args.usage_error("unknown import command"));
+                }
+                PageServiceCommand::ImportBasebackup {
+                    tenant_id: args.tenant_id()?,
+                    timeline_id: args.timeline_id()?,
+                    start_lsn: args.lsn("start_lsn")?,
+                    end_lsn: args.lsn("end_lsn")?,
+                }
+            }
+            "callmemaybe" => {
+                args.command = "callmemaybe";
+                PageServiceCommand::CallMeMaybe {
+                    tenant_id: args.tenant_id()?,
+                    timeline_id: args.timeline_id()?,
+                    connstr: args.rest_of_line("connstr")?.to_owned(),
+                }
+            }
+            "failpoints" => {
+                args.command = "failpoints";
+                let mut failpoints = Vec::new();
+                for failpoint in args.rest_of_line("failpoint")?.split(';') {
+                    match failpoint.trim().split_once('=') {
+                        Some((name, actions)) if !name.is_empty() => {
+                            failpoints.push((name.to_owned(), actions.to_owned()))
+                        }
+                        _ => {
+                            return Err(args
+                                .usage_error(format!("invalid failpoint '{}'", failpoint.trim())))
+                        }
+                    }
+                }
+                PageServiceCommand::Failpoints(failpoints)
+            }
+            "show" => {
+                args.command = "show";
+                PageServiceCommand::Show {
+                    tenant_id: args.tenant_id()?,
+                }
+            }
+            "do_gc" => {
+                args.command = "do_gc";
+                let tenant_id = args.tenant_id()?;
+                let timeline_id = args.timeline_id()?;
+                let mut gc_horizon = None;
+                let mut dry_run = false;
+                while let Some(arg) = args.next() {
+                    match arg {
+                        "--dry-run" => dry_run = true,
+                        _ if gc_horizon.is_none() => gc_horizon = Some(args.parse(arg)?),
+                        _ => {
+                            let msg = format!("unexpected argument '{}'", arg);
+                            return Err(args.usage_error(msg));
+                        }
+                    }
+                }
+                PageServiceCommand::DoGc {
+                    tenant_id,
+                    timeline_id,
+                    gc_horizon,
+                    dry_run,
+                }
+            }
+            "compact" => {
+                args.command = "compact";
+                PageServiceCommand::Compact {
+                    tenant_id: args.tenant_id()?,
+                    timeline_id: args.timeline_id()?,
+                }
+            }
+            "checkpoint" => {
+                args.command = "checkpoint";
+                PageServiceCommand::Checkpoint {
+                    tenant_id: args.tenant_id()?,
+                    timeline_id: args.timeline_id()?,
+                }
+            }
+            "get_lsn_by_timestamp" => {
+                args.command = "get_lsn_by_timestamp";
+                let tenant_id = args.tenant_id()?;
+                let timeline_id = args.timeline_id()?;
+                let timestamp = args.rest_of_line("timestamp")?;
+                let timestamp = timestamp
+                    .strip_prefix('\'')
+                    .and_then(|t| t.strip_suffix('\''))
+                    .unwrap_or(timestamp);
+                let timestamp = humantime::parse_rfc3339(timestamp).map_err(|e| {
+                    invalid_parameter(format!("invalid timestamp '{}': {}", timestamp, e))
+                })?;
+                PageServiceCommand::GetLsnByTimestamp {
+                    tenant_id,
+                    timeline_id,
+                    timestamp,
+                }
+            }
+            "help" => {
+                args.command = "help";
+                PageServiceCommand::Help
+            }
+            _ => {
+                return Err(syntax_error(format!(
+                    "unknown command '{}', use 'help' for a list of commands",
+                    name
+                )))
+            }
+        };
+
+        args.finish()?;
+        Ok(command)
+    }
+}
+
+/// Splits the arguments of a command, and reports errors with its synopsis.
+struct Args<'a> {
+    command: &'static str,
+    rest: &'a str,
+}
+
+impl<'a> Args<'a> {
+    fn new(query: &'a str) -> Self {
+        Args {
+            command: "",
+            rest: query,
+        }
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = self.rest.trim_start();
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (word, rest) = rest.split_at(end);
+        self.rest = rest;
+        if word.is_empty() {
+            None
+        } else {
+            Some(word)
+        }
+    }
+
+    fn required(&mut self, what: &str) -> Result<&'a str, QueryError> {
+        match self.next() {
+            Some(word) => Ok(word),
+            None => Err(self.usage_error(format!("missing {}", what))),
+        }
+    }
+
+    /// Everything up to the end of the line, for free-form arguments.
+    fn rest_of_line(&mut self, what: &str) -> Result<&'a str, QueryError> {
+        let rest = self.rest.trim();
+        self.rest = "";
+        if rest.is_empty() {
+            Err(self.usage_error(format!("missing {}", what)))
+        } else {
+            Ok(rest)
+        }
+    }
+
+    fn parse<T>(&self, word: &str) -> Result<T, QueryError>
+    where
+        T: FromStr,
+    {
+        word.parse().map_err(|_| {
+            invalid_parameter(format!(
+                "invalid argument '{}' for {} command",
+                word, self.command
+            ))
+        })
+    }
+
+    fn tenant_id(&mut self) -> Result<ZTenantId, QueryError> {
+        let word = self.required("tenant_id")?;
+        ZTenantId::from_str(word)
+            .map_err(|_| invalid_parameter(format!("invalid tenant_id '{}'", word)))
+    }
+
+    fn timeline_id(&mut self) -> Result<ZTimelineId, QueryError> {
+        let word = self.required("timeline_id")?;
+        ZTimelineId::from_str(word)
+            .map_err(|_| invalid_parameter(format!("invalid timeline_id '{}'", word)))
+    }
+
+    fn lsn(&mut self, what: &str) -> Result<Lsn, QueryError> {
+        let word = self.required(what)?;
+        Lsn::from_str(word).map_err(|_| invalid_parameter(format!("invalid {} '{}'", what, word)))
+    }
+
+    fn finish(mut self) -> Result<(), QueryError> {
+        match self.next() {
+            Some(word) => Err(self.usage_error(format!("unexpected argument '{}'", word))),
+            None => Ok(()),
+        }
+    }
+
+    fn usage_error(&self, msg: impl AsRef<str>) -> QueryError {
+        let synopsis = COMMANDS
+            .iter()
+            .find(|help| help.name == self.command)
+            .map_or(self.command, |help| help.synopsis);
+        syntax_error(format!(
+            "invalid {} command: {}, usage: {}",
+            self.command,
+            msg.as_ref(),
+            synopsis
+        ))
+    }
+}
+
+fn syntax_error(msg: impl Into<String>) -> QueryError {
+    QueryError::new(SQLSTATE_SYNTAX_ERROR, msg)
+}
+
+fn invalid_parameter(msg: impl Into<String>) -> QueryError {
+    QueryError::new(SQLSTATE_INVALID_PARAMETER_VALUE, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TENANT: &str = "3aa8fcc61f6d357410b7de754b1d9001";
+    const TIMELINE: &str = "de200bd42b49cc1814412c7e592dd6e9";
+
+    fn parse_err(query: &str) -> QueryError {
+        PageServiceCommand::parse(query).unwrap_err()
+    }
+
+    #[test]
+    fn parse_commands() {
+        let tenant_id = ZTenantId::from_str(TENANT).unwrap();
+        let timeline_id = ZTimelineId::from_str(TIMELINE).unwrap();
+
+        assert_eq!(
+            PageServiceCommand::parse(&format!("pagestream {} {}", TENANT, TIMELINE)).unwrap(),
+            PageServiceCommand::PageStream {
+                tenant_id,
+                timeline_id,
+                protocol_version: None,
+                capabilities: 0,
+            }
+        );
+        assert_eq!(
+            PageServiceCommand::parse(&format!("pagestream {} {} 2 3", TENANT, TIMELINE)).unwrap(),
+            PageServiceCommand::PageStream {
+                tenant_id,
+                timeline_id,
+                protocol_version: Some(2),
+                capabilities: 3,
+            }
+        );
+        assert_eq!(
+            PageServiceCommand::parse(&format!(
+                "basebackup {} {} --no-slru 0/16B5A50",
+                TENANT, TIMELINE
+            ))
+            .unwrap(),
+            PageServiceCommand::BaseBackup {
+                tenant_id,
+                timeline_id,
+                lsn: Some(Lsn(0x16B5A50)),
+                include_slru: false,
+            }
+        );
+        assert_eq!(
+            PageServiceCommand::parse(&format!(
+                "import basebackup {} {} 0/2000028 0/2000100",
+                TENANT, TIMELINE
+            ))
+            .unwrap(),
+            PageServiceCommand::ImportBasebackup {
+                tenant_id,
+                timeline_id,
+                start_lsn: Lsn(0x2000028),
+                end_lsn: Lsn(0x2000100),
+            }
+        );
+        assert_eq!(
+            PageServiceCommand::parse(&format!(
+                "callmemaybe {} {} host=localhost port=5454 options='-c x=y'",
+                TENANT, TIMELINE
+            ))
+            .unwrap(),
+            PageServiceCommand::CallMeMaybe {
+                tenant_id,
+                timeline_id,
+                connstr: "host=localhost port=5454 options='-c x=y'".to_owned(),
+            }
+        );
+        assert_eq!(
+            PageServiceCommand::parse("failpoints a=return;b=sleep(10)").unwrap(),
+            PageServiceCommand::Failpoints(vec![
+                ("a".to_owned(), "return".to_owned()),
+                ("b".to_owned(), "sleep(10)".to_owned()),
+            ])
+        );
+        assert_eq!(
+            PageServiceCommand::parse(&format!("do_gc {} {} 0", TENANT, TIMELINE)).unwrap(),
+            PageServiceCommand::DoGc {
+                tenant_id,
+                timeline_id,
+                gc_horizon: Some(0),
+                dry_run: false,
+            }
+        );
+        assert_eq!(
+            PageServiceCommand::parse(&format!("do_gc {} {} --dry-run", TENANT, TIMELINE)).unwrap(),
+            PageServiceCommand::DoGc {
+                tenant_id,
+                timeline_id,
+                gc_horizon: None,
+                dry_run: true,
+            }
+        );
+        assert_eq!(
+            PageServiceCommand::parse(&format!(
+                "get_lsn_by_timestamp {} {} '2022-05-01T12:00:00Z'",
+                TENANT, TIMELINE
+            ))
+            .unwrap(),
+            PageServiceCommand::GetLsnByTimestamp {
+                tenant_id,
+                timeline_id,
+                timestamp: humantime::parse_rfc3339("2022-05-01T12:00:00Z").unwrap(),
+            }
+        );
+        assert_eq!(
+            PageServiceCommand::parse("SET datestyle TO 'ISO'").unwrap(),
+            PageServiceCommand::Set
+        );
+        assert_eq!(
+            PageServiceCommand::parse("help").unwrap(),
+            PageServiceCommand::Help
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = parse_err("frobnicate");
+        assert_eq!(err.code, SQLSTATE_SYNTAX_ERROR);
+        assert!(err.message.contains("unknown command 'frobnicate'"));
+
+        assert_eq!(parse_err("").code, SQLSTATE_SYNTAX_ERROR);
+
+        let err = parse_err(&format!("compact {}", TENANT));
+        assert_eq!(err.code, SQLSTATE_SYNTAX_ERROR);
+        assert_eq!(
+            err.message,
+            "invalid compact command: missing timeline_id, usage: compact <tenant_id> <timeline_id>"
+        );
+
+        let err = parse_err(&format!("checkpoint {} {} extra", TENANT, TIMELINE));
+        assert_eq!(err.code, SQLSTATE_SYNTAX_ERROR);
+        assert!(err.message.contains("unexpected argument 'extra'"));
+
+        let err = parse_err(&format!("show {}x", TENANT));
+        assert_eq!(err.code, SQLSTATE_INVALID_PARAMETER_VALUE);
+
+        let err = parse_err(&format!("fullbackup {} {} 0/zz", TENANT, TIMELINE));
+        assert_eq!(err.code, SQLSTATE_INVALID_PARAMETER_VALUE);
+
+        let err = parse_err(&format!("do_gc {} {} -1", TENANT, TIMELINE));
+        assert_eq!(err.code, SQLSTATE_INVALID_PARAMETER_VALUE);
+
+        let err = parse_err(&format!("do_gc {} {} 0 --dry-run 1", TENANT, TIMELINE));
+        assert_eq!(err.code, SQLSTATE_SYNTAX_ERROR);
+        assert!(err.message.contains("unexpected argument '1'"));
+
+        let err = parse_err("failpoints a=return;oops");
+        assert_eq!(err.code, SQLSTATE_SYNTAX_ERROR);
+        assert!(err.message.contains("invalid failpoint 'oops'"));
+
+        let err = parse_err(&format!("import {} {}", TENANT, TIMELINE));
+        assert_eq!(err.code, SQLSTATE_SYNTAX_ERROR);
+    }
+}
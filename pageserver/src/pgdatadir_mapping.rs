@@ -16,6 +16,7 @@ use bytes::{Buf, Bytes};
 use postgres_ffi::xlog_utils::TimestampTz;
 use postgres_ffi::{pg_constants, Oid, TransactionId};
 use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
 use std::collections::{HashMap, HashSet};
 use std::ops::Range;
 use std::sync::atomic::{AtomicIsize, Ordering};
@@ -26,6 +27,10 @@ use utils::{bin_ser::BeSer, lsn::Lsn};
 /// Block number within a relation or SLRU. This matches PostgreSQL's BlockNumber type.
 pub type BlockNumber = u32;
 
+/// How many pending updates DatadirModification::flush lets accumulate before
+/// it writes out the data pages.
+const MAX_PENDING_UPDATES_BEFORE_FLUSH: usize = 10000;
+
 pub struct DatadirTimeline<R>
 where
     R: Repository,
@@ -46,11 +51,37 @@ where
     current_logical_size: AtomicIsize,
 }
 
-#[derive(Debug)]
+/// Result of `DatadirTimeline::find_lsn_for_timestamp`
+#[serde_as]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", content = "lsn")]
 pub enum LsnForTimestamp {
-    Present(Lsn),
-    Future(Lsn),
-    Past(Lsn),
+    /// The LSN just before the first commit after the timestamp
+    Present(#[serde_as(as = "DisplayFromStr")] Lsn),
+    /// All commits are older than the timestamp, the LSN is the end of WAL
+    Future(#[serde_as(as = "DisplayFromStr")] Lsn),
+    /// All commits that are still available are newer than the timestamp
+    Past(#[serde_as(as = "DisplayFromStr")] Lsn),
+    /// No commit timestamps found, e.g. just after importing a cluster
+    NoData(#[serde_as(as = "DisplayFromStr")] Lsn),
+}
+
+/// Logical size of a database, see `DatadirTimeline::get_logical_size_breakdown`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DbLogicalSize {
+    pub spcnode: Oid,
+    pub dbnode: Oid,
+    /// Total size of the database's relation forks, in bytes
+    pub size: u64,
+    pub relations: Vec<RelLogicalSize>,
+}
+
+/// Logical size of a relation fork, in bytes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RelLogicalSize {
+    pub relnode: Oid,
+    pub forknum: u8,
+    pub size: u64,
 }
 
 impl<R: Repository> DatadirTimeline<R> {
@@ -250,7 +281,7 @@ impl<R: Repository> DatadirTimeline<R> {
             (false, false) => {
                 // This can happen if no commit records have been processed yet, e.g.
                 // just after importing a cluster.
-                bail!("no commit timestamps found");
+                Ok(LsnForTimestamp::NoData(max_lsn))
             }
             (true, false) => {
                 // Didn't find any commit timestamps larger than the request
@@ -418,11 +449,45 @@ impl<R: Repository> DatadirTimeline<R> {
         Ok(total_size * pg_constants::BLCKSZ as usize)
     }
 
+    /// Logical size at the given LSN, broken down by database and relation fork.
+    /// Like in `get_current_logical_size_non_incremental`, only relation blocks
+    /// are counted.
+    pub fn get_logical_size_breakdown(&self, lsn: Lsn) -> Result<Vec<DbLogicalSize>> {
+        let mut dbdirs = self.list_dbdirs(lsn)?.into_keys().collect::<Vec<_>>();
+        dbdirs.sort_unstable();
+
+        let mut databases = Vec::with_capacity(dbdirs.len());
+        for (spcnode, dbnode) in dbdirs {
+            let mut rels = self
+                .list_rels(spcnode, dbnode, lsn)?
+                .into_iter()
+                .collect::<Vec<_>>();
+            rels.sort_unstable();
+
+            let mut relations = Vec::with_capacity(rels.len());
+            for rel in rels {
+                let nblocks = self.get_rel_size(rel, lsn)?;
+                relations.push(RelLogicalSize {
+                    relnode: rel.relnode,
+                    forknum: rel.forknum,
+                    size: nblocks as u64 * pg_constants::BLCKSZ as u64,
+                });
+            }
+            databases.push(DbLogicalSize {
+                spcnode,
+                dbnode,
+                size: relations.iter().map(|rel| rel.size).sum(),
+                relations,
+            });
+        }
+        Ok(databases)
+    }
+
     ///
     /// Get a KeySpace that covers all the Keys that are in use at the given LSN.
     /// Anything that's not listed maybe removed from the underlying storage (from
     /// that LSN forwards).
-    fn collect_keyspace(&self, lsn: Lsn) -> Result<KeySpace> {
+    pub fn collect_keyspace(&self, lsn: Lsn) -> Result<KeySpace> {
         // Iterate through key ranges, greedily packing them into partitions
         let mut result = KeySpaceAccum::new();
 
@@ -763,6 +828,23 @@ impl<'a, R: Repository> DatadirModification<'a, R> {
         Ok(())
     }
 
+    /// Does relation exist, including relations created in this modification?
+    pub fn get_rel_exists(&self, rel: RelTag) -> Result<bool> {
+        ensure!(rel.relnode != 0, "invalid relnode");
+        let dbdir = DbDirectory::des(&self.get(DBDIR_KEY)?)?;
+        if !dbdir.dbdirs.contains_key(&(rel.spcnode, rel.dbnode)) {
+            return Ok(false);
+        }
+        let rel_dir = RelDirectory::des(&self.get(rel_dir_to_key(rel.spcnode, rel.dbnode))?)?;
+        Ok(rel_dir.rels.contains(&(rel.relnode, rel.forknum)))
+    }
+
+    /// Get size of a relation, including any changes made in this modification
+    pub fn get_rel_size(&self, rel: RelTag) -> Result<BlockNumber> {
+        ensure!(rel.relnode != 0, "invalid relnode");
+        Ok(self.get(rel_size_to_key(rel))?.get_u32_le())
+    }
+
     /// Drop a relation.
     pub fn put_rel_drop(&mut self, rel: RelTag) -> Result<()> {
         ensure!(rel.relnode != 0, "invalid relnode");
@@ -879,6 +961,52 @@ impl<'a, R: Repository> DatadirModification<'a, R> {
         Ok(())
     }
 
+    ///
+    /// Write the relation and SLRU data pages accumulated so far to the
+    /// underlying timeline, if there are enough of them to be worth it.
+    ///
+    /// This breaks the atomicity of the modification: the flushed pages
+    /// become visible before 'commit' is called, and they are not visible
+    /// through this modification anymore. It is meant for bulk imports
+    /// into a fresh timeline, where nothing reads the timeline until the
+    /// import has finished, and a failed import removes the timeline
+    /// anyway. The metadata updates are kept until 'commit', so that the
+    /// relation sizes and directories still appear atomically.
+    ///
+    pub fn flush(&mut self) -> Result<()> {
+        // Unless we have accumulated a decent number of pages, it's not
+        // worth scanning through the pending updates.
+        if self.pending_updates.len() < MAX_PENDING_UPDATES_BEFORE_FLUSH {
+            return Ok(());
+        }
+
+        let writer = self.tline.tline.writer();
+
+        let lsn = self.lsn;
+        let mut result: Result<()> = Ok(());
+        self.pending_updates.retain(|&key, value| {
+            if result.is_ok() && (is_rel_block_key(key) || is_slru_block_key(key)) {
+                result = writer.put(key, lsn, value.clone());
+                false
+            } else {
+                true
+            }
+        });
+        result?;
+
+        // The pages are in the timeline now, account for them in the
+        // logical size too.
+        if self.pending_nblocks != 0 {
+            self.tline.current_logical_size.fetch_add(
+                self.pending_nblocks * pg_constants::BLCKSZ as isize,
+                Ordering::SeqCst,
+            );
+            self.pending_nblocks = 0;
+        }
+
+        Ok(())
+    }
+
     ///
     /// Finish this atomic update, writing all the updated keys to the
     /// underlying timeline.
@@ -1115,6 +1243,10 @@ fn rel_block_to_key(rel: RelTag, blknum: BlockNumber) -> Key {
     }
 }
 
+fn is_rel_block_key(key: Key) -> bool {
+    key.field1 == 0x00 && key.field4 != 0 && key.field6 != 0xffffffff
+}
+
 fn rel_size_to_key(rel: RelTag) -> Key {
     Key {
         field1: 0x00,
@@ -1176,6 +1308,10 @@ fn slru_block_to_key(kind: SlruKind, segno: u32, blknum: BlockNumber) -> Key {
     }
 }
 
+fn is_slru_block_key(key: Key) -> bool {
+    key.field1 == 0x01 && key.field3 == 0x00000001 && key.field6 != 0xffffffff
+}
+
 fn slru_segment_size_to_key(kind: SlruKind, segno: u32) -> Key {
     Key {
         field1: 0x01,
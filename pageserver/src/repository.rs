@@ -6,6 +6,7 @@ use anyhow::{bail, Result};
 use byteorder::{ByteOrder, BE};
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
 use std::fmt;
 use std::fmt::Display;
 use std::ops::{AddAssign, Range};
@@ -195,6 +196,15 @@ impl Display for TimelineSyncStatusUpdate {
         f.write_str(s)
     }
 }
+
+/// Returned by [`Repository::detach_timeline`] when other timelines are branched off the timeline.
+#[derive(Debug, thiserror::Error)]
+#[error("timeline {timeline_id} has child timelines {children:?}")]
+pub struct TimelineHasChildren {
+    pub timeline_id: ZTimelineId,
+    pub children: Vec<ZTimelineId>,
+}
+
 ///
 /// A repository corresponds to one .zenith directory. One repository holds multiple
 /// timelines, forked off from the same initial call to 'initdb'.
@@ -241,16 +251,20 @@ pub trait Repository: Send + Sync {
     /// also it can be explicitly requested through page server api 'do_gc' command.
     ///
     /// 'timelineid' specifies the timeline to GC, or None for all.
-    /// `horizon` specifies delta from last lsn to preserve all object versions (pitr interval).
+    /// `horizon` specifies delta from last lsn to preserve all object versions, or None to use
+    /// each timeline's `gc_horizon` setting. An explicit horizon takes precedence over it.
     /// `checkpoint_before_gc` parameter is used to force compaction of storage before CG
     /// to make tests more deterministic.
     /// TODO Do we still need it or we can call checkpoint explicitly in tests where needed?
+    /// With `dry_run`, nothing is removed. Instead, the result lists all the layer files
+    /// with the reason why each of them would be kept, if any.
     fn gc_iteration(
         &self,
         timelineid: Option<ZTimelineId>,
-        horizon: u64,
+        horizon: Option<u64>,
         pitr: Duration,
         checkpoint_before_gc: bool,
+        dry_run: bool,
     ) -> Result<GcResult>;
 
     /// Perform one compaction iteration.
@@ -259,7 +273,8 @@ pub trait Repository: Send + Sync {
     /// api's 'compact' command.
     fn compaction_iteration(&self) -> Result<()>;
 
-    /// detaches timeline-related in-memory data.
+    /// detaches timeline-related in-memory data. Fails with [`TimelineHasChildren`] if other
+    /// timelines are branched off it, the check is atomic with the branch creation.
     fn detach_timeline(&self, timeline_id: ZTimelineId) -> Result<()>;
 
     // Allows to retrieve remote timeline index from the repo. Used in walreceiver to grab remote consistent lsn.
@@ -311,9 +326,38 @@ pub struct GcResult {
     pub layers_not_updated: u64,
     pub layers_removed: u64, // # of layer files removed because they have been made obsolete by newer ondisk files.
 
+    /// All the layer files that GC looked at, only filled in a dry run
+    pub layers: Vec<GcLayerInfo>,
+
     pub elapsed: Duration,
 }
 
+/// Why GC keeps a layer file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum GcKeepReason {
+    /// Newer than the `gc_horizon` cutoff
+    Horizon,
+    /// Newer than the PITR cutoff
+    Pitr,
+    /// Might be needed by a child branch or a restore point
+    BranchPoint,
+    /// Newer layers exist, but no image layer that covers the whole key range
+    NewerImageMissing,
+    /// Nothing newer covers any part of the key range
+    Latest,
+}
+
+/// The decision of a GC dry run about a layer file
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GcLayerInfo {
+    #[serde_as(as = "DisplayFromStr")]
+    pub timeline_id: ZTimelineId,
+    pub layer_file_name: String,
+    /// Why the layer would be kept, or None if it would be removed
+    pub keep_reason: Option<GcKeepReason>,
+}
+
 impl AddAssign for GcResult {
     fn add_assign(&mut self, other: Self) {
         self.layers_total += other.layers_total;
@@ -322,11 +366,13 @@ impl AddAssign for GcResult {
         self.layers_needed_by_branches += other.layers_needed_by_branches;
         self.layers_not_updated += other.layers_not_updated;
         self.layers_removed += other.layers_removed;
+        self.layers.extend(other.layers);
 
         self.elapsed += other.elapsed;
     }
 }
 
+#[async_trait::async_trait]
 pub trait Timeline: Send + Sync {
     //------------------------------------------------------------------------------
     // Public GET functions
@@ -338,7 +384,7 @@ pub trait Timeline: Send + Sync {
     /// You should call this before any of the other get_* or list_* functions. Calling
     /// those functions with an LSN that has been processed yet is an error.
     ///
-    fn wait_lsn(&self, lsn: Lsn) -> Result<()>;
+    async fn wait_lsn(&self, lsn: Lsn) -> Result<()>;
 
     /// Lock and get timeline's GC cuttof
     fn get_latest_gc_cutoff_lsn(&self) -> RwLockReadGuard<Lsn>;
@@ -465,10 +511,17 @@ pub mod repo_harness {
                 compaction_target_size: Some(tenant_conf.compaction_target_size),
                 compaction_period: Some(tenant_conf.compaction_period),
                 compaction_threshold: Some(tenant_conf.compaction_threshold),
+                compaction_strategy: Some(tenant_conf.compaction_strategy),
+                compaction_tier_ratio: Some(tenant_conf.compaction_tier_ratio),
                 gc_horizon: Some(tenant_conf.gc_horizon),
                 gc_period: Some(tenant_conf.gc_period),
                 image_creation_threshold: Some(tenant_conf.image_creation_threshold),
                 pitr_interval: Some(tenant_conf.pitr_interval),
+                getpage_rate_limit: Some(tenant_conf.getpage_rate_limit),
+                getpage_burst: Some(tenant_conf.getpage_burst),
+                compression: Some(tenant_conf.compression),
+                scrub_period: Some(tenant_conf.scrub_period),
+                scrub_rate_limit: Some(tenant_conf.scrub_rate_limit),
             }
         }
     }
@@ -746,7 +799,7 @@ mod tests {
         // FIXME: this doesn't actually remove any layer currently, given how the checkpointing
         // and compaction works. But it does set the 'cutoff' point so that the cross check
         // below should fail.
-        repo.gc_iteration(Some(TIMELINE_ID), 0x10, Duration::ZERO, false)?;
+        repo.gc_iteration(Some(TIMELINE_ID), Some(0x10), Duration::ZERO, false, false)?;
 
         // try to branch at lsn 25, should fail because we already garbage collected the data
         match repo.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Lsn(0x25)) {
@@ -797,7 +850,7 @@ mod tests {
         let tline = repo.create_empty_timeline(TIMELINE_ID, Lsn(0))?;
         make_some_layers(tline.as_ref(), Lsn(0x20))?;
 
-        repo.gc_iteration(Some(TIMELINE_ID), 0x10, Duration::ZERO, false)?;
+        repo.gc_iteration(Some(TIMELINE_ID), Some(0x10), Duration::ZERO, false, false)?;
         let latest_gc_cutoff_lsn = tline.get_latest_gc_cutoff_lsn();
         assert!(*latest_gc_cutoff_lsn > Lsn(0x25));
         match tline.get(*TEST_KEY, Lsn(0x25)) {
@@ -820,7 +873,7 @@ mod tests {
             .get_timeline_load(NEW_TIMELINE_ID)
             .expect("Should have a local timeline");
         // this removes layers before lsn 40 (50 minus 10), so there are two remaining layers, image and delta for 31-50
-        repo.gc_iteration(Some(TIMELINE_ID), 0x10, Duration::ZERO, false)?;
+        repo.gc_iteration(Some(TIMELINE_ID), Some(0x10), Duration::ZERO, false, false)?;
         assert!(newtline.get(*TEST_KEY, Lsn(0x25)).is_ok());
 
         Ok(())
@@ -839,7 +892,7 @@ mod tests {
         make_some_layers(newtline.as_ref(), Lsn(0x60))?;
 
         // run gc on parent
-        repo.gc_iteration(Some(TIMELINE_ID), 0x10, Duration::ZERO, false)?;
+        repo.gc_iteration(Some(TIMELINE_ID), Some(0x10), Duration::ZERO, false, false)?;
 
         // Check that the data is still accessible on the branch.
         assert_eq!(
@@ -850,6 +903,50 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_gc_dry_run() -> Result<()> {
+        let repo = RepoHarness::create("test_gc_dry_run")?.load();
+        let tline = repo.create_empty_timeline(TIMELINE_ID, Lsn(0))?;
+        make_some_layers(tline.as_ref(), Lsn(0x20))?;
+
+        let keep_reasons = |result: &GcResult| {
+            result
+                .layers
+                .iter()
+                .map(|l| l.keep_reason)
+                .collect::<Vec<_>>()
+        };
+
+        // The older delta layer is behind the horizon, but there's no image
+        // layer that would replace it
+        let result =
+            repo.gc_iteration(Some(TIMELINE_ID), Some(0x10), Duration::ZERO, false, true)?;
+        assert_eq!(result.layers.len() as u64, result.layers_total);
+        assert_eq!(result.layers_removed, 0);
+        let reasons = keep_reasons(&result);
+        assert!(reasons.contains(&Some(GcKeepReason::Horizon)));
+        assert!(reasons.contains(&Some(GcKeepReason::NewerImageMissing)));
+
+        // A dry run doesn't move the GC cutoff
+        assert_eq!(*tline.get_latest_gc_cutoff_lsn(), Lsn(0));
+
+        // With a branch, the older layer is needed by the branch point
+        repo.branch_timeline(TIMELINE_ID, NEW_TIMELINE_ID, Lsn(0x40))?;
+        let result =
+            repo.gc_iteration(Some(TIMELINE_ID), Some(0x10), Duration::ZERO, false, true)?;
+        let reasons = keep_reasons(&result);
+        assert!(reasons.contains(&Some(GcKeepReason::Horizon)));
+        assert!(reasons.contains(&Some(GcKeepReason::BranchPoint)));
+
+        // A real GC run doesn't report the layers
+        let result =
+            repo.gc_iteration(Some(TIMELINE_ID), Some(0x10), Duration::ZERO, false, false)?;
+        assert!(result.layers.is_empty());
+        assert_eq!(result.layers_removed, 0);
+
+        Ok(())
+    }
+
     #[test]
     fn timeline_load() -> Result<()> {
         const TEST_NAME: &str = "timeline_load";
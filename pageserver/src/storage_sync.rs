@@ -11,6 +11,8 @@
 //!     * [`start_local_timeline_sync`] to launch a background async loop to handle the synchronization
 //!     * [`schedule_layer_upload`], [`schedule_layer_download`], and[`schedule_layer_delete`] to enqueue a new task
 //!       to be processed by the async loop
+//!     * [`download_layer`] to download a single layer file right away, when a read needs a layer that is not present locally
+//!     * [`schedule_index_upload`] to upload the timeline's config overrides and restore points, that are kept in its [`IndexPart`] rather than as separate files
 //!
 //! Here's a schematic overview of all interactions backup and the rest of the pageserver perform:
 //!
@@ -167,19 +169,21 @@ use tracing::*;
 
 use self::{
     delete::delete_timeline_layers,
-    download::{download_timeline_layers, DownloadedTimeline},
+    download::{download_layer_file, download_timeline_layers, DownloadedTimeline},
     index::{IndexPart, RemoteTimeline, RemoteTimelineIndex},
-    upload::{upload_index_part, upload_timeline_layers, UploadedTimeline},
+    upload::{upload_index_part, upload_layer_file, upload_timeline_layers, UploadedTimeline},
 };
 use crate::{
     config::PageServerConf,
     layered_repository::{
         ephemeral_file::is_ephemeral_file,
         metadata::{metadata_path, TimelineMetadata, METADATA_FILE_NAME},
+        restore_points::{self, RestorePoint, RESTORE_POINTS_FILE_NAME},
         LayeredRepository,
     },
     repository::TimelineSyncStatusUpdate,
     storage_sync::{self, index::RemoteIndex},
+    tenant_config::{TimelineConfOpt, TIMELINE_CONFIG_NAME},
     tenant_mgr::apply_timeline_sync_status_updates,
     thread_mgr,
     thread_mgr::ThreadKind,
@@ -191,8 +195,8 @@ use metrics::{
 };
 use utils::zid::{ZTenantId, ZTenantTimelineId, ZTimelineId};
 
-pub use self::download::download_index_part;
 pub use self::download::TEMP_DOWNLOAD_EXTENSION;
+pub use self::download::{download_index_part, list_remote_timelines};
 
 lazy_static! {
     static ref REMAINING_SYNC_ITEMS: IntGauge = register_int_gauge!(
@@ -216,10 +220,18 @@ lazy_static! {
         ]
     )
     .expect("failed to register pageserver image sync time histogram vec");
+    static ref ON_DEMAND_DOWNLOADS: IntCounter = register_int_counter!(
+        "pageserver_remote_storage_on_demand_downloads_total",
+        "Number of layer files downloaded when a read needed them"
+    )
+    .expect("failed to register pageserver remote storage on-demand downloads int counter");
 }
 
 static SYNC_QUEUE: OnceCell<SyncQueue> = OnceCell::new();
 
+/// Remote storage used to download layer files on demand and to rewrite timelines, outside of the sync loop.
+static ON_DEMAND_STORAGE: OnceCell<GenericRemoteStorage> = OnceCell::new();
+
 /// A timeline status to share with pageserver's sync counterpart,
 /// after comparing local and remote timeline state.
 #[derive(Clone, Copy, Debug)]
@@ -229,8 +241,8 @@ pub enum LocalTimelineInitStatus {
     /// but this does not block the timeline from any user interaction.
     LocallyComplete,
     /// A timeline has some files remotely, that are not present locally and need downloading.
-    /// Downloading might update timeline's metadata locally and current pageserver logic deals with local layers only,
-    /// so the data needs to be downloaded first before the timeline can be used.
+    /// Downloading might update timeline's metadata locally, so the data needs to be downloaded first before the timeline can be used.
+    /// With on-demand downloads enabled, only the newer metadata is downloaded and the missing layers are fetched when they're accessed.
     NeedsSync,
 }
 
@@ -256,6 +268,13 @@ pub fn start_local_timeline_sync(
 
     match config.remote_storage_config.as_ref() {
         Some(storage_config) => {
+            let on_demand_storage =
+                GenericRemoteStorage::new(config.workdir.clone(), storage_config)
+                    .context("Failed to init the remote storage for on-demand downloads")?;
+            if ON_DEMAND_STORAGE.set(on_demand_storage).is_err() {
+                bail!("Remote storage for on-demand downloads was already initialized");
+            }
+
             match GenericRemoteStorage::new(config.workdir.clone(), storage_config)
                 .context("Failed to init the generic remote storage")?
             {
@@ -408,6 +427,14 @@ fn collect_timeline_files(
         if entry_path.is_file() {
             if entry_path.file_name().and_then(OsStr::to_str) == Some(METADATA_FILE_NAME) {
                 timeline_metadata_path = Some(entry_path);
+            } else if matches!(
+                entry_path.file_name().and_then(OsStr::to_str),
+                Some(TIMELINE_CONFIG_NAME | RESTORE_POINTS_FILE_NAME)
+            ) {
+                // The timeline config and the restore points are stored in
+                // the remote index part instead
+                debug!("skipping timeline config file {}", entry_path.display());
+                continue;
             } else if is_ephemeral_file(&entry_path.file_name().unwrap().to_string_lossy()) {
                 debug!("skipping ephemeral file {}", entry_path.display());
                 continue;
@@ -675,10 +702,17 @@ impl SyncTaskBatch {
                         .data
                         .deleted_layers
                         .extend(new_delete.data.deleted_layers.into_iter());
+                    batch_delete.data.delete_timeline |= new_delete.data.delete_timeline;
                 }
                 None => self.delete = Some(new_delete),
             },
         }
+
+        // Nothing to upload or download for a timeline that is being deleted
+        if matches!(&self.delete, Some(delete) if delete.data.delete_timeline) {
+            self.upload = None;
+            self.download = None;
+        }
     }
 }
 
@@ -714,6 +748,10 @@ struct LayersDeletion {
     /// the corresponding files on S3 won't exist for pageserver albeit being physically present on that remote storage still.
     /// Then all that's left is to remove the files from the remote storage, without concerns about consistency.
     deletion_registered: bool,
+    /// The whole timeline is deleted. Instead of updating the [`IndexPart`], the deletion is
+    /// registered by removing the timeline from the [`RemoteIndex`]. The [`IndexPart`] is removed
+    /// from the remote storage after all the layers.
+    delete_timeline: bool,
 }
 
 /// Adds the new checkpoint files as an upload sync task to the queue.
@@ -747,6 +785,15 @@ pub fn schedule_layer_upload(
     debug!("Upload task for tenant {tenant_id}, timeline {timeline_id} sent")
 }
 
+/// Adds an upload of the timeline's [`IndexPart`] to the queue, to store the latest timeline config
+/// overrides and restore points remotely. Does nothing if the timeline has not been uploaded yet:
+/// they are stored along with its first layers then.
+///
+/// Ensure that the loop is started otherwise the task is never processed.
+pub fn schedule_index_upload(tenant_id: ZTenantId, timeline_id: ZTimelineId) {
+    schedule_layer_upload(tenant_id, timeline_id, HashSet::new(), None)
+}
+
 /// Adds the new files to delete as a deletion task to the queue.
 /// On task failure, it gets retried again from the start a number of times.
 ///
@@ -772,11 +819,138 @@ pub fn schedule_layer_delete(
             layers_to_delete,
             deleted_layers: HashSet::new(),
             deletion_registered: false,
+            delete_timeline: false,
         }),
     );
     debug!("Deletion task for tenant {tenant_id}, timeline {timeline_id} sent")
 }
 
+/// Rewrites the remote copy of a timeline's history, for a change that doesn't move its `disk_consistent_lsn`
+/// forward, like a reset to an earlier LSN or a detach from its ancestor. Regular uploads never do that, as they
+/// only move the remote metadata forward. Unlike the other sync tasks, the rewrite is performed right away, in the
+/// calling thread: the new layers are uploaded, then the [`IndexPart`] with the new metadata and without the removed
+/// layers. The removed layers themselves are left in the remote storage, for the caller to delete.
+///
+/// The caller must do this before it replaces the local metadata: otherwise, if the pageserver stopped in between,
+/// the startup sync would find the remote timeline ahead of the local one and download the old history back.
+/// Like [`download_layer`], this must not be called on a runtime worker thread.
+pub fn rewrite_remote_timeline(
+    conf: &'static PageServerConf,
+    index: &RemoteIndex,
+    sync_id: ZTenantTimelineId,
+    layers_to_delete: &HashSet<PathBuf>,
+    layers_to_upload: &HashSet<PathBuf>,
+    metadata: &TimelineMetadata,
+) -> anyhow::Result<()> {
+    let storage = ON_DEMAND_STORAGE
+        .get()
+        .context("No remote storage configured to rewrite the timeline in")?;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Failed to create timeline rewrite runtime")?;
+    runtime.block_on(async {
+        match storage {
+            GenericRemoteStorage::Local(local_fs_storage) => {
+                rewrite_remote_timeline_data(
+                    conf,
+                    local_fs_storage,
+                    index,
+                    sync_id,
+                    (layers_to_delete, layers_to_upload),
+                    metadata,
+                )
+                .await
+            }
+            GenericRemoteStorage::S3(s3_bucket_storage) => {
+                rewrite_remote_timeline_data(
+                    conf,
+                    s3_bucket_storage,
+                    index,
+                    sync_id,
+                    (layers_to_delete, layers_to_upload),
+                    metadata,
+                )
+                .await
+            }
+        }
+    })
+}
+
+async fn rewrite_remote_timeline_data<P, S>(
+    conf: &'static PageServerConf,
+    storage: &S,
+    index: &RemoteIndex,
+    sync_id: ZTenantTimelineId,
+    (layers_to_delete, layers_to_upload): (&HashSet<PathBuf>, &HashSet<PathBuf>),
+    metadata: &TimelineMetadata,
+) -> anyhow::Result<()>
+where
+    P: Debug + Send + Sync + 'static,
+    S: RemoteStorage<RemoteObjectId = P> + Send + Sync + 'static,
+{
+    for layer_path in layers_to_upload {
+        upload_layer_file(storage, layer_path).await?;
+    }
+
+    let timeline_config = read_timeline_config(conf, sync_id).await?;
+    let restore_points = read_restore_points(conf, sync_id).await?;
+    // Hold the lock until the index part is uploaded, so that the sync loop doesn't
+    // upload one made from the old entry meanwhile
+    let mut index_accessor = index.write().await;
+    let mut remote_timeline = index_accessor
+        .timeline_entry(&sync_id)
+        .with_context(|| format!("No remote index entry for timeline {sync_id} to rewrite"))?
+        .clone();
+    remote_timeline.remove_layers(layers_to_delete);
+    remote_timeline.add_timeline_layers(layers_to_upload.iter().cloned());
+    remote_timeline.metadata = metadata.clone();
+    remote_timeline.timeline_config = timeline_config;
+    remote_timeline.restore_points = restore_points;
+
+    let timeline_path = conf.timeline_path(&sync_id.timeline_id, &sync_id.tenant_id);
+    let new_index_part =
+        IndexPart::from_remote_timeline(&timeline_path, remote_timeline.clone())
+            .context("Failed to create an index part from the rewritten remote timeline")?;
+    info!("Uploading rewritten remote index for the timeline");
+    upload_index_part(conf, storage, sync_id, new_index_part)
+        .await
+        .context("Failed to upload rewritten index part")?;
+
+    index_accessor.add_timeline_entry(sync_id, remote_timeline);
+    Ok(())
+}
+
+/// Adds the deletion of an entire timeline from the remote storage to the queue: all the layers listed
+/// in its [`IndexPart`] are deleted first, then the [`IndexPart`] itself, so that an interrupted deletion
+/// doesn't leave behind layers that nothing refers to. Pending uploads and downloads of the timeline
+/// are dropped. On task failure, it gets retried again from the start a number of times.
+///
+/// Ensure that the loop is started otherwise the task is never processed.
+pub fn schedule_timeline_delete(tenant_id: ZTenantId, timeline_id: ZTimelineId) {
+    let sync_queue = match SYNC_QUEUE.get() {
+        Some(queue) => queue,
+        None => {
+            warn!("Could not send timeline deletion task for tenant {tenant_id}, timeline {timeline_id}");
+            return;
+        }
+    };
+    sync_queue.push(
+        ZTenantTimelineId {
+            tenant_id,
+            timeline_id,
+        },
+        SyncTask::delete(LayersDeletion {
+            layers_to_delete: HashSet::new(),
+            deleted_layers: HashSet::new(),
+            deletion_registered: false,
+            delete_timeline: true,
+        }),
+    );
+    debug!("Timeline deletion task for tenant {tenant_id}, timeline {timeline_id} sent")
+}
+
 /// Requests the download of the entire timeline for a given tenant.
 /// No existing local files are currently overwritten, except the metadata file (if its disk_consistent_lsn is less than the downloaded one).
 /// The metadata file is always updated last, to avoid inconsistencies.
@@ -805,6 +979,67 @@ pub fn schedule_layer_download(tenant_id: ZTenantId, timeline_id: ZTimelineId) {
     debug!("Download task for tenant {tenant_id}, timeline {timeline_id} sent")
 }
 
+/// Requests the download of a timeline, skipping some of its layers. All the layers of the remote timeline
+/// except `layers_to_skip` are downloaded, unless they exist locally, and then the metadata file is updated.
+/// Used to replace corrupt layer files of a timeline that is already present locally, and to download only
+/// the metadata of a timeline whose layers are downloaded on demand.
+///
+/// Like with [`schedule_layer_download`], the caller must set `awaits_download` of the remote timeline.
+///
+/// Ensure that the loop is started otherwise the task is never processed.
+pub fn schedule_layer_redownload(
+    tenant_id: ZTenantId,
+    timeline_id: ZTimelineId,
+    layers_to_skip: HashSet<PathBuf>,
+) {
+    debug!("Scheduling layer redownload for tenant {tenant_id}, timeline {timeline_id}");
+    let sync_queue = match SYNC_QUEUE.get() {
+        Some(queue) => queue,
+        None => {
+            warn!("Could not send download task for tenant {tenant_id}, timeline {timeline_id}");
+            return;
+        }
+    };
+    sync_queue.push(
+        ZTenantTimelineId {
+            tenant_id,
+            timeline_id,
+        },
+        SyncTask::download(LayersDownload { layers_to_skip }),
+    );
+    debug!("Redownload task for tenant {tenant_id}, timeline {timeline_id} sent")
+}
+
+/// Downloads a layer file of a timeline that is in use, because a read needs the layer.
+/// Unlike the other sync tasks, the download is performed right away, in the calling thread.
+///
+/// The caller must make sure that the file is present in the remote index, and that no other
+/// download of the same file is in progress. The download runs on a runtime of its own, so this
+/// must not be called on a runtime worker thread; async callers need to use `block_in_place`.
+pub fn download_layer(layer_path: &Path) -> anyhow::Result<()> {
+    let storage = ON_DEMAND_STORAGE
+        .get()
+        .context("No remote storage configured to download the layer from")?;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Failed to create on-demand download runtime")?;
+    runtime.block_on(async {
+        match storage {
+            GenericRemoteStorage::Local(local_fs_storage) => {
+                download_layer_file(local_fs_storage, layer_path).await
+            }
+            GenericRemoteStorage::S3(s3_bucket_storage) => {
+                download_layer_file(s3_bucket_storage, layer_path).await
+            }
+        }
+    })?;
+
+    ON_DEMAND_DOWNLOADS.inc();
+    Ok(())
+}
+
 /// Launch a thread to perform remote storage sync tasks.
 /// See module docs for loop step description.
 pub(super) fn spawn_storage_sync_thread<P, S>(
@@ -844,6 +1079,7 @@ where
         &mut runtime.block_on(remote_index.write()),
         sync_queue,
         local_timeline_files,
+        conf.on_demand_download,
     );
 
     let remote_index_clone = remote_index.clone();
@@ -1045,8 +1281,11 @@ where
                         }
                     }
                 }
+                None
+            } else {
+                // Nothing to upload, the deletions can proceed
+                Some(())
             }
-            None
         }
         .instrument(info_span!("upload_timeline_data")),
         async {
@@ -1155,7 +1394,13 @@ where
             register_sync_status(sync_start, task_name, Some(false));
         }
         DownloadedTimeline::Successful(mut download_data) => {
-            match update_local_metadata(conf, sync_id, current_remote_timeline).await {
+            let local_update = match update_local_metadata(conf, sync_id, current_remote_timeline)
+                .await
+            {
+                Ok(()) => update_local_timeline_files(conf, sync_id, current_remote_timeline).await,
+                Err(e) => Err(e),
+            };
+            match local_update {
                 Ok(()) => match index.write().await.set_awaits_download(&sync_id, false) {
                     Ok(()) => {
                         register_sync_status(sync_start, task_name, Some(true));
@@ -1166,7 +1411,7 @@ where
                     }
                 },
                 Err(e) => {
-                    error!("Failed to update local timeline metadata: {e:?}");
+                    error!("Failed to update local timeline metadata and files: {e:?}");
                     download_data.retries += 1;
                     sync_queue.push(sync_id, SyncTask::Download(download_data));
                     register_sync_status(sync_start, task_name, Some(false));
@@ -1239,6 +1484,67 @@ async fn update_local_metadata(
     Ok(())
 }
 
+/// Stores the timeline config overrides and the restore points from the remote index locally, unless
+/// the timeline already has the local files: those are at least as recent as the remote data, which is
+/// uploaded from them.
+async fn update_local_timeline_files(
+    conf: &'static PageServerConf,
+    sync_id: ZTenantTimelineId,
+    remote_timeline: Option<&RemoteTimeline>,
+) -> anyhow::Result<()> {
+    let remote_timeline = match remote_timeline {
+        Some(timeline) => timeline,
+        None => return Ok(()),
+    };
+    let ZTenantTimelineId {
+        tenant_id,
+        timeline_id,
+    } = sync_id;
+
+    let remote_config = remote_timeline.timeline_config;
+    let local_config_path = TimelineConfOpt::path(conf, timeline_id, tenant_id);
+    if remote_config != TimelineConfOpt::default() && !local_config_path.exists() {
+        info!("Restoring local timeline config from remote timeline: {remote_config:?}");
+        tokio::task::spawn_blocking(move || {
+            LayeredRepository::persist_timeline_config(conf, timeline_id, tenant_id, remote_config)
+        })
+        .await
+        .with_context(|| {
+            format!(
+                "failed to join persist_timeline_config task for {}",
+                local_config_path.display()
+            )
+        })??;
+    }
+
+    let remote_restore_points = remote_timeline.restore_points.clone();
+    let local_restore_points_path =
+        restore_points::restore_points_path(conf, timeline_id, tenant_id);
+    if !remote_restore_points.is_empty() && !local_restore_points_path.exists() {
+        info!(
+            "Restoring {} local restore points from remote timeline",
+            remote_restore_points.len()
+        );
+        tokio::task::spawn_blocking(move || {
+            restore_points::save_restore_points(
+                conf,
+                timeline_id,
+                tenant_id,
+                &remote_restore_points,
+            )
+        })
+        .await
+        .with_context(|| {
+            format!(
+                "failed to join save_restore_points task for {}",
+                local_restore_points_path.display()
+            )
+        })??;
+    }
+
+    Ok(())
+}
+
 async fn delete_timeline_data<P, S>(
     conf: &'static PageServerConf,
     (storage, index, sync_queue): (&S, &RemoteIndex, &SyncQueue),
@@ -1253,15 +1559,20 @@ async fn delete_timeline_data<P, S>(
     let timeline_delete = &mut new_delete_data.data;
 
     if !timeline_delete.deletion_registered {
-        if let Err(e) = update_remote_data(
-            conf,
-            storage,
-            index,
-            sync_id,
-            RemoteDataUpdate::Delete(&timeline_delete.layers_to_delete),
-        )
-        .await
-        {
+        let registration_result = if timeline_delete.delete_timeline {
+            register_timeline_deletion(index, sync_id, &mut timeline_delete.layers_to_delete).await;
+            Ok(())
+        } else {
+            update_remote_data(
+                conf,
+                storage,
+                index,
+                sync_id,
+                RemoteDataUpdate::Delete(&timeline_delete.layers_to_delete),
+            )
+            .await
+        };
+        if let Err(e) = registration_result {
             error!("Failed to update remote timeline {sync_id}: {e:?}");
             new_delete_data.retries += 1;
             sync_queue.push(sync_id, SyncTask::Delete(new_delete_data));
@@ -1271,10 +1582,24 @@ async fn delete_timeline_data<P, S>(
     }
     timeline_delete.deletion_registered = true;
 
-    let sync_status = delete_timeline_layers(storage, sync_queue, sync_id, new_delete_data).await;
+    let sync_status =
+        delete_timeline_layers(conf, storage, sync_queue, sync_id, new_delete_data).await;
     register_sync_status(sync_start, task_name, Some(sync_status));
 }
 
+/// Registers the deletion of an entire timeline: removes the timeline from the [`RemoteIndex`], and adds all
+/// the layers of the timeline to the files to delete. Its [`IndexPart`] is deleted along with the layers, last.
+async fn register_timeline_deletion(
+    index: &RemoteIndex,
+    sync_id: ZTenantTimelineId,
+    layers_to_delete: &mut HashSet<PathBuf>,
+) {
+    match index.write().await.remove_timeline_entry(&sync_id) {
+        Some(remote_timeline) => layers_to_delete.extend(remote_timeline.stored_files().clone()),
+        None => warn!("No remote index entry for timeline {sync_id}, deleting its index part only"),
+    }
+}
+
 async fn read_metadata_file(metadata_path: &Path) -> anyhow::Result<TimelineMetadata> {
     TimelineMetadata::from_bytes(
         &fs::read(metadata_path)
@@ -1284,6 +1609,33 @@ async fn read_metadata_file(metadata_path: &Path) -> anyhow::Result<TimelineMeta
     .context("Failed to parse metadata bytes")
 }
 
+/// Reads the local timeline config overrides, to store them in the [`IndexPart`].
+async fn read_timeline_config(
+    conf: &'static PageServerConf,
+    sync_id: ZTenantTimelineId,
+) -> anyhow::Result<TimelineConfOpt> {
+    let config_path = TimelineConfOpt::path(conf, sync_id.timeline_id, sync_id.tenant_id);
+    if !config_path.exists() {
+        return Ok(TimelineConfOpt::default());
+    }
+    let config = fs::read_to_string(&config_path)
+        .await
+        .context("Failed to read local timeline config from fs")?;
+    toml_edit::easy::from_str(&config).context("Failed to parse timeline config")
+}
+
+/// Reads the local restore points, to store them in the [`IndexPart`].
+async fn read_restore_points(
+    conf: &'static PageServerConf,
+    sync_id: ZTenantTimelineId,
+) -> anyhow::Result<Vec<RestorePoint>> {
+    tokio::task::spawn_blocking(move || {
+        restore_points::load_restore_points(conf, sync_id.timeline_id, sync_id.tenant_id)
+    })
+    .await
+    .context("failed to join load_restore_points task")?
+}
+
 async fn upload_timeline_data<P, S>(
     conf: &'static PageServerConf,
     (storage, index, sync_queue): (&S, &RemoteIndex, &SyncQueue),
@@ -1355,6 +1707,8 @@ where
     P: Debug + Send + Sync + 'static,
     S: RemoteStorage<RemoteObjectId = P> + Send + Sync + 'static,
 {
+    let timeline_config = read_timeline_config(conf, sync_id).await?;
+    let restore_points = read_restore_points(conf, sync_id).await?;
     let updated_remote_timeline = {
         let mut index_accessor = index.write().await;
 
@@ -1385,6 +1739,8 @@ where
                         existing_entry.remove_layers(layers_to_remove)
                     }
                 }
+                existing_entry.timeline_config = timeline_config;
+                existing_entry.restore_points = restore_points;
                 existing_entry.clone()
             }
             None => match update {
@@ -1394,9 +1750,15 @@ where
                 } => {
                     let new_metadata = match uploaded_data.metadata.as_ref() {
                         Some(new_metadata) => new_metadata,
+                        None if uploaded_data.layers_to_upload.is_empty() => {
+                            debug!("No remote index entry for timeline {sync_id} to update, skipping the index upload");
+                            return Ok(());
+                        }
                         None => bail!("For timeline {sync_id} upload, there's no upload metadata and no remote index entry, cannot create a new one"),
                     };
                     let mut new_remote_timeline = RemoteTimeline::new(new_metadata.clone());
+                    new_remote_timeline.timeline_config = timeline_config;
+                    new_remote_timeline.restore_points = restore_points;
                     if upload_failed {
                         new_remote_timeline
                             .add_upload_failures(uploaded_data.layers_to_upload.iter().cloned());
@@ -1481,6 +1843,7 @@ fn schedule_first_sync_tasks(
     index: &mut RemoteTimelineIndex,
     sync_queue: &SyncQueue,
     local_timeline_files: HashMap<ZTenantTimelineId, (TimelineMetadata, HashSet<PathBuf>)>,
+    on_demand_download: bool,
 ) -> LocalTimelineInitStatuses {
     let mut local_timeline_init_statuses = LocalTimelineInitStatuses::new();
 
@@ -1496,6 +1859,7 @@ fn schedule_first_sync_tasks(
                     local_metadata,
                     local_files,
                     remote_timeline,
+                    on_demand_download,
                 );
                 let was_there = local_timeline_init_statuses
                     .entry(sync_id.tenant_id)
@@ -1545,6 +1909,7 @@ fn compare_local_and_remote_timeline(
     local_metadata: TimelineMetadata,
     local_files: HashSet<PathBuf>,
     remote_entry: &RemoteTimeline,
+    on_demand_download: bool,
 ) -> (LocalTimelineInitStatus, bool) {
     let remote_files = remote_entry.stored_files();
 
@@ -1557,19 +1922,34 @@ fn compare_local_and_remote_timeline(
     //   If one of the tasks fails they will be reordered in the queue which can lead
     //   to timeline being stuck in evicted state
     let number_of_layers_to_download = remote_files.difference(&local_files).count();
-    let (initial_timeline_status, awaits_download) = if number_of_layers_to_download > 0 {
-        new_sync_tasks.push_back((
-            sync_id,
-            SyncTask::download(LayersDownload {
-                layers_to_skip: local_files.clone(),
-            }),
-        ));
-        (LocalTimelineInitStatus::NeedsSync, true)
-        // we do not need to manipulate with remote consistent lsn here
-        // because it will be updated when sync will be completed
-    } else {
-        (LocalTimelineInitStatus::LocallyComplete, false)
-    };
+    let (initial_timeline_status, awaits_download) =
+        if number_of_layers_to_download > 0 && on_demand_download {
+            // The missing layers are downloaded when they're first accessed,
+            // only the metadata needs to be updated if the remote one is newer.
+            if remote_entry.metadata.disk_consistent_lsn() > local_metadata.disk_consistent_lsn() {
+                new_sync_tasks.push_back((
+                    sync_id,
+                    SyncTask::download(LayersDownload {
+                        layers_to_skip: remote_files.union(&local_files).cloned().collect(),
+                    }),
+                ));
+                (LocalTimelineInitStatus::NeedsSync, true)
+            } else {
+                (LocalTimelineInitStatus::LocallyComplete, false)
+            }
+        } else if number_of_layers_to_download > 0 {
+            new_sync_tasks.push_back((
+                sync_id,
+                SyncTask::download(LayersDownload {
+                    layers_to_skip: local_files.clone(),
+                }),
+            ));
+            (LocalTimelineInitStatus::NeedsSync, true)
+            // we do not need to manipulate with remote consistent lsn here
+            // because it will be updated when sync will be completed
+        } else {
+            (LocalTimelineInitStatus::LocallyComplete, false)
+        };
 
     let layers_to_upload = local_files
         .difference(remote_files)
@@ -1690,6 +2070,7 @@ mod tests {
             layers_to_delete: HashSet::from([PathBuf::from("de")]),
             deleted_layers: HashSet::from([PathBuf::from("del")]),
             deletion_registered: false,
+            delete_timeline: false,
         });
 
         sync_queue.push(TEST_SYNC_ID, download_task.clone());
@@ -1739,6 +2120,7 @@ mod tests {
             layers_to_delete: HashSet::from([PathBuf::from("de")]),
             deleted_layers: HashSet::from([PathBuf::from("del")]),
             deletion_registered: false,
+            delete_timeline: false,
         };
 
         sync_queue.push(TEST_SYNC_ID, SyncTask::download(download.clone()));
@@ -1777,6 +2159,55 @@ mod tests {
         assert_eq!(sync_queue.len(), 0);
     }
 
+    #[tokio::test]
+    async fn timeline_delete_batch() {
+        let sync_queue = SyncQueue::new(NonZeroUsize::new(100).unwrap());
+
+        sync_queue.push(
+            TEST_SYNC_ID,
+            SyncTask::upload(LayersUpload {
+                layers_to_upload: HashSet::from([PathBuf::from("up")]),
+                uploaded_layers: HashSet::new(),
+                metadata: Some(dummy_metadata(Lsn(2))),
+            }),
+        );
+        sync_queue.push(
+            TEST_SYNC_ID,
+            SyncTask::delete(LayersDeletion {
+                layers_to_delete: HashSet::new(),
+                deleted_layers: HashSet::new(),
+                deletion_registered: false,
+                delete_timeline: true,
+            }),
+        );
+        sync_queue.push(
+            TEST_SYNC_ID,
+            SyncTask::download(LayersDownload {
+                layers_to_skip: HashSet::new(),
+            }),
+        );
+
+        let (mut batch, _) = sync_queue.next_task_batch();
+        assert_eq!(
+            Some(SyncTaskBatch {
+                upload: None,
+                download: None,
+                delete: Some(SyncData {
+                    retries: 0,
+                    data: LayersDeletion {
+                        layers_to_delete: HashSet::new(),
+                        deleted_layers: HashSet::new(),
+                        deletion_registered: false,
+                        delete_timeline: true,
+                    }
+                }),
+            }),
+            batch.remove(&TEST_SYNC_ID),
+            "Should drop the uploads and downloads of a timeline that is deleted"
+        );
+        assert_eq!(sync_queue.len(), 0);
+    }
+
     #[tokio::test]
     async fn same_task_id_same_tasks_batch() {
         let sync_queue = SyncQueue::new(NonZeroUsize::new(1).unwrap());
@@ -4,15 +4,55 @@ use anyhow::Context;
 use futures::stream::{FuturesUnordered, StreamExt};
 use tracing::{debug, error, info};
 
-use crate::storage_sync::{SyncQueue, SyncTask};
+use crate::config::PageServerConf;
+use crate::layered_repository::metadata::metadata_path;
+use crate::storage_sync::{index::IndexPart, SyncQueue, SyncTask};
 use remote_storage::RemoteStorage;
 use utils::zid::ZTenantTimelineId;
 
 use super::{LayersDeletion, SyncData};
 
+/// Removes the [`IndexPart`] of a timeline from the remote storage, which makes the pageserver
+/// forget about the timeline's remote layers.
+pub(super) async fn delete_index_part<P, S>(
+    conf: &'static PageServerConf,
+    storage: &S,
+    sync_id: ZTenantTimelineId,
+) -> anyhow::Result<()>
+where
+    P: std::fmt::Debug + Send + Sync + 'static,
+    S: RemoteStorage<RemoteObjectId = P> + Send + Sync + 'static,
+{
+    let index_part_path = metadata_path(conf, sync_id.timeline_id, sync_id.tenant_id)
+        .with_file_name(IndexPart::FILE_NAME)
+        .with_extension(IndexPart::FILE_EXTENSION);
+    let index_part_storage_path =
+        storage
+            .remote_object_id(&index_part_path)
+            .with_context(|| {
+                format!(
+                    "Failed to get the index part storage path for local path '{}'",
+                    index_part_path.display()
+                )
+            })?;
+
+    info!("Deleting remote index of the timeline");
+    storage
+        .delete(&index_part_storage_path)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to delete index part from the storage path '{index_part_storage_path:?}'"
+            )
+        })
+}
+
 /// Attempts to remove the timleline layers from the remote storage.
 /// If the task had not adjusted the metadata before, the deletion will fail.
+/// When the whole timeline is deleted, its [`IndexPart`] is removed after all the layers are,
+/// so that the layers left behind by an interrupted deletion are still listed in it.
 pub(super) async fn delete_timeline_layers<'a, P, S>(
+    conf: &'static PageServerConf,
     storage: &'a S,
     sync_queue: &SyncQueue,
     sync_id: ZTenantTimelineId,
@@ -29,7 +69,7 @@ where
         return false;
     }
 
-    if delete_data.data.layers_to_delete.is_empty() {
+    if delete_data.data.layers_to_delete.is_empty() && !delete_data.data.delete_timeline {
         info!("No layers to delete, skipping");
         return true;
     }
@@ -91,6 +131,13 @@ where
         }
     }
 
+    if !errored && delete_data.data.delete_timeline {
+        if let Err(e) = delete_index_part(conf, storage, sync_id).await {
+            errored = true;
+            error!("Failed to delete the index part of timeline {sync_id}: {e:?}");
+        }
+    }
+
     if errored {
         debug!("Reenqueuing failed delete task for timeline {sync_id}");
         delete_data.retries += 1;
@@ -127,6 +174,7 @@ mod tests {
         )?;
 
         let deleted = delete_timeline_layers(
+            harness.conf,
             &storage,
             &sync_queue,
             sync_id,
@@ -136,6 +184,7 @@ mod tests {
                     deleted_layers: HashSet::new(),
                     layers_to_delete: HashSet::new(),
                     deletion_registered: false,
+                    delete_timeline: false,
                 },
             },
         )
@@ -191,6 +240,7 @@ mod tests {
         );
 
         let deleted = delete_timeline_layers(
+            harness.conf,
             &storage,
             &sync_queue,
             sync_id,
@@ -204,6 +254,7 @@ mod tests {
                         local_timeline_path.join("something_different"),
                     ]),
                     deletion_registered: true,
+                    delete_timeline: false,
                 },
             },
         )
@@ -14,7 +14,7 @@ use tracing::{debug, error, info, warn};
 use crate::{
     config::PageServerConf, layered_repository::metadata::metadata_path, storage_sync::SyncTask,
 };
-use utils::zid::ZTenantTimelineId;
+use utils::zid::{ZTenantId, ZTenantTimelineId, ZTimelineId};
 
 use super::{
     index::{IndexPart, RemoteTimeline},
@@ -64,6 +64,58 @@ where
     Ok(index_part)
 }
 
+/// Lists the timelines of a tenant that have an index part in the remote storage.
+pub async fn list_remote_timelines<P, S>(
+    conf: &'static PageServerConf,
+    storage: &S,
+    tenant_id: ZTenantId,
+) -> anyhow::Result<HashSet<ZTimelineId>>
+where
+    P: Debug + Send + Sync + 'static,
+    S: RemoteStorage<RemoteObjectId = P> + Send + Sync + 'static,
+{
+    let timelines_path = conf.timelines_path(&tenant_id);
+    let index_part_file_name =
+        Path::new(IndexPart::FILE_NAME).with_extension(IndexPart::FILE_EXTENSION);
+
+    let mut timeline_ids = HashSet::new();
+    for remote_object_id in storage
+        .list()
+        .await
+        .context("Failed to list the remote storage files")?
+    {
+        let local_path = match storage.local_path(&remote_object_id) {
+            Ok(local_path) => local_path,
+            Err(e) => {
+                warn!("Skipping remote storage file {remote_object_id:?}: {e:#}");
+                continue;
+            }
+        };
+        if local_path.file_name() != Some(index_part_file_name.as_os_str()) {
+            continue;
+        }
+        let timeline_dir = match local_path.parent() {
+            Some(timeline_dir) if timeline_dir.parent() == Some(timelines_path.as_path()) => {
+                timeline_dir
+            }
+            _ => continue,
+        };
+        let timeline_id = timeline_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.parse::<ZTimelineId>().ok())
+            .with_context(|| {
+                format!(
+                    "Failed to parse the timeline id of the remote index part '{}'",
+                    local_path.display()
+                )
+            })?;
+        timeline_ids.insert(timeline_id);
+    }
+
+    Ok(timeline_ids)
+}
+
 /// Timeline download result, with extra data, needed for downloading.
 #[derive(Debug)]
 pub(super) enum DownloadedTimeline {
@@ -133,81 +185,7 @@ where
                     layer_desination_path.display()
                 );
             } else {
-                let layer_storage_path = storage
-                    .remote_object_id(&layer_desination_path)
-                    .with_context(|| {
-                        format!(
-                            "Failed to get the layer storage path for local path '{}'",
-                            layer_desination_path.display()
-                        )
-                    })?;
-
-                // Perform a rename inspired by durable_rename from file_utils.c.
-                // The sequence:
-                //     write(tmp)
-                //     fsync(tmp)
-                //     rename(tmp, new)
-                //     fsync(new)
-                //     fsync(parent)
-                // For more context about durable_rename check this email from postgres mailing list:
-                // https://www.postgresql.org/message-id/56583BDD.9060302@2ndquadrant.com
-                // If pageserver crashes the temp file will be deleted on startup and re-downloaded.
-                let temp_file_path =
-                    path_with_suffix_extension(&layer_desination_path, TEMP_DOWNLOAD_EXTENSION);
-
-                let mut destination_file =
-                    fs::File::create(&temp_file_path).await.with_context(|| {
-                        format!(
-                            "Failed to create a destination file for layer '{}'",
-                            temp_file_path.display()
-                        )
-                    })?;
-
-                storage
-                    .download(&layer_storage_path, &mut destination_file)
-                    .await
-                    .with_context(|| {
-                        format!(
-                            "Failed to download a layer from storage path '{layer_storage_path:?}'"
-                        )
-                    })?;
-
-                // Tokio doc here: https://docs.rs/tokio/1.17.0/tokio/fs/struct.File.html states that:
-                // A file will not be closed immediately when it goes out of scope if there are any IO operations
-                // that have not yet completed. To ensure that a file is closed immediately when it is dropped,
-                // you should call flush before dropping it.
-                //
-                // From the tokio code I see that it waits for pending operations to complete. There shouldt be any because
-                // we assume that `destination_file` file is fully written. I e there is no pending .write(...).await operations.
-                // But for additional safety lets check/wait for any pending operations.
-                destination_file.flush().await.with_context(|| {
-                    format!(
-                        "failed to flush source file at {}",
-                        temp_file_path.display()
-                    )
-                })?;
-
-                // not using sync_data because it can lose file size update
-                destination_file.sync_all().await.with_context(|| {
-                    format!(
-                        "failed to fsync source file at {}",
-                        temp_file_path.display()
-                    )
-                })?;
-                drop(destination_file);
-
-                fail::fail_point!("remote-storage-download-pre-rename", |_| {
-                    anyhow::bail!("remote-storage-download-pre-rename failpoint triggered")
-                });
-
-                fs::rename(&temp_file_path, &layer_desination_path).await?;
-
-                fsync_path(&layer_desination_path).await.with_context(|| {
-                    format!(
-                        "Cannot fsync layer destination path {}",
-                        layer_desination_path.display(),
-                    )
-                })?;
+                download_layer_file(storage, &layer_desination_path).await?;
             }
             Ok::<_, anyhow::Error>(layer_desination_path)
         })
@@ -258,6 +236,86 @@ where
     }
 }
 
+/// Downloads a single layer file from the remote storage into the given local path.
+/// The file is written into a temporary file first, and renamed into place once it's complete.
+pub(super) async fn download_layer_file<P, S>(storage: &S, layer_path: &Path) -> anyhow::Result<()>
+where
+    P: Debug + Send + Sync + 'static,
+    S: RemoteStorage<RemoteObjectId = P> + Send + Sync + 'static,
+{
+    let layer_storage_path = storage.remote_object_id(layer_path).with_context(|| {
+        format!(
+            "Failed to get the layer storage path for local path '{}'",
+            layer_path.display()
+        )
+    })?;
+
+    // Perform a rename inspired by durable_rename from file_utils.c.
+    // The sequence:
+    //     write(tmp)
+    //     fsync(tmp)
+    //     rename(tmp, new)
+    //     fsync(new)
+    //     fsync(parent)
+    // For more context about durable_rename check this email from postgres mailing list:
+    // https://www.postgresql.org/message-id/56583BDD.9060302@2ndquadrant.com
+    // If pageserver crashes the temp file will be deleted on startup and re-downloaded.
+    let temp_file_path = path_with_suffix_extension(layer_path, TEMP_DOWNLOAD_EXTENSION);
+
+    let mut destination_file = fs::File::create(&temp_file_path).await.with_context(|| {
+        format!(
+            "Failed to create a destination file for layer '{}'",
+            temp_file_path.display()
+        )
+    })?;
+
+    storage
+        .download(&layer_storage_path, &mut destination_file)
+        .await
+        .with_context(|| {
+            format!("Failed to download a layer from storage path '{layer_storage_path:?}'")
+        })?;
+
+    // Tokio doc here: https://docs.rs/tokio/1.17.0/tokio/fs/struct.File.html states that:
+    // A file will not be closed immediately when it goes out of scope if there are any IO operations
+    // that have not yet completed. To ensure that a file is closed immediately when it is dropped,
+    // you should call flush before dropping it.
+    //
+    // From the tokio code I see that it waits for pending operations to complete. There shouldt be any because
+    // we assume that `destination_file` file is fully written. I e there is no pending .write(...).await operations.
+    // But for additional safety lets check/wait for any pending operations.
+    destination_file.flush().await.with_context(|| {
+        format!(
+            "failed to flush source file at {}",
+            temp_file_path.display()
+        )
+    })?;
+
+    // not using sync_data because it can lose file size update
+    destination_file.sync_all().await.with_context(|| {
+        format!(
+            "failed to fsync source file at {}",
+            temp_file_path.display()
+        )
+    })?;
+    drop(destination_file);
+
+    fail::fail_point!("remote-storage-download-pre-rename", |_| {
+        anyhow::bail!("remote-storage-download-pre-rename failpoint triggered")
+    });
+
+    fs::rename(&temp_file_path, layer_path).await?;
+
+    fsync_path(layer_path).await.with_context(|| {
+        format!(
+            "Cannot fsync layer destination path {}",
+            layer_path.display(),
+        )
+    })?;
+
+    Ok(())
+}
+
 async fn fsync_path(path: impl AsRef<Path>) -> Result<(), io::Error> {
     fs::File::open(path).await?.sync_all().await
 }
@@ -478,6 +536,44 @@ mod tests {
             "Downloaded index part should be the same as the one in storage"
         );
 
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_list_remote_timelines() -> anyhow::Result<()> {
+        let harness = RepoHarness::create("test_list_remote_timelines")?;
+        let storage = LocalFs::new(
+            tempdir()?.path().to_path_buf(),
+            harness.conf.workdir.clone(),
+        )?;
+        let other_timeline_id = ZTimelineId::generate();
+        let other_tenant_id = ZTenantId::generate();
+
+        for (tenant_id, timeline_id) in [
+            (harness.tenant_id, TIMELINE_ID),
+            (harness.tenant_id, other_timeline_id),
+            (other_tenant_id, TIMELINE_ID),
+        ] {
+            let local_index_part_path = metadata_path(harness.conf, timeline_id, tenant_id)
+                .with_file_name(IndexPart::FILE_NAME)
+                .with_extension(IndexPart::FILE_EXTENSION);
+            let storage_path = storage.remote_object_id(&local_index_part_path)?;
+            fs::create_dir_all(storage_path.parent().unwrap()).await?;
+            fs::write(&storage_path, b"index part").await?;
+        }
+        // Layer files are not listed as timelines
+        let layer_path =
+            storage.remote_object_id(&harness.timeline_path(&TIMELINE_ID).join("a"))?;
+        fs::write(&layer_path, b"layer").await?;
+
+        assert_eq!(
+            list_remote_timelines(harness.conf, &storage, harness.tenant_id).await?,
+            HashSet::from([TIMELINE_ID, other_timeline_id]),
+        );
+        assert_eq!(
+            list_remote_timelines(harness.conf, &storage, ZTenantId::generate()).await?,
+            HashSet::new(),
+        );
+
         Ok(())
     }
 }
@@ -13,7 +13,11 @@ use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 use tokio::sync::RwLock;
 
-use crate::{config::PageServerConf, layered_repository::metadata::TimelineMetadata};
+use crate::{
+    config::PageServerConf,
+    layered_repository::{metadata::TimelineMetadata, restore_points::RestorePoint},
+    tenant_config::TimelineConfOpt,
+};
 use utils::{lsn::Lsn, zid::ZTenantTimelineId};
 
 /// A part of the filesystem path, that needs a root to become a path again.
@@ -82,6 +86,18 @@ impl RemoteIndex {
     pub async fn write(&self) -> tokio::sync::RwLockWriteGuard<'_, RemoteTimelineIndex> {
         self.0.write().await
     }
+
+    /// Like [`Self::read`], for the code that runs outside of the async runtimes.
+    /// Panics if called from an async context.
+    pub fn blocking_read(&self) -> tokio::sync::RwLockReadGuard<'_, RemoteTimelineIndex> {
+        self.0.blocking_read()
+    }
+
+    /// Like [`Self::write`], for the code that runs outside of the async runtimes.
+    /// Panics if called from an async context.
+    pub fn blocking_write(&self) -> tokio::sync::RwLockWriteGuard<'_, RemoteTimelineIndex> {
+        self.0.blocking_write()
+    }
 }
 
 impl Clone for RemoteIndex {
@@ -103,6 +119,10 @@ impl RemoteTimelineIndex {
         self.timeline_entries.insert(id, entry);
     }
 
+    pub fn remove_timeline_entry(&mut self, id: &ZTenantTimelineId) -> Option<RemoteTimeline> {
+        self.timeline_entries.remove(id)
+    }
+
     pub fn all_sync_ids(&self) -> impl Iterator<Item = ZTenantTimelineId> + '_ {
         self.timeline_entries.keys().copied()
     }
@@ -126,6 +146,8 @@ pub struct RemoteTimeline {
     missing_layers: HashSet<PathBuf>,
 
     pub metadata: TimelineMetadata,
+    pub timeline_config: TimelineConfOpt,
+    pub restore_points: Vec<RestorePoint>,
     pub awaits_download: bool,
 }
 
@@ -135,6 +157,8 @@ impl RemoteTimeline {
             timeline_layers: HashSet::new(),
             missing_layers: HashSet::new(),
             metadata,
+            timeline_config: TimelineConfOpt::default(),
+            restore_points: Vec::new(),
             awaits_download: false,
         }
     }
@@ -165,6 +189,8 @@ impl RemoteTimeline {
             timeline_layers: to_local_paths(timeline_path, index_part.timeline_layers),
             missing_layers: to_local_paths(timeline_path, index_part.missing_layers),
             metadata,
+            timeline_config: index_part.timeline_config,
+            restore_points: index_part.restore_points,
             awaits_download: false,
         })
     }
@@ -185,6 +211,12 @@ pub struct IndexPart {
     #[serde_as(as = "DisplayFromStr")]
     disk_consistent_lsn: Lsn,
     metadata_bytes: Vec<u8>,
+    /// The timeline's overrides of the tenant config and its restore points, stored
+    /// here so that they are restored when the timeline is attached to another pageserver.
+    #[serde(default)]
+    timeline_config: TimelineConfOpt,
+    #[serde(default)]
+    restore_points: Vec<RestorePoint>,
 }
 
 impl IndexPart {
@@ -203,6 +235,8 @@ impl IndexPart {
             missing_layers,
             disk_consistent_lsn,
             metadata_bytes,
+            timeline_config: TimelineConfOpt::default(),
+            restore_points: Vec::new(),
         }
     }
 
@@ -222,6 +256,8 @@ impl IndexPart {
                 .context("Failed to convert missing layers' paths to relative ones")?,
             disk_consistent_lsn: remote_timeline.metadata.disk_consistent_lsn(),
             metadata_bytes,
+            timeline_config: remote_timeline.timeline_config,
+            restore_points: remote_timeline.restore_points,
         })
     }
 }
@@ -269,6 +305,15 @@ mod tests {
                 timeline_path.join("missing_2"),
             ]),
             metadata: metadata.clone(),
+            timeline_config: TimelineConfOpt {
+                gc_horizon: Some(0x10),
+                pitr_interval: None,
+            },
+            restore_points: vec![RestorePoint {
+                name: "nightly".to_string(),
+                lsn: Lsn(0x20),
+                created_at: std::time::SystemTime::UNIX_EPOCH,
+            }],
             awaits_download: false,
         };
 
@@ -342,6 +387,15 @@ mod tests {
             "remote timeline -> index part -> remote timeline conversion should not alter metadata"
         );
 
+        assert_eq!(
+            remote_timeline.timeline_config, restored_timeline.timeline_config,
+            "remote timeline -> index part -> remote timeline conversion should not alter timeline config"
+        );
+        assert_eq!(
+            remote_timeline.restore_points, restored_timeline.restore_points,
+            "remote timeline -> index part -> remote timeline conversion should not loose restore points"
+        );
+
         assert_eq!(
             remote_timeline.awaits_download, restored_timeline.awaits_download,
             "remote timeline -> index part -> remote timeline conversion should not loose download flag"
@@ -390,6 +444,8 @@ mod tests {
                     timeline_path.join("missing_2"),
                 ]),
                 metadata: metadata.clone(),
+                timeline_config: TimelineConfOpt::default(),
+                restore_points: Vec::new(),
                 awaits_download: false,
             },
         );
@@ -407,6 +463,8 @@ mod tests {
                     timeline_path.join("missing_2"),
                 ]),
                 metadata,
+                timeline_config: TimelineConfOpt::default(),
+                restore_points: Vec::new(),
                 awaits_download: false,
             },
         );
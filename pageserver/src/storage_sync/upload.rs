@@ -1,6 +1,9 @@
 //! Timeline synchronization logic to compress and upload to the remote storage all new timeline files from the checkpoints.
 
-use std::{fmt::Debug, path::PathBuf};
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+};
 
 use anyhow::Context;
 use futures::stream::{FuturesUnordered, StreamExt};
@@ -60,6 +63,47 @@ where
         })
 }
 
+/// Uploads a single layer file to the remote storage, overwriting it if it's there already.
+pub(super) async fn upload_layer_file<P, S>(storage: &S, layer_path: &Path) -> anyhow::Result<()>
+where
+    P: Debug + Send + Sync + 'static,
+    S: RemoteStorage<RemoteObjectId = P> + Send + Sync + 'static,
+{
+    let layer_storage_path = storage.remote_object_id(layer_path).with_context(|| {
+        format!(
+            "Failed to get the layer storage path for local path '{}'",
+            layer_path.display()
+        )
+    })?;
+
+    let source_file = fs::File::open(layer_path).await.with_context(|| {
+        format!(
+            "Failed to open a source file for layer '{}'",
+            layer_path.display()
+        )
+    })?;
+    let source_size = source_file
+        .metadata()
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to get the source file metadata for layer '{}'",
+                layer_path.display()
+            )
+        })?
+        .len() as usize;
+
+    storage
+        .upload(source_file, source_size, &layer_storage_path, None)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to upload a layer from local path '{}'",
+                layer_path.display()
+            )
+        })
+}
+
 /// Timeline upload result, with extra data, needed for uploading.
 #[derive(Debug)]
 pub(super) enum UploadedTimeline {
@@ -8,13 +8,22 @@
 //! We cannot use global or default config instead, because wrong settings
 //! may lead to a data loss.
 //!
+//! A few of the GC-related settings can also be overridden for a single
+//! timeline, see [`TimelineConfOpt`]. The overrides are stored in the
+//! timeline's directory, next to its metadata file, and in the timeline's
+//! remote index part, so that they survive an attach to another pageserver.
+//!
 use crate::config::PageServerConf;
+use crate::layered_repository::blob_io::CompressionAlgorithm;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::Duration;
-use utils::zid::ZTenantId;
+use utils::zid::{ZTenantId, ZTimelineId};
 
 pub const TENANT_CONFIG_NAME: &str = "config";
+pub const TIMELINE_CONFIG_NAME: &str = "timeline_config";
 
 pub mod defaults {
     // FIXME: This current value is very low. I would imagine something like 1 GB or 10 GB
@@ -29,11 +38,53 @@ pub mod defaults {
 
     pub const DEFAULT_COMPACTION_PERIOD: &str = "1 s";
     pub const DEFAULT_COMPACTION_THRESHOLD: usize = 10;
+    pub const DEFAULT_COMPACTION_STRATEGY: &str = "level0";
+    pub const DEFAULT_COMPACTION_TIER_RATIO: usize = 4;
 
     pub const DEFAULT_GC_HORIZON: u64 = 64 * 1024 * 1024;
     pub const DEFAULT_GC_PERIOD: &str = "100 s";
     pub const DEFAULT_IMAGE_CREATION_THRESHOLD: usize = 3;
     pub const DEFAULT_PITR_INTERVAL: &str = "30 days";
+    // GetPage@LSN rate limiting is disabled by default.
+    pub const DEFAULT_GETPAGE_RATE_LIMIT: u64 = 0;
+    pub const DEFAULT_GETPAGE_BURST: u64 = 1000;
+    // Layer files are not compressed by default.
+    pub const DEFAULT_COMPRESSION: &str = "none";
+    pub const DEFAULT_SCRUB_PERIOD: &str = "1 day";
+    pub const DEFAULT_SCRUB_RATE_LIMIT: u64 = 10 * 1024 * 1024;
+}
+
+/// How the delta layers of a timeline are compacted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompactionStrategy {
+    /// Only reshuffle L0 delta layers into L1 delta layers. The L1 layers
+    /// pile up until image layers are created over them.
+    Level0,
+    /// In addition, merge adjacent runs of L1 delta layers of similar size
+    /// into larger runs, so that a lookup has fewer delta layers to visit.
+    Tiered,
+}
+
+impl FromStr for CompactionStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "level0" => Ok(CompactionStrategy::Level0),
+            "tiered" => Ok(CompactionStrategy::Tiered),
+            _ => anyhow::bail!("unknown compaction strategy '{}'", s),
+        }
+    }
+}
+
+impl fmt::Display for CompactionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CompactionStrategy::Level0 => "level0",
+            CompactionStrategy::Tiered => "tiered",
+        })
+    }
 }
 
 /// Per-tenant configuration options
@@ -52,6 +103,11 @@ pub struct TenantConf {
     pub compaction_period: Duration,
     // Level0 delta layer threshold for compaction.
     pub compaction_threshold: usize,
+    // How the L1 delta layers are compacted further, see CompactionStrategy.
+    pub compaction_strategy: CompactionStrategy,
+    // With the tiered strategy, this many adjacent runs of L1 delta layers
+    // of the same size tier are merged into one run of the next tier.
+    pub compaction_tier_ratio: usize,
     // Determines how much history is retained, to allow
     // branching and read replicas at an older point in time.
     // The unit is #of bytes of WAL.
@@ -68,6 +124,22 @@ pub struct TenantConf {
     // Page versions older than this are garbage collected away.
     #[serde(with = "humantime_serde")]
     pub pitr_interval: Duration,
+    // Maximum sustained rate of GetPage@LSN requests per second that the
+    // page service admits for this tenant. 0 means unlimited.
+    pub getpage_rate_limit: u64,
+    // Number of GetPage@LSN requests that can be admitted in a burst
+    // above the sustained rate.
+    pub getpage_burst: u64,
+    // Compression algorithm for the page images and WAL records in new
+    // layer files. Existing layer files are not affected.
+    pub compression: CompressionAlgorithm,
+    // Interval at which the scrubber verifies the checksums of all layer
+    // files. 0 disables scrubbing.
+    #[serde(with = "humantime_serde")]
+    pub scrub_period: Duration,
+    // Maximum number of bytes per second that the scrubber reads.
+    // 0 means unlimited.
+    pub scrub_rate_limit: u64,
 }
 
 /// Same as TenantConf, but this struct preserves the information about
@@ -79,12 +151,20 @@ pub struct TenantConfOpt {
     #[serde(with = "humantime_serde")]
     pub compaction_period: Option<Duration>,
     pub compaction_threshold: Option<usize>,
+    pub compaction_strategy: Option<CompactionStrategy>,
+    pub compaction_tier_ratio: Option<usize>,
     pub gc_horizon: Option<u64>,
     #[serde(with = "humantime_serde")]
     pub gc_period: Option<Duration>,
     pub image_creation_threshold: Option<usize>,
     #[serde(with = "humantime_serde")]
     pub pitr_interval: Option<Duration>,
+    pub getpage_rate_limit: Option<u64>,
+    pub getpage_burst: Option<u64>,
+    pub compression: Option<CompressionAlgorithm>,
+    #[serde(with = "humantime_serde")]
+    pub scrub_period: Option<Duration>,
+    pub scrub_rate_limit: Option<u64>,
 }
 
 impl TenantConfOpt {
@@ -102,12 +182,27 @@ impl TenantConfOpt {
             compaction_threshold: self
                 .compaction_threshold
                 .unwrap_or(global_conf.compaction_threshold),
+            compaction_strategy: self
+                .compaction_strategy
+                .unwrap_or(global_conf.compaction_strategy),
+            compaction_tier_ratio: self
+                .compaction_tier_ratio
+                .unwrap_or(global_conf.compaction_tier_ratio),
             gc_horizon: self.gc_horizon.unwrap_or(global_conf.gc_horizon),
             gc_period: self.gc_period.unwrap_or(global_conf.gc_period),
             image_creation_threshold: self
                 .image_creation_threshold
                 .unwrap_or(global_conf.image_creation_threshold),
             pitr_interval: self.pitr_interval.unwrap_or(global_conf.pitr_interval),
+            getpage_rate_limit: self
+                .getpage_rate_limit
+                .unwrap_or(global_conf.getpage_rate_limit),
+            getpage_burst: self.getpage_burst.unwrap_or(global_conf.getpage_burst),
+            compression: self.compression.unwrap_or(global_conf.compression),
+            scrub_period: self.scrub_period.unwrap_or(global_conf.scrub_period),
+            scrub_rate_limit: self
+                .scrub_rate_limit
+                .unwrap_or(global_conf.scrub_rate_limit),
         }
     }
 
@@ -124,6 +219,12 @@ impl TenantConfOpt {
         if let Some(compaction_threshold) = other.compaction_threshold {
             self.compaction_threshold = Some(compaction_threshold);
         }
+        if let Some(compaction_strategy) = other.compaction_strategy {
+            self.compaction_strategy = Some(compaction_strategy);
+        }
+        if let Some(compaction_tier_ratio) = other.compaction_tier_ratio {
+            self.compaction_tier_ratio = Some(compaction_tier_ratio);
+        }
         if let Some(gc_horizon) = other.gc_horizon {
             self.gc_horizon = Some(gc_horizon);
         }
@@ -136,6 +237,44 @@ impl TenantConfOpt {
         if let Some(pitr_interval) = other.pitr_interval {
             self.pitr_interval = Some(pitr_interval);
         }
+        if let Some(getpage_rate_limit) = other.getpage_rate_limit {
+            self.getpage_rate_limit = Some(getpage_rate_limit);
+        }
+        if let Some(getpage_burst) = other.getpage_burst {
+            self.getpage_burst = Some(getpage_burst);
+        }
+        if let Some(compression) = other.compression {
+            self.compression = Some(compression);
+        }
+        if let Some(scrub_period) = other.scrub_period {
+            self.scrub_period = Some(scrub_period);
+        }
+        if let Some(scrub_rate_limit) = other.scrub_rate_limit {
+            self.scrub_rate_limit = Some(scrub_rate_limit);
+        }
+    }
+}
+
+/// Per-timeline overrides of the tenant's GC settings. The settings that
+/// are not set fall back to the tenant's config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct TimelineConfOpt {
+    pub gc_horizon: Option<u64>,
+    #[serde(with = "humantime_serde")]
+    pub pitr_interval: Option<Duration>,
+}
+
+impl TimelineConfOpt {
+    /// Points to a place in pageserver's local directory,
+    /// where certain timeline's config file should be located.
+    pub fn path(
+        conf: &'static PageServerConf,
+        timelineid: ZTimelineId,
+        tenantid: ZTenantId,
+    ) -> PathBuf {
+        conf.timeline_path(&timelineid, &tenantid)
+            .join(TIMELINE_CONFIG_NAME)
     }
 }
 
@@ -149,12 +288,24 @@ impl TenantConf {
             compaction_period: humantime::parse_duration(DEFAULT_COMPACTION_PERIOD)
                 .expect("cannot parse default compaction period"),
             compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            compaction_strategy: DEFAULT_COMPACTION_STRATEGY
+                .parse()
+                .expect("cannot parse default compaction strategy"),
+            compaction_tier_ratio: DEFAULT_COMPACTION_TIER_RATIO,
             gc_horizon: DEFAULT_GC_HORIZON,
             gc_period: humantime::parse_duration(DEFAULT_GC_PERIOD)
                 .expect("cannot parse default gc period"),
             image_creation_threshold: DEFAULT_IMAGE_CREATION_THRESHOLD,
             pitr_interval: humantime::parse_duration(DEFAULT_PITR_INTERVAL)
                 .expect("cannot parse default PITR interval"),
+            getpage_rate_limit: DEFAULT_GETPAGE_RATE_LIMIT,
+            getpage_burst: DEFAULT_GETPAGE_BURST,
+            compression: DEFAULT_COMPRESSION
+                .parse()
+                .expect("cannot parse default compression"),
+            scrub_period: humantime::parse_duration(DEFAULT_SCRUB_PERIOD)
+                .expect("cannot parse default scrub period"),
+            scrub_rate_limit: DEFAULT_SCRUB_RATE_LIMIT,
         }
     }
 
@@ -171,10 +322,17 @@ impl TenantConf {
             compaction_target_size: 4 * 1024 * 1024,
             compaction_period: Duration::from_secs(10),
             compaction_threshold: defaults::DEFAULT_COMPACTION_THRESHOLD,
+            compaction_strategy: CompactionStrategy::Level0,
+            compaction_tier_ratio: defaults::DEFAULT_COMPACTION_TIER_RATIO,
             gc_horizon: defaults::DEFAULT_GC_HORIZON,
             gc_period: Duration::from_secs(10),
             image_creation_threshold: defaults::DEFAULT_IMAGE_CREATION_THRESHOLD,
             pitr_interval: Duration::from_secs(60 * 60),
+            getpage_rate_limit: defaults::DEFAULT_GETPAGE_RATE_LIMIT,
+            getpage_burst: defaults::DEFAULT_GETPAGE_BURST,
+            compression: CompressionAlgorithm::None,
+            scrub_period: Duration::ZERO,
+            scrub_rate_limit: defaults::DEFAULT_SCRUB_RATE_LIMIT,
         }
     }
 }
@@ -4,27 +4,32 @@
 use crate::config::PageServerConf;
 use crate::layered_repository::{load_metadata, LayeredRepository};
 use crate::pgdatadir_mapping::DatadirTimeline;
-use crate::repository::{Repository, TimelineSyncStatusUpdate};
+use crate::repository::{Repository, RepositoryTimeline, Timeline, TimelineSyncStatusUpdate};
 use crate::storage_sync::index::RemoteIndex;
 use crate::storage_sync::{self, LocalTimelineInitStatus, SyncStartupData};
 use crate::tenant_config::TenantConfOpt;
 use crate::thread_mgr;
 use crate::thread_mgr::ThreadKind;
+use crate::throttle::TokenBucket;
 use crate::timelines;
 use crate::timelines::CreateRepo;
 use crate::walredo::PostgresRedoManager;
-use crate::{DatadirTimelineImpl, RepositoryImpl};
-use anyhow::{bail, Context};
+use crate::{CheckpointConfig, DatadirTimelineImpl, RepositoryImpl};
+use anyhow::{bail, ensure, Context};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr};
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fmt;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::*;
 use utils::lsn::Lsn;
 
-use utils::zid::{ZTenantId, ZTimelineId};
+use utils::zid::{ZTenantId, ZTenantTimelineId, ZTimelineId};
+
+/// How long a tenant detach waits for the tenant's layers to be uploaded
+const DETACH_UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
 
 mod tenants_state {
     use std::{
@@ -63,6 +68,9 @@ struct Tenant {
     /// Local timelines have more metadata that's loaded into memory,
     /// that is located in the `repo.timelines` field, [`crate::layered_repository::LayeredTimelineEntry`].
     local_timelines: HashMap<ZTimelineId, Arc<DatadirTimelineImpl>>,
+    /// Admission control for GetPage@LSN requests, shared by all page service
+    /// connections of the tenant.
+    getpage_throttle: Arc<TokenBucket>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
@@ -75,6 +83,9 @@ pub enum TenantState {
     Active,
     // Tenant is active, but there is no walreceiver connection.
     Idle,
+    // The tenant is being attached, and its timelines are being downloaded from the
+    // remote storage. It becomes Idle when all of them have been downloaded.
+    Attaching,
     // This tenant exists on local disk, and the layer map has been loaded into memory.
     // The local disk might have some newer files that don't exist in cloud storage yet.
     // The tenant cannot be accessed anymore for any reason, but graceful shutdown.
@@ -89,6 +100,7 @@ impl fmt::Display for TenantState {
         match self {
             TenantState::Active => f.write_str("Active"),
             TenantState::Idle => f.write_str("Idle"),
+            TenantState::Attaching => f.write_str("Attaching"),
             TenantState::Stopping => f.write_str("Stopping"),
             TenantState::Broken => f.write_str("Broken"),
         }
@@ -153,6 +165,34 @@ pub fn apply_timeline_sync_status_updates(
                 "Failed to apply timeline sync timeline status updates for tenant {tenant_id}: {e:?}"
             ),
         }
+        if get_tenant_state(tenant_id) == Some(TenantState::Attaching) {
+            finish_tenant_attach(tenant_id, remote_index);
+        }
+    }
+}
+
+/// Moves an attached tenant from Attaching to Idle state, once none of its
+/// timelines are waiting for a download anymore.
+fn finish_tenant_attach(tenant_id: ZTenantId, remote_index: &RemoteIndex) {
+    let index_accessor = remote_index.blocking_read();
+    let downloads_pending = index_accessor
+        .all_sync_ids()
+        .filter(|sync_id| sync_id.tenant_id == tenant_id)
+        .any(|sync_id| {
+            index_accessor
+                .timeline_entry(&sync_id)
+                .map_or(false, |remote_timeline| remote_timeline.awaits_download)
+        });
+    if downloads_pending {
+        return;
+    }
+    drop(index_accessor);
+
+    if let Some(tenant) = tenants_state::write_tenants().get_mut(&tenant_id) {
+        if tenant.state == TenantState::Attaching {
+            info!("all timelines of tenant {tenant_id} are downloaded, attach is finished");
+            tenant.state = TenantState::Idle;
+        }
     }
 }
 
@@ -164,7 +204,10 @@ pub fn shutdown_all_tenants() {
     let mut tenantids = Vec::new();
     for (tenantid, tenant) in m.iter_mut() {
         match tenant.state {
-            TenantState::Active | TenantState::Idle | TenantState::Stopping => {
+            TenantState::Active
+            | TenantState::Idle
+            | TenantState::Attaching
+            | TenantState::Stopping => {
                 tenant.state = TenantState::Stopping;
                 tenantids.push(*tenantid)
             }
@@ -174,6 +217,7 @@ pub fn shutdown_all_tenants() {
     drop(m);
 
     thread_mgr::shutdown_threads(Some(ThreadKind::WalReceiver), None, None);
+    thread_mgr::shutdown_threads(Some(ThreadKind::Scrubber), None, None);
     thread_mgr::shutdown_threads(Some(ThreadKind::GarbageCollector), None, None);
     thread_mgr::shutdown_threads(Some(ThreadKind::Compactor), None, None);
 
@@ -226,6 +270,7 @@ pub fn create_tenant_repository(
                 state: TenantState::Idle,
                 repo,
                 local_timelines: HashMap::new(),
+                getpage_throttle: Arc::new(TokenBucket::new()),
             });
             Ok(Some(tenant_id))
         }
@@ -248,8 +293,9 @@ pub fn get_tenant_state(tenantid: ZTenantId) -> Option<TenantState> {
 }
 
 ///
-/// Change the state of a tenant to Active and launch its compactor and GC
-/// threads. If the tenant was already in Active state or Stopping, does nothing.
+/// Change the state of a tenant to Active and launch its compactor, GC and
+/// scrubber threads. If the tenant was already in Active state or Stopping,
+/// does nothing.
 ///
 pub fn activate_tenant(tenant_id: ZTenantId) -> anyhow::Result<()> {
     let mut m = tenants_state::write_tenants();
@@ -263,7 +309,7 @@ pub fn activate_tenant(tenant_id: ZTenantId) -> anyhow::Result<()> {
         // If the tenant is already active, nothing to do.
         TenantState::Active => {}
 
-        // If it's Idle, launch the compactor and GC threads
+        // If it's Idle, launch the compactor, GC and scrubber threads
         TenantState::Idle => {
             thread_mgr::spawn(
                 ThreadKind::Compactor,
@@ -290,9 +336,35 @@ pub fn activate_tenant(tenant_id: ZTenantId) -> anyhow::Result<()> {
                 thread_mgr::shutdown_threads(Some(ThreadKind::Compactor), Some(tenant_id), None);
                 return gc_spawn_result;
             }
+
+            let scrubber_spawn_result = thread_mgr::spawn(
+                ThreadKind::Scrubber,
+                Some(tenant_id),
+                None,
+                "Scrubber thread",
+                false,
+                move || crate::tenant_threads::scrub_loop(tenant_id),
+            )
+            .map(|_thread_id| ())
+            .with_context(|| format!("Failed to launch scrubber thread for tenant {tenant_id}"));
+
+            if let Err(e) = &scrubber_spawn_result {
+                error!("Failed to start scrubber thread for tenant {tenant_id}, stopping its compactor and GC threads: {e:?}");
+                thread_mgr::shutdown_threads(Some(ThreadKind::Compactor), Some(tenant_id), None);
+                thread_mgr::shutdown_threads(
+                    Some(ThreadKind::GarbageCollector),
+                    Some(tenant_id),
+                    None,
+                );
+                return scrubber_spawn_result;
+            }
             tenant.state = TenantState::Active;
         }
 
+        TenantState::Attaching => {
+            // the tenant becomes Idle when all of its timelines are downloaded
+        }
+
         TenantState::Stopping => {
             // don't re-activate it if it's being stopped
         }
@@ -313,6 +385,15 @@ pub fn get_repository_for_tenant(tenant_id: ZTenantId) -> anyhow::Result<Arc<Rep
     Ok(Arc::clone(&tenant.repo))
 }
 
+pub fn get_getpage_throttle(tenant_id: ZTenantId) -> anyhow::Result<Arc<TokenBucket>> {
+    let m = tenants_state::read_tenants();
+    let tenant = m
+        .get(&tenant_id)
+        .with_context(|| format!("Tenant {tenant_id} not found"))?;
+
+    Ok(Arc::clone(&tenant.getpage_throttle))
+}
+
 /// Retrieves local timeline for tenant.
 /// Loads it into memory if it is not already loaded.
 pub fn get_local_timeline_with_load(
@@ -341,7 +422,8 @@ pub fn detach_timeline(
     tenant_id: ZTenantId,
     timeline_id: ZTimelineId,
 ) -> anyhow::Result<()> {
-    // shutdown the timeline threads (this shuts down the walreceiver)
+    // shutdown the timeline threads (this shuts down the walreceiver) and
+    // close the pagestream connections
     thread_mgr::shutdown_threads(None, Some(tenant_id), Some(timeline_id));
 
     match tenants_state::write_tenants().get_mut(&tenant_id) {
@@ -366,6 +448,237 @@ pub fn detach_timeline(
     Ok(())
 }
 
+///
+/// Stop the WAL receiver and close the pagestream connections of a timeline,
+/// so that they don't go on using a timeline object that is being replaced.
+///
+fn stop_timeline_users(tenant_id: ZTenantId, timeline_id: ZTimelineId) {
+    thread_mgr::shutdown_threads(
+        Some(ThreadKind::WalReceiver),
+        Some(tenant_id),
+        Some(timeline_id),
+    );
+    thread_mgr::shutdown_threads(
+        Some(ThreadKind::PageRequestHandler),
+        Some(tenant_id),
+        Some(timeline_id),
+    );
+}
+
+///
+/// Reset a timeline to an earlier LSN, see [`LayeredRepository::reset_timeline`].
+/// The safekeepers must have been reset to the same LSN already: the WAL
+/// receiver is stopped for the duration of the reset, and starts streaming
+/// from the reset point on the next callmemaybe request. Open pagestream
+/// connections to the timeline are closed.
+///
+pub fn reset_timeline(
+    tenant_id: ZTenantId,
+    timeline_id: ZTimelineId,
+    lsn: Lsn,
+    prev_record_lsn: Option<Lsn>,
+) -> anyhow::Result<()> {
+    let repo = get_repository_for_tenant(tenant_id)?;
+
+    stop_timeline_users(tenant_id, timeline_id);
+    repo.reset_timeline(timeline_id, lsn, prev_record_lsn)?;
+
+    // The cached timeline still refers to the replaced timeline object
+    if let Some(tenant) = tenants_state::write_tenants().get_mut(&tenant_id) {
+        tenant.local_timelines.remove(&timeline_id);
+    }
+    // A WAL receiver launched or a connection opened during the reset uses
+    // the replaced timeline object, stop them too.
+    stop_timeline_users(tenant_id, timeline_id);
+    Ok(())
+}
+
+///
+/// Detach a timeline from its ancestor, see [`LayeredRepository::detach_ancestor`].
+/// The WAL receiver is stopped for the duration of the operation, and all the
+/// ingested WAL is flushed to disk first, so that it isn't lost with the replaced
+/// timeline object. Open pagestream connections to the timeline are closed.
+///
+pub fn detach_timeline_ancestor(
+    tenant_id: ZTenantId,
+    timeline_id: ZTimelineId,
+) -> anyhow::Result<()> {
+    let repo = get_repository_for_tenant(tenant_id)?;
+
+    stop_timeline_users(tenant_id, timeline_id);
+    let timeline = get_local_timeline_with_load(tenant_id, timeline_id)?;
+    if timeline.tline.get_ancestor_timeline_id().is_none() {
+        bail!("timeline {timeline_id} has no ancestor");
+    }
+    timeline.tline.checkpoint(CheckpointConfig::Forced)?;
+    let keyspace = timeline
+        .collect_keyspace(timeline.tline.get_ancestor_lsn())
+        .context("failed to collect the keyspace at the branch point")?;
+    drop(timeline);
+    repo.detach_ancestor(timeline_id, &keyspace)?;
+
+    // The cached timeline still refers to the replaced timeline object
+    if let Some(tenant) = tenants_state::write_tenants().get_mut(&tenant_id) {
+        tenant.local_timelines.remove(&timeline_id);
+    }
+    stop_timeline_users(tenant_id, timeline_id);
+    Ok(())
+}
+
+///
+/// Register a tenant whose timelines are about to be downloaded from the remote
+/// storage. The tenant stays in Attaching state until the downloads complete.
+///
+pub fn attach_tenant(
+    conf: &'static PageServerConf,
+    tenant_id: ZTenantId,
+    remote_index: &RemoteIndex,
+) -> anyhow::Result<()> {
+    if tenants_state::read_tenants().contains_key(&tenant_id) {
+        bail!("Tenant {tenant_id} already exists");
+    }
+
+    let timelines_path = conf.timelines_path(&tenant_id);
+    std::fs::create_dir_all(&timelines_path).with_context(|| {
+        format!(
+            "Failed to create timelines directory '{}'",
+            timelines_path.display()
+        )
+    })?;
+
+    info!("attaching tenant {tenant_id}");
+    load_local_repo(conf, tenant_id, remote_index)?;
+    if let Some(tenant) = tenants_state::write_tenants().get_mut(&tenant_id) {
+        tenant.state = TenantState::Attaching;
+    }
+    Ok(())
+}
+
+///
+/// Shut down the threads of a tenant, upload all its data to the remote storage,
+/// and remove it from this pageserver, so that the tenant can be attached again,
+/// here or on another pageserver. The WAL ingested so far is flushed to layer
+/// files first. If the uploads don't complete, the tenant is left in place.
+///
+pub fn detach_tenant(conf: &'static PageServerConf, tenant_id: ZTenantId) -> anyhow::Result<()> {
+    ensure!(
+        conf.remote_storage_config.is_some(),
+        "Cannot detach tenant {tenant_id} without remote storage, its data would be lost"
+    );
+    let repo = stop_tenant(tenant_id)?;
+
+    if let Err(e) = upload_tenant(&repo) {
+        // The threads are launched again when the tenant is activated
+        if let Some(tenant) = tenants_state::write_tenants().get_mut(&tenant_id) {
+            tenant.state = TenantState::Idle;
+        }
+        return Err(e.context(format!("Failed to upload tenant {tenant_id}")));
+    }
+
+    // Forget the remote timelines, so that an attach reads their index parts
+    // from the remote storage again
+    let remote_index = repo.get_remote_index();
+    let mut index_accessor = remote_index.blocking_write();
+    let sync_ids = index_accessor
+        .all_sync_ids()
+        .filter(|sync_id| sync_id.tenant_id == tenant_id)
+        .collect::<Vec<_>>();
+    for sync_id in sync_ids {
+        index_accessor.remove_timeline_entry(&sync_id);
+    }
+    drop(index_accessor);
+
+    remove_local_tenant(conf, tenant_id)
+}
+
+///
+/// Shut down the threads of a tenant, and remove all its local data. Unlike
+/// [`detach_tenant`], nothing is uploaded: this is the first step of deleting
+/// the tenant, its remote data is deleted separately.
+///
+pub fn delete_local_tenant(
+    conf: &'static PageServerConf,
+    tenant_id: ZTenantId,
+) -> anyhow::Result<()> {
+    stop_tenant(tenant_id)?;
+    remove_local_tenant(conf, tenant_id)
+}
+
+fn stop_tenant(tenant_id: ZTenantId) -> anyhow::Result<Arc<RepositoryImpl>> {
+    let repo = match tenants_state::write_tenants().get_mut(&tenant_id) {
+        Some(tenant) => match tenant.state {
+            TenantState::Attaching => {
+                bail!("Tenant {tenant_id} is being attached, its timelines are still downloading")
+            }
+            TenantState::Stopping => bail!("Tenant {tenant_id} is already being stopped"),
+            TenantState::Active | TenantState::Idle | TenantState::Broken => {
+                info!("stopping tenant {tenant_id} in state {}", tenant.state);
+                tenant.state = TenantState::Stopping;
+                Arc::clone(&tenant.repo)
+            }
+        },
+        None => bail!("Tenant {tenant_id} not found in local tenant state"),
+    };
+
+    // shutdown the tenant and timeline threads (this shuts down the walreceivers)
+    // and close the pagestream connections
+    thread_mgr::shutdown_threads(None, Some(tenant_id), None);
+    Ok(repo)
+}
+
+///
+/// Flush the WAL ingested by the timelines of a stopped tenant to layer files,
+/// and wait until all the layers are uploaded to the remote storage.
+///
+fn upload_tenant(repo: &RepositoryImpl) -> anyhow::Result<()> {
+    // This schedules the upload of the new layer files
+    repo.checkpoint()?;
+
+    let remote_index = repo.get_remote_index();
+    let deadline = Instant::now() + DETACH_UPLOAD_TIMEOUT;
+    for (timeline_id, timeline) in repo.list_timelines() {
+        let disk_consistent_lsn = match timeline {
+            RepositoryTimeline::Loaded(timeline) => timeline.get_disk_consistent_lsn(),
+            RepositoryTimeline::Unloaded { metadata } => metadata.disk_consistent_lsn(),
+        };
+        let sync_id = ZTenantTimelineId {
+            tenant_id: repo.tenant_id(),
+            timeline_id,
+        };
+        loop {
+            // The remote metadata is updated once all the layers up to its
+            // disk_consistent_lsn are uploaded
+            let remote_lsn = remote_index
+                .blocking_read()
+                .timeline_entry(&sync_id)
+                .map(|remote_timeline| remote_timeline.metadata.disk_consistent_lsn());
+            if matches!(remote_lsn, Some(remote_lsn) if remote_lsn >= disk_consistent_lsn) {
+                break;
+            }
+            ensure!(
+                Instant::now() < deadline,
+                "timeline {timeline_id} is not uploaded up to {disk_consistent_lsn} after {DETACH_UPLOAD_TIMEOUT:?}, the remote storage has it up to {remote_lsn:?}"
+            );
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+    Ok(())
+}
+
+fn remove_local_tenant(conf: &'static PageServerConf, tenant_id: ZTenantId) -> anyhow::Result<()> {
+    tenants_state::write_tenants().remove(&tenant_id);
+
+    let local_tenant_directory = conf.tenant_path(&tenant_id);
+    std::fs::remove_dir_all(&local_tenant_directory).with_context(|| {
+        format!(
+            "Failed to remove local tenant directory '{}'",
+            local_tenant_directory.display()
+        )
+    })?;
+
+    Ok(())
+}
+
 fn load_local_timeline(
     repo: &RepositoryImpl,
     timeline_id: ZTimelineId,
@@ -468,6 +781,18 @@ fn apply_timeline_remote_sync_status_updates(
 
     // first need to register the in-mem representations, to avoid missing ancestors during the local disk data registration
     for (timeline_id, status_update) in status_updates {
+        let already_registered = tenants_state::read_tenants()
+            .get(&repo.tenant_id())
+            .map_or(false, |tenant| {
+                tenant.local_timelines.contains_key(&timeline_id)
+            });
+        if already_registered {
+            // The download replaced some layer files of a timeline that is
+            // in use, e.g. corrupt ones found by the scrubber. The layer map
+            // already refers to them.
+            info!("downloaded missing layers of timeline {timeline_id}");
+            continue;
+        }
         repo.apply_timeline_remote_sync_status_update(timeline_id, status_update)
             .with_context(|| {
                 format!("Failed to load timeline {timeline_id} into in-memory repository")
@@ -525,6 +850,7 @@ fn load_local_repo(
             state: TenantState::Idle,
             repo,
             local_timelines: HashMap::new(),
+            getpage_throttle: Arc::new(TokenBucket::new()),
         }
     });
 
@@ -0,0 +1,479 @@
+//! Calculate the "synthetic size" of a tenant, for billing.
+//!
+//! The logical size of a timeline doesn't account for the WAL history that we
+//! keep for point-in-time recovery and for the branch points of child
+//! timelines, although that history is what the storage actually costs. The
+//! synthetic size models it from the timeline tree and the `gc_horizon` and
+//! `pitr_interval` settings of the tenant and of its timelines:
+//!
+//! - Every timeline keeps the WAL between its retention horizon and its end.
+//!   The horizon is the oldest LSN that GC would keep, i.e. the minimum of
+//!   `last_record_lsn - gc_horizon` and the PITR cutoff LSN.
+//!
+//! - The full state of every timeline is needed at its horizon, and at every
+//!   branch point and restore point older than the horizon. Those newer than
+//!   the horizon are covered by the retained WAL.
+//!
+//! - The state at a needed point is either a copy of the logical data at that
+//!   point (an image), or the WAL from the previous needed point, whichever is
+//!   smaller. The first needed point of a branch can be reached with WAL from
+//!   its branch point, the first needed point of a root timeline is always an
+//!   image.
+//!
+//! The size of the tenant is the sum of the size of all the images and WAL
+//! segments. The WAL volume is approximated with the LSN distance, and the
+//! image size with the logical size, which only counts relation blocks.
+use crate::pgdatadir_mapping::LsnForTimestamp;
+use crate::repository::{Repository, Timeline};
+use crate::tenant_mgr;
+use crate::DatadirTimelineImpl;
+use anyhow::{Context, Result};
+use postgres_ffi::xlog_utils::to_pg_timestamp;
+use serde::Serialize;
+use serde_with::{serde_as, DisplayFromStr};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tracing::*;
+use utils::lsn::Lsn;
+use utils::zid::{ZTenantId, ZTimelineId};
+
+/// Inputs of the size calculation for one timeline
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TimelineInputs {
+    #[serde_as(as = "DisplayFromStr")]
+    pub timeline_id: ZTimelineId,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub ancestor_timeline_id: Option<ZTimelineId>,
+    #[serde_as(as = "DisplayFromStr")]
+    pub ancestor_lsn: Lsn,
+    /// Oldest LSN that needs to be kept for `gc_horizon` and `pitr_interval`
+    #[serde_as(as = "DisplayFromStr")]
+    pub horizon_lsn: Lsn,
+    #[serde_as(as = "DisplayFromStr")]
+    pub last_record_lsn: Lsn,
+    #[serde_as(as = "Vec<DisplayFromStr>")]
+    pub restore_point_lsns: Vec<Lsn>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SegmentKind {
+    /// Copy of the logical data at `end_lsn`
+    Image,
+    /// WAL between `start_lsn` and `end_lsn`
+    Wal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SegmentReason {
+    /// The history between the retention horizon and the end of the timeline
+    Retention,
+    /// State of the timeline at its retention horizon
+    Horizon,
+    /// State of the timeline at the branch point of a child timeline
+    BranchPoint,
+    /// State of the timeline at a named restore point
+    RestorePoint,
+}
+
+/// A piece of the tenant history that is included in the size
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SizeSegment {
+    #[serde_as(as = "DisplayFromStr")]
+    pub timeline_id: ZTimelineId,
+    pub kind: SegmentKind,
+    pub reason: SegmentReason,
+    /// The child timeline that branches off at `end_lsn`, for `BranchPoint` segments
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub child_timeline_id: Option<ZTimelineId>,
+    #[serde_as(as = "DisplayFromStr")]
+    pub start_lsn: Lsn,
+    #[serde_as(as = "DisplayFromStr")]
+    pub end_lsn: Lsn,
+    pub size: u64,
+}
+
+/// Synthetic size of a tenant, with the inputs and the segments it consists of
+#[serde_as]
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantSize {
+    #[serde_as(as = "DisplayFromStr")]
+    pub tenant_id: ZTenantId,
+    pub size: u64,
+    pub timelines: Vec<TimelineInputs>,
+    pub segments: Vec<SizeSegment>,
+}
+
+/// Calculate the size segments of the given timelines. `logical_size` returns
+/// the logical size of a timeline at an LSN; it's only called for the needed
+/// points where an image might be cheaper than the WAL.
+///
+/// Ancestors that are not among `timelines` are ignored, the branches of such
+/// timelines are treated like root timelines.
+pub fn calculate_segments<F>(
+    timelines: &[TimelineInputs],
+    mut logical_size: F,
+) -> Result<Vec<SizeSegment>>
+where
+    F: FnMut(ZTimelineId, Lsn) -> Result<u64>,
+{
+    let by_id = timelines
+        .iter()
+        .map(|t| (t.timeline_id, t))
+        .collect::<HashMap<_, _>>();
+
+    // Points where the full state of each timeline is needed, with the reason
+    // and the child timeline that needs it, if any.
+    type NeededPoints = BTreeMap<Lsn, (SegmentReason, Option<ZTimelineId>)>;
+    let mut needed_points: HashMap<ZTimelineId, NeededPoints> = timelines
+        .iter()
+        .map(|t| {
+            let mut points = BTreeMap::from([(t.horizon_lsn, (SegmentReason::Horizon, None))]);
+            for &lsn in t
+                .restore_point_lsns
+                .iter()
+                .filter(|&&lsn| lsn < t.horizon_lsn)
+            {
+                points
+                    .entry(lsn)
+                    .or_insert((SegmentReason::RestorePoint, None));
+            }
+            (t.timeline_id, points)
+        })
+        .collect();
+    for t in timelines {
+        let ancestor = match t.ancestor_timeline_id.and_then(|id| by_id.get(&id)) {
+            Some(ancestor) => ancestor,
+            None => continue,
+        };
+        if t.ancestor_lsn < ancestor.horizon_lsn {
+            needed_points
+                .get_mut(&ancestor.timeline_id)
+                .unwrap()
+                .entry(t.ancestor_lsn)
+                .or_insert((SegmentReason::BranchPoint, Some(t.timeline_id)));
+        }
+    }
+
+    let mut segments = Vec::new();
+    for t in timelines {
+        // The state at the branch point is provided by the ancestor
+        let mut prev_lsn = t
+            .ancestor_timeline_id
+            .filter(|id| by_id.contains_key(id))
+            .map(|_| t.ancestor_lsn);
+
+        for (&lsn, &(reason, child_timeline_id)) in &needed_points[&t.timeline_id] {
+            if prev_lsn == Some(lsn) {
+                continue;
+            }
+            let image_size = logical_size(t.timeline_id, lsn).with_context(|| {
+                format!(
+                    "Failed to get logical size of timeline {} at {}",
+                    t.timeline_id, lsn
+                )
+            })?;
+            let segment = match prev_lsn {
+                Some(prev_lsn) if lsn.0 - prev_lsn.0 <= image_size => SizeSegment {
+                    timeline_id: t.timeline_id,
+                    kind: SegmentKind::Wal,
+                    reason,
+                    child_timeline_id,
+                    start_lsn: prev_lsn,
+                    end_lsn: lsn,
+                    size: lsn.0 - prev_lsn.0,
+                },
+                _ => SizeSegment {
+                    timeline_id: t.timeline_id,
+                    kind: SegmentKind::Image,
+                    reason,
+                    child_timeline_id,
+                    start_lsn: lsn,
+                    end_lsn: lsn,
+                    size: image_size,
+                },
+            };
+            segments.push(segment);
+            prev_lsn = Some(lsn);
+        }
+
+        if t.last_record_lsn > t.horizon_lsn {
+            segments.push(SizeSegment {
+                timeline_id: t.timeline_id,
+                kind: SegmentKind::Wal,
+                reason: SegmentReason::Retention,
+                child_timeline_id: None,
+                start_lsn: t.horizon_lsn,
+                end_lsn: t.last_record_lsn,
+                size: t.last_record_lsn.0 - t.horizon_lsn.0,
+            });
+        }
+    }
+    Ok(segments)
+}
+
+/// Calculate the synthetic size of a tenant from its local timelines.
+pub fn calculate_tenant_size(tenant_id: ZTenantId) -> Result<TenantSize> {
+    let repo = tenant_mgr::get_repository_for_tenant(tenant_id)?;
+    let now = SystemTime::now();
+
+    let mut timeline_ids = repo
+        .list_timelines()
+        .into_iter()
+        .map(|(timeline_id, _)| timeline_id)
+        .collect::<Vec<_>>();
+    timeline_ids.sort_unstable();
+
+    let mut timelines = HashMap::with_capacity(timeline_ids.len());
+    let mut inputs = Vec::with_capacity(timeline_ids.len());
+    for timeline_id in timeline_ids {
+        let timeline = tenant_mgr::get_local_timeline_with_load(tenant_id, timeline_id)?;
+        inputs.push(timeline_inputs(timeline_id, &timeline, now)?);
+        timelines.insert(timeline_id, timeline);
+    }
+
+    let segments = calculate_segments(&inputs, |timeline_id, lsn| {
+        let size = timelines[&timeline_id].get_current_logical_size_non_incremental(lsn)?;
+        Ok(size as u64)
+    })?;
+    let size = segments.iter().map(|s| s.size).sum();
+    debug!(
+        "synthetic size of tenant {}: {} bytes in {} segments",
+        tenant_id,
+        size,
+        segments.len()
+    );
+
+    Ok(TenantSize {
+        tenant_id,
+        size,
+        timelines: inputs,
+        segments,
+    })
+}
+
+/// Determine the retention horizon of a timeline, the same way GC does,
+/// including the timeline's own overrides of the GC settings.
+fn timeline_inputs(
+    timeline_id: ZTimelineId,
+    timeline: &Arc<DatadirTimelineImpl>,
+    now: SystemTime,
+) -> Result<TimelineInputs> {
+    let tline = &timeline.tline;
+    let gc_horizon = tline.get_gc_horizon();
+    let pitr = tline.get_pitr_interval();
+    let last_record_lsn = tline.get_last_record_lsn();
+    let ancestor_lsn = tline.get_ancestor_lsn();
+    let latest_gc_cutoff_lsn = *tline.get_latest_gc_cutoff_lsn();
+
+    let gc_horizon_lsn = Lsn(last_record_lsn.0.saturating_sub(gc_horizon));
+    // If the PITR cutoff can't be determined, GC doesn't remove anything
+    // beyond what it has removed already.
+    let pitr_cutoff_lsn = match now.checked_sub(pitr) {
+        Some(pitr_cutoff_timestamp) => {
+            match timeline.find_lsn_for_timestamp(to_pg_timestamp(pitr_cutoff_timestamp))? {
+                LsnForTimestamp::Present(lsn) => lsn,
+                LsnForTimestamp::Future(_) => gc_horizon_lsn,
+                LsnForTimestamp::Past(_) | LsnForTimestamp::NoData(_) => latest_gc_cutoff_lsn,
+            }
+        }
+        None => latest_gc_cutoff_lsn,
+    };
+
+    // History older than the GC cutoff or the branch point is gone, or not
+    // part of this timeline.
+    let horizon_lsn = Lsn::min(gc_horizon_lsn, pitr_cutoff_lsn)
+        .max(latest_gc_cutoff_lsn)
+        .max(ancestor_lsn)
+        .min(last_record_lsn);
+
+    Ok(TimelineInputs {
+        timeline_id,
+        ancestor_timeline_id: tline.get_ancestor_timeline_id(),
+        ancestor_lsn,
+        horizon_lsn,
+        last_record_lsn,
+        restore_point_lsns: tline
+            .get_restore_points()
+            .iter()
+            .map(|restore_point| restore_point.lsn)
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn timeline_id(id: &str) -> ZTimelineId {
+        ZTimelineId::from_str(&format!("{:0>32}", id)).unwrap()
+    }
+
+    fn inputs(
+        id: &str,
+        ancestor: Option<(&str, u64)>,
+        horizon_lsn: u64,
+        last_record_lsn: u64,
+    ) -> TimelineInputs {
+        TimelineInputs {
+            timeline_id: timeline_id(id),
+            ancestor_timeline_id: ancestor.map(|(ancestor_id, _)| timeline_id(ancestor_id)),
+            ancestor_lsn: Lsn(ancestor.map_or(0, |(_, lsn)| lsn)),
+            horizon_lsn: Lsn(horizon_lsn),
+            last_record_lsn: Lsn(last_record_lsn),
+            restore_point_lsns: Vec::new(),
+        }
+    }
+
+    fn total(segments: &[SizeSegment]) -> u64 {
+        segments.iter().map(|s| s.size).sum()
+    }
+
+    #[test]
+    fn test_single_timeline() -> Result<()> {
+        let timelines = [inputs("1", None, 1000, 1500)];
+        let segments = calculate_segments(&timelines, |_, lsn| {
+            assert_eq!(lsn, Lsn(1000));
+            Ok(300)
+        })?;
+
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].kind, SegmentKind::Image);
+        assert_eq!(segments[0].reason, SegmentReason::Horizon);
+        assert_eq!(segments[0].size, 300);
+        assert_eq!(segments[1].kind, SegmentKind::Wal);
+        assert_eq!(segments[1].reason, SegmentReason::Retention);
+        assert_eq!(segments[1].size, 500);
+        assert_eq!(total(&segments), 800);
+        Ok(())
+    }
+
+    #[test]
+    fn test_branch_within_retention() -> Result<()> {
+        // The branch point is covered by the parent's retained WAL, and the
+        // child needs nothing but its own WAL.
+        let timelines = [
+            inputs("1", None, 1000, 1500),
+            inputs("2", Some(("1", 1200)), 1200, 1300),
+        ];
+        let segments = calculate_segments(&timelines, |id, lsn| {
+            assert_eq!((id, lsn), (timeline_id("1"), Lsn(1000)));
+            Ok(300)
+        })?;
+
+        assert_eq!(total(&segments), 300 + 500 + 100);
+        assert!(segments
+            .iter()
+            .all(|s| s.reason != SegmentReason::BranchPoint));
+        Ok(())
+    }
+
+    #[test]
+    fn test_branch_before_horizon() -> Result<()> {
+        // The branch point is 100 bytes of WAL before the parent's horizon,
+        // less than an image of the parent there.
+        let timelines = [
+            inputs("1", None, 1000, 1500),
+            inputs("2", Some(("1", 900)), 900, 950),
+        ];
+        let segments = calculate_segments(&timelines, |_, _| Ok(300))?;
+
+        let branch_point = segments
+            .iter()
+            .find(|s| s.reason == SegmentReason::BranchPoint)
+            .unwrap();
+        assert_eq!(branch_point.kind, SegmentKind::Image);
+        assert_eq!(branch_point.child_timeline_id, Some(timeline_id("2")));
+        assert_eq!(branch_point.end_lsn, Lsn(900));
+
+        // The parent's horizon is reached with WAL from the branch point
+        let horizon = segments
+            .iter()
+            .find(|s| s.timeline_id == timeline_id("1") && s.reason == SegmentReason::Horizon)
+            .unwrap();
+        assert_eq!(horizon.kind, SegmentKind::Wal);
+        assert_eq!((horizon.start_lsn, horizon.end_lsn), (Lsn(900), Lsn(1000)));
+
+        assert_eq!(total(&segments), 300 + 100 + 500 + 50);
+        Ok(())
+    }
+
+    #[test]
+    fn test_old_branch_uses_image() -> Result<()> {
+        // The branch point is far behind the parent's horizon, so an image at
+        // the horizon is cheaper than the WAL from the branch point.
+        let timelines = [
+            inputs("1", None, 100_000, 100_500),
+            inputs("2", Some(("1", 1000)), 1000, 1100),
+        ];
+        let segments = calculate_segments(&timelines, |_, _| Ok(300))?;
+
+        let parent = segments
+            .iter()
+            .filter(|s| s.timeline_id == timeline_id("1"))
+            .map(|s| (s.kind, s.reason))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parent,
+            [
+                (SegmentKind::Image, SegmentReason::BranchPoint),
+                (SegmentKind::Image, SegmentReason::Horizon),
+                (SegmentKind::Wal, SegmentReason::Retention),
+            ]
+        );
+        assert_eq!(total(&segments), 300 + 300 + 500 + 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_child_horizon_after_branch_point() -> Result<()> {
+        // The child's own horizon is past its branch point. The WAL between
+        // them is cheaper than an image.
+        let timelines = [
+            inputs("1", None, 1000, 1500),
+            inputs("2", Some(("1", 1200)), 1250, 1400),
+        ];
+        let segments = calculate_segments(&timelines, |_, _| Ok(300))?;
+
+        let child = segments
+            .iter()
+            .filter(|s| s.timeline_id == timeline_id("2"))
+            .map(|s| (s.kind, s.start_lsn, s.end_lsn))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            child,
+            [
+                (SegmentKind::Wal, Lsn(1200), Lsn(1250)),
+                (SegmentKind::Wal, Lsn(1250), Lsn(1400)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_points() -> Result<()> {
+        // Only the restore point behind the horizon needs its own segment
+        let mut timeline = inputs("1", None, 1000, 1500);
+        timeline.restore_point_lsns = vec![Lsn(400), Lsn(1200)];
+        let segments = calculate_segments(&[timeline], |_, _| Ok(300))?;
+
+        let kinds = segments
+            .iter()
+            .map(|s| (s.kind, s.reason, s.end_lsn))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                (SegmentKind::Image, SegmentReason::RestorePoint, Lsn(400)),
+                (SegmentKind::Image, SegmentReason::Horizon, Lsn(1000)),
+                (SegmentKind::Wal, SegmentReason::Retention, Lsn(1500)),
+            ]
+        );
+        assert_eq!(total(&segments), 300 + 300 + 500);
+        Ok(())
+    }
+}
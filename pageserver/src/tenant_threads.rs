@@ -1,10 +1,13 @@
 //! This module contains functions to serve per-tenant background processes,
-//! such as compaction and GC
+//! such as compaction, GC and scrubbing
 use crate::repository::Repository;
 use crate::tenant_mgr;
 use crate::tenant_mgr::TenantState;
+use crate::thread_mgr;
+use crate::throttle::TokenBucket;
+use crate::virtual_file;
 use anyhow::Result;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tracing::*;
 use utils::zid::ZTenantId;
 
@@ -12,6 +15,7 @@ use utils::zid::ZTenantId;
 /// Compaction thread's main loop
 ///
 pub fn compact_loop(tenantid: ZTenantId) -> Result<()> {
+    virtual_file::set_background_io();
     if let Err(err) = compact_loop_ext(tenantid) {
         error!("compact loop terminated with error: {:?}", err);
         Err(err)
@@ -48,6 +52,7 @@ fn compact_loop_ext(tenantid: ZTenantId) -> Result<()> {
 /// GC thread's main loop
 ///
 pub fn gc_loop(tenantid: ZTenantId) -> Result<()> {
+    virtual_file::set_background_io();
     loop {
         if tenant_mgr::get_tenant_state(tenantid) != Some(TenantState::Active) {
             break;
@@ -58,7 +63,7 @@ pub fn gc_loop(tenantid: ZTenantId) -> Result<()> {
         let gc_horizon = repo.get_gc_horizon();
         // Garbage collect old files that are not needed for PITR anymore
         if gc_horizon > 0 {
-            repo.gc_iteration(None, gc_horizon, repo.get_pitr_interval(), false)?;
+            repo.gc_iteration(None, None, repo.get_pitr_interval(), false, false)?;
         }
 
         // TODO Write it in more adequate way using
@@ -77,3 +82,62 @@ pub fn gc_loop(tenantid: ZTenantId) -> Result<()> {
     );
     Ok(())
 }
+
+///
+/// Scrubber thread's main loop
+///
+pub fn scrub_loop(tenantid: ZTenantId) -> Result<()> {
+    virtual_file::set_background_io();
+    let bucket = TokenBucket::new();
+    loop {
+        if tenant_mgr::get_tenant_state(tenantid) != Some(TenantState::Active) {
+            break;
+        }
+        let repo = tenant_mgr::get_repository_for_tenant(tenantid)?;
+        let scrub_period = repo.get_scrub_period();
+
+        // Scrubbing is disabled. Check again later, in case the config changes.
+        if scrub_period.is_zero() {
+            std::thread::sleep(Duration::from_secs(1));
+            continue;
+        }
+
+        // Sleep in short steps, so that we notice if the tenant is stopped
+        let mut sleep_time = scrub_period;
+        while !sleep_time.is_zero()
+            && tenant_mgr::get_tenant_state(tenantid) == Some(TenantState::Active)
+        {
+            let step = std::cmp::min(sleep_time, Duration::from_secs(1));
+            std::thread::sleep(step);
+            sleep_time -= step;
+        }
+        if tenant_mgr::get_tenant_state(tenantid) != Some(TenantState::Active) {
+            break;
+        }
+
+        trace!("scrubber thread for tenant {} waking up", tenantid);
+        let repo = tenant_mgr::get_repository_for_tenant(tenantid)?;
+        let rate_limit = repo.get_scrub_rate_limit();
+        let result = repo.scrub_iteration(&mut |bytes| {
+            let wait = bucket.acquire(rate_limit, rate_limit, bytes, Instant::now());
+            if !wait.is_zero() && !thread_mgr::is_shutdown_requested() {
+                std::thread::sleep(wait);
+            }
+            // The reads of the scrubber are charged against the background
+            // I/O budget too, wait for it between layer reads.
+            virtual_file::pay_io_debt();
+        })?;
+        info!(
+            "scrubbed {} layers of tenant {}, {} corrupt",
+            result.layers_scrubbed,
+            tenantid,
+            result.corrupt_layers.len()
+        );
+    }
+    trace!(
+        "scrubber thread stopped for tenant {} state is {:?}",
+        tenantid,
+        tenant_mgr::get_tenant_state(tenantid)
+    );
+    Ok(())
+}
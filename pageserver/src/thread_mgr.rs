@@ -26,6 +26,14 @@
 //! Tokio select!(), but note that it relies on thread-local storage, so it
 //! will only work with the "current-thread" Tokio runtime!
 //!
+//! # Tasks
+//!
+//! Async tasks running on a multi-threaded Tokio runtime can also be
+//! registered, with `register_task()`. They show up in the registry like
+//! threads, and `shutdown_threads()` shuts them down and waits for them in
+//! the same way. Instead of the thread-local functions, a task watches the
+//! `RegisteredTask` it got from the registration.
+//!
 //!
 //! TODO: This would be a good place to also handle panics in a somewhat sane way.
 //! Depending on what thread panics, we might want to kill the whole server, or
@@ -37,7 +45,7 @@ use std::collections::HashMap;
 use std::panic;
 use std::panic::AssertUnwindSafe;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::thread::JoinHandle;
 
@@ -79,16 +87,16 @@ thread_local!(static CURRENT_THREAD: RefCell<Option<Arc<PageServerThread>>> = Re
 ///
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ThreadKind {
-    // libpq listener thread. It just accepts connection and spawns a
-    // PageRequestHandler thread for each connection.
+    // libpq listener thread. It accepts connections, and serves each one
+    // in a separate task on the page service's own tokio runtime.
     LibpqEndpointListener,
 
     // HTTP endpoint listener.
     HttpEndpointListener,
 
-    // Thread that handles a single connection. A PageRequestHandler thread
-    // starts detached from any particular tenant or timeline, but it can be
-    // associated with one later, after receiving a command from the client.
+    // Task that serves a pagestream connection, on the page service's tokio
+    // runtime. It is registered once the client has said which timeline it
+    // wants, so that it's shut down when the timeline goes away.
     PageRequestHandler,
 
     // Thread that connects to a safekeeper to fetch WAL for one timeline.
@@ -100,16 +108,23 @@ pub enum ThreadKind {
     // Thread that handles GC of a tenant
     GarbageCollector,
 
+    // Thread that verifies the checksums of the layer files of a tenant
+    Scrubber,
+
     // Thread that flushes frozen in-memory layers to disk
     LayerFlushThread,
 
     // Thread for synchronizing pageserver layer files with the remote storage.
     // Shared by all tenants.
     StorageSync,
+
+    // Thread that evicts uploaded layer files when the disk is filling up.
+    // Shared by all tenants.
+    DiskUsageEviction,
 }
 
 struct PageServerThread {
-    _thread_id: u64,
+    thread_id: u64,
 
     kind: ThreadKind,
 
@@ -127,6 +142,10 @@ struct PageServerThread {
     /// Handle for waiting for the thread to exit. It can be None, if the
     /// the thread has already exited.
     join_handle: Mutex<Option<JoinHandle<()>>>,
+
+    /// Tasks have no JoinHandle. Instead, this channel is disconnected when
+    /// the task exits. None for threads.
+    task_exit_rx: Mutex<Option<mpsc::Receiver<()>>>,
 }
 
 /// Launch a new thread
@@ -146,7 +165,7 @@ where
     let (shutdown_tx, shutdown_rx) = watch::channel(());
     let thread_id = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
     let thread = PageServerThread {
-        _thread_id: thread_id,
+        thread_id,
         kind,
         tenant_id,
         timeline_id,
@@ -156,6 +175,7 @@ where
         shutdown_tx,
 
         join_handle: Mutex::new(None),
+        task_exit_rx: Mutex::new(None),
     };
 
     let thread_rc = Arc::new(thread);
@@ -262,6 +282,73 @@ fn thread_wrapper<F>(
     }
 }
 
+///
+/// Register an async task. Unlike a thread, a task isn't started here: the
+/// caller is the task, and it stays registered until it drops the returned
+/// RegisteredTask.
+///
+pub fn register_task(
+    kind: ThreadKind,
+    tenant_id: Option<ZTenantId>,
+    timeline_id: Option<ZTimelineId>,
+    name: &str,
+) -> RegisteredTask {
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    let (exit_tx, exit_rx) = mpsc::channel();
+    let thread_id = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+    let task = PageServerThread {
+        thread_id,
+        kind,
+        tenant_id,
+        timeline_id,
+        name: name.to_string(),
+
+        shutdown_requested: AtomicBool::new(false),
+        shutdown_tx,
+
+        join_handle: Mutex::new(None),
+        task_exit_rx: Mutex::new(Some(exit_rx)),
+    };
+    let task = Arc::new(task);
+    THREADS.lock().unwrap().insert(thread_id, Arc::clone(&task));
+
+    RegisteredTask {
+        task,
+        shutdown_rx,
+        _exit_tx: exit_tx,
+    }
+}
+
+///
+/// Registration of an async task, see `register_task()`. The task is
+/// unregistered when this is dropped.
+///
+pub struct RegisteredTask {
+    task: Arc<PageServerThread>,
+    shutdown_rx: watch::Receiver<()>,
+    // Dropped after the task has been removed from the registry, to wake up
+    // shutdown_threads() if it's waiting for the task.
+    _exit_tx: mpsc::Sender<()>,
+}
+
+impl RegisteredTask {
+    /// A Future that completes when the task has been requested to shut down.
+    pub async fn shutdown_watcher(&self) {
+        let _ = self.shutdown_rx.clone().changed().await;
+    }
+
+    /// Has the task been requested to shut down?
+    pub fn is_shutdown_requested(&self) -> bool {
+        self.task.shutdown_requested.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for RegisteredTask {
+    fn drop(&mut self) {
+        THREADS.lock().unwrap().remove(&self.task.thread_id);
+    }
+}
+
 /// Is there a thread running that matches the criteria
 
 /// Signal and wait for threads to shut down.
@@ -301,6 +388,10 @@ pub fn shutdown_threads(
         info!("waiting for {} to shut down", thread.name);
         if let Some(join_handle) = thread.join_handle.lock().unwrap().take() {
             let _ = join_handle.join();
+        } else if let Some(exit_rx) = thread.task_exit_rx.lock().unwrap().take() {
+            // This returns an error when the task has exited. The task never
+            // sends anything.
+            let _ = exit_rx.recv();
         } else {
             // The thread had not even fully started yet. Or it was shut down
             // concurrently and already exited
@@ -335,3 +426,58 @@ pub fn is_shutdown_requested() -> bool {
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shutdown_registered_task() {
+        let tenant_id = ZTenantId::generate();
+        let timeline_id = ZTimelineId::generate();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let (registered_tx, registered_rx) = mpsc::channel();
+        let task_thread = thread::spawn(move || {
+            runtime.block_on(async {
+                let task = register_task(
+                    ThreadKind::PageRequestHandler,
+                    Some(tenant_id),
+                    Some(timeline_id),
+                    "test task",
+                );
+                registered_tx.send(()).unwrap();
+                task.shutdown_watcher().await;
+                assert!(task.is_shutdown_requested());
+            })
+        });
+        registered_rx.recv().unwrap();
+
+        // Tasks of other timelines are left alone
+        shutdown_threads(
+            Some(ThreadKind::PageRequestHandler),
+            Some(tenant_id),
+            Some(ZTimelineId::generate()),
+        );
+        let registered = || {
+            THREADS
+                .lock()
+                .unwrap()
+                .values()
+                .any(|t| t.tenant_id == Some(tenant_id))
+        };
+        assert!(registered());
+
+        // This returns once the task has exited
+        shutdown_threads(
+            Some(ThreadKind::PageRequestHandler),
+            Some(tenant_id),
+            Some(timeline_id),
+        );
+        assert!(!registered());
+        task_thread.join().unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+//!
+//! Per-tenant admission control for the page service.
+//!
+//! Every tenant has a token bucket that GetPage@LSN requests draw from. The
+//! bucket is refilled at `getpage_rate_limit` tokens per second, up to
+//! `getpage_burst` tokens. A request that finds the bucket empty still takes
+//! its tokens, driving the balance negative, and is told how long to wait
+//! until the debt is repaid. Requests arriving later queue up behind it, so
+//! a tenant's connections are admitted in arrival order and the tenant as a
+//! whole cannot exceed its configured rate, no matter how many connections
+//! it opens.
+//!
+//! The limits are passed in on each call rather than stored, so that tenant
+//! config updates take effect on the next request.
+//!
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct TokenBucket {
+    state: Mutex<TokenBucketState>,
+}
+
+struct TokenBucketState {
+    rate: u64,
+    burst: u64,
+    /// Available tokens. Negative when requests are queued behind the limit.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new() -> Self {
+        TokenBucket {
+            state: Mutex::new(TokenBucketState {
+                rate: 0,
+                burst: 0,
+                // Start with a full bucket, clamped to the burst size on the first request.
+                tokens: f64::MAX,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    ///
+    /// Take `cost` tokens from the bucket, with the given limits.
+    ///
+    /// Returns how long the caller has to wait before proceeding, which is
+    /// zero if the request is admitted immediately. A `rate` of 0 disables
+    /// throttling.
+    ///
+    pub fn acquire(&self, rate: u64, burst: u64, cost: u64, now: Instant) -> Duration {
+        let mut state = self.state.lock().unwrap();
+
+        if rate == 0 {
+            // Unlimited. Start with a full bucket if a limit is set later.
+            state.rate = 0;
+            state.burst = burst;
+            state.tokens = burst as f64;
+            state.last_refill = now;
+            return Duration::ZERO;
+        }
+
+        if state.rate != rate || state.burst != burst {
+            state.rate = rate;
+            state.burst = burst;
+            state.tokens = state.tokens.min(burst as f64);
+        }
+
+        // Refill for the time that has passed since the last request.
+        if now > state.last_refill {
+            let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+            state.tokens = (state.tokens + elapsed * rate as f64).min(burst as f64);
+            state.last_refill = now;
+        }
+
+        state.tokens -= cost as f64;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / rate as f64)
+        }
+    }
+}
+
+impl Default for TokenBucket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited() {
+        let bucket = TokenBucket::new();
+        let now = Instant::now();
+        for _ in 0..10_000 {
+            assert_eq!(bucket.acquire(0, 10, 1, now), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn burst_then_throttle() {
+        let bucket = TokenBucket::new();
+        let now = Instant::now();
+
+        // The first requests find a full bucket.
+        for _ in 0..10 {
+            assert_eq!(bucket.acquire(100, 10, 1, now), Duration::ZERO);
+        }
+
+        // The bucket is empty now, so each further request queues 10 ms
+        // behind the previous one.
+        assert_eq!(bucket.acquire(100, 10, 1, now), Duration::from_millis(10));
+        assert_eq!(bucket.acquire(100, 10, 1, now), Duration::from_millis(20));
+
+        // After 20 ms the debt is repaid, and the next request has to wait
+        // for its own token only.
+        let later = now + Duration::from_millis(20);
+        assert_eq!(bucket.acquire(100, 10, 1, later), Duration::from_millis(10));
+
+        // After a long idle period, the bucket is full again, but not more.
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(bucket.acquire(100, 10, 10, much_later), Duration::ZERO);
+        assert_eq!(
+            bucket.acquire(100, 10, 1, much_later),
+            Duration::from_millis(10)
+        );
+    }
+
+    #[test]
+    fn limit_change() {
+        let bucket = TokenBucket::new();
+        let now = Instant::now();
+
+        bucket.acquire(0, 100, 0, now);
+        assert_eq!(bucket.acquire(1000, 100, 50, now), Duration::ZERO);
+
+        // Lowering the burst drops the tokens above it.
+        assert_eq!(bucket.acquire(1000, 10, 10, now), Duration::ZERO);
+        assert_eq!(bucket.acquire(1000, 10, 1, now), Duration::from_millis(1));
+    }
+}
@@ -321,6 +321,91 @@ fn bootstrap_timeline<R: Repository>(
     Ok(())
 }
 
+///
+/// Create a new timeline from a basebackup tarball.
+///
+/// The data files in the tarball are imported at 'start_lsn', and the WAL
+/// included in the tarball is replayed up to 'end_lsn'. See
+/// import_datadir::import_basebackup_from_tar for details.
+///
+pub(crate) fn import_timeline_from_tar<R: std::io::Read>(
+    conf: &'static PageServerConf,
+    tenant_id: ZTenantId,
+    timeline_id: ZTimelineId,
+    start_lsn: Lsn,
+    end_lsn: Lsn,
+    reader: R,
+) -> Result<TimelineInfo> {
+    let _enter =
+        info_span!("import", timeline = %timeline_id, tenant = %tenant_id, %start_lsn, %end_lsn)
+            .entered();
+
+    let repo = tenant_mgr::get_repository_for_tenant(tenant_id)?;
+    ensure!(
+        !conf.timeline_path(&timeline_id, &tenant_id).exists(),
+        "timeline {} already exists",
+        timeline_id
+    );
+    ensure!(
+        start_lsn.is_aligned(),
+        "start LSN {} is not aligned",
+        start_lsn
+    );
+
+    // The WAL segments in the tarball are stored in a temporary directory
+    // until the data files have been imported, and removed afterwards.
+    let wal_dir = conf
+        .tenant_path(&tenant_id)
+        .join(format!("tmp-import-wal-{}", timeline_id));
+    crashsafe_dir::create_dir_all(&wal_dir)?;
+    let _wal_dir_guard = scopeguard::guard(wal_dir.clone(), |wal_dir| {
+        if let Err(e) = fs::remove_dir_all(&wal_dir) {
+            error!("could not remove {}: {:?}", wal_dir.display(), e);
+        }
+    });
+
+    let timeline = repo.create_empty_timeline(timeline_id, start_lsn)?;
+    let mut page_tline: DatadirTimeline<RepositoryImpl> = DatadirTimeline::new(timeline, u64::MAX);
+    let result = import_datadir::import_basebackup_from_tar(
+        &mut page_tline,
+        reader,
+        &wal_dir,
+        start_lsn,
+        end_lsn,
+    )
+    .and_then(|()| page_tline.tline.checkpoint(CheckpointConfig::Forced));
+    if let Err(e) = result {
+        // Don't leave a half-imported timeline behind.
+        drop(page_tline);
+        if let Err(detach_err) = repo.detach_timeline(timeline_id) {
+            error!("could not detach failed import: {:?}", detach_err);
+        } else if let Err(remove_err) =
+            fs::remove_dir_all(conf.timeline_path(&timeline_id, &tenant_id))
+        {
+            error!("could not remove failed import: {:?}", remove_err);
+        }
+        return Err(e);
+    }
+
+    info!(
+        "imported timeline {} at {}",
+        timeline_id,
+        page_tline.tline.get_last_record_lsn()
+    );
+
+    // load the timeline into memory
+    let new_timeline = tenant_mgr::get_local_timeline_with_load(tenant_id, timeline_id)?;
+    Ok(TimelineInfo {
+        tenant_id,
+        timeline_id,
+        local: Some(
+            LocalTimelineInfo::from_loaded_timeline(&new_timeline, false)
+                .context("cannot fill timeline info")?,
+        ),
+        remote: None,
+    })
+}
+
 pub(crate) fn get_local_timelines(
     tenant_id: ZTenantId,
     include_non_incremental_logical_size: bool,
@@ -379,7 +464,10 @@ pub(crate) fn create_timeline(
                 // decoding the new WAL might need to look up previous pages, relation
                 // sizes etc. and that would get confused if the previous page versions
                 // are not in the repository yet.
-                ancestor_timeline.wait_lsn(start_lsn)?;
+                //
+                // This runs in a blocking task of the HTTP runtime, so block on the wait.
+                tokio::runtime::Handle::current()
+                    .block_on(ancestor_timeline.wait_lsn(start_lsn))?;
             }
             start_lsn = start_lsn.align();
 
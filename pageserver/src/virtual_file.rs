@@ -10,16 +10,36 @@
 //! This is similar to PostgreSQL's virtual file descriptor facility in
 //! src/backend/storage/file/fd.c
 //!
+//! Reads and writes are also charged against pageserver-wide I/O budgets.
+//! Background work (compaction, GC, layer flushes and scrubbing) and the
+//! page service each have their own budget, so that a burst of compaction
+//! cannot push up GetPage@LSN latency for every tenant on the node.
+//!
+//! The background threads declare that their I/O counts against the
+//! background budget with set_background_io(). Their reads can happen under
+//! the layer map lock, so a read only runs up a debt against the budget; the
+//! thread waits it out on its next write, or in pay_io_debt() at a point
+//! where it holds no locks. The page service runs on an async runtime, where
+//! a blocking wait would park a whole worker, so it charges each request
+//! against the foreground budget up front with charge_foreground_io(), and
+//! waits asynchronously. I/O on other threads is not throttled.
+//!
+use crate::throttle::TokenBucket;
 use lazy_static::lazy_static;
 use once_cell::sync::OnceCell;
+use std::cell::Cell;
 use std::fs::{File, OpenOptions};
 use std::io::{Error, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::{RwLock, RwLockWriteGuard};
+use std::time::{Duration, Instant};
 
-use metrics::{register_histogram_vec, register_int_gauge_vec, HistogramVec, IntGaugeVec};
+use metrics::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGaugeVec,
+};
 
 // Metrics collected on disk IO operations
 const STORAGE_IO_TIME_BUCKETS: &[f64] = &[
@@ -49,6 +69,148 @@ lazy_static! {
     )
     .expect("failed to define a metric");
 }
+lazy_static! {
+    static ref IO_BUDGET_BYTES: IntCounterVec = register_int_counter_vec!(
+        "pageserver_io_budget_bytes_total",
+        "Bytes read and written against the background and foreground I/O budgets",
+        &["class", "operation", "tenant_id"]
+    )
+    .expect("failed to define a metric");
+    static ref IO_BUDGET_WAIT_TIME: HistogramVec = register_histogram_vec!(
+        "pageserver_io_budget_wait_seconds",
+        "Time spent waiting for the background and foreground I/O budgets",
+        &["class", "tenant_id"],
+        vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]
+    )
+    .expect("failed to define a metric");
+}
+
+/// Which I/O budget the reads and writes are charged against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    /// Reads on behalf of page service requests
+    Foreground,
+    /// Compaction, GC, layer flushes and scrubbing
+    Background,
+}
+
+impl IoClass {
+    fn as_str(&self) -> &'static str {
+        match self {
+            IoClass::Foreground => "foreground",
+            IoClass::Background => "background",
+        }
+    }
+}
+
+/// The per-tenant metrics of one I/O budget. Looking them up is too
+/// expensive to do on every read and write, so the users keep them around.
+pub struct IoBudgetMetrics {
+    read_bytes: IntCounter,
+    write_bytes: IntCounter,
+    wait_time: Histogram,
+}
+
+impl IoBudgetMetrics {
+    pub fn new(class: IoClass, tenant_id: &str) -> Self {
+        IoBudgetMetrics {
+            read_bytes: IO_BUDGET_BYTES.with_label_values(&[class.as_str(), "read", tenant_id]),
+            write_bytes: IO_BUDGET_BYTES.with_label_values(&[class.as_str(), "write", tenant_id]),
+            wait_time: IO_BUDGET_WAIT_TIME.with_label_values(&[class.as_str(), tenant_id]),
+        }
+    }
+}
+
+struct IoBudget {
+    bucket: TokenBucket,
+    /// Bytes per second, 0 means unlimited
+    rate_limit: AtomicU64,
+}
+
+impl IoBudget {
+    fn new(rate_limit: u64) -> Self {
+        IoBudget {
+            bucket: TokenBucket::new(),
+            rate_limit: AtomicU64::new(rate_limit),
+        }
+    }
+
+    /// Take 'size' bytes from the budget, and return how long the caller has
+    /// to wait before doing the I/O.
+    fn acquire(&self, size: u64) -> Duration {
+        let rate_limit = self.rate_limit.load(Ordering::Relaxed);
+        if rate_limit == 0 {
+            // Don't touch the bucket's lock on the I/O path without a limit
+            return Duration::ZERO;
+        }
+        self.bucket
+            .acquire(rate_limit, rate_limit, size, Instant::now())
+    }
+}
+
+lazy_static! {
+    static ref BACKGROUND_IO_BUDGET: IoBudget = IoBudget::new(0);
+    static ref FOREGROUND_IO_BUDGET: IoBudget = IoBudget::new(0);
+}
+
+thread_local! {
+    /// The budget that the reads and writes of the current thread are charged against
+    static THREAD_IO_BUDGET: Cell<Option<&'static IoBudget>> = Cell::new(None);
+    /// The wait for reads charged against THREAD_IO_BUDGET that hasn't been waited out yet
+    static THREAD_IO_DEBT: Cell<Duration> = Cell::new(Duration::ZERO);
+}
+
+///
+/// Charge the I/O of the current thread against the background budget, for
+/// the rest of the thread's life.
+///
+pub fn set_background_io() {
+    THREAD_IO_BUDGET.with(|b| b.set(Some(&BACKGROUND_IO_BUDGET)));
+}
+
+#[cfg(test)]
+fn set_thread_io_budget(budget: &'static IoBudget) {
+    THREAD_IO_BUDGET.with(|b| b.set(Some(budget)));
+}
+
+///
+/// Wait for the reads that the current thread was charged for, but hasn't
+/// waited for yet. Must not be called while holding locks that other
+/// threads need.
+///
+pub fn pay_io_debt() {
+    let debt = THREAD_IO_DEBT.with(|d| d.replace(Duration::ZERO));
+    if !debt.is_zero() {
+        std::thread::sleep(debt);
+    }
+}
+
+///
+/// Charge a page service request that reads about 'size' bytes against the
+/// foreground budget. Returns how long the request has to wait before it
+/// proceeds, which the caller should wait out asynchronously.
+///
+pub fn charge_foreground_io(metrics: &IoBudgetMetrics, size: u64) -> Duration {
+    metrics.read_bytes.inc_by(size);
+    let wait = FOREGROUND_IO_BUDGET.acquire(size);
+    if !wait.is_zero() {
+        metrics.wait_time.observe(wait.as_secs_f64());
+    }
+    wait
+}
+
+///
+/// Set the pageserver-wide I/O budgets, in bytes per second. 0 means
+/// unlimited. Each budget allows a burst of one second's worth of I/O.
+///
+pub fn set_io_rate_limits(background: u64, foreground: u64) {
+    BACKGROUND_IO_BUDGET
+        .rate_limit
+        .store(background, Ordering::Relaxed);
+    FOREGROUND_IO_BUDGET
+        .rate_limit
+        .store(foreground, Ordering::Relaxed);
+}
 
 ///
 /// A virtual file descriptor. You can use this just like std::fs::File, but internally
@@ -87,6 +249,8 @@ pub struct VirtualFile {
     /// For metrics
     tenantid: String,
     timelineid: String,
+    /// Looked up on the first read or write charged against the background budget
+    background_io_metrics: OnceCell<IoBudgetMetrics>,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -260,6 +424,7 @@ impl VirtualFile {
             open_options: reopen_options,
             tenantid,
             timelineid,
+            background_io_metrics: OnceCell::new(),
         };
 
         slot_guard.file.replace(file);
@@ -272,6 +437,35 @@ impl VirtualFile {
         self.with_file("fsync", |file| file.sync_all())?
     }
 
+    /// Charge an I/O of 'size' bytes against the budget of the current
+    /// thread. A write waits until the budget allows it to proceed, along
+    /// with the earlier reads of the thread; a read only adds to the thread's
+    /// debt, as it may be done under the layer map lock.
+    fn charge_io_budget(&self, op: &str, size: usize) {
+        let budget = match THREAD_IO_BUDGET.with(|b| b.get()) {
+            Some(budget) => budget,
+            None => return,
+        };
+        let metrics = self
+            .background_io_metrics
+            .get_or_init(|| IoBudgetMetrics::new(IoClass::Background, &self.tenantid));
+        let counter = if op == "write" {
+            &metrics.write_bytes
+        } else {
+            &metrics.read_bytes
+        };
+        counter.inc_by(size as u64);
+
+        let wait = budget.acquire(size as u64);
+        if !wait.is_zero() {
+            metrics.wait_time.observe(wait.as_secs_f64());
+            THREAD_IO_DEBT.with(|d| d.set(d.get() + wait));
+        }
+        if op == "write" {
+            pay_io_debt();
+        }
+    }
+
     /// Helper function that looks up the underlying File for this VirtualFile,
     /// opening it and evicting some other File if necessary. It calls 'func'
     /// with the physical File.
@@ -428,6 +622,7 @@ impl Seek for VirtualFile {
 
 impl FileExt for VirtualFile {
     fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, Error> {
+        self.charge_io_budget("read", buf.len());
         let result = self.with_file("read", |file| file.read_at(buf, offset))?;
         if let Ok(size) = result {
             STORAGE_IO_SIZE
@@ -438,6 +633,7 @@ impl FileExt for VirtualFile {
     }
 
     fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize, Error> {
+        self.charge_io_budget("write", buf.len());
         let result = self.with_file("write", |file| file.write_at(buf, offset))?;
         if let Ok(size) = result {
             STORAGE_IO_SIZE
@@ -644,6 +840,64 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_io_budget() -> Result<(), Error> {
+        const CHUNK: usize = 10_000;
+
+        let testdir = crate::config::PageServerConf::test_repo_dir("io_budget");
+        std::fs::create_dir_all(&testdir)?;
+        let file = VirtualFile::open_with_options(
+            &testdir.join("io_budget_test_file"),
+            OpenOptions::new().read(true).write(true).create(true),
+        )?;
+
+        // I/O on threads without a budget is not charged against any budget.
+        let charged = |op| {
+            IO_BUDGET_BYTES
+                .with_label_values(&["background", op, "*"])
+                .get()
+        };
+        let written_before = charged("write");
+        file.write_all_at(&[0u8; CHUNK], 0)?;
+        assert_eq!(charged("write"), written_before);
+
+        // Use a budget of our own rather than the global one, so as not to
+        // throttle the background threads of other tests.
+        set_thread_io_budget(Box::leak(Box::new(IoBudget::new(100_000))));
+
+        // The first second's worth of background writes passes right away,
+        // the rest of it has to wait for the budget.
+        let start = Instant::now();
+        for i in 0..15 {
+            file.write_all_at(&[0u8; CHUNK], (i * CHUNK) as u64)?;
+        }
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(400),
+            "background writes were not throttled, took {:?}",
+            elapsed
+        );
+        assert!(charged("write") >= written_before + 15 * CHUNK as u64);
+
+        // Reads don't wait right away, only when the debt is paid.
+        set_thread_io_budget(Box::leak(Box::new(IoBudget::new(100_000))));
+        let start = Instant::now();
+        let mut buf = [0u8; CHUNK];
+        for i in 0..15 {
+            file.read_exact_at(&mut buf, (i * CHUNK) as u64)?;
+        }
+        assert!(start.elapsed() < Duration::from_millis(400));
+        pay_io_debt();
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(400),
+            "background reads were not throttled, took {:?}",
+            elapsed
+        );
+
+        Ok(())
+    }
+
     /// Test using VirtualFiles from many threads concurrently. This tests both using
     /// a lot of VirtualFiles concurrently, causing evictions, and also using the same
     /// VirtualFile from multiple threads concurrently.
@@ -1078,6 +1078,67 @@ mod tests {
         Ok(walingest)
     }
 
+    #[test]
+    fn test_logical_size_breakdown() -> Result<()> {
+        let repo = RepoHarness::create("test_logical_size_breakdown")?.load();
+        let tline = create_test_timeline(repo, TIMELINE_ID)?;
+        let mut walingest = init_walingest_test(&tline)?;
+
+        let testrel_a_fsm = RelTag {
+            forknum: pg_constants::FSM_FORKNUM,
+            ..TESTREL_A
+        };
+        let testrel_b = RelTag {
+            relnode: 1001,
+            ..TESTREL_A
+        };
+
+        let mut m = tline.begin_modification(Lsn(0x20));
+        walingest.put_rel_creation(&mut m, TESTREL_A)?;
+        walingest.put_rel_creation(&mut m, testrel_a_fsm)?;
+        for blknum in 0..3 {
+            walingest.put_rel_page_image(&mut m, TESTREL_A, blknum, TEST_IMG("foo"))?;
+        }
+        walingest.put_rel_page_image(&mut m, testrel_a_fsm, 0, TEST_IMG("fsm"))?;
+        m.commit()?;
+        let mut m = tline.begin_modification(Lsn(0x30));
+        walingest.put_rel_creation(&mut m, testrel_b)?;
+        walingest.put_rel_page_image(&mut m, testrel_b, 1, TEST_IMG("bar"))?;
+        m.commit()?;
+
+        let blcksz = pg_constants::BLCKSZ as u64;
+        let rel_size = |rel: RelTag, nblocks: u64| RelLogicalSize {
+            relnode: rel.relnode,
+            forknum: rel.forknum,
+            size: nblocks * blcksz,
+        };
+        assert_eq!(
+            tline.get_logical_size_breakdown(Lsn(0x20))?,
+            vec![DbLogicalSize {
+                spcnode: 0,
+                dbnode: 111,
+                size: 4 * blcksz,
+                relations: vec![rel_size(TESTREL_A, 3), rel_size(testrel_a_fsm, 1)],
+            }]
+        );
+        let breakdown = tline.get_logical_size_breakdown(Lsn(0x30))?;
+        assert_eq!(
+            breakdown[0].relations,
+            vec![
+                rel_size(TESTREL_A, 3),
+                rel_size(testrel_a_fsm, 1),
+                rel_size(testrel_b, 2)
+            ]
+        );
+        assert_eq!(breakdown[0].size, 6 * blcksz);
+        assert_eq!(
+            breakdown[0].size as usize,
+            tline.get_current_logical_size_non_incremental(Lsn(0x30))?
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_relsize() -> Result<()> {
         let repo = RepoHarness::create("test_relsize")?.load();
@@ -1397,4 +1458,33 @@ mod tests {
 
         Ok(())
     }
+
+    /// Test that a bulk modification that flushes its pages in batches
+    /// ends up with the same contents as one committed at once.
+    #[test]
+    fn test_flush_modification() -> Result<()> {
+        let repo = RepoHarness::create("test_flush_modification")?.load();
+        let tline = create_test_timeline(repo, TIMELINE_ID)?;
+
+        let nblocks = pg_constants::RELSEG_SIZE / 4;
+        let mut m = tline.begin_modification(Lsn(0x20));
+        m.put_rel_creation(TESTREL_A, nblocks)?;
+        for blknum in 0..nblocks {
+            let img = TEST_IMG(&format!("foo blk {} at {}", blknum, Lsn(0x20)));
+            m.put_rel_page_image(TESTREL_A, blknum, img)?;
+            m.flush()?;
+        }
+        m.commit()?;
+
+        assert_eq!(tline.get_rel_size(TESTREL_A, Lsn(0x20))?, nblocks);
+        for blknum in [0, nblocks / 2, nblocks - 1] {
+            assert_eq!(
+                tline.get_rel_page_at_lsn(TESTREL_A, blknum, Lsn(0x20))?,
+                TEST_IMG(&format!("foo blk {} at {}", blknum, Lsn(0x20)))
+            );
+        }
+        assert_current_logical_size(&tline, Lsn(0x20));
+
+        Ok(())
+    }
 }
@@ -1,5 +1,11 @@
 use serde::{Deserialize, Serialize};
-use utils::zid::{NodeId, ZTenantId, ZTimelineId};
+use serde_with::{serde_as, DisplayFromStr};
+use utils::{
+    lsn::Lsn,
+    zid::{NodeId, ZTenantId, ZTimelineId},
+};
+
+use crate::safekeeper::Term;
 
 #[derive(Serialize, Deserialize)]
 pub struct TimelineCreateRequest {
@@ -7,3 +13,22 @@ pub struct TimelineCreateRequest {
     pub timeline_id: ZTimelineId,
     pub peer_ids: Vec<NodeId>,
 }
+
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+pub struct TimelineResetRequest {
+    #[serde_as(as = "DisplayFromStr")]
+    pub lsn: Lsn,
+    /// The term to start at `lsn`, must be higher than the current term of
+    /// the safekeeper
+    pub term: Term,
+}
+
+#[serde_as]
+#[derive(Serialize, Deserialize)]
+pub struct TimelineResetResponse {
+    /// Start of the last WAL record before the reset LSN, None if there was no
+    /// WAL after the reset LSN
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub prev_record_lsn: Option<Lsn>,
+}
@@ -23,7 +23,7 @@ use utils::{
     zid::{NodeId, ZTenantId, ZTenantTimelineId, ZTimelineId},
 };
 
-use super::models::TimelineCreateRequest;
+use super::models::{TimelineCreateRequest, TimelineResetRequest, TimelineResetResponse};
 
 #[derive(Debug, Serialize)]
 struct SafekeeperStatus {
@@ -131,6 +131,23 @@ async fn timeline_create_handler(mut request: Request<Body>) -> Result<Response<
     json_response(StatusCode::CREATED, ())
 }
 
+/// Truncates the WAL of the timeline at the given LSN, and starts a new term
+/// there, so that the WAL after it is never streamed to anyone again.
+async fn timeline_reset_handler(mut request: Request<Body>) -> Result<Response<Body>, ApiError> {
+    let zttid = ZTenantTimelineId::new(
+        parse_request_param(&request, "tenant_id")?,
+        parse_request_param(&request, "timeline_id")?,
+    );
+    let request_data: TimelineResetRequest = json_request(&mut request).await?;
+
+    let tli = GlobalTimelines::get(get_conf(&request), zttid, false).map_err(ApiError::from_err)?;
+    let prev_record_lsn = tli
+        .reset_to(request_data.lsn, request_data.term)
+        .map_err(|e| ApiError::BadRequest(format!("{:#}", e)))?;
+
+    json_response(StatusCode::OK, TimelineResetResponse { prev_record_lsn })
+}
+
 /// Deactivates the timeline and removes its data directory.
 ///
 /// It does not try to stop any processing of the timeline; there is no such code at the time of writing.
@@ -202,6 +219,10 @@ pub fn make_router(conf: SafeKeeperConf) -> RouterBuilder<hyper::Body, ApiError>
             "/v1/tenant/:tenant_id/timeline/:timeline_id",
             timeline_delete_force_handler,
         )
+        .post(
+            "/v1/tenant/:tenant_id/timeline/:timeline_id/reset",
+            timeline_reset_handler,
+        )
         .delete("/v1/tenant/:tenant_id", tenant_delete_force_handler)
         // for tests
         .post(
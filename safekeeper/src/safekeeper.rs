@@ -924,6 +924,63 @@ where
         Ok(())
     }
 
+    /// Truncate WAL at the given LSN, forgetting everything after it, as if
+    /// it had never been written. Used to reset a timeline to an earlier LSN.
+    ///
+    /// To make sure the truncated WAL is never streamed again, we start the
+    /// new term `term` at the reset point: the computes of older terms can't
+    /// append to the WAL anymore, and the new term wins the epoch comparison
+    /// against safekeepers that still have the old WAL, so no proposer
+    /// recovers it from them. When resetting several safekeepers, the caller
+    /// must pass the same term to all of them, higher than any of their
+    /// current terms.
+    pub fn reset_to(&mut self, lsn: Lsn, term: Term) -> Result<()> {
+        let wal_seg_size = self.state.server.wal_seg_size as usize;
+        if wal_seg_size == 0 {
+            bail!("timeline has no WAL");
+        }
+        if term <= self.state.acceptor_state.term {
+            bail!(
+                "cannot reset in term {}, the current term is {}",
+                term,
+                self.state.acceptor_state.term
+            );
+        }
+        let flush_lsn = self.flush_lsn();
+        if lsn > flush_lsn {
+            bail!("cannot reset to {}, WAL ends at {}", lsn, flush_lsn);
+        }
+        if lsn < self.state.local_start_lsn {
+            bail!(
+                "cannot reset to {}, local WAL starts at {}",
+                lsn,
+                self.state.local_start_lsn
+            );
+        }
+
+        self.wal_store.truncate_wal(lsn)?;
+
+        let mut state = self.state.clone();
+        let mut term_history = state.acceptor_state.term_history.up_to(lsn);
+        term_history.0.push(TermSwitchEntry { term, lsn });
+        state.acceptor_state.term = term;
+        state.acceptor_state.term_history = term_history;
+
+        self.global_commit_lsn = min(self.global_commit_lsn, lsn);
+        self.inmem.commit_lsn = min(self.inmem.commit_lsn, lsn);
+        self.metrics.commit_lsn.set(self.inmem.commit_lsn.0 as f64);
+        self.inmem.peer_horizon_lsn = min(self.inmem.peer_horizon_lsn, lsn);
+        self.inmem.remote_consistent_lsn = min(self.inmem.remote_consistent_lsn, lsn);
+        // WAL is offloaded in whole segments, so the segment with the reset
+        // point has to be offloaded again.
+        let segment_start = Lsn(lsn.0 - lsn.segment_offset(wal_seg_size) as u64);
+        self.inmem.backup_lsn = min(self.inmem.backup_lsn, segment_start);
+        self.epoch_start_lsn = Lsn(0);
+
+        info!("reset WAL to {}, starting term {}", lsn, term);
+        self.persist_control_file(state)
+    }
+
     /// Get oldest segno we still need to keep. We hold WAL till it is consumed
     /// by all of 1) pageserver (remote_consistent_lsn) 2) peers 3) s3
     /// offloading.
@@ -1086,4 +1143,66 @@ mod tests {
         sk.wal_store.truncate_wal(Lsn(3)).unwrap(); // imitate the complete record at 3 %)
         assert_eq!(sk.get_epoch(), 1);
     }
+
+    #[test]
+    fn test_reset_to() {
+        let mut state = SafeKeeperState::empty();
+        state.server.wal_seg_size = 0x100;
+        state.acceptor_state.term = 1;
+        state.acceptor_state.term_history = TermHistory(vec![TermSwitchEntry {
+            term: 1,
+            lsn: Lsn(0x10),
+        }]);
+        state.commit_lsn = Lsn(0x280);
+        state.backup_lsn = Lsn(0x200);
+        let storage = InMemoryState {
+            persisted_state: state,
+        };
+        let wal_store = DummyWalStore { lsn: Lsn(0x280) };
+        let ztli = ZTimelineId::from([0u8; 16]);
+
+        let mut sk = SafeKeeper::new(ztli, storage, wal_store, NodeId(0)).unwrap();
+
+        // can't reset beyond the end of WAL, or without a new term
+        assert!(sk.reset_to(Lsn(0x300), 2).is_err());
+        assert!(sk.reset_to(Lsn(0x180), 1).is_err());
+
+        sk.reset_to(Lsn(0x180), 2).unwrap();
+        assert_eq!(sk.wal_store.flush_lsn(), Lsn(0x180));
+        assert_eq!(sk.inmem.commit_lsn, Lsn(0x180));
+        // the segment with the reset point is offloaded again
+        assert_eq!(sk.inmem.backup_lsn, Lsn(0x100));
+        // a new term starts at the reset point, and it is persisted
+        assert_eq!(sk.state.persisted_state.acceptor_state.term, 2);
+        assert_eq!(sk.state.persisted_state.commit_lsn, Lsn(0x180));
+        assert_eq!(sk.get_epoch(), 2);
+        let history = sk.get_term_history();
+        assert_eq!(
+            history
+                .0
+                .iter()
+                .map(|e| (e.term, e.lsn))
+                .collect::<Vec<_>>(),
+            vec![(1, Lsn(0x10)), (2, Lsn(0x180))]
+        );
+
+        // the proposer of the old term can't append anymore
+        let append_request = AppendRequest {
+            h: AppendRequestHeader {
+                term: 1,
+                epoch_start_lsn: Lsn(0x10),
+                begin_lsn: Lsn(0x180),
+                end_lsn: Lsn(0x181),
+                commit_lsn: Lsn(0),
+                truncate_lsn: Lsn(0),
+                proposer_uuid: [0; 16],
+            },
+            wal_data: Bytes::from_static(b"b"),
+        };
+        match sk.process_msg(&ProposerAcceptorMessage::AppendRequest(append_request)) {
+            Ok(Some(AcceptorProposerMessage::AppendResponse(resp))) => assert_eq!(resp.term, 2),
+            r => panic!("unexpected response: {:?}", r),
+        }
+        assert_eq!(sk.wal_store.flush_lsn(), Lsn(0x180));
+    }
 }
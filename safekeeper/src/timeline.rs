@@ -29,7 +29,7 @@ use crate::callmemaybe::{CallmeEvent, SubscriptionStateKey};
 use crate::control_file;
 use crate::safekeeper::{
     AcceptorProposerMessage, ProposerAcceptorMessage, SafeKeeper, SafeKeeperState,
-    SafekeeperMemState,
+    SafekeeperMemState, Term,
 };
 use crate::send_wal::HotStandbyFeedback;
 
@@ -515,6 +515,49 @@ impl Timeline {
         Ok(rmsg)
     }
 
+    /// Reset the timeline to an earlier LSN, removing all WAL after it, and
+    /// start the new term `term` there. Fails if a compute is connected, as
+    /// it would keep writing on top of the removed WAL, or if `lsn` is not a
+    /// WAL record boundary.
+    ///
+    /// Returns the start of the last record before the reset point, if it
+    /// could be read from the removed WAL. The pageserver needs it to start
+    /// computes at the reset point.
+    pub fn reset_to(&self, lsn: Lsn, term: Term) -> Result<Option<Lsn>> {
+        let commit_lsn: Lsn;
+        let prev_record_lsn: Option<Lsn>;
+        {
+            let mut shared_state = self.mutex.lock().unwrap();
+            if shared_state.num_computes > 0 {
+                bail!(
+                    "cannot reset timeline with {} connected computes",
+                    shared_state.num_computes
+                );
+            }
+            let wal_seg_size = shared_state.get_wal_seg_size();
+            if wal_seg_size != 0
+                && lsn.segment_number(wal_seg_size) < shared_state.last_removed_segno
+            {
+                bail!("cannot reset to {}, its WAL segment was removed", lsn);
+            }
+            // The WAL before the first segment that is still present can't
+            // be read anymore
+            let wal_start_lsn = max(
+                shared_state.sk.state.local_start_lsn,
+                Lsn(shared_state.last_removed_segno * wal_seg_size as u64),
+            );
+            prev_record_lsn = shared_state
+                .sk
+                .wal_store
+                .read_prev_record_lsn(lsn, wal_start_lsn)?;
+            shared_state.sk.reset_to(lsn, term)?;
+            shared_state.notified_commit_lsn = min(shared_state.notified_commit_lsn, lsn);
+            commit_lsn = shared_state.sk.inmem.commit_lsn;
+        }
+        self.commit_lsn_watch_tx.send(commit_lsn)?;
+        Ok(prev_record_lsn)
+    }
+
     pub fn get_wal_seg_size(&self) -> usize {
         self.mutex.lock().unwrap().get_wal_seg_size()
     }
@@ -239,6 +239,10 @@ impl WalBackupTask {
 
                 let commit_lsn = *self.commit_lsn_watch_rx.borrow();
 
+                // If the timeline was reset to an earlier LSN, the segments
+                // after the reset point have to be offloaded again.
+                backup_lsn = min(backup_lsn, self.timeline.get_wal_backup_lsn());
+
                 // Note that backup_lsn can be higher than commit_lsn if we
                 // don't have much local WAL and others already uploaded
                 // segments we don't even have.
@@ -8,6 +8,7 @@
 //! Note that last file has `.partial` suffix, that's different from postgres.
 
 use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
 use std::io::{Read, Seek, SeekFrom};
 
 use lazy_static::lazy_static;
@@ -27,9 +28,10 @@ use utils::{lsn::Lsn, zid::ZTenantTimelineId};
 use crate::safekeeper::SafeKeeperState;
 
 use crate::SafeKeeperConf;
-use postgres_ffi::xlog_utils::{XLogFileName, XLOG_BLCKSZ};
+use postgres_ffi::xlog_utils::{XLogFileName, XLOG_BLCKSZ, XLOG_SIZE_OF_XLOG_RECORD};
 
 use postgres_ffi::waldecoder::WalStreamDecoder;
+use postgres_ffi::XLogRecord;
 
 use metrics::{
     register_gauge_vec, register_histogram_vec, Gauge, GaugeVec, Histogram, HistogramVec,
@@ -194,6 +196,66 @@ impl PhysicalStorage {
         Ok(())
     }
 
+    /// Read the start of the record preceding the record boundary `lsn`, from
+    /// the xl_prev field of the record that starts at `lsn`. Returns None if
+    /// there is no complete record after `lsn`.
+    ///
+    /// Fails if `lsn` is not a record boundary: the record at `lsn` must
+    /// decode, and the record it points back to must end exactly at `lsn`.
+    /// The latter is only checked if the WAL of the previous record is still
+    /// present, that is, if it starts at or after `wal_start_lsn`.
+    pub fn read_prev_record_lsn(&self, lsn: Lsn, wal_start_lsn: Lsn) -> Result<Option<Lsn>> {
+        if lsn >= self.flush_record_lsn {
+            return Ok(None);
+        }
+
+        let (_, rec) = self
+            .read_record(lsn)
+            .with_context(|| format!("{} is not a WAL record boundary", lsn))?;
+        let prev_lsn = Lsn(XLogRecord::from_slice(&rec[..XLOG_SIZE_OF_XLOG_RECORD])?.xl_prev);
+        if prev_lsn >= wal_start_lsn {
+            let (prev_end_lsn, _) = self
+                .read_record(prev_lsn)
+                .with_context(|| format!("failed to read the WAL record at {}", prev_lsn))?;
+            if prev_end_lsn != lsn {
+                bail!(
+                    "{} is not a WAL record boundary, the record at {} ends at {}",
+                    lsn,
+                    prev_lsn,
+                    prev_end_lsn
+                );
+            }
+        }
+        Ok(Some(prev_lsn))
+    }
+
+    /// Decode the record that starts at `lsn`, returning its aligned end LSN
+    /// and the record itself. Fails if there is no complete record there.
+    fn read_record(&self, lsn: Lsn) -> Result<(Lsn, Bytes)> {
+        let wal_seg_size = self
+            .wal_seg_size
+            .ok_or_else(|| anyhow!("wal_seg_size is not initialized"))?;
+
+        let mut reader = WalReader::new(self.timeline_dir.clone(), wal_seg_size, lsn);
+        let mut decoder = WalStreamDecoder::new(lsn);
+        let mut buf = vec![0u8; XLOG_BLCKSZ];
+        let mut pos = lsn;
+        while pos < self.flush_record_lsn {
+            let len = min(buf.len() as u64, self.flush_record_lsn.0 - pos.0) as usize;
+            let read = reader.read(&mut buf[..len])?;
+            pos += read as u64;
+            decoder.feed_bytes(&buf[..read]);
+            if let Some(record) = decoder.poll_decode()? {
+                return Ok(record);
+            }
+        }
+        bail!(
+            "no complete WAL record at {}, WAL ends at {}",
+            lsn,
+            self.flush_record_lsn
+        )
+    }
+
     /// Open or create WAL segment file. Caller must call seek to the wanted position.
     /// Returns `file` and `is_partial`.
     fn open_or_create(&self, segno: XLogSegNo, wal_seg_size: usize) -> Result<(File, bool)> {
@@ -0,0 +1,45 @@
+import io
+import tarfile
+from contextlib import closing
+from typing import List
+
+from fixtures.zenith_fixtures import ZenithEnv
+from fixtures.log_helper import log
+
+
+def basebackup_members(env: ZenithEnv, timeline: str, options: str = '') -> List[str]:
+    buf = io.BytesIO()
+    with closing(env.pageserver.connect()) as psconn:
+        with psconn.cursor() as pscur:
+            pscur.copy_expert(f"basebackup {env.initial_tenant.hex} {timeline} {options}", buf)
+    buf.seek(0)
+    with tarfile.open(fileobj=buf) as tar:
+        return tar.getnames()
+
+
+#
+# Test that 'basebackup --no-slru' leaves the SLRU segments out of the tarball,
+# but still creates the directories for them.
+#
+def test_basebackup_no_slru(zenith_simple_env: ZenithEnv):
+    env = zenith_simple_env
+    env.zenith_cli.create_branch("test_basebackup_no_slru", "empty")
+    pg = env.postgres.create_start('test_basebackup_no_slru')
+    log.info("postgres is running on 'test_basebackup_no_slru' branch")
+
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE foo (x integer)")
+            cur.execute("INSERT INTO foo SELECT generate_series(1, 1000)")
+            cur.execute("SHOW neon.timeline_id")
+            timeline = cur.fetchone()[0]
+
+    slru_dirs = ('pg_xact/', 'pg_multixact/offsets/', 'pg_multixact/members/')
+
+    members = basebackup_members(env, timeline)
+    assert any(name.startswith('pg_xact/') for name in members)
+
+    members = basebackup_members(env, timeline, '--no-slru')
+    assert not any(name.startswith(slru_dirs) for name in members)
+    assert 'pg_xact' in members
+    assert 'global/pg_control' in members
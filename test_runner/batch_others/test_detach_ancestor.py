@@ -0,0 +1,71 @@
+from contextlib import closing
+
+import pytest
+from fixtures.log_helper import log
+from fixtures.utils import lsn_from_hex
+from fixtures.zenith_fixtures import (ZenithEnvBuilder,
+                                      ZenithPageserverApiException,
+                                      wait_for_last_record_lsn)
+
+
+#
+# Check that a branch detached from its ancestor keeps working after the
+# ancestor is deleted.
+#
+def test_detach_ancestor(zenith_env_builder: ZenithEnvBuilder):
+    env = zenith_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    client = env.pageserver.http_client()
+
+    root_timeline_id = env.zenith_cli.create_timeline('test_detach_ancestor_root')
+    parent_timeline_id = env.zenith_cli.create_branch('test_detach_ancestor_parent')
+    pg_parent = env.postgres.create_start('test_detach_ancestor_parent')
+    with closing(pg_parent.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE foo (i int, t text)")
+            cur.execute("INSERT INTO foo SELECT g, 'long string to consume some space' || g "
+                        "FROM generate_series(1, 10000) g")
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            current_lsn = lsn_from_hex(cur.fetchone()[0])
+    wait_for_last_record_lsn(client, tenant_id, parent_timeline_id, current_lsn)
+
+    child_timeline_id = env.zenith_cli.create_branch('test_detach_ancestor_child',
+                                                     'test_detach_ancestor_parent')
+    pg_child = env.postgres.create_start('test_detach_ancestor_child')
+    with closing(pg_child.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("INSERT INTO foo SELECT g, 'child' FROM generate_series(10001, 10100) g")
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            current_lsn = lsn_from_hex(cur.fetchone()[0])
+    wait_for_last_record_lsn(client, tenant_id, child_timeline_id, current_lsn)
+
+    # Changes in the parent after the branch point are not visible in the child
+    with closing(pg_parent.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("DELETE FROM foo")
+    pg_parent.stop()
+
+    # Root timelines have no ancestor to detach from
+    with pytest.raises(ZenithPageserverApiException, match='has no ancestor'):
+        client.timeline_detach_ancestor(tenant_id, root_timeline_id)
+
+    client.timeline_detach_ancestor(tenant_id, child_timeline_id)
+    detail = client.timeline_detail(tenant_id, child_timeline_id)
+    log.info(f"detached timeline: {detail}")
+    assert detail['local']['ancestor_timeline_id'] is None
+
+    # The parent has no children anymore, and can be deleted
+    client.timeline_delete(tenant_id, parent_timeline_id)
+
+    # The child has all its data, also after a restart
+    pg_child.stop()
+    env.pageserver.stop()
+    env.pageserver.start()
+    pg_child.start()
+    with closing(pg_child.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("SELECT count(*) FROM foo")
+            assert cur.fetchone() == (10100, )
+            cur.execute("INSERT INTO foo SELECT g, 'new' FROM generate_series(10101, 10200) g")
+            cur.execute("SELECT count(*) FROM foo")
+            assert cur.fetchone() == (10200, )
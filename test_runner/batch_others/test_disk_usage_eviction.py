@@ -0,0 +1,72 @@
+from contextlib import closing
+from pathlib import Path
+from uuid import UUID
+
+from fixtures.zenith_fixtures import ZenithEnvBuilder, wait_until, wait_for_last_record_lsn, wait_for_upload
+from fixtures.log_helper import log
+from fixtures.metrics import parse_metrics
+from fixtures.utils import lsn_from_hex
+
+
+#
+# Set the eviction threshold so low that it's always exceeded, and check that
+# the uploaded layer files are evicted, and downloaded back when they're read.
+#
+def test_disk_usage_eviction(zenith_env_builder: ZenithEnvBuilder):
+    zenith_env_builder.enable_local_fs_remote_storage()
+    zenith_env_builder.pageserver_config_override = "disk_usage_eviction_threshold=1\ndisk_usage_eviction_period='1 s'"
+    env = zenith_env_builder.init_start()
+
+    # Disable background compaction and gc, so that the layer files stay put.
+    tenant, _ = env.zenith_cli.create_tenant(conf={
+        'gc_period': '10 m',
+        'compaction_period': '10 m',
+    })
+    env.zenith_cli.create_timeline('test_disk_usage_eviction', tenant_id=tenant)
+    pg = env.postgres.create_start('test_disk_usage_eviction', tenant_id=tenant)
+    client = env.pageserver.http_client()
+
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("SHOW neon.timeline_id")
+            timeline = UUID(cur.fetchone()[0])
+            cur.execute("CREATE TABLE foo (t text)")
+            cur.execute('''
+                INSERT INTO foo
+                    SELECT 'long string to consume some space' || g
+                    FROM generate_series(1, 100000) g
+            ''')
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            current_lsn = lsn_from_hex(cur.fetchone()[0])
+
+    wait_for_last_record_lsn(client, tenant, timeline, current_lsn)
+    env.pageserver.safe_psql(f"checkpoint {tenant.hex} {timeline.hex}")
+    wait_for_upload(client, tenant, timeline, current_lsn)
+    pg.stop()
+
+    timeline_dir = Path(env.repo_dir) / 'tenants' / tenant.hex / 'timelines' / timeline.hex
+
+    def layers_evicted():
+        evictions = client.disk_usage_eviction()['recent_evictions']
+        evicted = [e for e in evictions if e['timeline_id'] == timeline.hex]
+        assert len(evicted) > 0
+        for e in evicted:
+            assert not (timeline_dir / e['layer_file_name']).exists()
+        return evicted
+
+    evicted = wait_until(30, 1, layers_evicted)
+    log.info(f'evicted layers: {evicted}')
+
+    status = client.disk_usage_eviction()
+    assert status['disk_usage']['used_bytes'] > 0
+    assert status['evicted_bytes'] > 0
+
+    metrics = parse_metrics(client.get_metrics(), 'pageserver')
+    assert int(metrics.query_one('pageserver_evicted_layers_total').value) >= len(evicted)
+
+    # The evicted layers are downloaded back when they're read
+    pg.start()
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("SELECT count(*), sum(length(t)) FROM foo")
+            assert cur.fetchone() == (100000, 3788895)
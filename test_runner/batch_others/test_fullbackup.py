@@ -0,0 +1,67 @@
+import io
+import os
+import tarfile
+import uuid
+from contextlib import closing
+
+from fixtures.zenith_fixtures import PgBin, PortDistributor, VanillaPostgres, ZenithEnv
+from fixtures.log_helper import log
+
+
+#
+# Test that a vanilla Postgres can start on the tarball from 'fullbackup',
+# without the page server, and sees the data of the timeline at the
+# requested LSN.
+#
+def test_fullbackup(zenith_simple_env: ZenithEnv,
+                    pg_bin: PgBin,
+                    port_distributor: PortDistributor,
+                    test_output_dir: str):
+    env = zenith_simple_env
+    env.zenith_cli.create_branch("test_fullbackup", "empty")
+    pg = env.postgres.create_start('test_fullbackup')
+    log.info("postgres is running on 'test_fullbackup' branch")
+
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("SHOW neon.timeline_id")
+            timeline = cur.fetchone()[0]
+
+            # Large enough that the relation doesn't fit in the buffer cache,
+            # and some pages have to be reconstructed by the page server.
+            cur.execute("CREATE TABLE foo (x integer, t text)")
+            cur.execute("INSERT INTO foo SELECT g, 'long string to consume some space' || g "
+                        "FROM generate_series(1, 100000) g")
+            cur.execute("CREATE INDEX ON foo (x)")
+            cur.execute("SELECT pg_current_wal_insert_lsn()")
+            lsn = cur.fetchone()[0]
+
+            # Not included in the backup
+            cur.execute("INSERT INTO foo VALUES (-1, 'after backup LSN')")
+
+    # Take the backup through the page service command
+    buf = io.BytesIO()
+    with closing(env.pageserver.connect()) as psconn:
+        with psconn.cursor() as pscur:
+            pscur.copy_expert(f"fullbackup {env.initial_tenant.hex} {timeline} {lsn}", buf)
+
+    # ... and through the HTTP API
+    http_backup = env.pageserver.http_client().timeline_fullbackup(env.initial_tenant,
+                                                                   uuid.UUID(timeline),
+                                                                   lsn)
+
+    for (name, backup) in [('psql', buf.getvalue()), ('http', http_backup)]:
+        restored_dir = os.path.join(test_output_dir, f"restored_{name}")
+        os.mkdir(restored_dir, 0o700)
+        with tarfile.open(fileobj=io.BytesIO(backup)) as tar:
+            tar.extractall(restored_dir)
+
+        port = port_distributor.get_port()
+        with VanillaPostgres(restored_dir, pg_bin, port, init=False) as vanilla_pg:
+            vanilla_pg.configure([f"port={port}"])
+            vanilla_pg.start()
+            # The cluster was initialized by the page server, with its superuser
+            res = vanilla_pg.safe_psql("SELECT count(*), sum(x) FROM foo", user='cloud_admin')
+            assert res == [(100000, 5000050000)]
+            res = vanilla_pg.safe_psql("SELECT t FROM foo WHERE x = 4242", user='cloud_admin')
+            assert res == [('long string to consume some space4242', )]
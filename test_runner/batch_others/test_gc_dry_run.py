@@ -0,0 +1,86 @@
+from contextlib import closing
+from uuid import UUID
+
+import psycopg2.extras
+from fixtures.log_helper import log
+from fixtures.zenith_fixtures import ZenithEnvBuilder
+
+KEEP_REASONS = {'Horizon', 'Pitr', 'BranchPoint', 'NewerImageMissing', 'Latest'}
+
+
+#
+# Check that a GC dry run reports the layer files without removing any, and
+# that a real GC run removes the ones it reported as removable.
+#
+def test_gc_dry_run(zenith_env_builder: ZenithEnvBuilder):
+    # Disable PITR, and the background GC and compaction, so that the layers
+    # only change when the test asks for it
+    zenith_env_builder.pageserver_config_override = \
+        "tenant_config={pitr_interval = '0 sec', gc_period = '10 m', compaction_period = '10 m'}"
+    env = zenith_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    client = env.pageserver.http_client()
+
+    pg = env.postgres.create_start('main')
+    timeline_id = UUID(pg.safe_psql("show neon.timeline_id")[0][0])
+
+    # Create a few generations of layers, and let compaction create image
+    # layers on top of them
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE foo (t text)")
+            for _ in range(5):
+                cur.execute("INSERT INTO foo SELECT 'long string to consume some space' || g "
+                            "FROM generate_series(1, 10000) g")
+                cur.execute("UPDATE foo SET t = t || 'x'")
+                env.pageserver.safe_psql(f"checkpoint {tenant_id.hex} {timeline_id.hex}")
+    pg.stop()
+
+    timeline_dir = env.repo_dir / 'tenants' / tenant_id.hex / 'timelines' / timeline_id.hex
+
+    def layer_files():
+        # Layer file names look like <key range>__<lsn range>
+        return {p.name for p in timeline_dir.iterdir() if '__' in p.name}
+
+    # With a horizon longer than the timeline, everything is needed by the horizon
+    report = client.timeline_gc_dry_run(tenant_id, timeline_id, gc_horizon=1 << 40)
+    log.info(f"dry run with a long gc_horizon: {report}")
+    assert report['layers_total'] == len(report['layers']) > 0
+    assert report['layers_to_remove'] == 0
+    assert all(layer['keep_reason'] == 'Horizon' for layer in report['layers'])
+
+    # With no horizon at all, only the latest data has to be kept
+    files_before = layer_files()
+    report = client.timeline_gc_dry_run(tenant_id, timeline_id, gc_horizon=0)
+    log.info(f"dry run with zero gc_horizon: {report}")
+    assert report['layers_total'] == len(report['layers'])
+    assert {layer['keep_reason'] for layer in report['layers']} <= KEEP_REASONS | {None}
+    assert report['layers_to_remove'] == sum(1 for layer in report['layers']
+                                             if layer['keep_reason'] is None)
+    to_remove = {
+        layer['layer_file_name']
+        for layer in report['layers'] if layer['keep_reason'] is None
+    }
+    assert to_remove <= files_before
+
+    # Nothing was removed
+    assert layer_files() == files_before
+
+    with closing(env.pageserver.connect()) as psconn:
+        with psconn.cursor(cursor_factory=psycopg2.extras.DictCursor) as pscur:
+            # The page service command reports the same layers
+            pscur.execute(f"do_gc {tenant_id.hex} {timeline_id.hex} 0 --dry-run")
+            rows = pscur.fetchall()
+            assert {row['layer']
+                    for row in rows} == {layer['layer_file_name']
+                                         for layer in report['layers']}
+            assert {row['layer'] for row in rows if row['keep_reason'] is None} == to_remove
+            assert layer_files() == files_before
+
+            # A real GC run removes the layers that the dry run reported
+            pscur.execute(f"do_gc {tenant_id.hex} {timeline_id.hex} 0")
+            row = pscur.fetchone()
+            log.info(f"GC result: {dict(row)}")
+            assert row['layers_removed'] >= len(to_remove)
+
+    assert not (to_remove & layer_files())
@@ -0,0 +1,98 @@
+import io
+import json
+import os
+import tarfile
+import uuid
+from contextlib import closing
+
+import pytest
+
+from fixtures.zenith_fixtures import PgBin, PortDistributor, VanillaPostgres, ZenithEnv
+from fixtures.log_helper import log
+from fixtures.utils import lsn_from_hex, lsn_to_hex
+
+
+#
+# Test importing a pg_basebackup tarball of a vanilla Postgres cluster into
+# a new timeline.
+#
+# The imported timeline is checked by exporting it again with 'fullbackup',
+# and starting a vanilla Postgres on that.
+#
+def test_import_from_vanilla(zenith_simple_env: ZenithEnv,
+                             pg_bin: PgBin,
+                             port_distributor: PortDistributor,
+                             test_output_dir: str):
+    env = zenith_simple_env
+
+    # Create a vanilla Postgres cluster with some data
+    source_port = port_distributor.get_port()
+    source_dir = os.path.join(test_output_dir, "source")
+    with VanillaPostgres(source_dir, pg_bin, source_port) as vanilla_pg:
+        vanilla_pg.configure([f"port={source_port}"])
+        vanilla_pg.start()
+        vanilla_pg.safe_psql("CREATE TABLE foo (x integer, t text)")
+        vanilla_pg.safe_psql("INSERT INTO foo SELECT g, 'long string to consume some space' || g "
+                             "FROM generate_series(1, 100000) g")
+        vanilla_pg.safe_psql("CREATE INDEX ON foo (x)")
+
+        # Take a tar format backup, with the WAL needed to make it consistent
+        backup_dir = os.path.join(test_output_dir, "backup")
+        pg_bin.run([
+            'pg_basebackup',
+            '--format=tar',
+            '--wal-method=fetch',
+            '-D',
+            backup_dir,
+            '-h',
+            'localhost',
+            '-p',
+            str(source_port),
+        ])
+
+    # The start and end of the WAL needed are recorded in the backup manifest
+    with open(os.path.join(backup_dir, "backup_manifest")) as f:
+        wal_range = json.load(f)["WAL-Ranges"][0]
+    start_lsn = wal_range["Start-LSN"]
+    end_lsn = wal_range["End-LSN"]
+    log.info(f"backup WAL range {start_lsn} - {end_lsn}")
+
+    tenant = env.initial_tenant
+    timeline = uuid.uuid4()
+
+    with closing(env.pageserver.connect()) as psconn:
+        with psconn.cursor() as pscur:
+            # An end LSN in the middle of a record is rejected, and the
+            # timeline is not created
+            bad_end_lsn = lsn_to_hex(lsn_from_hex(end_lsn) - 1)
+            with pytest.raises(Exception, match='is not at a WAL record boundary'):
+                with open(os.path.join(backup_dir, "base.tar"), "rb") as f:
+                    cmd = f"import basebackup {tenant.hex} {timeline.hex} {start_lsn} {bad_end_lsn}"
+                    pscur.copy_expert(cmd, f)
+
+            with open(os.path.join(backup_dir, "base.tar"), "rb") as f:
+                pscur.copy_expert(
+                    f"import basebackup {tenant.hex} {timeline.hex} {start_lsn} {end_lsn}", f)
+
+    detail = env.pageserver.http_client().timeline_detail(tenant, timeline)
+    assert detail['local']['last_record_lsn'] == end_lsn
+
+    # Export the imported timeline, and check the data
+    buf = io.BytesIO()
+    with closing(env.pageserver.connect()) as psconn:
+        with psconn.cursor() as pscur:
+            pscur.copy_expert(f"fullbackup {tenant.hex} {timeline.hex}", buf)
+
+    restored_dir = os.path.join(test_output_dir, "restored")
+    os.mkdir(restored_dir, 0o700)
+    with tarfile.open(fileobj=io.BytesIO(buf.getvalue())) as tar:
+        tar.extractall(restored_dir)
+
+    port = port_distributor.get_port()
+    with VanillaPostgres(restored_dir, pg_bin, port, init=False) as restored_pg:
+        restored_pg.configure([f"port={port}"])
+        restored_pg.start()
+        res = restored_pg.safe_psql("SELECT count(*), sum(x) FROM foo")
+        assert res == [(100000, 5000050000)]
+        res = restored_pg.safe_psql("SET enable_seqscan=off; SELECT t FROM foo WHERE x = 4242")
+        assert res == [('long string to consume some space4242', )]
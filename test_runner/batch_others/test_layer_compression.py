@@ -0,0 +1,60 @@
+from contextlib import closing
+
+import psycopg2.extras
+from fixtures.log_helper import log
+from fixtures.zenith_fixtures import ZenithEnvBuilder
+
+
+#
+# Write layer files with different compression settings, and check that
+# they can all be read back, also after a pageserver restart.
+#
+def test_layer_compression(zenith_env_builder: ZenithEnvBuilder):
+    env = zenith_env_builder.init_start()
+
+    # Extend compaction_period and gc_period to disable background compaction and gc.
+    tenant, _ = env.zenith_cli.create_tenant(
+        conf={
+            'gc_period': '10 m',
+            'compaction_period': '10 m',
+            'compaction_target_size': '4194304',
+            'compression': 'zstd',
+        })
+    env.zenith_cli.create_timeline('test_layer_compression', tenant_id=tenant)
+    pg = env.postgres.create_start('test_layer_compression', tenant_id=tenant)
+
+    cur = pg.connect().cursor()
+    cur.execute("SHOW neon.timeline_id")
+    timeline = cur.fetchone()[0]
+
+    def insert_and_checkpoint(name: str):
+        cur.execute(f'CREATE TABLE {name} (t text)')
+        cur.execute(f'''
+            INSERT INTO {name}
+                SELECT 'long string to consume some space' || g
+                FROM generate_series(1, 100000) g
+        ''')
+        with closing(env.pageserver.connect()) as psconn:
+            with psconn.cursor(cursor_factory=psycopg2.extras.DictCursor) as pscur:
+                pscur.execute(f"checkpoint {tenant.hex} {timeline}")
+                pscur.execute(f"show {tenant.hex}")
+                log.info(f"wrote layers for {name} with {pscur.fetchone()['compression']}")
+
+    insert_and_checkpoint('foo')
+
+    env.zenith_cli.config_tenant(tenant_id=tenant, conf={'compression': 'lz4'})
+    insert_and_checkpoint('bar')
+
+    env.zenith_cli.config_tenant(tenant_id=tenant, conf={'compression': 'none'})
+    insert_and_checkpoint('baz')
+
+    # Restart, so that all the pages are read back from the layer files
+    pg.stop()
+    env.pageserver.stop()
+    env.pageserver.start()
+    pg.start()
+
+    cur = pg.connect().cursor()
+    for name in ['foo', 'bar', 'baz']:
+        cur.execute(f"SELECT count(*), sum(length(t)) FROM {name}")
+        assert cur.fetchone() == (100000, 3788895)
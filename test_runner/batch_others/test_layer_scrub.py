@@ -0,0 +1,75 @@
+from contextlib import closing
+from pathlib import Path
+from uuid import UUID
+
+from fixtures.zenith_fixtures import ZenithEnvBuilder, wait_until, wait_for_last_record_lsn, wait_for_upload
+from fixtures.log_helper import log
+from fixtures.metrics import parse_metrics
+from fixtures.utils import lsn_from_hex
+
+
+#
+# Corrupt a layer file that has been uploaded to remote storage, and check
+# that the scrubber notices it and replaces the file with the remote copy.
+#
+def test_layer_scrub(zenith_env_builder: ZenithEnvBuilder):
+    zenith_env_builder.enable_local_fs_remote_storage()
+    env = zenith_env_builder.init_start()
+
+    # Disable background compaction and gc, so that the layer files stay put.
+    tenant, _ = env.zenith_cli.create_tenant(conf={
+        'gc_period': '10 m',
+        'compaction_period': '10 m',
+        'scrub_period': '1 s',
+    })
+    env.zenith_cli.create_timeline('test_layer_scrub', tenant_id=tenant)
+    pg = env.postgres.create_start('test_layer_scrub', tenant_id=tenant)
+    client = env.pageserver.http_client()
+
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("SHOW neon.timeline_id")
+            timeline = UUID(cur.fetchone()[0])
+            cur.execute("CREATE TABLE foo (t text)")
+            cur.execute('''
+                INSERT INTO foo
+                    SELECT 'long string to consume some space' || g
+                    FROM generate_series(1, 100000) g
+            ''')
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            current_lsn = lsn_from_hex(cur.fetchone()[0])
+
+    wait_for_last_record_lsn(client, tenant, timeline, current_lsn)
+    env.pageserver.safe_psql(f"checkpoint {tenant.hex} {timeline.hex}")
+    wait_for_upload(client, tenant, timeline, current_lsn)
+    pg.stop()
+
+    # Flip a bit in the first block of values of a delta layer
+    timeline_dir = Path(env.repo_dir) / 'tenants' / tenant.hex / 'timelines' / timeline.hex
+    layer_path = next(p for p in timeline_dir.iterdir() if '__' in p.name and '-' in p.name.split('__')[1])
+    good_contents = layer_path.read_bytes()
+    corrupt_contents = bytearray(good_contents)
+    corrupt_contents[8192 + 100] ^= 1
+    layer_path.write_bytes(corrupt_contents)
+    log.info(f'corrupted layer {layer_path.name}')
+
+    def layer_restored():
+        assert layer_path.exists()
+        assert layer_path.read_bytes() == good_contents
+
+    wait_until(30, 1, layer_restored)
+
+    # The corrupt file is kept aside
+    assert any(p.name.startswith(layer_path.name) and p.name.endswith('.old')
+               for p in timeline_dir.iterdir())
+
+    metrics = parse_metrics(client.get_metrics(), 'pageserver')
+    corrupt_layers = metrics.query_one('pageserver_corrupt_layers_total',
+                                       filter={'tenant_id': tenant.hex})
+    assert int(corrupt_layers.value) >= 1
+
+    pg.start()
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("SELECT count(*), sum(length(t)) FROM foo")
+            assert cur.fetchone() == (100000, 3788895)
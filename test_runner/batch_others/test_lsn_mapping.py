@@ -4,7 +4,8 @@ import math
 from uuid import UUID
 import psycopg2.extras
 import psycopg2.errors
-from fixtures.zenith_fixtures import ZenithEnv, ZenithEnvBuilder, Postgres
+import pytest
+from fixtures.zenith_fixtures import ZenithEnv, ZenithEnvBuilder, ZenithPageserverApiException, Postgres
 from fixtures.log_helper import log
 import time
 
@@ -82,3 +83,61 @@ def test_lsn_mapping(zenith_env_builder: ZenithEnvBuilder):
                 assert cur_here.fetchone()[0] == i
 
         pg_here.stop_and_destroy()
+
+
+#
+# Test creating a branch at a point in time, with the ancestor_start_timestamp
+# of the timeline create API
+#
+def test_branch_by_timestamp(zenith_env_builder: ZenithEnvBuilder):
+    zenith_env_builder.num_safekeepers = 1
+    env = zenith_env_builder.init_start()
+
+    timeline_id = env.zenith_cli.create_branch('test_branch_by_timestamp')
+    pgmain = env.postgres.create_start("test_branch_by_timestamp")
+    client = env.pageserver.http_client()
+
+    ps_cur = env.pageserver.connect().cursor()
+    cur = pgmain.connect().cursor()
+
+    cur.execute("SET synchronous_commit=off")
+    cur.execute("CREATE TABLE foo (x integer)")
+    tbl = []
+    for i in range(100):
+        cur.execute(f"INSERT INTO foo VALUES({i})")
+        cur.execute(f'SELECT clock_timestamp()')
+        after_timestamp = cur.fetchone()[0].replace(tzinfo=None)
+        tbl.append([i, after_timestamp])
+    cur.execute("SET synchronous_commit=on")
+    cur.execute("INSERT INTO foo VALUES (-1)")
+
+    def branch_at(timestamp):
+        return client.timeline_create(env.initial_tenant,
+                                      ancestor_timeline_id=timeline_id,
+                                      ancestor_start_timestamp=f'{timestamp.isoformat()}Z')
+
+    # The branch point is the same LSN that get_lsn_by_timestamp finds
+    probe_timestamp = tbl[50][1]
+    ps_cur.execute(
+        f"get_lsn_by_timestamp {env.initial_tenant.hex} {timeline_id.hex} '{probe_timestamp.isoformat()}Z'"
+    )
+    expected_lsn = ps_cur.fetchone()[0]
+
+    branch = branch_at(probe_timestamp)
+    log.info(f'branch created at timestamp {probe_timestamp}: {branch}')
+    assert branch['lsn_for_timestamp'] == {'kind': 'Present', 'lsn': expected_lsn}
+    assert branch['local']['ancestor_lsn'] == expected_lsn
+
+    # A timestamp after the last commit branches at the end of WAL
+    branch = branch_at(tbl[-1][1] + timedelta(hours=1))
+    assert branch['lsn_for_timestamp']['kind'] == 'Future'
+
+    # There's no data to branch from before the first commit
+    with pytest.raises(ZenithPageserverApiException, match="Past"):
+        branch_at(tbl[0][1] - timedelta(hours=10))
+
+    with pytest.raises(ZenithPageserverApiException, match="cannot be used together"):
+        client.timeline_create(env.initial_tenant,
+                               ancestor_timeline_id=timeline_id,
+                               ancestor_start_lsn=expected_lsn,
+                               ancestor_start_timestamp=f'{probe_timestamp.isoformat()}Z')
@@ -0,0 +1,135 @@
+import shutil, os
+from contextlib import closing
+from pathlib import Path
+from uuid import UUID
+from fixtures.zenith_fixtures import ZenithEnvBuilder, assert_local, wait_until, wait_for_last_record_lsn, wait_for_upload
+from fixtures.log_helper import log
+from fixtures.metrics import parse_metrics
+from fixtures.utils import lsn_from_hex
+
+
+#
+# Attach a timeline with on-demand downloads enabled, and check that the
+# attach doesn't download the layer files, but the data can still be read.
+#
+def test_ondemand_download(zenith_env_builder: ZenithEnvBuilder):
+    zenith_env_builder.enable_local_fs_remote_storage()
+    zenith_env_builder.pageserver_config_override = 'on_demand_download=true'
+
+    ##### First start, insert data and upload it to the remote storage
+    env = zenith_env_builder.init_start()
+    pg = env.postgres.create_start('main')
+
+    client = env.pageserver.http_client()
+
+    tenant_id = pg.safe_psql("show neon.tenant_id")[0][0]
+    timeline_id = pg.safe_psql("show neon.timeline_id")[0][0]
+
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE foo (t text)")
+            cur.execute('''
+                INSERT INTO foo
+                    SELECT 'long string to consume some space' || g
+                    FROM generate_series(1, 100000) g
+            ''')
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            current_lsn = lsn_from_hex(cur.fetchone()[0])
+
+    wait_for_last_record_lsn(client, UUID(tenant_id), UUID(timeline_id), current_lsn)
+    env.pageserver.safe_psql(f"checkpoint {tenant_id} {timeline_id}")
+    wait_for_upload(client, UUID(tenant_id), UUID(timeline_id), current_lsn)
+
+    ##### Stop the pageserver, erase all its data
+    env.postgres.stop_all()
+    env.pageserver.stop()
+
+    dir_to_clear = Path(env.repo_dir) / 'tenants'
+    shutil.rmtree(dir_to_clear)
+    os.mkdir(dir_to_clear)
+
+    ##### Second start, attach the timeline without downloading the layers
+    env.pageserver.start()
+    client.timeline_attach(UUID(tenant_id), UUID(timeline_id))
+
+    wait_until(number_of_iterations=10,
+               interval=1,
+               func=lambda: assert_local(client, UUID(tenant_id), UUID(timeline_id)))
+
+    timeline_dir = Path(env.repo_dir) / 'tenants' / tenant_id / 'timelines' / timeline_id
+    assert [p.name for p in timeline_dir.iterdir()] == ['metadata']
+
+    # The layers needed by the queries are downloaded when they're read
+    pg = env.postgres.create_start('main')
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("SELECT count(*), sum(length(t)) FROM foo")
+            assert cur.fetchone() == (100000, 3788895)
+
+    layer_files = [p.name for p in timeline_dir.iterdir() if '__' in p.name]
+    log.info(f'layer files after reading: {layer_files}')
+    assert len(layer_files) > 0
+
+    metrics = parse_metrics(client.get_metrics(), 'pageserver')
+    downloads = metrics.query_one('pageserver_remote_storage_on_demand_downloads_total')
+    assert int(downloads.value) >= 1
+
+
+#
+# Remove the local layer files of a timeline while the pageserver is down, and
+# check that the first access through the page service, which loads the
+# timeline, downloads them.
+#
+def test_ondemand_download_after_restart(zenith_env_builder: ZenithEnvBuilder):
+    zenith_env_builder.enable_local_fs_remote_storage()
+    zenith_env_builder.pageserver_config_override = 'on_demand_download=true'
+
+    env = zenith_env_builder.init_start()
+    pg = env.postgres.create_start('main')
+
+    client = env.pageserver.http_client()
+
+    tenant_id = pg.safe_psql("show neon.tenant_id")[0][0]
+    timeline_id = pg.safe_psql("show neon.timeline_id")[0][0]
+
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE foo (t text)")
+            cur.execute('''
+                INSERT INTO foo
+                    SELECT 'long string to consume some space' || g
+                    FROM generate_series(1, 100000) g
+            ''')
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            current_lsn = lsn_from_hex(cur.fetchone()[0])
+
+    wait_for_last_record_lsn(client, UUID(tenant_id), UUID(timeline_id), current_lsn)
+    env.pageserver.safe_psql(f"checkpoint {tenant_id} {timeline_id}")
+    wait_for_upload(client, UUID(tenant_id), UUID(timeline_id), current_lsn)
+
+    ##### Stop the pageserver, and remove the layer files but keep the metadata
+    env.postgres.stop_all()
+    env.pageserver.stop()
+
+    timeline_dir = Path(env.repo_dir) / 'tenants' / tenant_id / 'timelines' / timeline_id
+    for p in timeline_dir.iterdir():
+        if '__' in p.name:
+            p.unlink()
+
+    ##### Restart. The timeline is loaded by the basebackup request of the
+    # compute, on a page service thread, and the pages are downloaded there too.
+    env.pageserver.start()
+
+    pg = env.postgres.create_start('main')
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("SELECT count(*), sum(length(t)) FROM foo")
+            assert cur.fetchone() == (100000, 3788895)
+
+    layer_files = [p.name for p in timeline_dir.iterdir() if '__' in p.name]
+    log.info(f'layer files after reading: {layer_files}')
+    assert len(layer_files) > 0
+
+    metrics = parse_metrics(client.get_metrics(), 'pageserver')
+    downloads = metrics.query_one('pageserver_remote_storage_on_demand_downloads_total')
+    assert int(downloads.value) >= 1
@@ -1,5 +1,7 @@
+from contextlib import closing
 from typing import Optional
 from uuid import uuid4, UUID
+import psycopg2.errors
 import pytest
 from fixtures.utils import lsn_from_hex
 from fixtures.zenith_fixtures import (
@@ -121,3 +123,28 @@ def test_pageserver_http_api_client_auth_enabled(zenith_env_builder: ZenithEnvBu
 
     client = env.pageserver.http_client(auth_token=management_token)
     check_client(client, env.initial_tenant)
+
+
+def test_page_service_commands(zenith_simple_env: ZenithEnv):
+    env = zenith_simple_env
+
+    with closing(env.pageserver.connect()) as psconn:
+        with psconn.cursor() as pscur:
+            pscur.execute("help")
+            commands = {row[0].split()[0] for row in pscur.fetchall()}
+            assert {'pagestream', 'basebackup', 'do_gc', 'help'} <= commands
+
+            # Errors are reported with SQLSTATE codes that tools can rely on
+            with pytest.raises(psycopg2.errors.SyntaxError, match="unknown command 'frobnicate'"):
+                pscur.execute("frobnicate")
+            with pytest.raises(psycopg2.errors.SyntaxError, match="usage: compact"):
+                pscur.execute(f"compact {env.initial_tenant.hex}")
+            with pytest.raises(psycopg2.errors.InvalidParameterValue,
+                               match="invalid tenant_id 'foo'"):
+                pscur.execute("show foo")
+            with pytest.raises(psycopg2.errors.InternalError_, match="Tenant .* not found"):
+                pscur.execute(f"show {uuid4().hex}")
+
+            # The connection is still usable after errors
+            pscur.execute(f"show {env.initial_tenant.hex}")
+            assert len(pscur.fetchall()) == 1
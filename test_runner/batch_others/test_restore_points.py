@@ -0,0 +1,141 @@
+import os
+import shutil
+from contextlib import closing
+from uuid import UUID
+
+import pytest
+from fixtures.log_helper import log
+from fixtures.utils import lsn_from_hex
+from fixtures.zenith_fixtures import (ZenithEnvBuilder,
+                                      ZenithPageserverApiException,
+                                      assert_local,
+                                      wait_for_last_record_lsn,
+                                      wait_for_upload,
+                                      wait_until)
+
+
+#
+# Check that a named restore point keeps the history needed to branch from it
+# after GC, until it is deleted.
+#
+def test_restore_points(zenith_env_builder: ZenithEnvBuilder):
+    # Disable PITR, and the background GC and compaction, so that GC only
+    # runs when the test asks for it
+    zenith_env_builder.pageserver_config_override = \
+        "tenant_config={pitr_interval = '0 sec', gc_period = '10 m', compaction_period = '10 m'}"
+    env = zenith_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    client = env.pageserver.http_client()
+
+    pg = env.postgres.create_start('main')
+    timeline_id = UUID(pg.safe_psql("show neon.timeline_id")[0][0])
+
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE foo (i int, t text)")
+            cur.execute("INSERT INTO foo SELECT g, 'long string to consume some space' || g "
+                        "FROM generate_series(1, 10000) g")
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            current_lsn = lsn_from_hex(cur.fetchone()[0])
+    wait_for_last_record_lsn(client, tenant_id, timeline_id, current_lsn)
+
+    restore_point = client.restore_point_create(tenant_id, timeline_id, 'before-migration-42')
+    log.info(f"created restore point {restore_point}")
+    assert restore_point['name'] == 'before-migration-42'
+    assert lsn_from_hex(restore_point['lsn']) >= current_lsn
+
+    # Names are unique
+    with pytest.raises(ZenithPageserverApiException, match='already exists'):
+        client.restore_point_create(tenant_id, timeline_id, 'before-migration-42')
+    with pytest.raises(ZenithPageserverApiException, match='invalid restore point name'):
+        client.restore_point_create(tenant_id, timeline_id, 'no/slashes')
+
+    # The restore points are persisted, and a half-written file left behind
+    # by a crash is ignored and removed
+    pg.stop()
+    env.pageserver.stop()
+    timeline_dir = env.repo_dir / 'tenants' / tenant_id.hex / 'timelines' / timeline_id.hex
+    (timeline_dir / 'restore_points.temp').write_text('[[restore_po')
+    env.pageserver.start()
+    assert client.restore_point_list(tenant_id, timeline_id) == [restore_point]
+    assert not (timeline_dir / 'restore_points.temp').exists()
+    pg = env.postgres.create_start('main')
+
+    # Rewrite and then delete half of the table, and collect all the garbage
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            for _ in range(5):
+                cur.execute("UPDATE foo SET t = t || 'x'")
+                env.pageserver.safe_psql(f"checkpoint {tenant_id.hex} {timeline_id.hex}")
+            cur.execute("DELETE FROM foo WHERE i > 5000")
+    pg.stop()
+    env.pageserver.safe_psql(f"do_gc {tenant_id.hex} {timeline_id.hex} 0")
+
+    detail = client.timeline_detail(tenant_id, timeline_id)
+    latest_gc_cutoff_lsn = lsn_from_hex(detail['local']['latest_gc_cutoff_lsn'])
+    assert latest_gc_cutoff_lsn > lsn_from_hex(restore_point['lsn'])
+
+    # The state at the restore point can still be restored
+    env.zenith_cli.create_branch('restored', 'main', ancestor_start_lsn=restore_point['lsn'])
+    pg_restored = env.postgres.create_start('restored')
+    assert pg_restored.safe_psql("SELECT count(*), max(length(t)) FROM foo")[0] == (10000, 38)
+    pg_restored.stop()
+
+    # Once the restore point is gone, its LSN is behind the GC cutoff like
+    # any other
+    assert client.restore_point_delete(tenant_id, timeline_id,
+                                       'before-migration-42') == restore_point
+    assert client.restore_point_list(tenant_id, timeline_id) == []
+    with pytest.raises(ZenithPageserverApiException, match='not found'):
+        client.restore_point_delete(tenant_id, timeline_id, 'before-migration-42')
+    with pytest.raises(Exception):
+        env.zenith_cli.create_branch('too_late', 'main', ancestor_start_lsn=restore_point['lsn'])
+
+
+#
+# Check that the restore points are stored remotely, and restored when the
+# timeline is attached to a pageserver that doesn't have it locally.
+#
+def test_restore_points_remote_storage(zenith_env_builder: ZenithEnvBuilder):
+    zenith_env_builder.enable_local_fs_remote_storage()
+    env = zenith_env_builder.init_start()
+    pg = env.postgres.create_start('main')
+    client = env.pageserver.http_client()
+
+    tenant_id = UUID(pg.safe_psql("show neon.tenant_id")[0][0])
+    timeline_id = UUID(pg.safe_psql("show neon.timeline_id")[0][0])
+
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE foo AS SELECT g FROM generate_series(1, 1000) g")
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            current_lsn = lsn_from_hex(cur.fetchone()[0])
+
+    wait_for_last_record_lsn(client, tenant_id, timeline_id, current_lsn)
+    env.pageserver.safe_psql(f"checkpoint {tenant_id.hex} {timeline_id.hex}")
+    wait_for_upload(client, tenant_id, timeline_id, current_lsn)
+
+    # Creating a restore point on an uploaded timeline uploads its index part
+    # again, there's no new layer to wait for
+    restore_point = client.restore_point_create(tenant_id, timeline_id, 'nightly')
+
+    def restore_point_uploaded():
+        index_part_path = (env.repo_dir / 'local_fs_remote_storage' / 'tenants' / tenant_id.hex /
+                           'timelines' / timeline_id.hex / 'index_part.json')
+        assert '"name":"nightly"' in index_part_path.read_text()
+
+    wait_until(number_of_iterations=10, interval=1, func=restore_point_uploaded)
+
+    env.postgres.stop_all()
+    env.pageserver.stop()
+    dir_to_clear = env.repo_dir / 'tenants'
+    shutil.rmtree(dir_to_clear)
+    os.mkdir(dir_to_clear)
+    env.pageserver.start()
+
+    client.timeline_attach(tenant_id, timeline_id)
+    wait_until(number_of_iterations=10,
+               interval=1,
+               func=lambda: assert_local(client, tenant_id, timeline_id))
+
+    assert client.restore_point_list(tenant_id, timeline_id) == [restore_point]
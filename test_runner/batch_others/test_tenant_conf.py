@@ -41,10 +41,17 @@ tenant_config={checkpoint_distance = 10000, compaction_target_size = 1048576}'''
                     "compaction_target_size": 1048576,
                     "compaction_period": 1,
                     "compaction_threshold": 10,
+                    "compaction_strategy": "level0",
+                    "compaction_tier_ratio": 4,
                     "gc_horizon": 67108864,
                     "gc_period": 100,
                     "image_creation_threshold": 3,
-                    "pitr_interval": 2592000
+                    "pitr_interval": 2592000,
+                    "getpage_rate_limit": 0,
+                    "getpage_burst": 1000,
+                    "compression": "none",
+                    "scrub_period": 86400,
+                    "scrub_rate_limit": 10485760,
                 }.items())
 
     # check the configuration of the new tenant
@@ -59,10 +66,17 @@ tenant_config={checkpoint_distance = 10000, compaction_target_size = 1048576}'''
                     "compaction_target_size": 1048576,
                     "compaction_period": 1,
                     "compaction_threshold": 10,
+                    "compaction_strategy": "level0",
+                    "compaction_tier_ratio": 4,
                     "gc_horizon": 67108864,
                     "gc_period": 30,
                     "image_creation_threshold": 3,
-                    "pitr_interval": 2592000
+                    "pitr_interval": 2592000,
+                    "getpage_rate_limit": 0,
+                    "getpage_burst": 1000,
+                    "compression": "none",
+                    "scrub_period": 86400,
+                    "scrub_rate_limit": 10485760,
                 }.items())
 
     # update the config and ensure that it has changed
@@ -70,6 +84,10 @@ tenant_config={checkpoint_distance = 10000, compaction_target_size = 1048576}'''
                                  conf={
                                      'checkpoint_distance': '15000',
                                      'gc_period': '80sec',
+                                     'getpage_rate_limit': '1000',
+                                     'compression': 'zstd',
+                                     'compaction_strategy': 'tiered',
+                                     'scrub_rate_limit': '1048576',
                                  })
 
     with closing(env.pageserver.connect()) as psconn:
@@ -83,10 +101,17 @@ tenant_config={checkpoint_distance = 10000, compaction_target_size = 1048576}'''
                     "compaction_target_size": 1048576,
                     "compaction_period": 1,
                     "compaction_threshold": 10,
+                    "compaction_strategy": "tiered",
+                    "compaction_tier_ratio": 4,
                     "gc_horizon": 67108864,
                     "gc_period": 80,
                     "image_creation_threshold": 3,
-                    "pitr_interval": 2592000
+                    "pitr_interval": 2592000,
+                    "getpage_rate_limit": 1000,
+                    "getpage_burst": 1000,
+                    "compression": "zstd",
+                    "scrub_period": 86400,
+                    "scrub_rate_limit": 1048576,
                 }.items())
 
     # restart the pageserver and ensure that the config is still correct
@@ -104,8 +129,15 @@ tenant_config={checkpoint_distance = 10000, compaction_target_size = 1048576}'''
                     "compaction_target_size": 1048576,
                     "compaction_period": 1,
                     "compaction_threshold": 10,
+                    "compaction_strategy": "tiered",
+                    "compaction_tier_ratio": 4,
                     "gc_horizon": 67108864,
                     "gc_period": 80,
                     "image_creation_threshold": 3,
-                    "pitr_interval": 2592000
+                    "pitr_interval": 2592000,
+                    "getpage_rate_limit": 1000,
+                    "getpage_burst": 1000,
+                    "compression": "zstd",
+                    "scrub_period": 86400,
+                    "scrub_rate_limit": 1048576,
                 }.items())
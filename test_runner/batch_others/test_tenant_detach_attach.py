@@ -0,0 +1,70 @@
+from contextlib import closing
+from uuid import UUID
+import pytest
+from fixtures.zenith_fixtures import ZenithEnvBuilder, ZenithPageserverApiException, wait_until, wait_for_last_record_lsn
+from fixtures.log_helper import log
+from fixtures.utils import lsn_from_hex
+
+
+#
+# Detach a tenant with a branch, attach it back from the remote storage, and
+# finally delete it.
+#
+def test_tenant_detach_attach(zenith_env_builder: ZenithEnvBuilder):
+    zenith_env_builder.enable_local_fs_remote_storage()
+    env = zenith_env_builder.init_start()
+
+    tenant_id = env.initial_tenant
+    child_timeline_id = env.zenith_cli.create_branch('test_tenant_detach_attach_child', 'main')
+    client = env.pageserver.http_client()
+
+    pg = env.postgres.create_start('main')
+    main_timeline_id = UUID(pg.safe_psql("show neon.timeline_id")[0][0])
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE foo (t text)")
+            cur.execute("INSERT INTO foo SELECT 'row' || g FROM generate_series(1, 10000) g")
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            current_lsn = lsn_from_hex(cur.fetchone()[0])
+    wait_for_last_record_lsn(client, tenant_id, main_timeline_id, current_lsn)
+    env.postgres.stop_all()
+
+    tenant_dir = env.repo_dir / 'tenants' / tenant_id.hex
+    remote_timelines_dir = env.repo_dir / 'local_fs_remote_storage' / 'tenants' / tenant_id.hex / 'timelines'
+
+    ##### Detach, the ingested WAL is uploaded and the local files are removed
+    client.tenant_detach(tenant_id)
+    assert not tenant_dir.exists()
+    with pytest.raises(ZenithPageserverApiException, match="not found"):
+        client.tenant_status(tenant_id)
+
+    ##### Attach, all the timelines are downloaded
+    client.tenant_attach(tenant_id)
+    with pytest.raises(ZenithPageserverApiException, match="already exists"):
+        client.tenant_attach(tenant_id)
+
+    def assert_attached():
+        state = client.tenant_status(tenant_id)['state']
+        log.info(f'tenant state: {state}')
+        assert state in ('Idle', 'Active')
+
+    wait_until(number_of_iterations=10, interval=1, func=assert_attached)
+
+    timeline_ids = {UUID(t['timeline_id']) for t in client.timeline_list(tenant_id)}
+    assert timeline_ids == {main_timeline_id, child_timeline_id}
+
+    pg = env.postgres.create_start('main')
+    assert pg.safe_psql("SELECT count(*) FROM foo") == [(10000, )]
+    env.postgres.stop_all()
+
+    ##### Delete, both the local and the remote data are removed
+    client.tenant_delete(tenant_id)
+    assert not tenant_dir.exists()
+
+    def assert_deleted_remotely():
+        assert list(remote_timelines_dir.glob('*/index_part.json')) == []
+
+    wait_until(number_of_iterations=10, interval=1, func=assert_deleted_remotely)
+
+    with pytest.raises(ZenithPageserverApiException, match="not found"):
+        client.tenant_delete(tenant_id)
@@ -0,0 +1,68 @@
+from contextlib import closing
+from uuid import UUID
+from fixtures.zenith_fixtures import ZenithEnvBuilder, wait_for_last_record_lsn
+from fixtures.log_helper import log
+from fixtures.utils import lsn_from_hex
+
+
+#
+# Check that the synthetic size of a tenant grows with the WAL and the
+# branches, and that it is explained by its segments.
+#
+def test_tenant_size(zenith_env_builder: ZenithEnvBuilder):
+    env = zenith_env_builder.init_start()
+
+    # 1M gc_horizon and no PITR, so that the retained WAL is easy to reason
+    # about. Disable background GC.
+    tenant_id, _ = env.zenith_cli.create_tenant(conf={
+        'gc_period': '10 m',
+        'gc_horizon': '1048576',
+        'pitr_interval': '0 sec',
+    })
+    client = env.pageserver.http_client()
+
+    def tenant_size():
+        size = client.tenant_size(tenant_id)
+        log.info(f"tenant size: {size}")
+        assert UUID(size['tenant_id']) == tenant_id
+        assert size['size'] == sum(s['size'] for s in size['segments'])
+        return size
+
+    pg = env.postgres.create_start('main', tenant_id=tenant_id)
+    main_timeline_id = UUID(pg.safe_psql("show neon.timeline_id")[0][0])
+
+    def insert_rows():
+        with closing(pg.connect()) as conn:
+            with conn.cursor() as cur:
+                cur.execute("INSERT INTO foo SELECT 'long string to consume some space' || g "
+                            "FROM generate_series(1, 100000) g")
+                cur.execute("SELECT pg_current_wal_flush_lsn()")
+                current_lsn = lsn_from_hex(cur.fetchone()[0])
+        wait_for_last_record_lsn(client, tenant_id, main_timeline_id, current_lsn)
+        return current_lsn
+
+    pg.safe_psql("CREATE TABLE foo (t text)")
+    initial_size = tenant_size()['size']
+
+    branch_lsn = insert_rows()
+    size_with_data = tenant_size()
+    assert size_with_data['size'] > initial_size
+    assert any(s['reason'] == 'Retention' for s in size_with_data['segments'])
+
+    # Branch off, and write enough on main to push the branch point behind
+    # its retention horizon. The branch point has to be kept then.
+    child_timeline_id = env.zenith_cli.create_branch('test_tenant_size_child',
+                                                     'main',
+                                                     tenant_id=tenant_id)
+    insert_rows()
+
+    size_with_branch = tenant_size()
+    assert size_with_branch['size'] > size_with_data['size']
+    assert {UUID(t['timeline_id'])
+            for t in size_with_branch['timelines']} == {main_timeline_id, child_timeline_id}
+
+    branch_points = [s for s in size_with_branch['segments'] if s['reason'] == 'BranchPoint']
+    assert len(branch_points) == 1
+    assert UUID(branch_points[0]['timeline_id']) == main_timeline_id
+    assert UUID(branch_points[0]['child_timeline_id']) == child_timeline_id
+    assert lsn_from_hex(branch_points[0]['end_lsn']) >= branch_lsn
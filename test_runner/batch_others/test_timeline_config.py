@@ -0,0 +1,130 @@
+import os
+import shutil
+from contextlib import closing
+from uuid import UUID
+
+from fixtures.log_helper import log
+from fixtures.utils import lsn_from_hex
+from fixtures.zenith_fixtures import ZenithEnvBuilder, assert_local, wait_until, wait_for_last_record_lsn, wait_for_upload
+
+
+#
+# Check that the gc_horizon and pitr_interval of a timeline can be overridden,
+# that GC honours the overrides, and that they survive a pageserver restart.
+#
+def test_timeline_config(zenith_env_builder: ZenithEnvBuilder):
+    env = zenith_env_builder.init_start()
+
+    # A long retention for the tenant, and no background GC or compaction, so
+    # that the layers only change when the test asks for it
+    tenant_id, main_timeline_id = env.zenith_cli.create_tenant(conf={
+        'gc_period': '10 m',
+        'compaction_period': '10 m',
+        'gc_horizon': str(1 << 30),
+        'pitr_interval': '0 sec',
+    })
+    dev_timeline_id = env.zenith_cli.create_branch('dev', 'main', tenant_id=tenant_id)
+    client = env.pageserver.http_client()
+
+    # A short-lived dev branch with next to no retention
+    env.zenith_cli.config_timeline('dev', {'gc_horizon': '0'}, tenant_id=tenant_id)
+
+    dev_conf = client.timeline_config(tenant_id, dev_timeline_id)
+    log.info(f"dev timeline config: {dev_conf}")
+    assert dev_conf['gc_horizon'] == 0
+    assert dev_conf['pitr_interval'] is None
+    assert dev_conf['effective_gc_horizon'] == 0
+    assert dev_conf['effective_pitr_interval'] == '0s'
+
+    main_conf = client.timeline_config(tenant_id, main_timeline_id)
+    assert main_conf['gc_horizon'] is None
+    assert main_conf['effective_gc_horizon'] == 1 << 30
+
+    # Create a few generations of layers on both branches
+    for branch_name in ['main', 'dev']:
+        pg = env.postgres.create_start(branch_name, tenant_id=tenant_id)
+        timeline_id = UUID(pg.safe_psql("show neon.timeline_id")[0][0])
+        with closing(pg.connect()) as conn:
+            with conn.cursor() as cur:
+                cur.execute("CREATE TABLE foo (t text)")
+                for _ in range(5):
+                    cur.execute("INSERT INTO foo SELECT 'long string to consume some space' || g "
+                                "FROM generate_series(1, 10000) g")
+                    cur.execute("UPDATE foo SET t = t || 'x'")
+                    env.pageserver.safe_psql(f"checkpoint {tenant_id.hex} {timeline_id.hex}")
+        pg.stop()
+
+    def keep_reasons(timeline_id: UUID):
+        report = client.timeline_gc_dry_run(tenant_id, timeline_id)
+        log.info(f"dry run of timeline {timeline_id.hex}: {report}")
+        assert len(report['layers']) > 0
+        return {layer['keep_reason'] for layer in report['layers']}
+
+    # Everything on main is within the tenant's horizon, while GC would
+    # collect the old layers of the dev branch
+    assert keep_reasons(main_timeline_id) == {'Horizon'}
+    assert keep_reasons(dev_timeline_id) != {'Horizon'}
+
+    # The overrides are persisted
+    env.pageserver.stop()
+    env.pageserver.start()
+    assert client.timeline_config(tenant_id, dev_timeline_id) == dev_conf
+    assert keep_reasons(dev_timeline_id) != {'Horizon'}
+
+    # Without overrides, the dev branch falls back to the tenant config
+    client.timeline_config_set(tenant_id, dev_timeline_id, {})
+    dev_conf = client.timeline_config(tenant_id, dev_timeline_id)
+    assert dev_conf['gc_horizon'] is None
+    assert dev_conf['effective_gc_horizon'] == 1 << 30
+    assert keep_reasons(dev_timeline_id) == {'Horizon'}
+
+
+#
+# Check that the overrides are stored remotely, and restored when the timeline
+# is attached to a pageserver that doesn't have it locally.
+#
+def test_timeline_config_remote_storage(zenith_env_builder: ZenithEnvBuilder):
+    zenith_env_builder.enable_local_fs_remote_storage()
+    env = zenith_env_builder.init_start()
+    pg = env.postgres.create_start('main')
+    client = env.pageserver.http_client()
+
+    tenant_id = UUID(pg.safe_psql("show neon.tenant_id")[0][0])
+    timeline_id = UUID(pg.safe_psql("show neon.timeline_id")[0][0])
+
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE foo AS SELECT g FROM generate_series(1, 1000) g")
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            current_lsn = lsn_from_hex(cur.fetchone()[0])
+
+    wait_for_last_record_lsn(client, tenant_id, timeline_id, current_lsn)
+    env.pageserver.safe_psql(f"checkpoint {tenant_id.hex} {timeline_id.hex}")
+    wait_for_upload(client, tenant_id, timeline_id, current_lsn)
+
+    # Changing the config of an uploaded timeline uploads its index part
+    # again, there's no new layer to wait for
+    env.zenith_cli.config_timeline('main', {'gc_horizon': '1000', 'pitr_interval': '5 min'},
+                                   tenant_id=tenant_id)
+    conf = client.timeline_config(tenant_id, timeline_id)
+
+    def config_uploaded():
+        index_part_path = (env.repo_dir / 'local_fs_remote_storage' / 'tenants' / tenant_id.hex /
+                           'timelines' / timeline_id.hex / 'index_part.json')
+        assert '"gc_horizon":1000' in index_part_path.read_text()
+
+    wait_until(number_of_iterations=10, interval=1, func=config_uploaded)
+
+    env.postgres.stop_all()
+    env.pageserver.stop()
+    dir_to_clear = env.repo_dir / 'tenants'
+    shutil.rmtree(dir_to_clear)
+    os.mkdir(dir_to_clear)
+    env.pageserver.start()
+
+    client.timeline_attach(tenant_id, timeline_id)
+    wait_until(number_of_iterations=10,
+               interval=1,
+               func=lambda: assert_local(client, tenant_id, timeline_id))
+
+    assert client.timeline_config(tenant_id, timeline_id) == conf
@@ -0,0 +1,62 @@
+from uuid import UUID
+import pytest
+from fixtures.zenith_fixtures import ZenithEnvBuilder, ZenithPageserverApiException, wait_until
+from fixtures.log_helper import log
+
+
+#
+# Delete timelines through the HTTP API, and check that the local files and
+# the remote index are removed, and that a timeline with children can't be
+# deleted.
+#
+def test_timeline_delete(zenith_env_builder: ZenithEnvBuilder):
+    zenith_env_builder.enable_local_fs_remote_storage()
+    env = zenith_env_builder.init_start()
+
+    parent_timeline_id = env.zenith_cli.create_branch("test_timeline_delete_parent", "empty")
+    child_timeline_id = env.zenith_cli.create_branch("test_timeline_delete_child",
+                                                     "test_timeline_delete_parent")
+
+    tenant_id = env.initial_tenant
+    client = env.pageserver.http_client()
+
+    def timeline_dir(timeline_id: UUID):
+        return env.repo_dir / 'tenants' / tenant_id.hex / 'timelines' / timeline_id.hex
+
+    def remote_index_part(timeline_id: UUID):
+        return (env.repo_dir / 'local_fs_remote_storage' / 'tenants' / tenant_id.hex /
+                'timelines' / timeline_id.hex / 'index_part.json')
+
+    # Wait for the child branch to be uploaded
+    def assert_uploaded():
+        assert remote_index_part(child_timeline_id).exists()
+
+    wait_until(number_of_iterations=10, interval=1, func=assert_uploaded)
+
+    # The parent can't be deleted while it has children
+    with pytest.raises(ZenithPageserverApiException, match="child branches"):
+        client.timeline_delete(tenant_id, parent_timeline_id)
+    assert timeline_dir(parent_timeline_id).exists()
+
+    log.info(f'deleting timeline {child_timeline_id.hex}')
+    client.timeline_delete(tenant_id, child_timeline_id)
+    assert not timeline_dir(child_timeline_id).exists()
+
+    def assert_deleted_remotely():
+        assert not remote_index_part(child_timeline_id).exists()
+
+    wait_until(number_of_iterations=10, interval=1, func=assert_deleted_remotely)
+
+    # The index part is deleted last, once all the layers are gone
+    remote_timeline_dir = remote_index_part(child_timeline_id).parent
+    assert not remote_timeline_dir.exists() or not any(remote_timeline_dir.iterdir())
+
+    timeline_ids = [UUID(t['timeline_id']) for t in client.timeline_list(tenant_id)]
+    assert child_timeline_id not in timeline_ids
+
+    # With the child gone, the parent can be deleted as well
+    client.timeline_delete(tenant_id, parent_timeline_id)
+    assert not timeline_dir(parent_timeline_id).exists()
+
+    with pytest.raises(ZenithPageserverApiException, match="not found"):
+        client.timeline_delete(tenant_id, child_timeline_id)
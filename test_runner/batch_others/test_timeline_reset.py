@@ -0,0 +1,110 @@
+from contextlib import closing
+from uuid import UUID
+
+import pytest
+import requests
+from fixtures.log_helper import log
+from fixtures.utils import lsn_from_hex, lsn_to_hex
+from fixtures.zenith_fixtures import (ZenithEnvBuilder,
+                                      ZenithPageserverApiException,
+                                      wait_for_last_record_lsn)
+
+
+#
+# Check that a branch can be reset to an earlier LSN, dropping the changes
+# made after it on the safekeepers and on the pageserver.
+#
+def test_timeline_reset(zenith_env_builder: ZenithEnvBuilder):
+    zenith_env_builder.num_safekeepers = 3
+    env = zenith_env_builder.init_start()
+    tenant_id = env.initial_tenant
+    client = env.pageserver.http_client()
+
+    env.zenith_cli.create_branch('test_timeline_reset')
+    pg = env.postgres.create_start('test_timeline_reset')
+    timeline_id = UUID(pg.safe_psql("show neon.timeline_id")[0][0])
+
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE foo (i int, t text)")
+            cur.execute("INSERT INTO foo SELECT g, 'long string to consume some space' || g "
+                        "FROM generate_series(1, 10000) g")
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            current_lsn = lsn_from_hex(cur.fetchone()[0])
+    wait_for_last_record_lsn(client, tenant_id, timeline_id, current_lsn)
+    env.pageserver.safe_psql(f"checkpoint {tenant_id.hex} {timeline_id.hex}")
+    # The reset point must be a record boundary
+    reset_lsn = client.timeline_detail(tenant_id, timeline_id)['local']['last_record_lsn']
+
+    # A bad migration, spanning a few layer files
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("ALTER TABLE foo ADD COLUMN j int")
+            cur.execute("UPDATE foo SET j = i * 2")
+            env.pageserver.safe_psql(f"checkpoint {tenant_id.hex} {timeline_id.hex}")
+            cur.execute("DELETE FROM foo WHERE i > 5000")
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            bad_lsn = lsn_from_hex(cur.fetchone()[0])
+    wait_for_last_record_lsn(client, tenant_id, timeline_id, bad_lsn)
+
+    # The computes of the branch have to be stopped first
+    with pytest.raises(Exception, match='stop it before the reset'):
+        env.zenith_cli.reset_timeline('test_timeline_reset', reset_lsn)
+
+    pg.stop()
+
+    # The pageserver doesn't accept an LSN beyond the end of the timeline
+    with pytest.raises(ZenithPageserverApiException, match='newer than the last record LSN'):
+        client.timeline_reset(tenant_id, timeline_id, lsn_to_hex(bad_lsn + 0x1000000))
+
+    # The safekeepers only reset to a record boundary, and in a new term
+    sk_client = env.safekeepers[0].http_client()
+    term = sk_client.timeline_status(tenant_id.hex, timeline_id.hex).acceptor_term
+    with pytest.raises(requests.exceptions.HTTPError) as excinfo:
+        sk_client.timeline_reset(tenant_id.hex,
+                                 timeline_id.hex,
+                                 lsn_to_hex(lsn_from_hex(reset_lsn) + 8),
+                                 term + 1)
+    assert 'not a WAL record boundary' in excinfo.value.response.text
+    with pytest.raises(requests.exceptions.HTTPError) as excinfo:
+        sk_client.timeline_reset(tenant_id.hex, timeline_id.hex, reset_lsn, term)
+    assert 'cannot reset in term' in excinfo.value.response.text
+
+    res = env.zenith_cli.reset_timeline('test_timeline_reset', reset_lsn)
+    log.info(f"timeline reset output: {res.stdout}")
+
+    detail = client.timeline_detail(tenant_id, timeline_id)
+    assert detail['local']['last_record_lsn'] == reset_lsn
+    # All the safekeepers start the same new term at the reset point
+    sk_statuses = [
+        sk.http_client().timeline_status(tenant_id.hex, timeline_id.hex) for sk in env.safekeepers
+    ]
+    for sk_status in sk_statuses:
+        assert lsn_from_hex(sk_status.flush_lsn) == lsn_from_hex(reset_lsn)
+    assert {sk_status.acceptor_term for sk_status in sk_statuses} == {sk_statuses[0].acceptor_term}
+    assert sk_statuses[0].acceptor_term > term
+
+    # The compute starts from the reset point, and the data written after it is gone
+    pg.start()
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("SELECT count(*) FROM foo")
+            assert cur.fetchone() == (10000, )
+            cur.execute("SELECT count(*) FROM information_schema.columns "
+                        "WHERE table_name = 'foo' AND column_name = 'j'")
+            assert cur.fetchone() == (0, )
+
+            # New changes are accepted, and survive a pageserver restart
+            cur.execute("INSERT INTO foo SELECT g, 'new' FROM generate_series(10001, 10100) g")
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            current_lsn = lsn_from_hex(cur.fetchone()[0])
+    wait_for_last_record_lsn(client, tenant_id, timeline_id, current_lsn)
+
+    pg.stop()
+    env.pageserver.stop()
+    env.pageserver.start()
+    pg.start()
+    with closing(pg.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("SELECT count(*) FROM foo")
+            assert cur.fetchone() == (10100, )
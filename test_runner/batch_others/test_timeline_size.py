@@ -1,8 +1,9 @@
 from contextlib import closing
 import psycopg2.extras
 import psycopg2.errors
-from fixtures.zenith_fixtures import ZenithEnv, ZenithEnvBuilder, Postgres, assert_local
+from fixtures.zenith_fixtures import ZenithEnv, ZenithEnvBuilder, Postgres, assert_local, wait_for_last_record_lsn
 from fixtures.log_helper import log
+from fixtures.utils import lsn_from_hex
 import time
 
 
@@ -43,6 +44,51 @@ def test_timeline_size(zenith_simple_env: ZenithEnv):
                 "current_logical_size_non_incremental"]
 
 
+def test_timeline_size_breakdown(zenith_simple_env: ZenithEnv):
+    env = zenith_simple_env
+    new_timeline_id = env.zenith_cli.create_branch('test_timeline_size_breakdown', 'empty')
+
+    client = env.pageserver.http_client()
+    pgmain = env.postgres.create_start("test_timeline_size_breakdown")
+
+    with closing(pgmain.connect()) as conn:
+        with conn.cursor() as cur:
+            cur.execute("CREATE TABLE foo (t text)")
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            lsn_before_insert = cur.fetchone()[0]
+            cur.execute("""
+                INSERT INTO foo
+                    SELECT 'long string to consume some space' || g
+                    FROM generate_series(1, 10000) g
+            """)
+            cur.execute("SELECT pg_current_wal_flush_lsn()")
+            lsn_after_insert = cur.fetchone()[0]
+
+            cur.execute("SELECT oid FROM pg_database WHERE datname = current_database()")
+            db_oid = cur.fetchone()[0]
+            cur.execute("SELECT pg_relation_filenode('foo'), pg_relation_size('foo')")
+            foo_relnode, foo_size = cur.fetchone()
+
+    wait_for_last_record_lsn(client,
+                             env.initial_tenant,
+                             new_timeline_id,
+                             lsn_from_hex(lsn_after_insert))
+
+    def foo_fork_sizes(lsn):
+        breakdown = client.timeline_logical_size(env.initial_tenant, new_timeline_id, lsn)
+        log.info(f'logical size at {lsn}: {breakdown["size"]}')
+        assert breakdown['lsn'] == lsn
+        assert breakdown['size'] == sum(db['size'] for db in breakdown['databases'])
+
+        db = next(db for db in breakdown['databases'] if db['dbnode'] == db_oid)
+        assert db['size'] == sum(rel['size'] for rel in db['relations'])
+        return {rel['forknum']: rel['size'] for rel in db['relations'] if rel['relnode'] == foo_relnode}
+
+    assert foo_fork_sizes(lsn_before_insert) == {0: 0}
+    assert foo_fork_sizes(lsn_after_insert)[0] == foo_size
+    assert foo_size > 0
+
+
 # wait until received_lsn_lag is 0
 def wait_for_pageserver_catchup(pgmain: Postgres, polling_interval=1, timeout=60):
     started_at = time.time()
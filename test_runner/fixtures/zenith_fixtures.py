@@ -789,12 +789,18 @@ class ZenithPageserverHttpClient(requests.Session):
         )
         self.verbose_error(res)
 
+    def timeline_delete(self, tenant_id: uuid.UUID, timeline_id: uuid.UUID):
+        res = self.delete(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline/{timeline_id.hex}")
+        self.verbose_error(res)
+
     def timeline_create(
         self,
         tenant_id: uuid.UUID,
         new_timeline_id: Optional[uuid.UUID] = None,
         ancestor_timeline_id: Optional[uuid.UUID] = None,
         ancestor_start_lsn: Optional[str] = None,
+        ancestor_start_timestamp: Optional[str] = None,
     ) -> Dict[Any, Any]:
         res = self.post(f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline",
                         json={
@@ -802,6 +808,8 @@ class ZenithPageserverHttpClient(requests.Session):
                             new_timeline_id.hex if new_timeline_id else None,
                             'ancestor_start_lsn':
                             ancestor_start_lsn,
+                            'ancestor_start_timestamp':
+                            ancestor_start_timestamp,
                             'ancestor_timeline_id':
                             ancestor_timeline_id.hex if ancestor_timeline_id else None,
                         })
@@ -834,6 +842,32 @@ class ZenithPageserverHttpClient(requests.Session):
         assert isinstance(new_tenant_id, str)
         return uuid.UUID(new_tenant_id)
 
+    def tenant_status(self, tenant_id: uuid.UUID) -> Dict[Any, Any]:
+        res = self.get(f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def tenant_size(self, tenant_id: uuid.UUID) -> Dict[Any, Any]:
+        res = self.get(f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/size")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def tenant_attach(self, tenant_id: uuid.UUID):
+        res = self.post(f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/attach")
+        self.verbose_error(res)
+
+    def tenant_detach(self, tenant_id: uuid.UUID):
+        res = self.post(f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/detach")
+        self.verbose_error(res)
+
+    def tenant_delete(self, tenant_id: uuid.UUID):
+        res = self.delete(f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}")
+        self.verbose_error(res)
+
     def timeline_list(self, tenant_id: uuid.UUID) -> List[Dict[Any, Any]]:
         res = self.get(f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline")
         self.verbose_error(res)
@@ -850,6 +884,103 @@ class ZenithPageserverHttpClient(requests.Session):
         assert isinstance(res_json, dict)
         return res_json
 
+    def timeline_logical_size(self,
+                              tenant_id: uuid.UUID,
+                              timeline_id: uuid.UUID,
+                              lsn: Optional[str] = None) -> Dict[Any, Any]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline/{timeline_id.hex}/logical_size",
+            params={'lsn': lsn} if lsn else None)
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_gc_dry_run(self,
+                            tenant_id: uuid.UUID,
+                            timeline_id: uuid.UUID,
+                            gc_horizon: Optional[int] = None) -> Dict[Any, Any]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline/{timeline_id.hex}/gc_dry_run",
+            params={'gc_horizon': gc_horizon} if gc_horizon is not None else None)
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_config(self, tenant_id: uuid.UUID, timeline_id: uuid.UUID) -> Dict[Any, Any]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline/{timeline_id.hex}/config"
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def timeline_config_set(self,
+                            tenant_id: uuid.UUID,
+                            timeline_id: uuid.UUID,
+                            conf: Dict[str, Any]):
+        res = self.put(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline/{timeline_id.hex}/config",
+            json=conf)
+        self.verbose_error(res)
+
+    def timeline_reset(self,
+                       tenant_id: uuid.UUID,
+                       timeline_id: uuid.UUID,
+                       lsn: str,
+                       prev_record_lsn: Optional[str] = None):
+        body: Dict[str, Any] = {'lsn': lsn}
+        if prev_record_lsn is not None:
+            body['prev_record_lsn'] = prev_record_lsn
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline/{timeline_id.hex}/reset",
+            json=body)
+        self.verbose_error(res)
+
+    def timeline_detach_ancestor(self, tenant_id: uuid.UUID, timeline_id: uuid.UUID):
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline/{timeline_id.hex}/detach_ancestor"
+        )
+        self.verbose_error(res)
+
+    def restore_point_list(self, tenant_id: uuid.UUID,
+                           timeline_id: uuid.UUID) -> List[Dict[str, Any]]:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline/{timeline_id.hex}/restore_point"
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, list)
+        return res_json
+
+    def restore_point_create(self,
+                             tenant_id: uuid.UUID,
+                             timeline_id: uuid.UUID,
+                             name: str,
+                             lsn: Optional[str] = None) -> Dict[str, Any]:
+        body: Dict[str, Any] = {'name': name}
+        if lsn is not None:
+            body['lsn'] = lsn
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline/{timeline_id.hex}/restore_point",
+            json=body)
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
+    def restore_point_delete(self, tenant_id: uuid.UUID, timeline_id: uuid.UUID,
+                             name: str) -> Dict[str, Any]:
+        res = self.delete(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline/{timeline_id.hex}/restore_point/{name}"
+        )
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
     def wal_receiver_get(self, tenant_id: uuid.UUID, timeline_id: uuid.UUID) -> Dict[Any, Any]:
         res = self.get(
             f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline/{timeline_id.hex}/wal_receiver"
@@ -859,6 +990,24 @@ class ZenithPageserverHttpClient(requests.Session):
         assert isinstance(res_json, dict)
         return res_json
 
+    def timeline_fullbackup(self,
+                            tenant_id: uuid.UUID,
+                            timeline_id: uuid.UUID,
+                            lsn: Optional[str] = None) -> bytes:
+        res = self.get(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id.hex}/timeline/{timeline_id.hex}/fullbackup",
+            params={'lsn': lsn} if lsn else None,
+        )
+        self.verbose_error(res)
+        return res.content
+
+    def disk_usage_eviction(self) -> Dict[Any, Any]:
+        res = self.get(f"http://localhost:{self.port}/v1/disk_usage_eviction")
+        self.verbose_error(res)
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
     def get_metrics(self) -> str:
         res = self.get(f"http://localhost:{self.port}/metrics")
         self.verbose_error(res)
@@ -1006,6 +1155,37 @@ class ZenithCli:
         else:
             return uuid.UUID(created_timeline_id)
 
+    def config_timeline(self,
+                        branch_name: str,
+                        conf: Dict[str, str],
+                        tenant_id: Optional[uuid.UUID] = None):
+        """
+        Override the tenant's GC settings for the timeline of a branch.
+        """
+        res = self.raw_cli(
+            ['timeline', 'config', '--branch-name', branch_name, '--tenant-id',
+             (tenant_id or self.env.initial_tenant).hex] +
+            sum(list(map(lambda kv: (['-c', kv[0] + ':' + kv[1]]), conf.items())), []))
+        res.check_returncode()
+
+    def reset_timeline(self,
+                       branch_name: str,
+                       lsn: str,
+                       tenant_id: Optional[uuid.UUID] = None) -> 'subprocess.CompletedProcess[str]':
+        """
+        Truncate the history of a branch at the given LSN on the safekeepers and the pageserver.
+        """
+        return self.raw_cli([
+            'timeline',
+            'reset',
+            '--branch-name',
+            branch_name,
+            '--lsn',
+            lsn,
+            '--tenant-id',
+            (tenant_id or self.env.initial_tenant).hex,
+        ])
+
     def list_timelines(self, tenant_id: Optional[uuid.UUID] = None) -> List[Tuple[str, str]]:
         """
         Returns a list of (branch_name, timeline_id) tuples out of parsed `zenith timeline list` CLI output.
@@ -1353,12 +1533,13 @@ def pg_bin(test_output_dir: str) -> PgBin:
 
 
 class VanillaPostgres(PgProtocol):
-    def __init__(self, pgdatadir: str, pg_bin: PgBin, port: int):
+    def __init__(self, pgdatadir: str, pg_bin: PgBin, port: int, init: bool = True):
         super().__init__(host='localhost', port=port, dbname='postgres')
         self.pgdatadir = pgdatadir
         self.pg_bin = pg_bin
         self.running = False
-        self.pg_bin.run_capture(['initdb', '-D', pgdatadir])
+        if init:
+            self.pg_bin.run_capture(['initdb', '-D', pgdatadir])
 
     def configure(self, options: List[str]):
         """Append lines into postgresql.conf file."""
@@ -1808,6 +1989,7 @@ class Safekeeper:
 
 @dataclass
 class SafekeeperTimelineStatus:
+    acceptor_term: int
     acceptor_epoch: int
     flush_lsn: str
     timeline_start_lsn: str
@@ -1835,7 +2017,8 @@ class SafekeeperHttpClient(requests.Session):
         res = self.get(f"http://localhost:{self.port}/v1/timeline/{tenant_id}/{timeline_id}")
         res.raise_for_status()
         resj = res.json()
-        return SafekeeperTimelineStatus(acceptor_epoch=resj['acceptor_state']['epoch'],
+        return SafekeeperTimelineStatus(acceptor_term=resj['acceptor_state']['term'],
+                                        acceptor_epoch=resj['acceptor_state']['epoch'],
                                         flush_lsn=resj['flush_lsn'],
                                         timeline_start_lsn=resj['timeline_start_lsn'],
                                         backup_lsn=resj['backup_lsn'],
@@ -1847,6 +2030,18 @@ class SafekeeperHttpClient(requests.Session):
             json=body)
         res.raise_for_status()
 
+    def timeline_reset(self, tenant_id: str, timeline_id: str, lsn: str,
+                       term: int) -> Dict[Any, Any]:
+        res = self.post(
+            f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}/reset",
+            json={
+                'lsn': lsn, 'term': term
+            })
+        res.raise_for_status()
+        res_json = res.json()
+        assert isinstance(res_json, dict)
+        return res_json
+
     def timeline_delete_force(self, tenant_id: str, timeline_id: str) -> Dict[Any, Any]:
         res = self.delete(
             f"http://localhost:{self.port}/v1/tenant/{tenant_id}/timeline/{timeline_id}")